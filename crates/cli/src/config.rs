@@ -0,0 +1,85 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// User settings read from the config file, which is `$CRISP_CONFIG` if set
+/// and `~/.crisprc` otherwise. Each line is a `key = value` pair, and lines
+/// starting with `#` are ignored:
+///
+/// ```text
+/// # Show the number of the input being entered
+/// prompt = "[{count}]> "
+/// continuation-prompt = "...> "
+/// ```
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Prompt shown before each input, where `{count}` is replaced by the
+    /// number of the input
+    pub prompt: String,
+    /// Prompt shown on following lines while an input has unclosed parens
+    pub continuation_prompt: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prompt: "> ".to_string(),
+            continuation_prompt: "..> ".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file, falling back to the defaults if there isn't one
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match Self::path() {
+            Some(path) if path.exists() => {
+                let contents = fs::read_to_string(&path)?;
+                Self::parse(&contents).map_err(|err| format!("{}: {err}", path.display()).into())
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        match env::var_os("CRISP_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".crisprc")),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("line {}: expected `key = value`", i + 1))?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value)
+                .to_string();
+
+            match key.trim() {
+                "prompt" => config.prompt = value,
+                "continuation-prompt" => config.continuation_prompt = value,
+                key => return Err(format!("line {}: unknown setting '{key}'", i + 1)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The prompt for the `count`th input of the session
+    pub fn prompt(&self, count: usize) -> String {
+        self.prompt.replace("{count}", &count.to_string())
+    }
+}
@@ -0,0 +1,41 @@
+use crisp::format::format_source;
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::options::Options;
+
+pub fn run(files: &[PathBuf], check: bool, options: &Options) -> Result<(), Box<dyn Error>> {
+    let renderer = options.renderer();
+    let mut unformatted = 0;
+
+    for file in files {
+        let contents = fs::read_to_string(file)?;
+        let formatted = match format_source(&contents) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                println!("{}", renderer.error(&err, &contents, Some(file)));
+                return Err("couldn't parse file".into());
+            }
+        };
+
+        if formatted == contents {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", file.display());
+            unformatted += 1;
+        } else {
+            fs::write(file, formatted)?;
+            println!("formatted {}", file.display());
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(format!("{unformatted} file(s) need formatting").into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crisp::eval::is_keyword;
+use crisp::format::to_source;
+use crisp::lang::{CrispError, CrispExpr, MapKey, Primitive};
+use crisp::lexer;
+use crisp::parse::parse_forms;
+use serde_json::{Map, Number, Value};
+
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::process::ExitCode;
+
+use crate::options::Options;
+
+/// Evaluate `expr` once for every JSON value on stdin, one per line, with the
+/// value bound to `it`, printing each result as a line of JSON
+pub fn run(expr: &str, options: &Options) -> Result<ExitCode, Box<dyn Error>> {
+    let renderer = options.renderer();
+    let forms = match parse_forms(&lexer(expr)) {
+        Ok(forms) => forms,
+        Err(err) => {
+            eprintln!("{}", renderer.error(&err, expr, None));
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let env = options.env();
+
+    let mut failed = false;
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value = match serde_json::from_str(&line) {
+            Ok(value) => from_json(value),
+            Err(err) => {
+                eprintln!("{}", renderer.failure(format!("invalid JSON: {err}")));
+                failed = true;
+                continue;
+            }
+        };
+
+        // Each line gets a fresh scope, so the expression can def names
+        let mut scope = env.fork();
+        scope.symbols.insert("it".to_string(), value);
+        let res = crisp::eval_forms(&forms, &mut scope).map(|res| res.value);
+        eprint!("{}", renderer.warnings(scope.take_warnings(), None));
+
+        match res.and_then(|val| to_json(&val)) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("{}", renderer.error(&err, expr, None));
+                failed = true;
+            }
+        }
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// A JSON value as crisp data. Objects become maps with keyword keys, so
+/// `(get it :key)` reads a field, in the order the fields were written.
+fn from_json(value: Value) -> CrispExpr {
+    match value {
+        Value::Null => CrispExpr::Primitive(Primitive::Nil),
+        Value::Bool(b) => CrispExpr::Primitive(Primitive::Bool(b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => CrispExpr::Primitive(Primitive::Int(n)),
+            None => CrispExpr::Primitive(Primitive::Number(n.as_f64().unwrap_or(f64::NAN) as f32)),
+        },
+        Value::String(s) => CrispExpr::Primitive(Primitive::Str(s)),
+        Value::Array(items) => CrispExpr::List(items.into_iter().map(from_json).collect()),
+        Value::Object(fields) => CrispExpr::Map(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = MapKey::new(CrispExpr::Symbol(format!(":{key}")))
+                        .expect("keywords are map keys");
+                    (key, from_json(value))
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Crisp data as JSON, the reverse of `from_json`. Keywords and other
+/// symbols are written as strings, and so are map keys. Lists of
+/// `(:key value)` pairs are written as objects too.
+fn to_json(expr: &CrispExpr) -> Result<Value, CrispError> {
+    Ok(match expr {
+        CrispExpr::Primitive(Primitive::Nil) => Value::Null,
+        CrispExpr::Primitive(Primitive::Bool(b)) => Value::Bool(*b),
+        CrispExpr::Primitive(Primitive::Int(n)) => Value::Number(Number::from(*n)),
+        // Whole floats are written without a fraction too, as JSON has one
+        // kind of number
+        CrispExpr::Primitive(Primitive::Number(n)) if n.fract() == 0. && n.abs() < 1e15 => {
+            Value::Number(Number::from(*n as i64))
+        }
+        CrispExpr::Primitive(Primitive::Number(n)) => {
+            Number::from_f64(*n as f64).map_or(Value::Null, Value::Number)
+        }
+        CrispExpr::Primitive(Primitive::Decimal(d)) => d
+            .to_string()
+            .parse()
+            .ok()
+            .and_then(Number::from_f64)
+            .map_or(Value::Null, Value::Number),
+        CrispExpr::Primitive(Primitive::Str(s)) => Value::String(s.clone()),
+        CrispExpr::Symbol(name) => Value::String(name.trim_start_matches(':').to_string()),
+        CrispExpr::List(xs) => match object_fields(xs) {
+            Some(fields) => Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key.to_string(), to_json(value)?)))
+                    .collect::<Result<Map<String, Value>, CrispError>>()?,
+            ),
+            None => Value::Array(xs.iter().map(to_json).collect::<Result<_, _>>()?),
+        },
+        CrispExpr::Vector(xs) => Value::Array(xs.iter().map(to_json).collect::<Result<_, _>>()?),
+        CrispExpr::Map(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let key = match key.expr() {
+                        CrispExpr::Primitive(Primitive::Str(s)) => s.clone(),
+                        CrispExpr::Symbol(name) => name.trim_start_matches(':').to_string(),
+                        key => to_source(key),
+                    };
+                    Ok((key, to_json(value)?))
+                })
+                .collect::<Result<Map<String, Value>, CrispError>>()?,
+        ),
+        _ => {
+            return Err(CrispError::EvalError(
+                "only data can be written as JSON, not functions".to_string(),
+            ))
+        }
+    })
+}
+
+/// The fields of a non-empty list of `(:key value)` pairs
+fn object_fields(xs: &[CrispExpr]) -> Option<Vec<(&str, &CrispExpr)>> {
+    let fields = xs.iter().map(field).collect::<Option<Vec<_>>>()?;
+    (!fields.is_empty()).then_some(fields)
+}
+
+fn field(x: &CrispExpr) -> Option<(&str, &CrispExpr)> {
+    match x {
+        CrispExpr::List(pair) => match pair.as_slice() {
+            [CrispExpr::Symbol(key), value] if is_keyword(key) => Some((&key[1..], value)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,171 @@
+use crisp::eval::{CrispEnvBuilder, EvalOptions};
+use crisp::format::to_source;
+use crisp::interpreter::Interpreter;
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::options::Options;
+use crate::render::Renderer;
+
+/// Most steps an answer can take, so a runaway loop fails instead of hanging
+const MAX_STEPS: usize = 1_000_000;
+
+/// A lesson: something to read, then a task whose answer must evaluate to
+/// the same value as `expected`
+struct Lesson {
+    title: &'static str,
+    explanation: &'static str,
+    task: &'static str,
+    expected: &'static str,
+    hint: &'static str,
+}
+
+const LESSONS: &[Lesson] = &[
+    Lesson {
+        title: "Calling functions",
+        explanation: "Everything in crisp is a list. The first item is the function\n\
+                      and the rest are its arguments, so 1 + 2 is written (+ 1 2).",
+        task: "Add 4 and 5.",
+        expected: "9",
+        hint: "(+ 4 5)",
+    },
+    Lesson {
+        title: "Nesting",
+        explanation: "Arguments can be calls themselves. The innermost calls are\n\
+                      evaluated first, so (* (+ 1 2) 3) is 9.",
+        task: "Multiply the sum of 2 and 3 by 4.",
+        expected: "20",
+        hint: "(* (+ 2 3) 4)",
+    },
+    Lesson {
+        title: "Definitions",
+        explanation: "def gives a value a name. Later forms can use the name, and\n\
+                      an answer can be several forms on one line.",
+        task: "Define width as 6 and height as 7, then multiply them.",
+        expected: "42",
+        hint: "(def width 6) (def height 7) (* width height)",
+    },
+    Lesson {
+        title: "Conditions",
+        explanation: "(if test then else) evaluates `then` when the test is true\n\
+                      and `else` otherwise. Comparisons like (> 3 2) return true or false.",
+        task: "Return 1 if 10 is greater than 3, otherwise 0.",
+        expected: "1",
+        hint: "(if (> 10 3) 1 0)",
+    },
+    Lesson {
+        title: "Functions",
+        explanation: "(fn (x) body) makes a function of x, which def can name.",
+        task: "Define square, which multiplies a number by itself, and call it on 7.",
+        expected: "49",
+        hint: "(def square (fn (x) (* x x))) (square 7)",
+    },
+    Lesson {
+        title: "Lists as data",
+        explanation: "quote returns a list without evaluating it, so it can be\n\
+                      passed around as data rather than called.",
+        task: "Quote the list (1 2 3).",
+        expected: "(quote (1 2 3))",
+        hint: "(quote (1 2 3))",
+    },
+    Lesson {
+        title: "Working with lists",
+        explanation: "sum adds up a list of numbers.",
+        task: "Sum the numbers 1 to 5 in a quoted list.",
+        expected: "15",
+        hint: "(sum (quote (1 2 3 4 5)))",
+    },
+    Lesson {
+        title: "Exact decimals",
+        explanation: "Floats round, so (+ 0.1 0.2) isn't quite 0.3. Decimals,\n\
+                      written with an m like 0.1m, are exact.",
+        task: "Add 0.1m and 0.2m.",
+        expected: "0.3m",
+        hint: "(+ 0.1m 0.2m)",
+    },
+];
+
+/// Walk through the lessons in order, reading answers from stdin
+pub fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let renderer = options.renderer();
+    let mut lines = io::stdin().lock().lines();
+
+    println!("Welcome to crisp! Answer each task with an expression.");
+    println!("Type :hint for a hint, :skip to move on or :quit to stop.");
+
+    for (i, lesson) in LESSONS.iter().enumerate() {
+        println!(
+            "\n{} {}\n\n{}\n\n{}",
+            renderer.ok(&format!("Lesson {}/{}:", i + 1, LESSONS.len())),
+            lesson.title,
+            lesson.explanation,
+            lesson.task
+        );
+
+        loop {
+            print!("> ");
+            io::stdout().flush()?;
+            let answer = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            match answer.trim() {
+                "" => continue,
+                ":quit" => return Ok(()),
+                ":skip" => break,
+                ":hint" => println!("Try {}", lesson.hint),
+                answer => {
+                    if check(lesson, answer, &renderer) {
+                        println!("{}", renderer.ok("Correct!"));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("\nThat's every lesson. Run `crisp repl` to keep exploring.");
+    Ok(())
+}
+
+/// Evaluate an answer in a fresh sandbox, printing what it returned when it
+/// isn't the expected value
+fn check(lesson: &Lesson, answer: &str, renderer: &Renderer) -> bool {
+    let (res, printed) = sandbox().eval_capture(answer);
+    print!("{printed}");
+
+    let value = match res {
+        Ok(value) => value,
+        Err(err) => {
+            println!("{}", renderer.error(&err, answer, None));
+            return false;
+        }
+    };
+
+    let expected = sandbox().eval(lesson.expected);
+    if expected.is_ok_and(|expected| to_source(&expected) == to_source(&value)) {
+        return true;
+    }
+
+    println!(
+        "{} got {}, try again",
+        renderer.failed("Not quite:"),
+        renderer.value(&value)
+    );
+    false
+}
+
+/// An interpreter without access to files, with a limit on steps
+fn sandbox() -> Interpreter {
+    let builder = CrispEnvBuilder::new()
+        .core()
+        .math()
+        .strings()
+        .options(EvalOptions {
+            max_steps: Some(MAX_STEPS),
+            ..Default::default()
+        });
+    Interpreter::with_builder(builder)
+}
@@ -0,0 +1,37 @@
+use crisp::lexer;
+use crisp::lint::lint;
+use crisp::parse::parse_all;
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::options::Options;
+
+pub fn run(files: &[PathBuf], options: &Options) -> Result<(), Box<dyn Error>> {
+    let env = options.env();
+    let renderer = options.renderer();
+    let mut warnings = 0;
+
+    for file in files {
+        let contents = fs::read_to_string(file)?;
+        let forms = match parse_all(&lexer(&contents)) {
+            Ok(forms) => forms,
+            Err(err) => {
+                println!("{}", renderer.error(&err, &contents, Some(file)));
+                return Err("couldn't parse file".into());
+            }
+        };
+
+        for lint in lint(&forms, &env) {
+            println!("{}", renderer.warning(lint.message, Some(file)));
+            warnings += 1;
+        }
+    }
+
+    if warnings > 0 {
+        return Err(format!("{warnings} warning(s) found").into());
+    }
+
+    Ok(())
+}
@@ -1,30 +1,428 @@
+mod config;
+mod fmt;
+mod jsonl;
+mod learn;
+mod lint;
+mod options;
+mod render;
 mod repl;
+mod test;
+mod watch;
+
+use clap::{CommandFactory, Parser, Subcommand};
 
-use std::env;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, LazyLock};
+use std::thread;
 
+use crisp::bundle;
+use crisp::cache::ParseCache;
+use crisp::compile;
 use crisp::eval::CrispEnv;
 use crisp::lang::CrispResult;
-use crisp::run_program;
+use crisp::parse::parse_source;
+use crisp::snapshot;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
+use options::Options;
 
-    if let Some(file) = args.get(1) {
-        let contents = fs::read_to_string(file)?;
-        let output = interpret(&contents)?;
+/// The version of crisp and the optional features it was built with, for
+/// `--version`
+static VERSION: LazyLock<String> = LazyLock::new(|| match crisp::FEATURES {
+    [] => crisp::VERSION.to_string(),
+    features => format!("{} (features: {})", crisp::VERSION, features.join(", ")),
+});
 
-        println!("{}", output.to_string());
-    } else {
-        let mut env = CrispEnv::default();
-        repl::run(&mut env)?;
+/// Simple Lisp interpreter based on the Clojure spec
+#[derive(Parser)]
+#[command(
+    name = "crisp",
+    bin_name = "crisp",
+    version = VERSION.as_str(),
+    after_help = "A FILE given without a subcommand is run, as in `crisp run FILE`."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Evaluate EXPR for each line of JSON on stdin, with the line bound to
+    /// `it`, and print each result as JSON. Objects are maps with keyword
+    /// keys, and `(get it :key)` reads a field.
+    #[arg(long, value_name = "EXPR")]
+    jsonl: Option<String>,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+impl Cli {
+    /// Parse the command line, taking `crisp [OPTIONS] FILE ...` as
+    /// `crisp run [OPTIONS] FILE ...`. Options can't tell a FILE from a
+    /// subcommand, so `run` is put in front of the first argument that
+    /// isn't an option, an option's value or a subcommand.
+    fn parse_args(args: impl IntoIterator<Item = OsString>) -> Self {
+        let mut args: Vec<OsString> = args.into_iter().collect();
+        let command = Cli::command();
+        let takes_value =
+            |arg: Option<&clap::Arg>| arg.is_some_and(|arg| arg.get_action().takes_values());
+
+        let mut i = 1;
+        while let Some(arg) = args.get(i).map(|arg| arg.to_string_lossy()) {
+            if arg == "--" {
+                break;
+            }
+            if let Some(long) = arg.strip_prefix("--") {
+                let named = command.get_arguments().find(|a| a.get_long() == Some(long));
+                if takes_value(named) {
+                    i += 1;
+                }
+            } else if let Some(short) = arg.strip_prefix('-').filter(|short| short.len() == 1) {
+                let named = command
+                    .get_arguments()
+                    .find(|a| a.get_short() == short.chars().next());
+                if takes_value(named) {
+                    i += 1;
+                }
+            } else if !arg.starts_with('-') {
+                if command.find_subcommand(arg.as_ref()).is_none() && arg != "help" {
+                    args.insert(1, "run".into());
+                }
+                break;
+            }
+            i += 1;
+        }
+
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program and print its result
+    Run {
+        file: PathBuf,
+
+        /// Arguments for the program, available to it as `*args*`
+        #[arg(last = true)]
+        args: Vec<String>,
+
+        /// Re-run the program every time the file changes
+        #[arg(short, long, conflicts_with = "interactive")]
+        watch: bool,
+
+        /// Start the REPL after running, with the program's definitions
+        #[arg(short, long)]
+        interactive: bool,
+    },
+    /// Start an interactive session
+    Repl,
+    /// Parse a program ahead of time into a .crispc file, which `run` and
+    /// `:load` accept in place of source
+    Compile {
+        file: PathBuf,
+
+        /// Where to write the compiled program, FILE with a .crispc
+        /// extension by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Generate a Rust program that embeds a compiled program and runs it,
+    /// for shipping a script as a single binary (experimental)
+    Build {
+        file: PathBuf,
+
+        /// Where to write the Rust source, FILE with a .rs extension by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run a prelude and save its definitions to a .crisps snapshot, for
+    /// embedders to start from with `Interpreter::from_snapshot`
+    Snapshot {
+        file: PathBuf,
+
+        /// Where to write the snapshot, FILE with a .crisps extension by
+        /// default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Combine a program and the files it loads into a single program
+    Bundle {
+        file: PathBuf,
+
+        /// Where to write the bundled program, instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Reformat programs in place
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Only report files that aren't formatted, without changing them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run every .crisp file under the given paths, failing on any error or,
+    /// with --golden, any unexpected result
+    Test {
+        #[arg(default_value = "tests")]
+        paths: Vec<PathBuf>,
+
+        /// Compare each program's result with its sibling .expected file
+        #[arg(long)]
+        golden: bool,
+
+        /// Rewrite the .expected files with the current results
+        #[arg(long, requires = "golden")]
+        update: bool,
+    },
+    /// Check programs for likely mistakes without running them
+    Lint {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Learn crisp through a series of short interactive lessons
+    Learn,
+}
+
+/// Stack size for the thread programs run on. Every nested call takes
+/// several Rust frames, so deep recursion needs far more than the main
+/// thread's stack. It's only reserved, not allocated, up front.
+const STACK_SIZE: usize = 1 << 30;
+
+/// How deep expressions can nest, far deeper than `ParseOptions`'s default
+/// allows as programs run on a stack of `STACK_SIZE`
+const MAX_NESTING: usize = 100_000;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_args(std::env::args_os());
+    let renderer = cli.options.renderer();
+
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(cli).map_err(|err| err.to_string()));
+
+    let res = match interpreter.map(|handle| handle.join()) {
+        Ok(Ok(res)) => res,
+        Ok(Err(panic)) => std::panic::resume_unwind(panic),
+        Err(err) => Err(format!("couldn't start the interpreter: {err}")),
+    };
+
+    match res {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", renderer.failure(err));
+            ExitCode::FAILURE
+        }
     }
+}
+
+fn run(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let options = cli.options;
+    let renderer = options.renderer();
+
+    if let Some(expr) = cli.jsonl {
+        if cli.command.is_some() {
+            return Err("--jsonl reads its input from stdin, not a FILE or subcommand".into());
+        }
+        return jsonl::run(&expr, &options);
+    }
+
+    let command = cli.command.unwrap_or(Command::Repl);
+
+    match command {
+        Command::Run {
+            file,
+            args,
+            watch: true,
+            ..
+        } => watch::run(&file, &args, &options)?,
+        Command::Run {
+            file,
+            args,
+            interactive,
+            ..
+        } => {
+            let mut env = options.env();
+            env.set_args(&args);
+            env.set_script(&file.display().to_string());
+            let (contents, res) = run_file(&file, &mut env, None)?;
+            print!("{}", renderer.warnings(env.take_warnings(), Some(&file)));
+            println!("{}", renderer.result(&res, &contents, Some(&file)));
+
+            if interactive {
+                repl::run(&mut env, &options)?;
+            } else if res.is_err() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Repl => {
+            let mut env = options.env();
+            repl::run(&mut env, &options)?;
+        }
+        Command::Compile { file, output } => {
+            let contents = fs::read_to_string(&file)?;
+            let compiled = match compile::compile(&contents) {
+                Ok(compiled) => compiled,
+                Err(err) => {
+                    println!("{}", renderer.error(&err, &contents, Some(&file)));
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+
+            fs::write(output.unwrap_or(file.with_extension("crispc")), compiled)?;
+        }
+        Command::Build { file, output } => {
+            let contents = fs::read_to_string(&file)?;
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let rust = match compile::to_rust(&contents, &name) {
+                Ok(rust) => rust,
+                Err(err) => {
+                    println!("{}", renderer.error(&err, &contents, Some(&file)));
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+
+            fs::write(output.unwrap_or(file.with_extension("rs")), rust)?;
+        }
+        Command::Snapshot { file, output } => {
+            // No `*script*`, which would end up in the snapshot
+            let mut env = options.env();
+            let (contents, res) = run_file(&file, &mut env, None)?;
+            print!("{}", renderer.warnings(env.take_warnings(), Some(&file)));
+
+            match res.and_then(|_| snapshot::snapshot(&env)) {
+                Ok(snapshot) => {
+                    fs::write(output.unwrap_or(file.with_extension("crisps")), snapshot)?
+                }
+                Err(err) => {
+                    println!("{}", renderer.error(&err, &contents, Some(&file)));
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        Command::Bundle { file, output } => {
+            let bundled = match bundle::bundle(&file.display().to_string(), &options.loader()) {
+                Ok(bundled) => bundled,
+                Err(err) => {
+                    let contents = fs::read_to_string(&file).unwrap_or_default();
+                    println!("{}", renderer.error(&err, &contents, Some(&file)));
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+
+            match output {
+                Some(output) => fs::write(output, bundled)?,
+                None => print!("{bundled}"),
+            }
+        }
+        Command::Fmt { files, check } => fmt::run(&files, check, &options)?,
+        Command::Test {
+            paths,
+            golden,
+            update,
+        } => {
+            let mode = match (golden, update) {
+                (_, true) => test::Mode::Update,
+                (true, false) => test::Mode::Golden,
+                (false, false) => test::Mode::Run,
+            };
+            test::run(&paths, mode, &options)?
+        }
+        Command::Lint { files } => lint::run(&files, &options)?,
+        Command::Learn => learn::run(&options)?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+/// Run a program in a fresh env, returning its source along with the result
+fn interpret(file: &Path, args: &[String], options: &Options) -> io::Result<(String, CrispResult)> {
+    let mut env = options.env();
+    env.set_args(args);
+    env.set_script(&file.display().to_string());
+    run_file(file, &mut env, None)
 }
 
-fn interpret(expr: &str) -> CrispResult {
-    let mut env = CrispEnv::default();
-    run_program(expr, &mut env)
+/// Run a program from either source or a file made by `crisp compile`,
+/// returning its source along with the result. The source is empty for
+/// compiled programs.
+fn run_file(
+    file: &Path,
+    env: &mut CrispEnv,
+    cache: Option<&mut ParseCache>,
+) -> io::Result<(String, CrispResult)> {
+    let bytes = fs::read(file)?;
+
+    let (src, forms) = if compile::is_compiled(&bytes) {
+        (
+            String::new(),
+            compile::decode_with(&bytes, &env.options.parse).map(Arc::new),
+        )
+    } else {
+        let src = String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let forms = match cache {
+            Some(cache) => cache.parse(&src),
+            None => parse_source(&src, &env.options.parse).map(Arc::new),
+        };
+        (src, forms)
+    };
+
+    let res = forms
+        .and_then(|forms| crisp::eval_forms(&forms, env))
+        .map(|res| res.value);
+
+    Ok((src, res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Cli {
+        Cli::parse_args(args.split_whitespace().map(OsString::from))
+    }
+
+    #[test]
+    fn global_options_come_before_subcommands() {
+        let cli = parse("crisp --color never repl");
+        assert!(matches!(cli.command, Some(Command::Repl)));
+        assert_eq!(cli.options.color, options::ColorChoice::Never);
+
+        let cli = parse("crisp --max-depth 5 run f.crisp");
+        assert!(
+            matches!(cli.command, Some(Command::Run { file, .. }) if file == Path::new("f.crisp"))
+        );
+        assert_eq!(cli.options.max_depth, Some(5));
+    }
+
+    #[test]
+    fn bare_files_are_run() {
+        let cli = parse("crisp --max-depth 5 -i f.crisp -- a b");
+        let Some(Command::Run {
+            file,
+            args,
+            interactive,
+            watch,
+        }) = cli.command
+        else {
+            panic!("a bare FILE should be run");
+        };
+        assert_eq!(file, Path::new("f.crisp"));
+        assert_eq!(args, ["a", "b"]);
+        assert!(interactive && !watch);
+        assert_eq!(cli.options.max_depth, Some(5));
+
+        // Options' values aren't taken for the FILE
+        let cli = parse("crisp --path repl f.crisp");
+        assert!(
+            matches!(cli.command, Some(Command::Run { file, .. }) if file == Path::new("f.crisp"))
+        );
+        assert!(parse("crisp").command.is_none());
+    }
 }
@@ -0,0 +1,135 @@
+use clap::{Args, ValueEnum};
+
+use crisp::eval::{self, CrispEnv, CrispEnvBuilder, EvalOptions};
+use crisp::format::PrintLimits;
+use crisp::fs::FileLoader;
+use crisp::io::StderrTracer;
+use crisp::lang::Tracer;
+use crisp::parse::ParseOptions;
+use crisp::StdBuilder;
+
+use crate::render::{ErrorFormat, Renderer};
+
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ColorChoice {
+    /// Use color when writing to a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// What dividing by zero gives, see `crisp::eval::DivisionByZero`
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DivisionByZero {
+    /// Fail with a division-by-zero error
+    #[default]
+    Error,
+    /// Give nil
+    Nil,
+    /// Give infinity from `/` on ints and floats, failing otherwise
+    Infinity,
+}
+
+impl From<DivisionByZero> for eval::DivisionByZero {
+    fn from(choice: DivisionByZero) -> Self {
+        match choice {
+            DivisionByZero::Error => Self::Error,
+            DivisionByZero::Nil => Self::Nil,
+            DivisionByZero::Infinity => Self::Infinity,
+        }
+    }
+}
+
+/// Options shared by every subcommand
+#[derive(Args, Clone, Debug)]
+pub struct Options {
+    /// Abort evaluation after this many nested function calls
+    #[arg(long, global = true, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Print every function call and its result to stderr
+    #[arg(long, global = true)]
+    pub trace: bool,
+
+    /// Compile numeric functions to native code after this many calls
+    #[cfg(feature = "jit")]
+    #[arg(long, global = true, value_name = "CALLS")]
+    pub jit: Option<usize>,
+
+    /// Allow def to rebind builtins, keeping the originals for unshadow
+    #[arg(long, global = true)]
+    pub redefine_builtins: bool,
+
+    /// What dividing by zero gives
+    #[arg(long, global = true, value_enum, default_value_t = DivisionByZero::Error)]
+    pub division_by_zero: DivisionByZero,
+
+    /// When to use colored output
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Look for files to load in this directory, before those in CRISP_PATH
+    #[arg(long, global = true, value_name = "DIR")]
+    pub path: Vec<PathBuf>,
+
+    /// How to print errors and warnings
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+}
+
+impl Options {
+    /// Create a fresh global env configured by these options
+    pub fn env<'a>(&self) -> CrispEnv<'a> {
+        CrispEnvBuilder::new()
+            .all()
+            .loader(self.loader())
+            .options(EvalOptions {
+                max_depth: self.max_depth,
+                trace: self
+                    .trace
+                    .then(|| Arc::new(StderrTracer) as Arc<dyn Tracer>),
+                #[cfg(feature = "jit")]
+                jit_threshold: self.jit,
+                redefine_builtins: self.redefine_builtins,
+                division_by_zero: self.division_by_zero.into(),
+                parse: ParseOptions {
+                    max_depth: Some(crate::MAX_NESTING),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .build()
+    }
+
+    /// A loader searching the `--path` directories, then those in CRISP_PATH
+    pub fn loader(&self) -> FileLoader {
+        let mut loader = FileLoader::from_env();
+        loader.search_path.splice(0..0, self.path.iter().cloned());
+        loader
+    }
+
+    pub fn use_color(&self) -> bool {
+        match self.color {
+            // https://no-color.org
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|val| val.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        Renderer {
+            color: self.use_color(),
+            error_format: self.error_format,
+            limits: PrintLimits::default(),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use clap::ValueEnum;
+use serde_json::json;
+
+use crisp::format::{self, to_source, PrintLimits};
+use crisp::lang::{CrispError, CrispExpr, Primitive};
+
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const BLUE: &str = "34";
+const MAGENTA: &str = "35";
+const CYAN: &str = "36";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Readable messages with the source underlined
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and other tools
+    Json,
+}
+
+/// Formats results, errors and warnings for the terminal, shared by the REPL
+/// and file mode so both look the same
+#[derive(Clone, Copy, Debug)]
+pub struct Renderer {
+    pub color: bool,
+    pub error_format: ErrorFormat,
+    /// How much of each value to print
+    pub limits: PrintLimits,
+}
+
+impl Renderer {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1B[{code}m{text}\x1B[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// An evaluation result, colored by type and elided to the print limits
+    pub fn value(&self, expr: &CrispExpr) -> String {
+        self.paint_value(&self.limits.elide(expr))
+    }
+
+    fn paint_value(&self, expr: &CrispExpr) -> String {
+        match expr {
+            CrispExpr::Primitive(
+                Primitive::Number(_) | Primitive::Int(_) | Primitive::Decimal(_),
+            ) => self.paint(CYAN, &to_source(expr)),
+            CrispExpr::Primitive(Primitive::Bool(_) | Primitive::Nil) => {
+                self.paint(MAGENTA, &to_source(expr))
+            }
+            CrispExpr::Primitive(Primitive::Str(_)) => self.paint(GREEN, &to_source(expr)),
+            CrispExpr::List(xs) => format!(
+                "({})",
+                xs.iter()
+                    .map(|x| self.paint_value(x))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            CrispExpr::Vector(xs) => format!(
+                "[{}]",
+                xs.iter()
+                    .map(|x| self.paint_value(x))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            CrispExpr::Map(map) => format!(
+                "{{{}}}",
+                map.iter()
+                    .map(|(key, value)| format!(
+                        "{} {}",
+                        self.paint_value(key.expr()),
+                        self.paint_value(value)
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            CrispExpr::Fn(_) | CrispExpr::Lambda(_) | CrispExpr::Handle(_) => {
+                self.paint(BLUE, &to_source(expr))
+            }
+            CrispExpr::Symbol(_) => to_source(expr),
+            #[cfg(feature = "ndarray")]
+            CrispExpr::Array(_) => self.paint(CYAN, &to_source(expr)),
+        }
+    }
+
+    /// An error from the interpreter, with the offending source underlined
+    /// when the error carries a span
+    pub fn error(&self, err: &CrispError, src: &str, file: Option<&Path>) -> String {
+        if self.error_format == ErrorFormat::Json {
+            let (line, column) = match (err.span(), err.inner()) {
+                (Some(span), _) => (Some(span.line), Some(span.col)),
+                (None, CrispError::MissingParen(line, col)) => (Some(*line), Some(*col)),
+                _ => (None, None),
+            };
+
+            return json!({
+                "severity": "error",
+                "kind": err.kind(),
+                "message": err.message(),
+                "file": file.map(|file| file.display().to_string()),
+                "line": line,
+                "column": column,
+                "span": err.span().map(|span| json!({"start": span.start, "end": span.end})),
+            })
+            .to_string();
+        }
+
+        let mut out = format!("{} {}", self.error_label(), err.inner());
+
+        let span = err.span();
+        // Decoded and compiled forms can have zeroed spans, which point at no
+        // line of the source
+        let line = span.and_then(|span| {
+            let index = (span.line as usize).checked_sub(1)?;
+            src.lines().nth(index)
+        });
+        let (span, line) = match (span, line) {
+            (Some(span), Some(line)) => (span, line),
+            // Compiled programs keep spans but not the source they point into
+            (span, _) => {
+                let location = [
+                    file.map(|file| file.display().to_string()),
+                    span.map(|span| format!("line {}, char {}", span.line, span.col)),
+                ];
+                let location = location.into_iter().flatten().collect::<Vec<_>>();
+                if !location.is_empty() {
+                    out += &format!("\n --> {}", location.join(", "));
+                }
+                return out;
+            }
+        };
+
+        // Only underline up to the end of the first line of a multi-line span
+        let start = (span.col as usize).saturating_sub(1);
+        let len = src
+            .get(span.start..span.end)
+            .unwrap_or_default()
+            .lines()
+            .next()
+            .map_or(1, |first| first.chars().count().max(1));
+
+        let file = file.map_or(String::new(), |file| format!("{}, ", file.display()));
+        let gutter = " ".repeat(span.line.to_string().len());
+        out += &format!("\n{gutter}--> {file}line {}, char {}", span.line, span.col);
+        out += &format!("\n{gutter} |");
+        out += &format!("\n{} | {line}", span.line);
+        out += &format!(
+            "\n{gutter} | {}{}",
+            " ".repeat(start),
+            self.paint(RED, &"^".repeat(len))
+        );
+
+        out
+    }
+
+    /// A result of running some source, either its value or its error
+    pub fn result(
+        &self,
+        res: &Result<CrispExpr, CrispError>,
+        src: &str,
+        file: Option<&Path>,
+    ) -> String {
+        match res {
+            Ok(val) => self.value(val),
+            Err(err) => self.error(err, src, file),
+        }
+    }
+
+    /// How long evaluating an input took
+    pub fn elapsed(&self, elapsed: Duration) -> String {
+        self.paint(CYAN, &format!("took {}", format::duration(elapsed)))
+    }
+
+    pub fn warning(&self, msg: impl Display, file: Option<&Path>) -> String {
+        if self.error_format == ErrorFormat::Json {
+            return json!({
+                "severity": "warning",
+                "kind": "lint",
+                "message": msg.to_string(),
+                "file": file.map(|file| file.display().to_string()),
+                "line": null,
+                "column": null,
+                "span": null,
+            })
+            .to_string();
+        }
+
+        match file {
+            Some(file) => format!(
+                "{}: {} {msg}",
+                file.display(),
+                self.paint(YELLOW, "warning:")
+            ),
+            None => format!("{} {msg}", self.paint(YELLOW, "warning:")),
+        }
+    }
+
+    /// Warnings raised while evaluating, one per line, e.g. from
+    /// `CrispEnv::take_warnings`
+    pub fn warnings(&self, warnings: Vec<String>, file: Option<&Path>) -> String {
+        warnings
+            .into_iter()
+            .map(|warning| self.warning(warning, file) + "\n")
+            .collect()
+    }
+
+    /// An error from the CLI itself rather than from running crisp code
+    pub fn failure(&self, msg: impl Display) -> String {
+        if self.error_format == ErrorFormat::Json {
+            return json!({
+                "severity": "error",
+                "kind": "cli",
+                "message": msg.to_string(),
+                "file": null,
+                "line": null,
+                "column": null,
+                "span": null,
+            })
+            .to_string();
+        }
+
+        format!("{} {msg}", self.error_label())
+    }
+
+    pub fn error_label(&self) -> String {
+        self.paint(RED, "Error:")
+    }
+
+    pub fn ok(&self, msg: &str) -> String {
+        self.paint(GREEN, msg)
+    }
+
+    pub fn failed(&self, msg: &str) -> String {
+        self.paint(RED, msg)
+    }
+}
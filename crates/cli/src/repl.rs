@@ -1,21 +1,111 @@
-use rustyline::validate::MatchingBracketValidator;
-use rustyline::{Cmd, Editor, EventHandler, KeyCode, KeyEvent, Modifiers};
-use rustyline::{Completer, Helper, Highlighter, Hinter, Validator};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::{Cmd, Context, Editor, EventHandler, KeyCode, KeyEvent, Modifiers};
+use rustyline::{Completer, Helper, Validator};
 
-use crisp::eval::CrispEnv;
+use crisp::cache::ParseCache;
+use crisp::doc;
+use crisp::eval::{CrispEnv, DivisionByZero};
+use crisp::format;
+use crisp::lang::CrispResult;
+use crisp::macros;
+use crisp::parse::{parse_all, parse_recovering};
 
+use crate::config::Config;
+use crate::options::Options;
+use crate::render::Renderer;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Completer, Helper, Validator)]
+struct ReplHelper {
+    /// Call signatures of every builtin and user function, by name
+    signatures: HashMap<String, String>,
+    color: bool,
+}
+
+impl ReplHelper {
+    fn update_signatures(&mut self, env: &CrispEnv) {
+        let names = doc::BUILTINS
+            .iter()
+            .map(|doc| doc.name.to_string())
+            .chain(env.bindings().into_iter().map(|(name, _)| name.to_string()));
+
+        self.signatures = names
+            .filter_map(|name| Some((name.clone(), doc::signature(&name, env)?)))
+            .collect();
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    /// Show the rest of a function's signature after its name, e.g. typing
+    /// `(if` hints ` cond then [else])`
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
 
-#[derive(Completer, Helper, Highlighter, Hinter, Validator)]
-struct InputValidator {
-    #[rustyline(Validator)]
-    brackets: MatchingBracketValidator,
+        let open = line.rfind('(')?;
+        let name = &line[open + 1..];
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let signature = self.signatures.get(name)?;
+        signature
+            .strip_prefix(&format!("({name}"))
+            .map(str::to_string)
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if self.color {
+            Cow::Owned(format!("\x1B[2m{hint}\x1B[0m"))
+        } else {
+            Cow::Borrowed(hint)
+        }
+    }
 }
 
-pub fn run(env: &mut CrispEnv) -> Result<(), Box<dyn Error>> {
-    let h = InputValidator {
-        brackets: MatchingBracketValidator::new(),
+/// Step budget for each input, so an accidental infinite loop doesn't
+/// freeze the session
+const DEFAULT_MAX_STEPS: usize = 10_000_000;
+
+pub fn run(env: &mut CrispEnv, options: &Options) -> Result<(), Box<dyn Error>> {
+    let renderer = options.renderer();
+    env.options.max_steps.get_or_insert(DEFAULT_MAX_STEPS);
+
+    // The terminal isn't in raw mode while evaluating, so Ctrl-C sends
+    // SIGINT; catch it to stop the evaluation rather than the whole session
+    let interrupt = Arc::new(AtomicBool::new(false));
+    env.options.interrupt = Some(interrupt.clone());
+    let handler = interrupt.clone();
+    ctrlc::set_handler(move || handler.store(true, Ordering::Relaxed))?;
+    let config = Config::load()?;
+    let mut session = Session {
+        renderer,
+        cache: ParseCache::new(),
+        tables: false,
+        show_time: false,
+        on_error: OnError::Keep,
+    };
+
+    let mut h = ReplHelper {
+        signatures: HashMap::new(),
+        color: renderer.color,
     };
+    h.update_signatures(env);
 
     let mut rl = Editor::new()?;
     rl.set_helper(Some(h));
@@ -24,12 +114,438 @@ pub fn run(env: &mut CrispEnv) -> Result<(), Box<dyn Error>> {
         EventHandler::Simple(Cmd::Insert(1, "\n\t".to_string())),
     );
 
-    loop {
-        let input = rl.readline("> ")?;
+    for count in 1.. {
+        let input = match read_input(&mut rl, &config, count) {
+            Ok(input) if input.trim() == ":paste" => {
+                println!("pasting, end with :end or Ctrl-D");
+                match read_paste(&mut rl) {
+                    Ok(input) if input.trim().is_empty() => continue,
+                    Ok(input) => input,
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(input) if input.trim().is_empty() => continue,
+            Ok(input) => input,
+            // Ctrl-C at the prompt discards the input, like most shells
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
         rl.add_history_entry(&input)?;
-        match crisp::run_program(&input, env) {
-            Ok(res) => println!("{res:?}"),
-            Err(err) => println!("Error: {err}"),
+        env.reset_steps();
+        interrupt.store(false, Ordering::Relaxed);
+
+        if input.trim_start().starts_with(':') {
+            println!("{}", session.command(input.trim(), env));
+        } else {
+            println!("{}", session.eval(&input, session.show_time, env));
+        }
+
+        if let Some(h) = rl.helper_mut() {
+            h.update_signatures(env);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read an input, prompting for more lines while it has unclosed parens
+fn read_input(
+    rl: &mut Editor<ReplHelper, DefaultHistory>,
+    config: &Config,
+    count: usize,
+) -> rustyline::Result<String> {
+    let mut input = rl.readline(&config.prompt(count))?;
+    while is_incomplete(&input) {
+        input.push('\n');
+        input += &rl.readline(&config.continuation_prompt)?;
+    }
+
+    Ok(input)
+}
+
+/// Read lines as they are for `:paste`, without waiting for parens to be
+/// closed, until a line with just `:end` or Ctrl-D
+fn read_paste(rl: &mut Editor<ReplHelper, DefaultHistory>) -> rustyline::Result<String> {
+    let mut lines = vec![];
+    loop {
+        match rl.readline("") {
+            Ok(line) if line.trim() == ":end" => break,
+            Ok(line) => lines.push(line),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err),
         }
     }
+
+    Ok(lines.join("\n"))
+}
+
+/// REPL commands and what they do, for `:help`
+const COMMANDS: &[(&str, &str)] = &[
+    (":help", "Show this message"),
+    (
+        ":load FILE",
+        "Run a source or compiled file, reusing cached parses of unchanged forms",
+    ),
+    (
+        ":reload MODULE",
+        "Run a file loaded with load again, updating the definitions it made",
+    ),
+    (
+        ":cache",
+        "Show how often :load found forms in the parse cache",
+    ),
+    (":where NAME", "Show where a name was defined"),
+    (
+        ":apropos TEXT",
+        "List the builtins whose names or descriptions mention TEXT",
+    ),
+    (":example NAME", "Show examples of calling a builtin"),
+    (
+        ":doc NAME",
+        "Show how to call a builtin or definition and what it does",
+    ),
+    (":time INPUT", "Evaluate an input and show how long it took"),
+    (
+        ":paste",
+        "Read lines as they are until :end or Ctrl-D, then evaluate them all",
+    ),
+    (
+        ":expand FORM",
+        "Show what a macro call expands to, one step at a time",
+    ),
+    (
+        ":expand-all FORM",
+        "Show a form with every macro call in it fully expanded",
+    ),
+    (
+        ":forget NAME",
+        "Remove a definition, restoring a builtin it shadowed",
+    ),
+    (":reset-builtins", "Restore every builtin rebound by a def"),
+    (
+        ":set [NAME VALUE]",
+        "Change a setting, or show them all. Settings: max-steps (a number or 'off'), \
+         on-error ('keep' or 'rollback' the definitions of an input that fails), \
+         tables ('on' to show lists of rows as tables, or 'off'), print-depth and \
+         print-length (how deeply nested and how long a list to print before eliding \
+         the rest with '...', a number or 'off'), redefine-builtins ('on' to let def \
+         rebind builtins, or 'off'), division-by-zero ('error', 'nil', or 'infinity' \
+         from float division), show-time ('on' to show how long every input took, \
+         or 'off')",
+    ),
+];
+
+/// State kept between inputs
+struct Session {
+    renderer: Renderer,
+    cache: ParseCache,
+    /// Show results that are lists of rows as tables
+    tables: bool,
+    /// Show how long each input took to evaluate after its result
+    show_time: bool,
+    on_error: OnError,
+}
+
+/// What happens to the definitions an input made before it failed
+#[derive(Clone, Copy, PartialEq)]
+enum OnError {
+    /// Keep them, as if the input had stopped at the failing form
+    Keep,
+    /// Undo them, so a failed input doesn't change the session
+    Rollback,
+}
+
+impl Session {
+    /// Run a `:command`, returning what to print
+    fn command(&mut self, input: &str, env: &mut CrispEnv) -> String {
+        let (name, arg) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let arg = arg.trim();
+
+        match name {
+            ":help" => COMMANDS
+                .iter()
+                .map(|(usage, about)| format!("{usage:<20}{about}"))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ":load" if arg.is_empty() => self.renderer.failure(":load expects a file"),
+            ":load" => self.load(Path::new(arg), env),
+            ":reload" if arg.is_empty() => self.renderer.failure(":reload expects a module"),
+            ":reload" => {
+                let res = self.guard(env, |env| env.reload(arg));
+                self.renderer.warnings(env.take_warnings(), None)
+                    + &self.renderer.result(&res, arg, None)
+            }
+            ":cache" => {
+                let stats = self.cache.stats();
+                format!("parse cache: {} hits, {} misses", stats.hits, stats.misses)
+            }
+            ":set" => self.set(arg, env),
+            ":time" if arg.is_empty() => self.renderer.failure(":time expects an input"),
+            ":time" => self.eval(arg, true, env),
+            ":forget" if arg.is_empty() => self.renderer.failure(":forget expects a name"),
+            ":forget" => match env.undef(arg, false) {
+                Ok(()) => format!("forgot {arg}"),
+                Err(err) => self.renderer.error(&err, arg, None),
+            },
+            ":reset-builtins" => match env.reset_builtins().as_slice() {
+                [] => "no builtins are shadowed".to_string(),
+                names => format!("restored {}", names.join(", ")),
+            },
+            ":where" if arg.is_empty() => self.renderer.failure(":where expects a name"),
+            ":where" => match env.location(arg) {
+                Some(location) => format!(
+                    "{arg} is defined at {}:{}:{}",
+                    location.file.as_deref().unwrap_or("<repl>"),
+                    location.span.line,
+                    location.span.col
+                ),
+                None if env.lookup(arg).is_some() => format!("{arg} is a builtin"),
+                None => self.renderer.failure(format!("'{arg}' isn't defined")),
+            },
+            ":apropos" if arg.is_empty() => self.renderer.failure(":apropos expects some text"),
+            ":apropos" => match doc::apropos(arg).as_slice() {
+                [] => format!("no builtins mention '{arg}'"),
+                docs => docs
+                    .iter()
+                    .map(|doc| format!("{:<20}{}", doc.name, doc.summary))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+            ":example" if arg.is_empty() => self.renderer.failure(":example expects a name"),
+            ":example" => match doc::lookup(arg) {
+                Some(doc) if doc.examples.is_empty() => format!("{arg} has no examples"),
+                Some(doc) => doc.examples.join("\n"),
+                None => self.renderer.failure(format!("'{arg}' isn't a builtin")),
+            },
+            ":doc" if arg.is_empty() => self.renderer.failure(":doc expects a name"),
+            ":doc" => {
+                let about = doc::lookup(arg)
+                    .map(|doc| doc.summary)
+                    .or_else(|| env.doc(arg));
+                match (doc::signature(arg, env), about) {
+                    (Some(signature), Some(about)) => format!("{signature}\n{about}"),
+                    (Some(signature), None) => format!("{signature}\n{arg} has no docs"),
+                    (None, Some(about)) => about.to_string(),
+                    (None, None) if env.lookup(arg).is_some() => format!("{arg} has no docs"),
+                    (None, None) => self.renderer.failure(format!("'{arg}' isn't defined")),
+                }
+            }
+            ":expand" | ":expand-all" if arg.is_empty() => {
+                self.renderer.failure(format!("{name} expects a form"))
+            }
+            ":expand" | ":expand-all" => self.expand(arg, name == ":expand-all", env),
+            _ => self
+                .renderer
+                .failure(format!("unknown command '{name}', try :help")),
+        }
+    }
+
+    /// Evaluate an input, returning its warnings and result to print,
+    /// followed by how long it took if `timed`
+    fn eval(&self, input: &str, timed: bool, env: &mut CrispEnv) -> String {
+        let start = Instant::now();
+        let res = self.guard(env, |env| crisp::run_program(input, env));
+        let elapsed = start.elapsed();
+
+        let mut out = self.renderer.warnings(env.take_warnings(), None) + &self.result(&res, input);
+        if timed {
+            out = out + "\n" + &self.renderer.elapsed(elapsed);
+        }
+        out
+    }
+
+    /// Evaluate an input, undoing its definitions if it fails and the
+    /// session rolls back on errors
+    fn guard(
+        &self,
+        env: &mut CrispEnv,
+        eval: impl FnOnce(&mut CrispEnv) -> CrispResult,
+    ) -> CrispResult {
+        let checkpoint = (self.on_error == OnError::Rollback).then(|| env.checkpoint());
+        let res = eval(env);
+
+        if let (Err(_), Some(checkpoint)) = (&res, checkpoint) {
+            env.rollback(checkpoint);
+        }
+        res
+    }
+
+    /// How to print the result of an input
+    fn result(&self, res: &CrispResult, src: &str) -> String {
+        match res {
+            Ok(val) if self.tables => format::to_table(&self.renderer.limits.elide(val))
+                .unwrap_or_else(|| self.renderer.value(val)),
+            Err(err) if err.kind() == "syntax" => self.syntax_errors(src),
+            res => self.renderer.result(res, src, None),
+        }
+    }
+
+    /// Expand the macro calls in a form, either just the call itself once
+    /// or every call in it until none are left
+    fn expand(&self, src: &str, all: bool, env: &CrispEnv) -> String {
+        let form = match parse_all(&crisp::lexer(src)).as_deref() {
+            Ok([form]) => form.clone(),
+            Ok(_) => return self.renderer.failure("expected exactly one form to expand"),
+            Err(err) => return self.renderer.error(err, src, None),
+        };
+
+        let res = if all {
+            macros::expand_all(&form, env)
+        } else {
+            macros::expand_once(&form, env).map(|expansion| expansion.unwrap_or(form))
+        };
+        match res {
+            Ok(expansion) => format::pretty(&expansion, 0),
+            Err(err) => self.renderer.error(&err, src, None),
+        }
+    }
+
+    /// The forms that could be parsed from an input with syntax errors,
+    /// followed by every error found
+    fn syntax_errors(&self, src: &str) -> String {
+        let recovered = parse_recovering(&crisp::lexer(src));
+        let forms = recovered
+            .forms
+            .iter()
+            .map(|form| format!("parsed: {}", format::to_source(&form.expr)));
+        let errors = recovered
+            .errors
+            .iter()
+            .map(|err| self.renderer.error(err, src, None));
+
+        forms.chain(errors).collect::<Vec<String>>().join("\n")
+    }
+
+    fn set(&mut self, arg: &str, env: &mut CrispEnv) -> String {
+        let (name, value) = arg
+            .split_once(char::is_whitespace)
+            .map_or((arg, ""), |(name, value)| (name, value.trim()));
+
+        match (name, value) {
+            ("", _) => {}
+            ("max-steps", "off") => env.options.max_steps = None,
+            ("max-steps", steps) => match steps.parse() {
+                Ok(steps) => env.options.max_steps = Some(steps),
+                Err(_) => return self.renderer.failure("max-steps expects a number or 'off'"),
+            },
+            ("print-depth" | "print-length", value) => {
+                let limit = match value {
+                    "off" => None,
+                    value => match value.parse() {
+                        Ok(limit) => Some(limit),
+                        Err(_) => {
+                            return self
+                                .renderer
+                                .failure(format!("{name} expects a number or 'off'"))
+                        }
+                    },
+                };
+
+                let limits = &mut self.renderer.limits;
+                if name == "print-depth" {
+                    limits.depth = limit;
+                } else {
+                    limits.length = limit;
+                }
+            }
+            ("on-error", "keep") => self.on_error = OnError::Keep,
+            ("on-error", "rollback") => self.on_error = OnError::Rollback,
+            ("on-error", _) => {
+                return self
+                    .renderer
+                    .failure("on-error expects 'keep' or 'rollback'")
+            }
+            ("tables", "on") => self.tables = true,
+            ("tables", "off") => self.tables = false,
+            ("tables", _) => return self.renderer.failure("tables expects 'on' or 'off'"),
+            ("show-time", "on") => self.show_time = true,
+            ("show-time", "off") => self.show_time = false,
+            ("show-time", _) => return self.renderer.failure("show-time expects 'on' or 'off'"),
+            ("redefine-builtins", "on") => env.options.redefine_builtins = true,
+            ("redefine-builtins", "off") => env.options.redefine_builtins = false,
+            ("redefine-builtins", _) => {
+                return self
+                    .renderer
+                    .failure("redefine-builtins expects 'on' or 'off'")
+            }
+            ("division-by-zero", "error") => env.options.division_by_zero = DivisionByZero::Error,
+            ("division-by-zero", "nil") => env.options.division_by_zero = DivisionByZero::Nil,
+            ("division-by-zero", "infinity") => {
+                env.options.division_by_zero = DivisionByZero::Infinity
+            }
+            ("division-by-zero", _) => {
+                return self
+                    .renderer
+                    .failure("division-by-zero expects 'error', 'nil' or 'infinity'")
+            }
+            _ => {
+                return self
+                    .renderer
+                    .failure(format!("unknown setting '{name}', try :help"))
+            }
+        }
+
+        let show = |limit: Option<usize>| limit.map_or("off".to_string(), |n| n.to_string());
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let on_error = match self.on_error {
+            OnError::Keep => "keep",
+            OnError::Rollback => "rollback",
+        };
+        let division_by_zero = match env.options.division_by_zero {
+            DivisionByZero::Error => "error",
+            DivisionByZero::Nil => "nil",
+            DivisionByZero::Infinity => "infinity",
+        };
+        format!(
+            "max-steps = {}\non-error = {on_error}\ntables = {}\nprint-depth = {}\n\
+             print-length = {}\nredefine-builtins = {}\ndivision-by-zero = {division_by_zero}\n\
+             show-time = {}",
+            show(env.options.max_steps),
+            on_off(self.tables),
+            show(self.renderer.limits.depth),
+            show(self.renderer.limits.length),
+            on_off(env.options.redefine_builtins),
+            on_off(self.show_time)
+        )
+    }
+
+    fn load(&mut self, file: &Path, env: &mut CrispEnv) -> String {
+        // Attribute the file's definitions to it, without changing the
+        // script name of the session itself
+        let script = env.symbols.get("*script*").cloned();
+        env.set_script(&file.display().to_string());
+        let checkpoint = (self.on_error == OnError::Rollback).then(|| env.checkpoint());
+        let loaded = crate::run_file(file, env, Some(&mut self.cache));
+        if let (Ok((_, Err(_))), Some(checkpoint)) = (&loaded, checkpoint) {
+            env.rollback(checkpoint);
+        }
+        match script {
+            Some(script) => env.symbols.insert("*script*".to_string(), script),
+            None => env.symbols.remove("*script*"),
+        };
+
+        let (src, res) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => return self.renderer.failure(format!("couldn't read file: {err}")),
+        };
+
+        self.renderer.warnings(env.take_warnings(), Some(file))
+            + &self.renderer.result(&res, &src, Some(file))
+    }
+}
+
+/// Whether the input has parens, brackets or braces that haven't been closed yet
+fn is_incomplete(input: &str) -> bool {
+    let depth = crisp::lexer(input)
+        .iter()
+        .fold(0, |depth, token| match token.text.as_str() {
+            "(" | "[" | "{" => depth + 1,
+            ")" | "]" | "}" => depth - 1,
+            _ => depth,
+        });
+
+    depth > 0
 }
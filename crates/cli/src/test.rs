@@ -0,0 +1,127 @@
+use crisp::format::to_source;
+use crisp::lang::CrispResult;
+use crisp::parse::parse_all;
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::options::Options;
+
+/// What makes a test pass
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Running without errors
+    Run,
+    /// Giving the output in the file's sibling `.expected` file
+    Golden,
+    /// Always passes, rewriting each `.expected` file with the new output
+    Update,
+}
+
+pub fn run(paths: &[PathBuf], mode: Mode, options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut files = vec![];
+    for path in paths {
+        collect_scripts(path, &mut files)?;
+    }
+    files.sort();
+
+    let renderer = options.renderer();
+    let mut failed = 0;
+    for file in &files {
+        let (src, res) = crate::interpret(file, &[], options)?;
+        let expected = file.with_extension("expected");
+
+        let failure = match mode {
+            Mode::Run => res.err().map(|err| renderer.error(&err, &src, Some(file))),
+            Mode::Golden => match fs::read_to_string(&expected) {
+                Ok(contents) if contents == golden(&res) => None,
+                Ok(contents) => Some(format!(
+                    "expected:\n{}\nfound:\n{}{}",
+                    indent(&contents),
+                    indent(&golden(&res)),
+                    differences(&contents, &res)
+                )),
+                Err(_) => Some(format!(
+                    "missing {}, run with --update to create it",
+                    expected.display()
+                )),
+            },
+            Mode::Update => {
+                fs::write(&expected, golden(&res))?;
+                None
+            }
+        };
+
+        match failure {
+            None if mode == Mode::Update => {
+                println!("test {} ... {}", file.display(), renderer.ok("updated"))
+            }
+            None => println!("test {} ... {}", file.display(), renderer.ok("ok")),
+            Some(failure) => {
+                println!("test {} ... {}", file.display(), renderer.failed("FAILED"));
+                println!("{failure}");
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\ntest result: {} passed; {failed} failed",
+        files.len() - failed
+    );
+
+    if failed > 0 {
+        return Err(format!("{failed} test(s) failed").into());
+    }
+
+    Ok(())
+}
+
+/// The output a golden test compares against its `.expected` file
+fn golden(res: &CrispResult) -> String {
+    match res {
+        Ok(val) => format!("{}\n", to_source(val)),
+        Err(err) => format!("error: {}\n", err.inner()),
+    }
+}
+
+/// How a result differs from the value in an `.expected` file, when both
+/// are values and the expected one can be read back
+fn differences(expected: &str, res: &CrispResult) -> String {
+    let (Ok(found), Ok(expected)) = (res, parse_all(&crisp::lexer(expected))) else {
+        return String::new();
+    };
+    let [expected] = expected.as_slice() else {
+        return String::new();
+    };
+
+    let edits = crisp::diff(expected, found)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>();
+    if edits.is_empty() {
+        return String::new();
+    }
+    format!("\ndifferences:\n{}", indent(&edits.join("\n")))
+}
+
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("    {line}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Find every `.crisp` file at or below `path`
+fn collect_scripts(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_scripts(&entry?.path(), files)?;
+        }
+    } else if path.extension().is_some_and(|ext| ext == "crisp") {
+        files.push(path.to_path_buf());
+    }
+
+    Ok(())
+}
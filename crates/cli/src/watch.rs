@@ -0,0 +1,76 @@
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crisp::cache::ParseCache;
+
+use crate::options::Options;
+
+/// How long to wait for further events after a change before re-running,
+/// so editors that write a file in several steps only trigger one run.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+pub fn run(file: &Path, args: &[String], options: &Options) -> Result<(), Box<dyn Error>> {
+    let path = fs::canonicalize(file)?;
+
+    // Watch the parent directory rather than the file itself: many editors
+    // save by writing a new file and renaming it over the old one, which
+    // would otherwise drop the watch after the first save.
+    let dir = path.parent().ok_or("file has no parent directory")?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    let mut cache = ParseCache::new();
+    rerun(&path, args, options, &mut cache);
+
+    loop {
+        let event = rx.recv()?;
+        if !touches(&event?, &path) {
+            continue;
+        }
+
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+        rerun(&path, args, options, &mut cache);
+    }
+}
+
+fn touches(event: &Event, path: &Path) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && event.paths.iter().any(|p| p == path)
+}
+
+fn rerun(path: &Path, args: &[String], options: &Options, cache: &mut ParseCache) {
+    let renderer = options.renderer();
+
+    let mut env = options.env();
+    env.set_args(args);
+    env.set_script(&path.display().to_string());
+
+    let output = match crate::run_file(path, &mut env, Some(cache)) {
+        Ok((src, res)) => {
+            renderer.warnings(env.take_warnings(), Some(path))
+                + &renderer.result(&res, &src, Some(path))
+        }
+        Err(err) => renderer.failure(format!("couldn't read file: {err}")),
+    };
+
+    // Clear the screen and move the cursor to the top-left corner
+    print!("\x1B[2J\x1B[1;1H");
+    let stats = cache.stats();
+    println!(
+        "[watching {}, parse cache: {} hits, {} misses]",
+        path.display(),
+        stats.hits,
+        stats.misses
+    );
+    println!("{output}");
+}
@@ -0,0 +1,248 @@
+//! Dense numeric arrays for small linear algebra, enabled by the `ndarray`
+//! feature. Vectors are made with `(array 1 2 3)` and matrices from rows,
+//! like `(array (quote (1 2)) (quote (3 4)))`.
+
+use ndarray::{Array1, ArrayD, Axis, Ix1, Ix2, IxDyn, Slice, Zip};
+
+use crate::lang::{CrispError, CrispExpr, CrispFn, CrispResult, Primitive};
+
+/// The value of an array, with any number of dimensions
+pub type Array = ArrayD<f32>;
+
+/// The array builtins, registered under `array/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        ("array", CrispFn::new(array)),
+        ("dot", CrispFn::new(dot)),
+        (
+            "transpose",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Array(a)] => Ok(CrispExpr::Array(a.t().to_owned())),
+                _ => Err(CrispError::EvalError(
+                    "transpose takes exactly one array".to_string(),
+                )),
+            }),
+        ),
+        ("slice", CrispFn::new(slice)),
+        (
+            "shape",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Array(a)] => Ok(CrispExpr::List(
+                    a.shape()
+                        .iter()
+                        .map(|&len| CrispExpr::Primitive(Primitive::Int(len as i64)))
+                        .collect(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "shape takes exactly one array".to_string(),
+                )),
+            }),
+        ),
+        (
+            "to-list",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Array(a)] => Ok(to_list(a)),
+                _ => Err(CrispError::EvalError(
+                    "to-list takes exactly one array".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// An array as nested lists of numbers, one level per dimension
+pub fn to_list(a: &Array) -> CrispExpr {
+    if a.ndim() == 0 {
+        return CrispExpr::Primitive(Primitive::Number(a.iter().next().copied().unwrap_or(0.)));
+    }
+
+    CrispExpr::List(
+        a.axis_iter(Axis(0))
+            .map(|sub| to_list(&sub.to_owned()))
+            .collect(),
+    )
+}
+
+/// A vector from numbers, or a matrix from lists of numbers as its rows
+fn array(args: &[CrispExpr]) -> CrispResult {
+    let number = |x: &CrispExpr| {
+        x.as_f32().ok_or(CrispError::EvalError(
+            "array takes numbers, or lists of numbers as rows".to_string(),
+        ))
+    };
+
+    let rows = args
+        .iter()
+        .map(|arg| match arg {
+            CrispExpr::List(row) => Some(row),
+            _ => None,
+        })
+        .collect::<Option<Vec<&Vec<CrispExpr>>>>();
+    let rows = match rows {
+        Some(rows) if !rows.is_empty() => rows
+            .into_iter()
+            .map(|row| row.iter().map(number).collect())
+            .collect::<Result<Vec<Vec<f32>>, _>>()?,
+        _ => {
+            let items = args.iter().map(number).collect::<Result<Vec<f32>, _>>()?;
+            return Ok(CrispExpr::Array(Array1::from(items).into_dyn()));
+        }
+    };
+
+    let cols = rows.first().map_or(0, Vec::len);
+    if rows.iter().any(|row| row.len() != cols) {
+        return Err(CrispError::EvalError(
+            "every row of an array must be the same length".to_string(),
+        ));
+    }
+
+    let shape = IxDyn(&[rows.len(), cols]);
+    let a = Array::from_shape_vec(shape, rows.concat())
+        .map_err(|err| CrispError::EvalError(err.to_string()))?;
+    Ok(CrispExpr::Array(a))
+}
+
+/// The dot product of vectors, or the matrix product when either is a matrix
+fn dot(args: &[CrispExpr]) -> CrispResult {
+    let (a, b) = match args {
+        [CrispExpr::Array(a), CrispExpr::Array(b)] => (a, b),
+        _ => {
+            return Err(CrispError::EvalError(
+                "dot takes exactly two arrays".to_string(),
+            ))
+        }
+    };
+
+    let mismatch = || {
+        CrispError::EvalError(format!(
+            "can't multiply arrays of shapes {:?} and {:?}",
+            a.shape(),
+            b.shape()
+        ))
+    };
+    // The inner lengths are checked up front, since ndarray panics on them
+    let inner = |a: &Array| a.shape().last().copied();
+    if inner(a) != b.shape().first().copied() {
+        return Err(mismatch());
+    }
+
+    let res = match (a.ndim(), b.ndim()) {
+        (1, 1) => {
+            let product = vector(a).dot(&vector(b));
+            return Ok(CrispExpr::Primitive(Primitive::Number(product)));
+        }
+        (1, 2) => vector(a).dot(&matrix(b)).into_dyn(),
+        (2, 1) => matrix(a).dot(&vector(b)).into_dyn(),
+        (2, 2) => matrix(a).dot(&matrix(b)).into_dyn(),
+        _ => return Err(mismatch()),
+    };
+    Ok(CrispExpr::Array(res))
+}
+
+fn vector(a: &Array) -> ndarray::ArrayView1<'_, f32> {
+    a.view().into_dimensionality::<Ix1>().expect("a vector")
+}
+
+fn matrix(a: &Array) -> ndarray::ArrayView2<'_, f32> {
+    a.view().into_dimensionality::<Ix2>().expect("a matrix")
+}
+
+/// The items of a vector or rows of a matrix from start up to end
+fn slice(args: &[CrispExpr]) -> CrispResult {
+    let (a, start, end) = match args {
+        [CrispExpr::Array(a), start, end] => match (start.as_f32(), end.as_f32()) {
+            (Some(start), Some(end)) => (a, start, end),
+            _ => return Err(slice_usage()),
+        },
+        _ => return Err(slice_usage()),
+    };
+
+    let len = a.len_of(Axis(0));
+    if start < 0. || start > end || end as usize > len || start.fract() != 0. || end.fract() != 0. {
+        return Err(CrispError::EvalError(format!(
+            "can't slice {start} to {end} of an array of length {len}"
+        )));
+    }
+
+    let range = Slice::from(start as usize..end as usize);
+    Ok(CrispExpr::Array(a.slice_axis(Axis(0), range).to_owned()))
+}
+
+fn slice_usage() -> CrispError {
+    CrispError::EvalError("slice takes an array, a start and an end".to_string())
+}
+
+/// Apply `op` to numbers and arrays, elementwise for arrays of the same
+/// shape and to every element when combining an array and a number.
+/// Returns `None` when none of the args are arrays.
+pub(crate) fn elementwise(args: &[CrispExpr], op: fn(f32, f32) -> f32) -> Option<CrispResult> {
+    if !args.iter().any(|arg| matches!(arg, CrispExpr::Array(_))) {
+        return None;
+    }
+
+    let number = |x: &CrispExpr| {
+        x.as_f32()
+            .ok_or(CrispError::EvalError("Expected a number".to_string()))
+    };
+    let (first, rest) = args.split_first()?;
+    Some(rest.iter().try_fold(first.clone(), |acc, x| {
+        let res = match (&acc, x) {
+            (CrispExpr::Array(a), CrispExpr::Array(b)) if a.shape() == b.shape() => {
+                CrispExpr::Array(Zip::from(a).and(b).map_collect(|&x, &y| op(x, y)))
+            }
+            (CrispExpr::Array(a), CrispExpr::Array(b)) => {
+                return Err(CrispError::EvalError(format!(
+                    "can't combine arrays of shapes {:?} and {:?}",
+                    a.shape(),
+                    b.shape()
+                )))
+            }
+            (CrispExpr::Array(a), b) => {
+                let b = number(b)?;
+                CrispExpr::Array(a.mapv(|x| op(x, b)))
+            }
+            (a, CrispExpr::Array(b)) => {
+                let a = number(a)?;
+                CrispExpr::Array(b.mapv(|x| op(a, x)))
+            }
+            (a, b) => CrispExpr::Primitive(Primitive::Number(op(number(a)?, number(b)?))),
+        };
+        Ok(res)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn build_and_combine_arrays() {
+        assert_eq!(
+            eval_source("(+ (array 1 2 3) (array 10 20 30) 1)"),
+            Ok("#<array (12.0 23.0 34.0)>".to_string())
+        );
+        assert_eq!(
+            eval_source("(* 2 (transpose (array (quote (1 2)) (quote (3 4)))))"),
+            Ok("#<array ((2.0 6.0) (4.0 8.0))>".to_string())
+        );
+        assert_eq!(
+            eval_source("(shape (slice (array 1 2 3 4) 1 3))"),
+            Ok("(2)".to_string())
+        );
+        assert!(eval_source("(+ (array 1 2) (array 1 2 3))").is_err());
+        assert!(eval_source("(array (quote (1 2)) (quote (3)))").is_err());
+    }
+
+    #[test]
+    fn dot_products() {
+        assert_eq!(
+            eval_source("(dot (array 1 2 3) (array 4 5 6))"),
+            Ok("32.0".to_string())
+        );
+        assert_eq!(
+            eval_source("(dot (array (quote (1 2)) (quote (3 4))) (array 1 1))"),
+            Ok("#<array (3.0 7.0)>".to_string())
+        );
+        assert!(eval_source("(dot (array 1 2) (array 1 2 3))").is_err());
+    }
+}
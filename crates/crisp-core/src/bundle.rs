@@ -0,0 +1,185 @@
+//! Bundling a script with the files it loads into a single file, so a
+//! script split into local modules can be shipped as one.
+//!
+//! Each top-level `(load path)` is replaced by the source of the file it
+//! loads, which has its own loads replaced in turn. Like `load` itself, a
+//! file is only included the first time it's loaded, so later loads of it
+//! are dropped.
+
+use crate::{
+    lang::{CrispError, CrispExpr, Loader, Primitive},
+    parse::{parse_source, ParseOptions},
+};
+
+/// Bundle the script `name` with every file it loads. Fails if a file can't
+/// be loaded or parsed, or loads a file other than with a top-level load of
+/// a literal path, which can't be resolved without running the script.
+pub fn bundle(name: &str, loader: &dyn Loader) -> Result<String, CrispError> {
+    let mut bundler = Bundler {
+        loader,
+        included: vec![],
+        out: String::new(),
+    };
+    bundler.include(name, None)?;
+    Ok(bundler.out)
+}
+
+struct Bundler<'l> {
+    loader: &'l dyn Loader,
+    /// Paths of the files included so far
+    included: Vec<String>,
+    out: String,
+}
+
+impl Bundler<'_> {
+    /// Append the file `name` loaded from the file `from`, with its loads
+    /// replaced, unless it's already been included
+    fn include(&mut self, name: &str, from: Option<&str>) -> Result<(), CrispError> {
+        let (path, src) = self.loader.load(name, from)?;
+        if self.included.contains(&path) {
+            return Ok(());
+        }
+        self.included.push(path.clone());
+
+        // Errors in the script itself keep their spans, and errors in the
+        // files it loads say which file they're in
+        let in_file = |err: CrispError| match from {
+            Some(_) => err.in_file(&path),
+            None => err,
+        };
+        let forms = parse_source(&src, &ParseOptions::default()).map_err(in_file)?;
+
+        if from.is_some() {
+            self.out.push_str(&format!("; {path}\n"));
+        }
+        let mut copied = 0;
+        for form in &forms {
+            match load_path(&form.expr) {
+                Some(target) => {
+                    self.out.push_str(&src[copied..form.span.start]);
+                    self.include(target, Some(&path))?;
+                    copied = form.span.end;
+                }
+                None if contains_load(&form.expr) => {
+                    let err = CrispError::EvalError(
+                        "only top-level loads of a literal path can be bundled, like \
+                         (load \"utils.crisp\")"
+                            .to_string(),
+                    );
+                    return Err(in_file(err.at(form.span)));
+                }
+                None => {}
+            }
+        }
+        self.out.push_str(&src[copied..]);
+        if !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+
+        Ok(())
+    }
+}
+
+/// The path of a `(load path)` form with a literal path
+fn load_path(expr: &CrispExpr) -> Option<&str> {
+    match expr {
+        CrispExpr::List(list) => match list.as_slice() {
+            [CrispExpr::Symbol(load), CrispExpr::Primitive(Primitive::Str(path))]
+                if load == "load" =>
+            {
+                Some(path)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `load` is called anywhere in `expr`, outside quoted data
+fn contains_load(expr: &CrispExpr) -> bool {
+    match expr {
+        CrispExpr::List(list) => match list.first() {
+            Some(CrispExpr::Symbol(name)) if name == "quote" => false,
+            Some(CrispExpr::Symbol(name)) if name == "load" => true,
+            _ => list.iter().any(contains_load),
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{eval::CrispEnvBuilder, run_program};
+
+    /// Files kept in memory, named by their paths
+    struct Files(HashMap<&'static str, &'static str>);
+
+    impl Loader for Files {
+        fn load(&self, name: &str, _from: Option<&str>) -> Result<(String, String), CrispError> {
+            let src = self
+                .0
+                .get(name)
+                .ok_or(CrispError::EvalError(format!("no such file {name}")))?;
+            Ok((name.to_string(), src.to_string()))
+        }
+    }
+
+    fn files() -> Files {
+        Files(HashMap::from([
+            (
+                "main.crisp",
+                "(load \"math.crisp\")\n(load \"strings.crisp\")\n(shout (square 3))",
+            ),
+            (
+                "math.crisp",
+                ";; Square a number\n(def square (fn (x) (* x x)))",
+            ),
+            (
+                "strings.crisp",
+                "(load \"math.crisp\")\n(def shout (fn (x) (str x \"!\")))\n",
+            ),
+        ]))
+    }
+
+    #[test]
+    fn bundles_each_file_once() {
+        let bundled = bundle("main.crisp", &files()).unwrap();
+        assert_eq!(
+            bundled,
+            "; math.crisp\n;; Square a number\n(def square (fn (x) (* x x)))\n\
+             \n; strings.crisp\n\n(def shout (fn (x) (str x \"!\")))\n\n(shout (square 3))\n"
+        );
+
+        // The bundle runs without a loader, and gives what loading does
+        let mut env = CrispEnvBuilder::new().pure().build();
+        let res = run_program(&bundled, &mut env);
+        let mut loading = CrispEnvBuilder::new().pure().loader(files()).build();
+        assert_eq!(
+            res,
+            run_program("(load \"main.crisp\") (shout (square 3))", &mut loading)
+        );
+        assert_eq!(
+            loading.loaded,
+            ["main.crisp", "math.crisp", "strings.crisp"]
+        );
+    }
+
+    #[test]
+    fn only_top_level_loads_can_be_bundled() {
+        let mut files = files();
+        files
+            .0
+            .insert("lazy.crisp", "(when true (load \"math.crisp\"))");
+        files.0.insert("broken.crisp", "(load \"lazy.crisp\")");
+
+        let err = bundle("lazy.crisp", &files).unwrap_err();
+        assert!(err.message().contains("only top-level loads"), "{err}");
+        assert_eq!(err.span().map(|span| span.line), Some(1));
+
+        let err = bundle("broken.crisp", &files).unwrap_err();
+        assert!(err.message().starts_with("lazy.crisp, line 1: "), "{err}");
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use crate::{
+    lang::{CrispError, CrispExpr},
+    lex_with_comments,
+    parse::{attach_docs, expr_spans, parse, parse_forms, Form},
+    Token,
+};
+
+/// Number of whole sources remembered before the cache starts over
+const MAX_SOURCES: usize = 64;
+
+/// How often forms were found in a `ParseCache`
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Remembers parsed forms by the hash of their source, so loading the same
+/// file again skips lexing and parsing entirely, and reloading an edited file
+/// only parses the top-level forms that changed
+#[derive(Debug, Default)]
+pub struct ParseCache {
+    sources: HashMap<u64, Arc<Vec<Form>>>,
+    forms: HashMap<u64, CrispExpr>,
+    stats: CacheStats,
+}
+
+fn hash(src: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    src.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Parse every top-level form in `src`, reusing earlier results where
+    /// the source is unchanged
+    pub fn parse(&mut self, src: &str) -> Result<Arc<Vec<Form>>, CrispError> {
+        let key = hash(src);
+        if let Some(forms) = self.sources.get(&key) {
+            self.stats.hits += forms.len();
+            return Ok(forms.clone());
+        }
+
+        let (tokens, comments) = lex_with_comments(src);
+        let mut forms = match split_forms(&tokens) {
+            Some(ranges) => {
+                let mut forms = vec![];
+                for form_tokens in ranges {
+                    forms.push(self.parse_form(src, form_tokens)?);
+                }
+                forms
+            }
+            // Let the parser report what's wrong with unbalanced parens
+            None => parse_forms(&tokens)?,
+        };
+        attach_docs(&mut forms, &tokens, &comments);
+
+        if self.sources.len() >= MAX_SOURCES {
+            self.sources.clear();
+        }
+
+        let forms = Arc::new(forms);
+        self.sources.insert(key, forms.clone());
+
+        Ok(forms)
+    }
+
+    fn parse_form(&mut self, src: &str, tokens: &[Token]) -> Result<Form, CrispError> {
+        let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+        let key = hash(&src[span.start..span.end]);
+
+        let expr = match self.forms.get(&key) {
+            Some(expr) => {
+                self.stats.hits += 1;
+                expr.clone()
+            }
+            None => {
+                self.stats.misses += 1;
+                let (expr, _) = parse(tokens)?;
+                self.forms.insert(key, expr.clone());
+                expr
+            }
+        };
+
+        Ok(Form {
+            expr,
+            span,
+            spans: expr_spans(tokens),
+            doc: None,
+        })
+    }
+}
+
+/// Split tokens into the tokens of each top-level form, or `None` if the
+/// parens aren't balanced
+fn split_forms(tokens: &[Token]) -> Option<Vec<&[Token]>> {
+    let mut forms = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text.as_str() {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return None,
+            ")" | "]" | "}" => depth -= 1,
+            // A quote belongs to the form after it
+            "'" => continue,
+            _ => {}
+        }
+
+        if depth == 0 {
+            forms.push(&tokens[start..=i]);
+            start = i + 1;
+        }
+    }
+
+    (depth == 0 && start == tokens.len()).then_some(forms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    #[test]
+    fn cache_unchanged_source() {
+        let mut cache = ParseCache::new();
+        let first = cache.parse("(def x 1) (+ x 2)").unwrap();
+        let second = cache.parse("(def x 1) (+ x 2)").unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 2 });
+    }
+
+    #[test]
+    fn cache_reparses_changed_forms() {
+        let mut cache = ParseCache::new();
+        cache.parse("(def x 1)\n(+ x 2)").unwrap();
+        let forms = cache.parse("(def x 1)\n(+ x 3)\n(+ x 2)").unwrap();
+
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 3 });
+        assert_eq!(forms[2].span.line, 3);
+        assert_eq!(
+            forms
+                .iter()
+                .map(|form| form.expr.clone())
+                .collect::<Vec<_>>(),
+            crate::parse::parse_all(&lexer("(def x 1) (+ x 3) (+ x 2)")).unwrap()
+        );
+    }
+
+    #[test]
+    fn cache_reports_syntax_errors() {
+        let mut cache = ParseCache::new();
+
+        assert!(cache.parse("(+ 1 2").is_err());
+        assert!(cache.parse("1 2)").is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! The `.crispc` format, which stores a program already lexed and parsed so
+//! it can be loaded without doing either again.
+//!
+//! A file starts with the bytes `CRISPC` followed by the format version as a
+//! little-endian `u16`, then the number of top-level forms and each form's
+//! span, expression, the spans of the expressions within it and its doc.
+
+use crate::{
+    decimal::Decimal,
+    lang::{CrispError, CrispExpr, Primitive, Span},
+    parse::{parse_source, Form, ParseOptions},
+};
+
+const MAGIC: &[u8] = b"CRISPC";
+
+/// Bumped whenever the layout of compiled files changes
+pub const FORMAT_VERSION: u16 = 5;
+
+const NUMBER: u8 = 0;
+const BOOL: u8 = 1;
+const STR: u8 = 2;
+const SYMBOL: u8 = 3;
+pub(crate) const LIST: u8 = 4;
+const NIL: u8 = 5;
+const DECIMAL: u8 = 6;
+/// Tags only used in snapshots, see `snapshot`
+pub(crate) const LAMBDA: u8 = 7;
+pub(crate) const BUILTIN: u8 = 8;
+const INT: u8 = 9;
+pub(crate) const VECTOR: u8 = 10;
+/// Only used in snapshots too, since maps are built at runtime
+pub(crate) const MAP: u8 = 11;
+
+/// Whether `bytes` look like a compiled program rather than source
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Parse a program and encode it in the compiled format, along with the
+/// docs written above its definitions
+pub fn compile(src: &str) -> Result<Vec<u8>, CrispError> {
+    encode(&parse_source(src, &ParseOptions::default())?)
+}
+
+/// Generate a Rust program that runs `src` with every builtin, printing the
+/// result. The program is embedded already compiled, so building the output
+/// in a crate that depends on `crisp-std` gives a standalone binary.
+pub fn to_rust(src: &str, name: &str) -> Result<String, CrispError> {
+    let program = compile(src)?
+        .into_iter()
+        .flat_map(std::ascii::escape_default)
+        .map(char::from)
+        .collect::<String>();
+
+    Ok(format!(
+        r#"//! Generated by `crisp build` from {name}. Build it in a crate that
+//! depends on `crisp-std`.
+
+use std::process::ExitCode;
+
+use crisp_std::eval::CrispEnvBuilder;
+use crisp_std::StdBuilder;
+
+const PROGRAM: &[u8] = b"{program}";
+
+fn main() -> ExitCode {{
+    let forms = crisp_std::compile::decode(PROGRAM).expect("embedded program is valid");
+
+    let mut env = CrispEnvBuilder::new().all().build();
+    env.set_args(&std::env::args().skip(1).collect::<Vec<_>>());
+    env.set_script({name:?});
+
+    match crisp_std::eval_forms(&forms, &mut env) {{
+        Ok(res) => {{
+            println!("{{}}", crisp_std::format::to_source(&res.value));
+            ExitCode::SUCCESS
+        }}
+        Err(err) => {{
+            eprintln!("Error: {{err}}");
+            ExitCode::FAILURE
+        }}
+    }}
+}}
+"#
+    ))
+}
+
+pub fn encode(forms: &[Form]) -> Result<Vec<u8>, CrispError> {
+    let mut out = MAGIC.to_vec();
+    out.extend(FORMAT_VERSION.to_le_bytes());
+    write_len(&mut out, forms.len());
+
+    for form in forms {
+        write_span(&mut out, form.span);
+        encode_expr(&mut out, &form.expr)?;
+        write_len(&mut out, form.spans.len());
+        for span in &form.spans {
+            write_span(&mut out, *span);
+        }
+        match &form.doc {
+            Some(doc) => {
+                out.push(1);
+                write_str(&mut out, doc);
+            }
+            None => out.push(0),
+        }
+    }
+
+    Ok(out)
+}
+
+fn write_span(out: &mut Vec<u8>, span: Span) {
+    out.extend((span.start as u64).to_le_bytes());
+    out.extend((span.end as u64).to_le_bytes());
+    out.extend(span.line.to_le_bytes());
+    out.extend(span.col.to_le_bytes());
+}
+
+pub(crate) fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend((len as u32).to_le_bytes());
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend(s.as_bytes());
+}
+
+pub(crate) fn encode_expr(out: &mut Vec<u8>, expr: &CrispExpr) -> Result<(), CrispError> {
+    match expr {
+        CrispExpr::Primitive(Primitive::Nil) => out.push(NIL),
+        CrispExpr::Primitive(Primitive::Number(n)) => {
+            out.push(NUMBER);
+            out.extend(n.to_le_bytes());
+        }
+        CrispExpr::Primitive(Primitive::Int(n)) => {
+            out.push(INT);
+            out.extend(n.to_le_bytes());
+        }
+        CrispExpr::Primitive(Primitive::Decimal(d)) => {
+            out.push(DECIMAL);
+            write_str(out, &d.to_string());
+        }
+        CrispExpr::Primitive(Primitive::Bool(b)) => {
+            out.push(BOOL);
+            out.push(*b as u8);
+        }
+        CrispExpr::Primitive(Primitive::Str(s)) => {
+            out.push(STR);
+            write_str(out, s);
+        }
+        CrispExpr::Symbol(name) => {
+            out.push(SYMBOL);
+            write_str(out, name);
+        }
+        CrispExpr::List(xs) => {
+            out.push(LIST);
+            write_len(out, xs.len());
+            for x in xs {
+                encode_expr(out, x)?;
+            }
+        }
+        CrispExpr::Vector(xs) => {
+            out.push(VECTOR);
+            write_len(out, xs.len());
+            for x in xs {
+                encode_expr(out, x)?;
+            }
+        }
+        CrispExpr::Fn(_) | CrispExpr::Lambda(_) | CrispExpr::Handle(_) => {
+            return Err(CrispError::FormatError(
+                "functions and handles can't be compiled, only source forms".to_string(),
+            ))
+        }
+        CrispExpr::Map(_) => {
+            return Err(CrispError::FormatError(
+                "maps can't be compiled, only source forms".to_string(),
+            ))
+        }
+        #[cfg(feature = "ndarray")]
+        CrispExpr::Array(_) => {
+            return Err(CrispError::FormatError(
+                "arrays can't be compiled, only source forms".to_string(),
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the forms of a compiled program
+pub fn decode(bytes: &[u8]) -> Result<Vec<Form>, CrispError> {
+    decode_with(bytes, &ParseOptions::default())
+}
+
+/// Read the forms of a compiled program, failing with
+/// `CrispError::TooLarge` on an expression nested deeper than
+/// `options.max_depth`, as parsing its source would
+pub fn decode_with(bytes: &[u8], options: &ParseOptions) -> Result<Vec<Form>, CrispError> {
+    let mut reader = Reader::new(bytes);
+    reader.max_depth = options.max_depth;
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(CrispError::FormatError(
+            "not a compiled crisp program".to_string(),
+        ));
+    }
+
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != FORMAT_VERSION {
+        return Err(CrispError::FormatError(format!(
+            "program was compiled with format version {version}, but this version of crisp \
+             reads version {FORMAT_VERSION}; recompile it with `crisp compile`"
+        )));
+    }
+
+    let count = reader.len()?;
+    let mut forms = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let span = reader.span()?;
+        let expr = reader.expr()?;
+        let spans = (0..reader.len()?)
+            .map(|_| reader.span())
+            .collect::<Result<_, _>>()?;
+        let doc = match reader.array()? {
+            [0] => None,
+            _ => Some(reader.string()?),
+        };
+
+        forms.push(Form {
+            expr,
+            span,
+            spans,
+            doc,
+        });
+    }
+
+    if !reader.at_end() {
+        return Err(CrispError::FormatError(
+            "unexpected data after the last form".to_string(),
+        ));
+    }
+
+    Ok(forms)
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Maximum number of lists and vectors an expression can be nested in
+    max_depth: Option<usize>,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            max_depth: None,
+        }
+    }
+
+    /// Whether every byte has been read
+    pub(crate) fn at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], CrispError> {
+        let end = self.pos.saturating_add(n);
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(CrispError::FormatError(
+                "compiled program is truncated".to_string(),
+            ))?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], CrispError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn len(&mut self) -> Result<usize, CrispError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn span(&mut self) -> Result<Span, CrispError> {
+        Ok(Span {
+            start: u64::from_le_bytes(self.array()?) as usize,
+            end: u64::from_le_bytes(self.array()?) as usize,
+            line: u32::from_le_bytes(self.array()?),
+            col: u32::from_le_bytes(self.array()?),
+        })
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, CrispError> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| CrispError::FormatError("invalid UTF-8 in compiled program".to_string()))
+    }
+
+    /// Read an expression without recursing, so a crafted file nesting
+    /// lists deeply can't overflow the stack
+    pub(crate) fn expr(&mut self) -> Result<CrispExpr, CrispError> {
+        // The lists and vectors still being read: their tag, their items so
+        // far and how many more they have
+        let mut open: Vec<(u8, Vec<CrispExpr>, usize)> = vec![];
+        loop {
+            let [tag] = self.array()?;
+            let mut expr = match tag {
+                LIST | VECTOR => {
+                    if let Some(max) = self.max_depth.filter(|max| open.len() >= *max) {
+                        return Err(CrispError::TooLarge(format!(
+                            "compiled program is nested more than {max} deep"
+                        )));
+                    }
+                    let len = self.len()?;
+                    if len > 0 {
+                        open.push((tag, Vec::with_capacity(len.min(1024)), len));
+                        continue;
+                    }
+                    sequence(tag, vec![])
+                }
+                tag => self.tagged(tag)?,
+            };
+
+            // Finish every list this expression completes
+            loop {
+                let Some((_, items, remaining)) = open.last_mut() else {
+                    return Ok(expr);
+                };
+                items.push(expr);
+                *remaining -= 1;
+                if *remaining > 0 {
+                    break;
+                }
+                let (tag, items, _) = open.pop().expect("a list is open");
+                expr = sequence(tag, items);
+            }
+        }
+    }
+
+    /// The rest of an atom whose tag has already been read
+    pub(crate) fn tagged(&mut self, tag: u8) -> Result<CrispExpr, CrispError> {
+        Ok(match tag {
+            NUMBER => CrispExpr::Primitive(Primitive::Number(f32::from_le_bytes(self.array()?))),
+            INT => CrispExpr::Primitive(Primitive::Int(i64::from_le_bytes(self.array()?))),
+            BOOL => CrispExpr::Primitive(Primitive::Bool(self.array::<1>()? != [0])),
+            STR => CrispExpr::Primitive(Primitive::Str(self.string()?)),
+            SYMBOL => CrispExpr::Symbol(self.string()?),
+            NIL => CrispExpr::Primitive(Primitive::Nil),
+            DECIMAL => {
+                let d = Decimal::parse(&self.string()?).ok_or(CrispError::FormatError(
+                    "invalid decimal in compiled program".to_string(),
+                ))?;
+                CrispExpr::Primitive(Primitive::Decimal(d))
+            }
+            tag => {
+                return Err(CrispError::FormatError(format!(
+                    "unknown expression tag {tag}"
+                )))
+            }
+        })
+    }
+}
+
+/// A list or vector of `items`, by its tag
+fn sequence(tag: u8, items: Vec<CrispExpr>) -> CrispExpr {
+    match tag {
+        VECTOR => CrispExpr::Vector(items),
+        _ => CrispExpr::List(items),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiled_round_trip() {
+        let src = ";; Adds\n(def add (fn (a b) (+ a b)))\n(add 1.5 (quote (true [false])))";
+        let bytes = compile(src).unwrap();
+
+        assert!(is_compiled(&bytes));
+        let forms = decode(&bytes).unwrap();
+        assert_eq!(
+            Ok(&forms),
+            parse_source(src, &ParseOptions::default()).as_ref()
+        );
+        assert_eq!(forms[0].doc.as_deref(), Some("Adds"));
+    }
+
+    #[test]
+    fn reject_other_format_versions() {
+        let mut bytes = compile("(+ 1 2)").unwrap();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&99u16.to_le_bytes());
+
+        let err = decode(&bytes).unwrap_err();
+        assert_eq!(err.kind(), "format");
+        assert!(err.message().contains("format version 99"));
+    }
+
+    #[test]
+    fn transpile_embeds_program() {
+        let rust = to_rust("(quote a\\b)", "escapes.crisp").unwrap();
+
+        assert!(rust.contains("fn main()"));
+        assert!(rust.contains(r#"b"CRISPC\x05\x00"#));
+        assert!(rust.contains(r#"a\\b"#));
+    }
+
+    #[test]
+    fn reject_truncated_programs() {
+        let bytes = compile("(+ 1 2)").unwrap();
+
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn deep_programs_decode_within_limits() {
+        // `((((...1))))`, written out by hand since encoding recurses
+        let nested = |depth| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(FORMAT_VERSION.to_le_bytes());
+            write_len(&mut bytes, 1);
+            write_span(&mut bytes, Span::default());
+            for _ in 0..depth {
+                bytes.push(LIST);
+                write_len(&mut bytes, 1);
+            }
+            encode_expr(&mut bytes, &CrispExpr::Primitive(Primitive::Int(1))).unwrap();
+            write_len(&mut bytes, 0);
+            bytes.push(0);
+            bytes
+        };
+        let max = crate::parse::DEFAULT_MAX_DEPTH;
+        assert!(decode(&nested(max)).is_ok());
+        assert_eq!(decode(&nested(max + 1)).unwrap_err().kind(), "limit");
+
+        let options = ParseOptions {
+            max_depth: Some(64),
+            ..Default::default()
+        };
+        let err = decode_with(&nested(max), &options).unwrap_err();
+        assert_eq!(err.kind(), "limit");
+    }
+}
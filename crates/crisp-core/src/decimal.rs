@@ -0,0 +1,297 @@
+//! Exact decimal numbers, written like `1.50m`, for sums of money and
+//! anything else that floats would round.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+/// Most digits a decimal can have after the point
+pub const MAX_SCALE: u32 = 28;
+
+/// A decimal number, stored as a whole number of units of `10^-scale`. The
+/// scale is kept from the literal, so `1.50m` prints as `1.50`.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    units: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { units: 0, scale: 0 };
+    pub const ONE: Decimal = Decimal { units: 1, scale: 0 };
+
+    /// The decimal `units * 10^-scale`
+    pub fn new(units: i128, scale: u32) -> Option<Self> {
+        (scale <= MAX_SCALE).then_some(Self { units, scale })
+    }
+
+    /// Parse digits with an optional sign and decimal point, like `-1.50`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.len() + fraction.len() == 0 || !all_digits(whole) || !all_digits(fraction) {
+            return None;
+        }
+
+        let scale = fraction.len() as u32;
+        if scale > MAX_SCALE {
+            return None;
+        }
+
+        let mut units: i128 = 0;
+        for b in whole.bytes().chain(fraction.bytes()) {
+            units = units.checked_mul(10)?.checked_add((b - b'0') as i128)?;
+        }
+
+        Some(Self {
+            units: if negative { -units } else { units },
+            scale,
+        })
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        Self {
+            units: n as i128,
+            scale: 0,
+        }
+    }
+
+    /// A whole number as a decimal. Other floats aren't converted, since
+    /// most of them can't be represented exactly.
+    pub fn from_f32(n: f32) -> Option<Self> {
+        (n.fract() == 0. && n.abs() < 1e30).then_some(Self {
+            units: n as i128,
+            scale: 0,
+        })
+    }
+
+    /// The nearest float to the decimal
+    pub fn to_f32(self) -> f32 {
+        self.units as f32 / 10f32.powi(self.scale as i32)
+    }
+
+    /// The units of the decimal at a larger scale
+    fn units_at(self, scale: u32) -> Option<i128> {
+        self.units
+            .checked_mul(10i128.checked_pow(scale - self.scale)?)
+    }
+
+    /// Both decimals' units at the larger of their scales
+    fn aligned(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.units_at(scale)?, other.units_at(scale)?, scale))
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self {
+            units: a.checked_add(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self {
+            units: a.checked_sub(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let scale = self.scale + other.scale;
+        if scale > MAX_SCALE {
+            return None;
+        }
+
+        Some(Self {
+            units: self.units.checked_mul(other.units)?,
+            scale,
+        })
+    }
+
+    /// The quotient, exact if it fits in `MAX_SCALE` digits after the
+    /// point and rounded half away from zero if not. Keeps at least the
+    /// dividend's scale, so `1.50m / 2` is `0.75` and `3.00m / 3` is `1.00`.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.units == 0 {
+            return None;
+        }
+
+        for scale in self.scale..=MAX_SCALE {
+            let shift = 10i128.checked_pow(scale - self.scale + other.scale)?;
+            let dividend = self.units.checked_mul(shift)?;
+            let (units, rest) = (dividend / other.units, dividend % other.units);
+
+            if rest == 0 {
+                return Some(Self { units, scale });
+            }
+            if scale == MAX_SCALE {
+                let round_up = rest.unsigned_abs() * 2 >= other.units.unsigned_abs();
+                let away = if (rest < 0) == (other.units < 0) {
+                    1
+                } else {
+                    -1
+                };
+                return Some(Self {
+                    units: if round_up { units + away } else { units },
+                    scale,
+                });
+            }
+        }
+        None
+    }
+
+    /// The remainder of truncating division, with the sign of `self`
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self {
+            units: a.checked_rem(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_abs(self) -> Option<Self> {
+        Some(Self {
+            units: self.units.checked_abs()?,
+            scale: self.scale,
+        })
+    }
+
+    /// The largest whole number no greater than the decimal
+    pub fn floor(self) -> Self {
+        let (whole, rest) = self.split();
+        Self::whole(if rest < 0 { whole - 1 } else { whole })
+    }
+
+    /// The smallest whole number no less than the decimal
+    pub fn ceil(self) -> Self {
+        let (whole, rest) = self.split();
+        Self::whole(if rest > 0 { whole + 1 } else { whole })
+    }
+
+    /// The nearest whole number, rounding halves away from zero
+    pub fn round(self) -> Self {
+        let (whole, rest) = self.split();
+        let half_or_more = rest.unsigned_abs() * 2 >= 10u128.pow(self.scale);
+        Self::whole(if half_or_more {
+            whole + rest.signum()
+        } else {
+            whole
+        })
+    }
+
+    /// The whole part of the decimal and the units after the point, both
+    /// with the decimal's sign
+    fn split(self) -> (i128, i128) {
+        let one = 10i128.pow(self.scale);
+        (self.units / one, self.units % one)
+    }
+
+    fn whole(units: i128) -> Self {
+        Self { units, scale: 0 }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.units == 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.units < 0
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (a, b, _) = self.aligned(*other)?;
+        Some(a.cmp(&b))
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.units < 0 { "-" } else { "" };
+        let digits = format!(
+            "{:0>width$}",
+            self.units.unsigned_abs(),
+            width = self.scale as usize + 1
+        );
+        let (whole, fraction) = digits.split_at(digits.len() - self.scale as usize);
+
+        match fraction {
+            "" => write!(f, "{sign}{whole}"),
+            fraction => write!(f, "{sign}{whole}.{fraction}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_print() {
+        for text in ["1.50", "-0.05", "12", "0.000"] {
+            assert_eq!(Decimal::parse(text).unwrap().to_string(), text);
+        }
+        for text in ["", ".", "1.2.3", "1e5", "abc", "-"] {
+            assert_eq!(Decimal::parse(text), None);
+        }
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        let tenth = Decimal::parse("0.1").unwrap();
+        let sum = (0..10).try_fold(Decimal::ZERO, |acc, _| acc.checked_add(tenth));
+        assert_eq!(sum, Some(Decimal::ONE));
+
+        let price = Decimal::parse("19.99").unwrap();
+        let total = price.checked_mul(Decimal::from_f32(3.).unwrap()).unwrap();
+        assert_eq!(total.to_string(), "59.97");
+        assert_eq!(total.checked_sub(price).unwrap().to_string(), "39.98");
+        assert_eq!(Decimal::from_f32(0.5), None);
+    }
+
+    #[test]
+    fn division() {
+        let d = |text| Decimal::parse(text).unwrap();
+        let quotient = |a, b| d(a).checked_div(d(b)).unwrap().to_string();
+
+        assert_eq!(quotient("1.50", "2"), "0.75");
+        assert_eq!(quotient("3.00", "3"), "1.00");
+        assert_eq!(quotient("1", "0.25"), "4");
+        assert_eq!(quotient("2", "3"), "0.6666666666666666666666666667");
+        assert_eq!(quotient("-2", "3"), "-0.6666666666666666666666666667");
+        assert_eq!(d("1").checked_div(Decimal::ZERO), None);
+
+        assert_eq!(d("7.5").checked_rem(d("2")).unwrap().to_string(), "1.5");
+        assert_eq!(d("-7").checked_rem(d("2")).unwrap().to_string(), "-1");
+    }
+
+    #[test]
+    fn rounding() {
+        let d = |text| Decimal::parse(text).unwrap();
+
+        for (text, floor, ceil, round) in [
+            ("2.50", "2", "3", "3"),
+            ("-2.50", "-3", "-2", "-3"),
+            ("2.49", "2", "3", "2"),
+            ("-0.4", "-1", "0", "0"),
+            ("7", "7", "7", "7"),
+        ] {
+            assert_eq!(d(text).floor().to_string(), floor, "floor {text}");
+            assert_eq!(d(text).ceil().to_string(), ceil, "ceil {text}");
+            assert_eq!(d(text).round().to_string(), round, "round {text}");
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Structural differences between expressions, for showing readably how a
+//! value differs from the one that was expected.
+
+use std::fmt::Display;
+
+use crate::{format::to_source, lang::CrispExpr};
+
+/// A change that turns part of one expression into another. Paths are the
+/// indices of nested list items, leading from the root to the change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// The item at `path` of the old expression became `new`
+    Replace {
+        path: Vec<usize>,
+        old: CrispExpr,
+        new: CrispExpr,
+    },
+    /// `new` was added, at `path` of the new expression
+    Insert { path: Vec<usize>, new: CrispExpr },
+    /// The item at `path` of the old expression was dropped
+    Remove { path: Vec<usize>, old: CrispExpr },
+}
+
+impl Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = |path: &[usize]| {
+            let indices = path.iter().map(usize::to_string).collect::<Vec<_>>();
+            format!("[{}]", indices.join(" "))
+        };
+
+        match self {
+            Edit::Replace { path: at, old, new } => {
+                write!(f, "{}: {} -> {}", path(at), to_source(old), to_source(new))
+            }
+            Edit::Insert { path: at, new } => write!(f, "{}: + {}", path(at), to_source(new)),
+            Edit::Remove { path: at, old } => write!(f, "{}: - {}", path(at), to_source(old)),
+        }
+    }
+}
+
+/// The edits that turn `old` into `new`, descending into lists so that only
+/// the items that differ are reported. Empty when they're equal.
+pub fn diff(old: &CrispExpr, new: &CrispExpr) -> Vec<Edit> {
+    let mut edits = vec![];
+    diff_at(old, new, &mut vec![], &mut edits);
+    edits
+}
+
+fn diff_at(old: &CrispExpr, new: &CrispExpr, path: &mut Vec<usize>, edits: &mut Vec<Edit>) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (CrispExpr::List(old), CrispExpr::List(new)) => diff_lists(old, new, path, edits),
+        _ => edits.push(Edit::Replace {
+            path: path.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+    }
+}
+
+/// Diff the items of two lists, keeping their longest common subsequence
+/// and pairing up what changes between the items they share
+fn diff_lists(old: &[CrispExpr], new: &[CrispExpr], path: &mut Vec<usize>, edits: &mut Vec<Edit>) {
+    // lengths[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        // Gather the run of items that differ before the next shared one
+        let (start_i, start_j) = (i, j);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                break;
+            }
+            if j == new.len() || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        let (removed, inserted) = (&old[start_i..i], &new[start_j..j]);
+        let paired = removed.len().min(inserted.len());
+        for k in 0..paired {
+            path.push(start_i + k);
+            diff_at(&removed[k], &inserted[k], path, edits);
+            path.pop();
+        }
+        for (k, old) in removed.iter().enumerate().skip(paired) {
+            let mut path = path.clone();
+            path.push(start_i + k);
+            edits.push(Edit::Remove {
+                path,
+                old: old.clone(),
+            });
+        }
+        for (k, new) in inserted.iter().enumerate().skip(paired) {
+            let mut path = path.clone();
+            path.push(start_j + k);
+            edits.push(Edit::Insert {
+                path,
+                new: new.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parse::parse};
+
+    fn expr(src: &str) -> CrispExpr {
+        parse(&lexer(src)).unwrap().0
+    }
+
+    fn edits(old: &str, new: &str) -> Vec<String> {
+        diff(&expr(old), &expr(new))
+            .iter()
+            .map(Edit::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn diff_reports_only_what_changed() {
+        assert!(edits("(1 (2 3) 4)", "(1 (2 3) 4)").is_empty());
+        assert_eq!(edits("1", "2"), ["[]: 1 -> 2"]);
+        assert_eq!(edits("(1 (2 3) 4)", "(1 (2 5) 4)"), ["[1 1]: 3 -> 5"]);
+    }
+
+    #[test]
+    fn diff_finds_insertions_and_removals() {
+        assert_eq!(edits("(1 2 3)", "(1 3)"), ["[1]: - 2"]);
+        assert_eq!(
+            edits("(1 3)", "(0 1 2 3 4)"),
+            ["[0]: + 0", "[2]: + 2", "[4]: + 4"]
+        );
+        assert_eq!(
+            edits("(a b c d)", "(a x y d)"),
+            ["[1]: b -> x", "[2]: c -> y"]
+        );
+    }
+}
@@ -0,0 +1,986 @@
+use crate::{eval::CrispEnv, lang::CrispExpr};
+
+/// Documentation for a builtin function or special form
+#[derive(Debug, Clone, PartialEq)]
+pub struct Doc {
+    pub name: &'static str,
+    /// How a call is written, e.g. `(if cond then [else])`
+    pub signature: &'static str,
+    pub summary: &'static str,
+    /// Runnable examples of calls, for `:example`
+    pub examples: &'static [&'static str],
+}
+
+pub const BUILTINS: &[Doc] = &[
+    Doc {
+        name: "*args*",
+        signature: "*args*",
+        summary: "The command line arguments given to the script after `--`",
+        examples: &["(count-if (fn (arg) true) *args*)"],
+    },
+    Doc {
+        name: "+",
+        signature: "(+ x ...)",
+        summary: "Add numbers together",
+        examples: &["(+ 1 2 3)", "(+ 0.1m 0.2m)"],
+    },
+    Doc {
+        name: "-",
+        signature: "(- x y ...)",
+        summary: "Subtract the rest of the numbers from the first",
+        examples: &["(- 10 3)", "(- 10 3 2)"],
+    },
+    Doc {
+        name: "*",
+        signature: "(* x ...)",
+        summary: "Multiply numbers together",
+        examples: &["(* 2 3 4)"],
+    },
+    Doc {
+        name: "/",
+        signature: "(/ x y ...)",
+        summary: "Divide the first number by the rest, or take the reciprocal of one number",
+        examples: &["(/ 12 2 3)", "(/ 1m 3)"],
+    },
+    Doc {
+        name: "mod",
+        signature: "(mod x y)",
+        summary: "Remainder of dividing x by y, with the sign of y",
+        examples: &["(mod -7 2)"],
+    },
+    Doc {
+        name: "rem",
+        signature: "(rem x y)",
+        summary: "Remainder of dividing x by y, with the sign of x",
+        examples: &["(rem -7 2)"],
+    },
+    Doc {
+        name: "quotient",
+        signature: "(quotient x y)",
+        summary: "Divide one int by another, dropping the remainder",
+        examples: &["(quotient 7 2)", "(quotient -7 2)"],
+    },
+    Doc {
+        name: "even?",
+        signature: "(even? n)",
+        summary: "Whether an int is even",
+        examples: &["(even? 4)"],
+    },
+    Doc {
+        name: "odd?",
+        signature: "(odd? n)",
+        summary: "Whether an int is odd",
+        examples: &["(odd? 4)"],
+    },
+    Doc {
+        name: "exact?",
+        signature: "(exact? x)",
+        summary: "Whether a number is exact, an int or a decimal",
+        examples: &["(exact? 1.5m)", "(exact? 1.5)"],
+    },
+    Doc {
+        name: "inexact?",
+        signature: "(inexact? x)",
+        summary: "Whether a number is an inexact float",
+        examples: &["(inexact? 1.5)"],
+    },
+    Doc {
+        name: "exact->inexact",
+        signature: "(exact->inexact x)",
+        summary: "The nearest float to a number",
+        examples: &["(exact->inexact 1.25m)"],
+    },
+    Doc {
+        name: "inexact->exact",
+        signature: "(inexact->exact x)",
+        summary: "A float as an int if it's whole, else as the shortest decimal that reads back as it",
+        examples: &["(inexact->exact 0.1)", "(inexact->exact 2.0)"],
+    },
+    Doc {
+        name: "abs",
+        signature: "(abs x)",
+        summary: "Absolute value of a number",
+        examples: &["(abs -2.5)"],
+    },
+    Doc {
+        name: "min",
+        signature: "(min x ...)",
+        summary: "Smallest of the numbers",
+        examples: &["(min 3 1 2)"],
+    },
+    Doc {
+        name: "max",
+        signature: "(max x ...)",
+        summary: "Largest of the numbers",
+        examples: &["(max 3 1 2)"],
+    },
+    Doc {
+        name: "floor",
+        signature: "(floor x)",
+        summary: "Round a number down to a whole number",
+        examples: &["(floor -1.5)"],
+    },
+    Doc {
+        name: "ceil",
+        signature: "(ceil x)",
+        summary: "Round a number up to a whole number",
+        examples: &["(ceil 1.2)"],
+    },
+    Doc {
+        name: "round",
+        signature: "(round x)",
+        summary: "Round a number to the nearest whole number, halves away from zero",
+        examples: &["(round 2.5)", "(round 19.99m)"],
+    },
+    Doc {
+        name: "sqrt",
+        signature: "(sqrt x)",
+        summary: "Square root of a float, failing for negative numbers",
+        examples: &["(sqrt 16)"],
+    },
+    Doc {
+        name: "pow",
+        signature: "(pow base exponent)",
+        summary: "Raise a number to a power, giving an int for an int to a non-negative int",
+        examples: &["(pow 2 10)"],
+    },
+    Doc {
+        name: "exp",
+        signature: "(exp x)",
+        summary: "e raised to a power",
+        examples: &["(exp 1)"],
+    },
+    Doc {
+        name: "ln",
+        signature: "(ln x)",
+        summary: "Natural logarithm of a positive float (`log` is for logging)",
+        examples: &["(ln (exp 2))"],
+    },
+    Doc {
+        name: "math/log",
+        signature: "(math/log x)",
+        summary: "Natural logarithm of a positive float, the same as `ln`",
+        examples: &["(math/log (exp 2))"],
+    },
+    Doc {
+        name: "sin",
+        signature: "(sin x)",
+        summary: "Sine of an angle in radians",
+        examples: &["(sin 0)"],
+    },
+    Doc {
+        name: "cos",
+        signature: "(cos x)",
+        summary: "Cosine of an angle in radians",
+        examples: &["(cos 0)"],
+    },
+    Doc {
+        name: "tan",
+        signature: "(tan x)",
+        summary: "Tangent of an angle in radians",
+        examples: &["(tan 0)"],
+    },
+    Doc {
+        name: "asin",
+        signature: "(asin x)",
+        summary: "Angle in radians whose sine is x",
+        examples: &["(asin 1)"],
+    },
+    Doc {
+        name: "acos",
+        signature: "(acos x)",
+        summary: "Angle in radians whose cosine is x",
+        examples: &["(acos 1)"],
+    },
+    Doc {
+        name: "atan",
+        signature: "(atan y [x])",
+        summary: "Angle in radians whose tangent is y, or of the point (x, y)",
+        examples: &["(atan 1)", "(atan 1 -1)"],
+    },
+    Doc {
+        name: ">",
+        signature: "(> x y ...)",
+        summary: "Check whether each number is greater than the next",
+        examples: &["(> 5 3)", "(> 5 3 1)"],
+    },
+    Doc {
+        name: "<",
+        signature: "(< x y ...)",
+        summary: "Check whether each number is less than the next",
+        examples: &["(< 1 2 3)"],
+    },
+    Doc {
+        name: ">=",
+        signature: "(>= x y ...)",
+        summary: "Check whether each number is greater than or equal to the next",
+        examples: &["(>= 3 3 1)"],
+    },
+    Doc {
+        name: "<=",
+        signature: "(<= x y ...)",
+        summary: "Check whether each number is less than or equal to the next",
+        examples: &["(<= 1 1 2)"],
+    },
+    Doc {
+        name: "=",
+        signature: "(= x y ...)",
+        summary: "Check whether all the arguments are equal, comparing numbers by value",
+        examples: &["(= 2 2.0 2m)", "(= (quote (1 2)) (quote (1 2)))"],
+    },
+    Doc {
+        name: "!=",
+        signature: "(!= x y ...)",
+        summary: "Check whether the arguments aren't all equal",
+        examples: &["(!= 1 2)"],
+    },
+    Doc {
+        name: "assert",
+        signature: "(assert cond)",
+        summary: "Fail with an error unless cond is true",
+        examples: &["(assert (> 2 1))"],
+    },
+    Doc {
+        name: "str",
+        signature: "(str x ...)",
+        summary: "Join the printed forms of the arguments into a string",
+        examples: &["(str 1 (quote a) true)"],
+    },
+    Doc {
+        name: "string-builder",
+        signature: "(string-builder)",
+        summary: "A handle to build a long string with, a piece at a time",
+        examples: &["(sb-build (sb-append! (string-builder) \"a\" 1))"],
+    },
+    Doc {
+        name: "sb-append!",
+        signature: "(sb-append! b x ...)",
+        summary: "Append the printed forms of the values to a string builder, returning it",
+        examples: &["(sb-append! (string-builder) \"line\" 1 \"\\n\")"],
+    },
+    Doc {
+        name: "sb-build",
+        signature: "(sb-build b)",
+        summary: "The string built so far by a string builder",
+        examples: &["(sb-build (string-builder))"],
+    },
+    Doc {
+        name: "render-template",
+        signature: "(render-template template m)",
+        summary: "Fill in the {{name}} placeholders of a string from a map, with {{a.b}} for nested maps",
+        examples: &["(render-template \"Hello {{name}}\" {:name \"Ana\"})"],
+    },
+    Doc {
+        name: "print",
+        signature: "(print x ...)",
+        summary: "Print the arguments separated by spaces, then a newline, returning the last",
+        examples: &["(print 1 2 3)"],
+    },
+    Doc {
+        name: "display",
+        signature: "(display x ...)",
+        summary: "Print the arguments separated by spaces, with strings unquoted, returning the last",
+        examples: &["(display (quote hello))"],
+    },
+    Doc {
+        name: "write",
+        signature: "(write x ...)",
+        summary: "Print the arguments separated by spaces as they would be written in source, returning the last",
+        examples: &["(write (quote (1 2)))"],
+    },
+    Doc {
+        name: "pprint",
+        signature: "(pprint x ...)",
+        summary: "Print the arguments as they would be written in source, breaking long lists across lines",
+        examples: &["(pprint (quote (def square (fn (x) (* x x)))))"],
+    },
+    Doc {
+        name: "print-table",
+        signature: "(print-table rows)",
+        summary: "Print a list of rows, each a list of cells, as an aligned table",
+        examples: &["(print-table (quote ((name age) (ada 36) (alan 41))))"],
+    },
+    Doc {
+        name: "read-line",
+        signature: "(read-line [handle])",
+        summary: "Read a line of input or from a handle, or return false at the end",
+        examples: &["(read-line)"],
+    },
+    Doc {
+        name: "array",
+        signature: "(array x ...)",
+        summary: "Make a vector from numbers, or a matrix from lists of numbers as its rows",
+        examples: &["(array 1 2 3)", "(array (quote (1 2)) (quote (3 4)))"],
+    },
+    Doc {
+        name: "dot",
+        signature: "(dot a b)",
+        summary: "The dot product of two vectors, or the matrix product if either is a matrix",
+        examples: &["(dot (array 1 2 3) (array 4 5 6))"],
+    },
+    Doc {
+        name: "transpose",
+        signature: "(transpose a)",
+        summary: "Swap the rows and columns of a matrix",
+        examples: &["(transpose (array (quote (1 2)) (quote (3 4))))"],
+    },
+    Doc {
+        name: "slice",
+        signature: "(slice a start end)",
+        summary: "The items of a vector or rows of a matrix from start up to end",
+        examples: &["(slice (array 1 2 3 4) 1 3)"],
+    },
+    Doc {
+        name: "shape",
+        signature: "(shape a)",
+        summary: "The length of each dimension of an array, as a list",
+        examples: &["(shape (array (quote (1 2 3)) (quote (4 5 6))))"],
+    },
+    Doc {
+        name: "to-list",
+        signature: "(to-list a)",
+        summary: "An array as nested lists of numbers",
+        examples: &["(to-list (array 1 2 3))"],
+    },
+    Doc {
+        name: "list",
+        signature: "(list x ...)",
+        summary: "A list of the arguments",
+        examples: &["(list 1 (+ 1 1) 3)"],
+    },
+    Doc {
+        name: "cons",
+        signature: "(cons x list)",
+        summary: "A list of x followed by the items of a list",
+        examples: &["(cons 1 '(2 3))", "(cons 1 ())"],
+    },
+    Doc {
+        name: "car",
+        signature: "(car list)",
+        summary: "The first item of a non-empty list",
+        examples: &["(car '(1 2 3))"],
+    },
+    Doc {
+        name: "first",
+        signature: "(first list)",
+        summary: "The first item of a non-empty list, like car",
+        examples: &["(first '(1 2 3))"],
+    },
+    Doc {
+        name: "cdr",
+        signature: "(cdr list)",
+        summary: "The items of a non-empty list after the first",
+        examples: &["(cdr '(1 2 3))"],
+    },
+    Doc {
+        name: "rest",
+        signature: "(rest list)",
+        summary: "The items of a non-empty list after the first, like cdr",
+        examples: &["(rest '(1 2 3))"],
+    },
+    Doc {
+        name: "empty?",
+        signature: "(empty? list)",
+        summary: "Whether a list, stack or queue, including a priority queue, has no items; nil counts as the empty list",
+        examples: &["(empty? ())", "(empty? '(1))"],
+    },
+    Doc {
+        name: "length",
+        signature: "(length list)",
+        summary: "The number of items in a list, stack or queue, including a priority queue",
+        examples: &["(length '(1 (2 3)))"],
+    },
+    Doc {
+        name: "nth",
+        signature: "(nth list i)",
+        summary: "The item at an index of a list, counting from 0",
+        examples: &["(nth '(a b c) 1)"],
+    },
+    Doc {
+        name: "range",
+        signature: "(range [start] end [step])",
+        summary: "The numbers from start (0) up to but not including end, counting by step (1), or down when step is negative",
+        examples: &["(range 5)", "(range 2 10 2)", "(range 3 0 -1)"],
+    },
+    Doc {
+        name: "append",
+        signature: "(append list ...)",
+        summary: "A list of the items of every list in turn",
+        examples: &["(append '(1 2) '(3) ())"],
+    },
+    Doc {
+        name: "reverse",
+        signature: "(reverse list)",
+        summary: "The items of a list in reverse order",
+        examples: &["(reverse '(1 2 3))"],
+    },
+    Doc {
+        name: "map",
+        signature: "(map f list ...)",
+        summary: "The results of calling f on the items of the lists at each index, up to the shortest",
+        examples: &["(map (fn (x) (* x x)) '(1 2 3))", "(map + '(1 2) '(10 20))"],
+    },
+    Doc {
+        name: "filter",
+        signature: "(filter pred list)",
+        summary: "The items of a list that pred gives a true value for",
+        examples: &["(filter (fn (x) (> x 1)) '(1 2 3))"],
+    },
+    Doc {
+        name: "reduce",
+        signature: "(reduce f [init] list)",
+        summary: "Combine the items of a list with f, starting from init or else the first item",
+        examples: &["(reduce + 0 '(1 2 3))", "(reduce max '(3 9 2))"],
+    },
+    Doc {
+        name: "stack",
+        signature: "(stack x ...)",
+        summary: "A new stack holding the values, the last on top",
+        examples: &["(peek (stack 1 2))"],
+    },
+    Doc {
+        name: "push",
+        signature: "(push s x ...)",
+        summary: "Push the values onto a stack in order, returning it",
+        examples: &["(pop (push (stack) 1 2))"],
+    },
+    Doc {
+        name: "pop",
+        signature: "(pop s)",
+        summary: "Remove and return the top of a stack",
+        examples: &["(pop (stack 1 2))"],
+    },
+    Doc {
+        name: "queue",
+        signature: "(queue x ...)",
+        summary: "A new queue holding the values, the first at the front",
+        examples: &["(peek (queue 1 2))"],
+    },
+    Doc {
+        name: "enqueue",
+        signature: "(enqueue q x ...)",
+        summary: "Add the values to the back of a queue in order, returning it",
+        examples: &["(dequeue (enqueue (queue) 1 2))"],
+    },
+    Doc {
+        name: "dequeue",
+        signature: "(dequeue q)",
+        summary: "Remove and return the front of a queue",
+        examples: &["(dequeue (queue 1 2))"],
+    },
+    Doc {
+        name: "peek",
+        signature: "(peek s)",
+        summary: "The item pop or dequeue would take from a stack or queue next, without taking it",
+        examples: &["(peek (queue 1 2))", "(peek (stack 1 2))"],
+    },
+    Doc {
+        name: "pqueue",
+        signature: "(pqueue)",
+        summary: "A new, empty priority queue",
+        examples: &["(empty? (pqueue))"],
+    },
+    Doc {
+        name: "pq-push!",
+        signature: "(pq-push! q priority x)",
+        summary: "Add a value to a priority queue with a number as its priority, returning the queue",
+        examples: &["(pq-pop! (pq-push! (pq-push! (pqueue) 2 :later) 1 :sooner))"],
+    },
+    Doc {
+        name: "pq-pop!",
+        signature: "(pq-pop! q)",
+        summary: "Remove and return the value with the lowest priority, the oldest among equals",
+        examples: &["(pq-pop! (pq-push! (pqueue) 5 :only))"],
+    },
+    Doc {
+        name: "weak-ref",
+        signature: "(weak-ref h)",
+        summary: "A weak reference to a handle, which doesn't keep what the handle refers to alive",
+        examples: &["(deref-weak (weak-ref (string-builder)))"],
+    },
+    Doc {
+        name: "deref-weak",
+        signature: "(deref-weak w)",
+        summary: "The handle a weak reference refers to, or nil once nothing else keeps it alive",
+        examples: &["(begin (def q (queue 1)) (peek (deref-weak (weak-ref q))))"],
+    },
+    Doc {
+        name: "vec",
+        signature: "(vec list)",
+        summary: "A vector of the items of a list",
+        examples: &["(vec (quote (1 2 3)))"],
+    },
+    Doc {
+        name: "vector-ref",
+        signature: "(vector-ref v i)",
+        summary: "The item of a vector at an index, counting from 0",
+        examples: &["(vector-ref [10 20 30] 1)"],
+    },
+    Doc {
+        name: "vector-set",
+        signature: "(vector-set v i x)",
+        summary: "A copy of a vector with the item at an index replaced by x",
+        examples: &["(vector-set [10 20 30] 1 25)"],
+    },
+    Doc {
+        name: "vector-len",
+        signature: "(vector-len v)",
+        summary: "The number of items in a vector",
+        examples: &["(vector-len [10 20 30])"],
+    },
+    Doc {
+        name: "hash-map",
+        signature: "(hash-map key value ...)",
+        summary: "A map of keys to values, which {key value ...} reads as",
+        examples: &["(hash-map :name \"jim\" :age 4)"],
+    },
+    Doc {
+        name: "get",
+        signature: "(get m key [default])",
+        summary: "The value of a key in a map, or the default, or nil",
+        examples: &["(get {:a 1} :a)", "(get {:a 1} :b 0)"],
+    },
+    Doc {
+        name: "assoc",
+        signature: "(assoc m key value ...)",
+        summary: "A copy of a map with the keys set to the values",
+        examples: &["(assoc {:a 1} :b 2)"],
+    },
+    Doc {
+        name: "dissoc",
+        signature: "(dissoc m key ...)",
+        summary: "A copy of a map without the keys",
+        examples: &["(dissoc {:a 1 :b 2} :a)"],
+    },
+    Doc {
+        name: "keys",
+        signature: "(keys m)",
+        summary: "The keys of a map, in the order they were added",
+        examples: &["(keys {:a 1 :b 2})"],
+    },
+    Doc {
+        name: "vals",
+        signature: "(vals m)",
+        summary: "The values of a map, in the same order as its keys",
+        examples: &["(vals {:a 1 :b 2})"],
+    },
+    Doc {
+        name: "contains?",
+        signature: "(contains? m key)",
+        summary: "Whether a map has a key",
+        examples: &["(contains? {:a nil} :a)"],
+    },
+    Doc {
+        name: "map->list",
+        signature: "(map->list m)",
+        summary: "The entries of a map as (key value) lists, in the order their keys were added",
+        examples: &["(map->list {:b 1 :a 2})"],
+    },
+    Doc {
+        name: "graph",
+        signature: "(graph (from to) ...)",
+        summary: "A directed graph with the given edges, as a map from each node to the list of nodes it has edges to",
+        examples: &["(graph '(:a :b) '(:b :c))"],
+    },
+    Doc {
+        name: "add-edge",
+        signature: "(add-edge g from to)",
+        summary: "A copy of a graph with an edge added, along with its nodes if they're new",
+        examples: &["(add-edge (graph) :a :b)"],
+    },
+    Doc {
+        name: "neighbors",
+        signature: "(neighbors g node)",
+        summary: "The nodes a node has edges to, in the order they were added",
+        examples: &["(neighbors (graph '(:a :b) '(:a :c)) :a)"],
+    },
+    Doc {
+        name: "bfs",
+        signature: "(bfs g start)",
+        summary: "The nodes reachable from start in breadth-first order, starting with start",
+        examples: &["(bfs (graph '(:a :b) '(:b :c) '(:a :d)) :a)"],
+    },
+    Doc {
+        name: "topo-sort",
+        signature: "(topo-sort g)",
+        summary: "Every node of a graph, each before the nodes it has edges to; a cycle is an error",
+        examples: &["(topo-sort (graph '(:shirt :tie) '(:tie :jacket) '(:shirt :jacket)))"],
+    },
+    Doc {
+        name: "glob",
+        signature: "(glob pattern)",
+        summary: "The paths matching a pattern like src/**/*.crisp, sorted",
+        examples: &["(glob (quote *.crisp))"],
+    },
+    Doc {
+        name: "list-dir",
+        signature: "(list-dir path)",
+        summary: "The paths of the entries of a directory, sorted",
+        examples: &["(list-dir (quote .))"],
+    },
+    Doc {
+        name: "is-dir?",
+        signature: "(is-dir? path)",
+        summary: "Check whether a path is a directory",
+        examples: &["(is-dir? (quote .))"],
+    },
+    Doc {
+        name: "open",
+        signature: "(open path [mode])",
+        summary: "Open a file for :read (the default), :write or :append, returning a handle",
+        examples: &["(with-open (f (open (temp-file) :write)) (write-text f 42))"],
+    },
+    Doc {
+        name: "close",
+        signature: "(close handle)",
+        summary: "Close a handle, flushing anything written to it",
+        examples: &["(close (open (temp-file)))"],
+    },
+    Doc {
+        name: "read-text",
+        signature: "(read-text handle)",
+        summary: "Read the rest of a file opened for reading, as a string",
+        examples: &["(with-open (f (open (temp-file))) (read-text f))"],
+    },
+    Doc {
+        name: "write-text",
+        signature: "(write-text handle x ...)",
+        summary: "Write the displayed forms of the values to a file opened for writing",
+        examples: &["(with-open (f (open (temp-file) :append)) (write-text f 1 2 3))"],
+    },
+    Doc {
+        name: "temp-file",
+        signature: "(temp-file)",
+        summary: "Create an empty file in the temp directory, returning its path. It's deleted when the env is dropped",
+        examples: &["(temp-file)"],
+    },
+    Doc {
+        name: "temp-dir",
+        signature: "(temp-dir)",
+        summary: "Create an empty directory in the temp directory, returning its path. It's deleted, with whatever it holds, when the env is dropped",
+        examples: &["(is-dir? (temp-dir))"],
+    },
+    Doc {
+        name: "sum",
+        signature: "(sum [f] list)",
+        summary: "Add up a list of numbers, or what f gives for each item of a list, or 0 for an empty list",
+        examples: &["(sum (quote (1 2 3)))", "(sum (fn (x) (* x x)) (quote (1 2 3)))"],
+    },
+    Doc {
+        name: "product",
+        signature: "(product list)",
+        summary: "Multiply a list of numbers together, or 1 for an empty list",
+        examples: &["(product (quote (1 2 3 4)))"],
+    },
+    Doc {
+        name: "identity",
+        signature: "(identity x)",
+        summary: "Return x",
+        examples: &["(identity 5)"],
+    },
+    Doc {
+        name: "const",
+        signature: "(const x)",
+        summary: "Create a function that ignores its arguments and returns x",
+        examples: &["((const 1) 2 3)"],
+    },
+    Doc {
+        name: "flip",
+        signature: "(flip f)",
+        summary: "Create a function of two arguments that calls f with them swapped",
+        examples: &["((flip -) 1 10)"],
+    },
+    Doc {
+        name: "comp",
+        signature: "(comp f ...)",
+        summary: "Create a function of one argument that applies the functions from right to left",
+        examples: &["((comp (fn (x) (* x 2)) (fn (x) (+ x 1))) 5)"],
+    },
+    Doc {
+        name: "crisp-version",
+        signature: "(crisp-version)",
+        summary: "The version of crisp running the script, as a string",
+        examples: &["(crisp-version)"],
+    },
+    Doc {
+        name: "feature?",
+        signature: "(feature? name)",
+        summary: "Whether crisp was built with an optional feature, one of :jit, :ndarray, :tracing, :arbitrary or :testing",
+        examples: &["(feature? :jit)"],
+    },
+    Doc {
+        name: "and",
+        signature: "(and x ...)",
+        summary: "Evaluate the arguments in order until one is false or nil, returning the last evaluated, or true without any",
+        examples: &["(and (> 2 1) (quote yes))"],
+    },
+    Doc {
+        name: "begin",
+        signature: "(begin expr ...)",
+        summary: "Evaluate each expression in order, returning the last, or nil if there are none",
+        examples: &["(begin (def x 2) (* x x))"],
+    },
+    Doc {
+        name: "case",
+        signature: "(case key (value body ...) ((value ...) body ...) (else body ...))",
+        summary: "Evaluate the body of the first clause whose literal values include key, or nil if none do",
+        examples: &["(case 2 (1 (quote one)) ((2 3) (quote few)) (else (quote many)))"],
+    },
+    Doc {
+        name: "count-if",
+        signature: "(count-if pred list)",
+        summary: "Count the items of the list that pred is true for",
+        examples: &["(count-if (fn (x) (> x 2)) (quote (1 2 3 4)))"],
+    },
+    Doc {
+        name: "def",
+        signature: "(def name value)",
+        summary: "Bind a value to a name in the current scope, which inside a function is local to the call",
+        examples: &["(def answer 42)", "(def square (fn (x) (* x x)))"],
+    },
+    Doc {
+        name: "def-global",
+        signature: "(def-global name value)",
+        summary: "Bind a value to a name in the global environment, even inside a function",
+        examples: &["(def setup (fn () (def-global ready true)))"],
+    },
+    Doc {
+        name: "defmacro",
+        signature: "(defmacro name (params ...) body ...)",
+        summary: "Define a macro, whose calls are replaced by the body with the unevaluated args substituted for the params",
+        examples: &["(defmacro swap-args (f a b) (f b a)) (swap-args - 1 10)"],
+    },
+    Doc {
+        name: "deprecate",
+        signature: "(deprecate name [instead])",
+        summary: "Mark a binding as deprecated, so using it warns once, suggesting instead",
+        examples: &["(def old-add +) (deprecate old-add +)"],
+    },
+    Doc {
+        name: "every?",
+        signature: "(every? pred list)",
+        summary: "Whether pred is true for every item of the list, stopping at the first it's false for",
+        examples: &["(every? (fn (x) (> x 0)) (quote (1 2 3)))"],
+    },
+    Doc {
+        name: "fn",
+        signature: "(fn (params ...) body ...)",
+        summary:
+            "Create a function that evaluates each body expression in order, returning the last",
+        examples: &["((fn (x y) (+ x y)) 1 2)"],
+    },
+    Doc {
+        name: "if",
+        signature: "(if cond then [else])",
+        summary: "Evaluate then unless cond is false or nil, otherwise else, or nil without an else",
+        examples: &["(if (> 2 1) (quote yes) (quote no))"],
+    },
+    Doc {
+        name: "let",
+        signature: "(let ((name value) ...) body ...)",
+        summary: "Evaluate the body with each name bound to its value, returning the last",
+        examples: &["(let ((x 1) (y 2)) (+ x y))"],
+    },
+    Doc {
+        name: "let*",
+        signature: "(let* ((name value) ...) body ...)",
+        summary: "Like let, but each value can refer to the names bound before it",
+        examples: &["(let* ((x 1) (y (+ x 1))) (* x y))"],
+    },
+    Doc {
+        name: "load",
+        signature: "(load path)",
+        summary: "Evaluate another file's forms once, resolving a relative path from the loading file",
+        examples: &[],
+    },
+    Doc {
+        name: "log",
+        signature: "(log level x ...)",
+        summary: "Log the arguments at a level like :info, with the script's name as the target",
+        examples: &["(log :info (quote starting))"],
+    },
+    Doc {
+        name: "max-by",
+        signature: "(max-by f list [default])",
+        summary: "Return the item of the list f gives the largest number for, or default if it's empty",
+        examples: &["(max-by (fn (x) (- 0 x)) (quote (3 1 2)))"],
+    },
+    Doc {
+        name: "min-by",
+        signature: "(min-by f list [default])",
+        summary: "Return the item of the list f gives the smallest number for, or default if it's empty",
+        examples: &["(min-by (fn (x) (- 0 x)) (quote (3 1 2)) 0)"],
+    },
+    Doc {
+        name: "eval",
+        signature: "(eval form)",
+        summary: "Evaluate form, then evaluate the data it gives as code in the current scope",
+        examples: &["(eval '(+ 1 2))", "(eval (list '* 6 7))"],
+    },
+    Doc {
+        name: "or",
+        signature: "(or x ...)",
+        summary: "Evaluate the arguments in order until one is neither false nor nil, returning the last evaluated, or nil without any",
+        examples: &["(or false (quote fallback))"],
+    },
+    Doc {
+        name: "or-else",
+        signature: "(or-else expr default)",
+        summary: "Evaluate expr, or default instead if evaluating expr fails",
+        examples: &["(or-else (assert false) 0)"],
+    },
+    Doc {
+        name: "pipe",
+        signature: "(pipe x f ...)",
+        summary: "Pass x through the functions from left to right, returning the last result",
+        examples: &["(pipe 5 (fn (x) (+ x 1)) (fn (x) (* x 2)))"],
+    },
+    Doc {
+        name: "quote",
+        signature: "(quote expr)",
+        summary: "Return expr without evaluating it",
+        examples: &["(quote (1 2 3))"],
+    },
+    Doc {
+        name: "retry",
+        signature: "(retry n expr)",
+        summary: "Evaluate expr up to n times until it succeeds, failing with its last error",
+        examples: &["(retry 3 (+ 1 2))"],
+    },
+    Doc {
+        name: "some",
+        signature: "(some pred list)",
+        summary: "Return the first item of the list that pred is true for, or nil if there isn't one",
+        examples: &["(some (fn (x) (> x 1)) (quote (1 2 3)))"],
+    },
+    Doc {
+        name: "source-of",
+        signature: "(source-of name)",
+        summary: "Return where name was defined as (file line column), or nil if it isn't known",
+        examples: &["(def x 1) (source-of x)"],
+    },
+    Doc {
+        name: "undef",
+        signature: "(undef name [:force])",
+        summary: "Remove a binding from the current scope, including a builtin if forced",
+        examples: &["(def x 1) (undef x)"],
+    },
+    Doc {
+        name: "unless",
+        signature: "(unless cond body ...)",
+        summary: "Evaluate the body in order if cond is false or nil, returning the last, otherwise nil",
+        examples: &["(unless (> 1 2) (quote smaller))"],
+    },
+    Doc {
+        name: "unshadow",
+        signature: "(unshadow (quote name))",
+        summary: "Restore a builtin that a def rebound, when redefining builtins is enabled",
+        examples: &[],
+    },
+    Doc {
+        name: "use",
+        signature: "(use namespace)",
+        summary: "Make the builtins in a namespace, like math/+, available by their plain names",
+        examples: &["(use math) (+ 1 2)"],
+    },
+    Doc {
+        name: "when",
+        signature: "(when cond body ...)",
+        summary: "Evaluate the body in order unless cond is false or nil, returning the last, otherwise nil",
+        examples: &["(when (> 2 1) (quote bigger))"],
+    },
+    Doc {
+        name: "with-open",
+        signature: "(with-open (name handle) body ...)",
+        summary: "Evaluate the body with a handle bound to name, closing it afterwards even if the body fails",
+        examples: &["(with-open (f (open (temp-file))) (read-line f))"],
+    },
+    Doc {
+        name: "with-timeout",
+        signature: "(with-timeout ms expr)",
+        summary: "Evaluate expr, returning :timeout instead if it takes longer than ms milliseconds",
+        examples: &["(with-timeout 100 (+ 1 2))"],
+    },
+];
+
+/// Find the documentation for a builtin, by its plain or namespaced name
+pub fn lookup(name: &str) -> Option<&'static Doc> {
+    let plain = match name.split_once('/') {
+        Some((ns, plain)) if !ns.is_empty() && !plain.is_empty() => plain,
+        _ => name,
+    };
+
+    // A few builtins only go by their namespaced names, like `math/log`
+    BUILTINS
+        .iter()
+        .find(|doc| doc.name == name)
+        .or_else(|| BUILTINS.iter().find(|doc| doc.name == plain))
+}
+
+/// The builtins whose names or summaries contain `query`, ignoring case
+pub fn apropos(query: &str) -> Vec<&'static Doc> {
+    let query = query.to_lowercase();
+    BUILTINS
+        .iter()
+        .filter(|doc| doc.name.contains(&query) || doc.summary.to_lowercase().contains(&query))
+        .collect()
+}
+
+/// How a call to `name` is written, either from the builtin docs or, for
+/// user functions, from the params they were defined with
+pub fn signature(name: &str, env: &CrispEnv) -> Option<String> {
+    if let Some(doc) = lookup(name) {
+        return Some(doc.signature.to_string());
+    }
+
+    match env.get(name)? {
+        CrispExpr::Lambda(lambda) => match lambda.param_list().as_str() {
+            "" => Some(format!("({name})")),
+            params => Some(format!("({name} {params})")),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::SPECIAL_FORMS, run_program};
+
+    #[test]
+    fn every_builtin_is_documented() {
+        let env = CrispEnv::default();
+
+        let names = env.bindings().into_iter().map(|(name, _)| name);
+        for name in names.chain(SPECIAL_FORMS.iter().copied()) {
+            assert!(lookup(name).is_some(), "{name} has no docs");
+        }
+    }
+
+    #[test]
+    fn apropos_searches_names_and_summaries() {
+        let names = |query| {
+            apropos(query)
+                .iter()
+                .map(|doc| doc.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("TRANSPOSE"), ["transpose"]);
+        assert!(names("directory").contains(&"is-dir?"));
+        assert!(names("no builtin mentions this").is_empty());
+    }
+
+    #[test]
+    fn signature_of_user_function() {
+        let mut env = CrispEnv::default();
+        run_program("(def add (fn (a b) (+ a b)))", &mut env).unwrap();
+
+        assert_eq!(signature("add", &env), Some("(add a b)".to_string()));
+        assert_eq!(
+            signature("if", &env),
+            Some("(if cond then [else])".to_string())
+        );
+        assert_eq!(signature("nope", &env), None);
+    }
+}
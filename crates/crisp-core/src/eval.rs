@@ -0,0 +1,3395 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{
+    format::{to_display, to_source},
+    lang::{
+        Clock, Closure, CrispError, CrispExpr, CrispFn, CrispLambda, CrispResult, Loader,
+        Primitive, Span, Tracer,
+    },
+    macros, math,
+    parse::{parse_param_list, parse_source, ParseOptions},
+    source_map,
+};
+
+/// Settings that control how expressions are evaluated
+#[derive(Debug, Clone, Default)]
+pub struct EvalOptions {
+    /// Maximum number of nested function calls before evaluation is aborted.
+    /// Calls in tail position replace their caller, so they aren't nested.
+    pub max_depth: Option<usize>,
+    /// Report every function call and its result to this tracer
+    pub trace: Option<Arc<dyn Tracer>>,
+    /// Maximum number of steps, counted since the env's steps were last
+    /// reset, before evaluation is aborted. Evaluating an expression is a
+    /// step, and so is each scope searched when looking up a symbol, so the
+    /// budget bounds the work done even in deep recursion.
+    pub max_steps: Option<usize>,
+    /// Evaluation stops with `CrispError::Interrupted` once this is set,
+    /// e.g. from a Ctrl-C handler. It's never cleared by the evaluator.
+    /// Functions running as native code through the JIT can't be interrupted.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Evaluation stops with `CrispError::TimedOut` once this time has
+    /// passed. Set by `with-timeout` while it evaluates its expression.
+    pub deadline: Option<Instant>,
+    /// The clock deadlines are checked against, or the system's monotonic
+    /// clock if there's none
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Compile functions to native code once they've been called this many
+    /// times. Only numeric functions are compiled, and never while tracing or
+    /// limiting the call depth or steps, or under a deadline.
+    #[cfg(feature = "jit")]
+    pub jit_threshold: Option<usize>,
+    /// Allow `def` to rebind builtins in the global env. The originals are
+    /// kept, so `unshadow` can restore them.
+    pub redefine_builtins: bool,
+    /// What dividing by zero gives, with `/`, `mod`, `rem` or `quotient`
+    pub division_by_zero: DivisionByZero,
+    /// Limits on the source `eval_str` will parse
+    pub parse: ParseOptions,
+}
+
+impl EvalOptions {
+    /// The time by the options' clock
+    pub(crate) fn now(&self) -> Instant {
+        self.clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock.now())
+    }
+}
+
+/// What happens on dividing by zero
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DivisionByZero {
+    /// Fail with `CrispError::DivisionByZero`
+    #[default]
+    Error,
+    /// Give nil, so formulas always have a value
+    Nil,
+    /// Give infinity, or NaN for zero divided by zero, from `/` on ints and
+    /// floats as IEEE floats do. Other divisions still fail, as ints and
+    /// decimals have no infinity.
+    Infinity,
+}
+
+/// Globals defined inside function calls, with where they were defined
+type PendingGlobals = HashMap<String, (CrispExpr, Option<Location>)>;
+
+pub struct CrispEnv<'a> {
+    pub symbols: HashMap<String, CrispExpr>,
+    pub parent: Option<&'a CrispEnv<'a>>,
+    pub options: EvalOptions,
+    /// Number of function calls between this env and the global env
+    pub depth: usize,
+    /// Expressions evaluated so far, shared with the envs of function calls
+    steps: Arc<AtomicUsize>,
+    /// Where the bindings in `symbols` were defined, for those defined by
+    /// top-level forms with a known span
+    pub definitions: HashMap<String, Location>,
+    /// Docs of the bindings and macros in this scope, from the `;;` comments
+    /// written before the top-level forms that defined them
+    pub docs: HashMap<String, String>,
+    /// Span of the top-level form being evaluated
+    pub(crate) form_span: Option<Span>,
+    /// Bindings in `symbols` that have been deprecated, with the names to
+    /// use instead if there are any
+    pub deprecated: HashMap<String, Option<String>>,
+    /// Warnings raised while evaluating, shared with the envs of function
+    /// calls
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    /// Globals defined with `def-global` inside function calls, which can't
+    /// reach the global env. Shared with the envs of function calls, and
+    /// moved into the global env after each top-level form.
+    pending_globals: Arc<Mutex<PendingGlobals>>,
+    /// Names of the builtins this env was built with, shared with its forks
+    builtins: Arc<HashSet<String>>,
+    /// In a function call's env, the locals of the scope the lambda being
+    /// called was made in, searched after this env's own symbols
+    closure: Option<Arc<Closure>>,
+    /// This scope's locals and those enclosing it, shared with the lambdas
+    /// made here. Only made once a lambda is.
+    shared: OnceLock<Arc<Closure>>,
+    /// Builtins that have been rebound by a `def`, with their original values
+    pub shadowed: HashMap<String, CrispExpr>,
+    /// Macros defined in this scope with `defmacro`
+    pub macros: HashMap<String, CrispLambda>,
+    /// Reads the files `load` evaluates, if the env can load files
+    loader: Option<Arc<dyn Loader>>,
+    /// Paths of the files `load` has evaluated in this env, in the order
+    /// they were loaded
+    pub loaded: Vec<String>,
+    /// Path of the file being reloaded, whose `def`s can rebind the names
+    /// it defined before
+    reloading: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Diagnostics {
+    warnings: Vec<String>,
+    /// Deprecated names already warned about, so each is only reported once
+    warned: HashSet<String>,
+    /// Addresses of the expressions the current error has propagated
+    /// through, innermost first. See `source_map`.
+    trace: Vec<usize>,
+}
+
+/// The bindings of an env at some point, to roll back to if evaluation fails
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    symbols: HashMap<String, CrispExpr>,
+    definitions: HashMap<String, Location>,
+    docs: HashMap<String, String>,
+    deprecated: HashMap<String, Option<String>>,
+    shadowed: HashMap<String, CrispExpr>,
+    macros: HashMap<String, CrispLambda>,
+    loaded: Vec<String>,
+}
+
+/// Where a binding was defined: the top-level form containing its `def`,
+/// and the script it was in if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub span: Span,
+}
+
+impl<'a> CrispEnv<'a> {
+    pub fn from_parent(parent: &'a CrispEnv) -> Self {
+        Self {
+            symbols: HashMap::new(),
+            parent: Some(parent),
+            options: parent.options.clone(),
+            depth: parent.depth + 1,
+            steps: parent.steps.clone(),
+            definitions: HashMap::new(),
+            docs: HashMap::new(),
+            form_span: parent.form_span,
+            deprecated: HashMap::new(),
+            diagnostics: parent.diagnostics.clone(),
+            pending_globals: parent.pending_globals.clone(),
+            builtins: Arc::default(),
+            closure: None,
+            shared: OnceLock::new(),
+            shadowed: HashMap::new(),
+            macros: HashMap::new(),
+            loader: parent.loader.clone(),
+            loaded: vec![],
+            reloading: None,
+        }
+    }
+
+    /// Create a copy-on-write view of this env: it sees every binding made
+    /// here, but its own definitions don't affect the original. Warnings
+    /// and error traces are kept apart too, so forks can run in parallel.
+    pub fn fork(&self) -> CrispEnv<'_> {
+        CrispEnv {
+            symbols: HashMap::new(),
+            parent: Some(self),
+            options: self.options.clone(),
+            depth: self.depth,
+            steps: Arc::default(),
+            definitions: HashMap::new(),
+            docs: HashMap::new(),
+            form_span: self.form_span,
+            deprecated: HashMap::new(),
+            // Errors and warnings belong to whatever runs in the fork
+            diagnostics: Arc::default(),
+            pending_globals: Arc::default(),
+            builtins: self.builtins.clone(),
+            closure: None,
+            shared: OnceLock::new(),
+            shadowed: HashMap::new(),
+            macros: HashMap::new(),
+            loader: self.loader.clone(),
+            loaded: self.loaded.clone(),
+            reloading: None,
+        }
+    }
+
+    /// The env of a call to `lambda` made from `caller`. Its parent is the
+    /// global env rather than the caller, so the body sees the locals of
+    /// the scope the lambda was made in instead of the caller's.
+    fn call_frame(caller: &'a CrispEnv, lambda: &CrispLambda) -> Self {
+        Self {
+            parent: Some(caller.globals()),
+            closure: lambda.closure.clone(),
+            ..CrispEnv::from_parent(caller)
+        }
+    }
+
+    /// The global env this scope is in
+    pub(crate) fn globals(&self) -> &CrispEnv<'_> {
+        let mut env = self;
+        while env.depth > 0 {
+            match env.parent {
+                Some(parent) => env = parent,
+                None => break,
+            }
+        }
+        env
+    }
+
+    /// The locals of this scope and those enclosing it, for a lambda made
+    /// here to close over. Globals are looked up when the lambda is called,
+    /// so there's nothing to share in the global env.
+    fn share(&self) -> Option<Arc<Closure>> {
+        if self.depth == 0 {
+            return None;
+        }
+
+        let shared = self.shared.get_or_init(|| {
+            let parent = self.closure.clone().or_else(|| self.parent?.share());
+            Arc::new(Closure::new(self.symbols.clone(), parent))
+        });
+        Some(shared.clone())
+    }
+
+    /// Pass a change to the binding of `name` in this scope on to the
+    /// lambdas made here
+    fn reshare(&self, name: &str) {
+        let Some(shared) = self.shared.get() else {
+            return;
+        };
+        match self.symbols.get(name) {
+            Some(value) => shared.insert(name.to_string(), value.clone()),
+            None => shared.remove(name),
+        }
+    }
+
+    /// Create a global env with the default builtins and the given options
+    pub fn with_options(options: EvalOptions) -> Self {
+        CrispEnvBuilder::new().pure().options(options).build()
+    }
+
+    /// Make `args` available to scripts as the list of strings `*args*`
+    pub fn set_args(&mut self, args: &[String]) {
+        let args = args
+            .iter()
+            .map(|arg| CrispExpr::Primitive(Primitive::Str(arg.clone())))
+            .collect();
+
+        self.symbols
+            .insert("*args*".to_string(), CrispExpr::List(args));
+    }
+
+    /// Make the name of the running script available as the string
+    /// `*script*`, which `log` uses as its target
+    pub fn set_script(&mut self, name: &str) {
+        self.symbols.insert(
+            "*script*".to_string(),
+            CrispExpr::Primitive(Primitive::Str(name.to_string())),
+        );
+    }
+
+    /// Save this scope's bindings, e.g. before evaluating an input that
+    /// should either succeed completely or not change the env at all
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            symbols: self.symbols.clone(),
+            definitions: self.definitions.clone(),
+            docs: self.docs.clone(),
+            deprecated: self.deprecated.clone(),
+            shadowed: self.shadowed.clone(),
+            macros: self.macros.clone(),
+            loaded: self.loaded.clone(),
+        }
+    }
+
+    /// Undo every binding made in this scope since `checkpoint` was taken,
+    /// forgetting the files loaded since too, so loading them again
+    /// redefines what they defined
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.symbols = checkpoint.symbols;
+        self.definitions = checkpoint.definitions;
+        self.docs = checkpoint.docs;
+        self.deprecated = checkpoint.deprecated;
+        self.shadowed = checkpoint.shadowed;
+        self.macros = checkpoint.macros;
+        self.loaded = checkpoint.loaded;
+    }
+
+    /// Number of steps taken since the steps were last reset
+    pub fn steps(&self) -> usize {
+        self.steps.load(Ordering::Relaxed)
+    }
+
+    /// Start counting steps towards `max_steps` from zero again
+    pub fn reset_steps(&self) {
+        self.steps.store(0, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, name: &str) -> Option<CrispExpr> {
+        match self.find(name).0 {
+            Some(Binding::Env(env)) => Some(env.symbols[name].clone()),
+            Some(Binding::Closure(value)) => Some(value),
+            None => self.pending_global(name),
+        }
+    }
+
+    /// A global defined by `def-global` in a function call that hasn't been
+    /// moved into the global env yet
+    fn pending_global(&self, name: &str) -> Option<CrispExpr> {
+        let pending = self.pending_globals.lock().ok()?;
+        pending.get(name).map(|(val, _)| val.clone())
+    }
+
+    /// Move globals defined in function calls into this env, which should be
+    /// the global env
+    pub(crate) fn flush_globals(&mut self) {
+        let Ok(mut pending) = self.pending_globals.lock() else {
+            return;
+        };
+
+        for (name, (val, location)) in pending.drain() {
+            self.symbols.insert(name.clone(), val);
+            if let Some(location) = location {
+                self.definitions.insert(name, location);
+            }
+        }
+    }
+
+    /// Like `get`, without cloning the value. The locals a lambda being
+    /// called closed over are shared, so they can't be borrowed, and
+    /// aren't found.
+    pub fn lookup(&self, name: &str) -> Option<&CrispExpr> {
+        match self.find(name).0? {
+            Binding::Env(env) => env.symbols.get(name),
+            Binding::Closure(_) => None,
+        }
+    }
+
+    /// The macro `name` refers to, from this scope or an enclosing one
+    pub fn lookup_macro(&self, name: &str) -> Option<&CrispLambda> {
+        let mut env = self;
+        loop {
+            if let Some(mac) = env.macros.get(name) {
+                return Some(mac);
+            }
+            env = env.parent?;
+        }
+    }
+
+    /// Where the binding `name` resolves to was defined, if it's known
+    pub fn location(&self, name: &str) -> Option<&Location> {
+        match self.find(name).0? {
+            Binding::Env(env) => env.definitions.get(name),
+            Binding::Closure(_) => None,
+        }
+    }
+
+    /// The doc of the binding or macro `name` resolves to, if it has one
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        let mut env = self;
+        loop {
+            if let Some(doc) = env.docs.get(name) {
+                return Some(doc);
+            }
+            if env.symbols.contains_key(name) || env.macros.contains_key(name) {
+                return None;
+            }
+            env = env.parent?;
+        }
+    }
+
+    /// Whether the binding `name` resolves to is deprecated, with the name to
+    /// use instead if there is one
+    pub fn deprecation(&self, name: &str) -> Option<&Option<String>> {
+        match self.find(name).0? {
+            Binding::Env(env) => env.deprecated.get(name),
+            Binding::Closure(_) => None,
+        }
+    }
+
+    /// Take the warnings raised since they were last taken, e.g. to show
+    /// after each REPL input
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.diagnostics
+            .lock()
+            .map(|mut diagnostics| std::mem::take(&mut diagnostics.warnings))
+            .unwrap_or_default()
+    }
+
+    /// Evaluate a file `load` evaluated before again, so changes to it take
+    /// effect without starting over. Its `def`s can rebind the names it
+    /// defined before, and every other binding, including those it no
+    /// longer defines, is kept. `module` is the path `load` returned, or
+    /// the file's name with or without its extension.
+    pub fn reload(&mut self, module: &str) -> CrispResult {
+        let matches: Vec<&String> = self
+            .loaded
+            .iter()
+            .filter(|loaded| {
+                let path = Path::new(loaded.as_str());
+                loaded.as_str() == module
+                    || path.file_name() == Some(module.as_ref())
+                    || path.file_stem() == Some(module.as_ref())
+            })
+            .collect();
+        let path = match matches.as_slice() {
+            [path] => path.to_string(),
+            [] => {
+                return Err(CrispError::EvalError(format!(
+                    "{module} hasn't been loaded"
+                )))
+            }
+            paths => {
+                return Err(CrispError::EvalError(format!(
+                    "{module} could be any of {}, give its path",
+                    paths
+                        .iter()
+                        .map(|path| path.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )))
+            }
+        };
+        let loader = self.loader.clone().ok_or(CrispError::EvalError(format!(
+            "can't reload {path}: this interpreter can't read files"
+        )))?;
+
+        let (_, src) = loader.load(&path, None)?;
+        self.reloading = Some(path.clone());
+        let res = eval_loaded(&path, &src, self);
+        self.reloading = None;
+        res.map(|()| CrispExpr::Primitive(Primitive::Str(path)))
+    }
+
+    /// Whether `name` was defined by the file being reloaded, so its `def`
+    /// can rebind it
+    fn reloads(&self, name: &str) -> bool {
+        let file = self
+            .definitions
+            .get(name)
+            .and_then(|location| location.file.as_ref());
+        self.reloading
+            .as_ref()
+            .is_some_and(|path| file == Some(path))
+    }
+
+    /// Remove a binding from this scope. Builtins are only removed when
+    /// `force` is set; otherwise undefining a builtin rebound by a `def`
+    /// restores the original.
+    pub fn undef(&mut self, name: &str, force: bool) -> Result<(), CrispError> {
+        if !self.symbols.contains_key(name) {
+            return Err(CrispError::EvalError(format!(
+                "'{name}' isn't defined in this scope"
+            )));
+        }
+
+        if self.builtins.contains(name) && !force {
+            if self.unshadow(name) {
+                return Ok(());
+            }
+            return Err(CrispError::EvalError(format!(
+                "'{name}' is a builtin, use (undef {name} :force) to remove it"
+            )));
+        }
+
+        self.symbols.remove(name);
+        self.shadowed.remove(name);
+        self.definitions.remove(name);
+        self.docs.remove(name);
+        self.deprecated.remove(name);
+        self.reshare(name);
+        Ok(())
+    }
+
+    /// The bindings made in this scope, sorted by name so that anything
+    /// listing them comes out the same from run to run
+    pub fn bindings(&self) -> Vec<(&str, &CrispExpr)> {
+        let mut bindings: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        bindings.sort_by_key(|(name, _)| *name);
+        bindings
+    }
+
+    /// The value the env was built with for a builtin, even if a `def` has
+    /// rebound it since
+    pub fn original_builtin(&self, name: &str) -> Option<&CrispExpr> {
+        if !self.builtins.contains(name) {
+            return None;
+        }
+        self.shadowed.get(name).or_else(|| self.lookup(name))
+    }
+
+    /// Restore every builtin rebound by a `def`, returning their names
+    pub fn reset_builtins(&mut self) -> Vec<String> {
+        let mut names: Vec<String> = self.shadowed.keys().cloned().collect();
+        names.sort();
+        for name in &names {
+            self.unshadow(name);
+        }
+        names
+    }
+
+    /// Restore a builtin rebound by a `def`, returning false if it wasn't
+    fn unshadow(&mut self, name: &str) -> bool {
+        let Some(original) = self.shadowed.remove(name) else {
+            return false;
+        };
+
+        self.symbols.insert(name.to_string(), original);
+        self.definitions.remove(name);
+        self.deprecated.remove(name);
+        true
+    }
+
+    /// Record that an error propagated through `expr`
+    fn trace_error(&self, expr: &CrispExpr) {
+        if let Ok(mut diagnostics) = self.diagnostics.lock() {
+            diagnostics.trace.push(source_map::address(expr));
+        }
+    }
+
+    /// Take the expressions the last error propagated through, innermost
+    /// first
+    pub(crate) fn take_trace(&self) -> Vec<usize> {
+        self.diagnostics
+            .lock()
+            .map(|mut diagnostics| std::mem::take(&mut diagnostics.trace))
+            .unwrap_or_default()
+    }
+
+    fn warn(&self, message: String) {
+        if let Ok(mut diagnostics) = self.diagnostics.lock() {
+            diagnostics.warnings.push(message);
+        }
+    }
+
+    /// Raise a warning about a deprecated binding, unless it's been raised
+    /// already
+    fn warn_deprecated(&self, name: &str, instead: &Option<String>) {
+        let Ok(mut diagnostics) = self.diagnostics.lock() else {
+            return;
+        };
+
+        if diagnostics.warned.insert(name.to_string()) {
+            diagnostics
+                .warnings
+                .push(deprecation_message(name, instead));
+        }
+    }
+
+    /// Find where a symbol is bound, along with the number of enclosing
+    /// scopes searched
+    fn find(&self, name: &str) -> (Option<Binding<'_>>, usize) {
+        let mut env = self;
+        let mut scopes = 0;
+        loop {
+            if env.symbols.contains_key(name) {
+                return (Some(Binding::Env(env)), scopes);
+            }
+            if let Some(closure) = &env.closure {
+                let (value, searched) = closure.find(name);
+                scopes += searched + 1;
+                if let Some(value) = value {
+                    return (Some(Binding::Closure(value)), scopes);
+                }
+            }
+            match env.parent {
+                Some(outer) => env = outer,
+                None => return (None, scopes),
+            }
+            scopes += 1;
+        }
+    }
+
+    /// Count `steps` towards `max_steps`, failing once the budget is used up
+    fn charge(&self, steps: usize) -> Result<(), CrispError> {
+        match self.options.max_steps {
+            Some(max) if self.steps.fetch_add(steps, Ordering::Relaxed) >= max => Err(
+                CrispError::EvalError(format!("evaluation aborted after {max} steps")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a symbol is bound
+enum Binding<'e> {
+    /// In the symbols of an env
+    Env(&'e CrispEnv<'e>),
+    /// Among the locals a lambda being called closed over
+    Closure(CrispExpr),
+}
+
+/// Builds a global env out of groups of builtins, so embedders can pick
+/// which ones scripts have access to
+#[derive(Default)]
+pub struct CrispEnvBuilder {
+    symbols: HashMap<String, CrispExpr>,
+    options: EvalOptions,
+    /// Leave out the plain names of builtins
+    namespaced: bool,
+    loader: Option<Arc<dyn Loader>>,
+}
+
+impl CrispEnvBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every group of builtins that only computes, without reaching outside
+    /// the interpreter
+    pub fn pure(self) -> Self {
+        let pure = self
+            .core()
+            .math()
+            .strings()
+            .lists()
+            .queues()
+            .vectors()
+            .maps()
+            .graphs();
+        #[cfg(feature = "ndarray")]
+        let pure = pure.arrays();
+        pure
+    }
+
+    /// `assert`, `*args*` and the functions for combining functions and
+    /// passing values through them, plus
+    /// `crisp-version` and `feature?` for checking the build being run on
+    /// and `weak-ref` and `deref-weak` for weak references to handles
+    pub fn core(mut self) -> Self {
+        self.builtin(
+            "core",
+            "assert",
+            CrispExpr::Fn(CrispFn::new(
+                |args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    match args {
+                        [CrispExpr::Primitive(Primitive::Bool(true))] => Ok(args[0].clone()),
+                        [CrispExpr::Primitive(Primitive::Bool(false))] => {
+                            Err(CrispError::EvalError("assertion failed".to_string()))
+                        }
+                        _ => Err(CrispError::EvalError(
+                            "assert takes exactly one boolean argument".to_string(),
+                        )),
+                    }
+                },
+            )),
+        );
+
+        self.symbols
+            .insert("*args*".to_string(), CrispExpr::List(vec![]));
+
+        self.builtin(
+            "core",
+            "identity",
+            CrispExpr::Fn(CrispFn::new(|args: &[CrispExpr]| -> CrispResult {
+                match args {
+                    [x] => Ok(x.clone()),
+                    _ => Err(CrispError::EvalError(
+                        "identity takes exactly one argument".to_string(),
+                    )),
+                }
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "const",
+            CrispExpr::Fn(CrispFn::new(|args: &[CrispExpr]| -> CrispResult {
+                match args {
+                    [x] => {
+                        let x = x.clone();
+                        Ok(CrispExpr::Fn(CrispFn::new(move |_: &[CrispExpr]| {
+                            Ok(x.clone())
+                        })))
+                    }
+                    _ => Err(CrispError::EvalError(
+                        "const takes exactly one argument".to_string(),
+                    )),
+                }
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "crisp-version",
+            CrispExpr::Fn(CrispFn::new(|args: &[CrispExpr]| -> CrispResult {
+                match args {
+                    [] => Ok(CrispExpr::Primitive(Primitive::Str(
+                        crate::VERSION.to_string(),
+                    ))),
+                    _ => Err(CrispError::EvalError(
+                        "crisp-version takes no arguments".to_string(),
+                    )),
+                }
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "feature?",
+            CrispExpr::Fn(CrispFn::new(|args: &[CrispExpr]| -> CrispResult {
+                match args {
+                    [CrispExpr::Symbol(name) | CrispExpr::Primitive(Primitive::Str(name))] => {
+                        let name = name.trim_start_matches(':');
+                        Ok(CrispExpr::Primitive(Primitive::Bool(
+                            crate::FEATURES.contains(&name),
+                        )))
+                    }
+                    _ => Err(CrispError::EvalError(
+                        "feature? takes the name of a feature, like :jit".to_string(),
+                    )),
+                }
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "flip",
+            CrispExpr::Fn(CrispFn::new(|args: &[CrispExpr]| -> CrispResult {
+                let [f] = args else {
+                    return Err(CrispError::EvalError(
+                        "flip takes exactly one function".to_string(),
+                    ));
+                };
+
+                let f = f.clone();
+                Ok(CrispExpr::Fn(CrispFn::with_env(
+                    move |args: &[CrispExpr], env: &CrispEnv| match args {
+                        [a, b] => apply(f.clone(), vec![b.clone(), a.clone()], env),
+                        _ => Err(CrispError::EvalError(
+                            "a flipped function takes exactly two arguments".to_string(),
+                        )),
+                    },
+                )))
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "comp",
+            CrispExpr::Fn(CrispFn::new(|fs: &[CrispExpr]| -> CrispResult {
+                let fs = fs.to_vec();
+                Ok(CrispExpr::Fn(CrispFn::with_env(
+                    move |args: &[CrispExpr], env: &CrispEnv| {
+                        let [x] = args else {
+                            return Err(CrispError::EvalError(
+                                "a composed function takes exactly one argument".to_string(),
+                            ));
+                        };
+
+                        fs.iter()
+                            .rev()
+                            .try_fold(x.clone(), |value, f| apply(f.clone(), vec![value], env))
+                    },
+                )))
+            })),
+        );
+
+        self.builtin(
+            "core",
+            "pipe",
+            CrispExpr::Fn(CrispFn::with_env(|args: &[CrispExpr], env: &CrispEnv| {
+                let Some((x, fs)) = args.split_first() else {
+                    return Err(CrispError::EvalError(
+                        "pipe takes a value and the functions to pass it through".to_string(),
+                    ));
+                };
+
+                fs.iter()
+                    .try_fold(x.clone(), |value, f| apply(f.clone(), vec![value], env))
+            })),
+        );
+
+        for (name, f) in crate::weak::builtins() {
+            self.builtin("core", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// Arithmetic and comparison of numbers
+    pub fn math(mut self) -> Self {
+        for (name, f) in math::builtins() {
+            self.builtin("math", name, CrispExpr::Fn(f.clone()));
+        }
+        // Plain `log` is the logging form, so the natural log only goes by
+        // that name in its namespace
+        let ln = self.symbols["math/ln"].clone();
+        self.symbols.insert("math/log".to_string(), ln);
+
+        self
+    }
+
+    /// Building and inspecting strings
+    pub fn strings(mut self) -> Self {
+        self.builtin(
+            "str",
+            "str",
+            CrispExpr::Fn(CrispFn::new(
+                |args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    Ok(CrispExpr::Primitive(Primitive::Str(
+                        args.iter().map(to_display).collect(),
+                    )))
+                },
+            )),
+        );
+        for (name, f) in crate::string_builder::builtins()
+            .into_iter()
+            .chain(crate::template::builtins())
+        {
+            self.builtin("str", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// `list`, `cons`, `car`, `cdr`, their aliases `first` and `rest`,
+    /// `empty?`, `length`, `nth`, `range`, `append`, `reverse`, `map`,
+    /// `filter`, `reduce`, `count-if`, `every?`, `some`, `max-by` and
+    /// `min-by`
+    pub fn lists(mut self) -> Self {
+        for (name, f) in crate::list::builtins() {
+            self.builtin("list", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// `stack`, `queue`, `pqueue` and the builtins for adding and taking
+    /// their items
+    pub fn queues(mut self) -> Self {
+        for (name, f) in crate::queue::builtins() {
+            self.builtin("queue", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// `vec`, `vector-ref`, `vector-set` and `vector-len`
+    pub fn vectors(mut self) -> Self {
+        for (name, f) in crate::vector::builtins() {
+            self.builtin("vector", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// `hash-map`, `get`, `assoc`, `dissoc`, `keys`, `vals`, `contains?` and
+    /// `map->list`
+    pub fn maps(mut self) -> Self {
+        for (name, f) in crate::map::builtins() {
+            self.builtin("map", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// `graph`, `add-edge`, `neighbors`, `bfs` and `topo-sort`, for directed
+    /// graphs kept as adjacency maps
+    pub fn graphs(mut self) -> Self {
+        for (name, f) in crate::graph::builtins() {
+            self.builtin("graph", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// Only add builtins under their namespaced names, like `math/+`, so
+    /// scripts have to import them with `use`
+    pub fn namespaced(mut self) -> Self {
+        self.namespaced = true;
+        self
+    }
+
+    /// `array`, `dot`, `transpose`, `slice`, `shape` and `to-list`. The math
+    /// builtins also work elementwise on arrays when this is enabled.
+    #[cfg(feature = "ndarray")]
+    pub fn arrays(mut self) -> Self {
+        for (name, f) in crate::array::builtins() {
+            self.builtin("array", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    /// Add a builtin as `ns/name`, and as plain `name` unless the builder is
+    /// namespaced. Groups of builtins from other crates are added with this.
+    pub fn builtin(&mut self, ns: &str, name: &str, value: CrispExpr) {
+        if !self.namespaced {
+            self.symbols.insert(name.to_string(), value.clone());
+        }
+        self.symbols.insert(format!("{ns}/{name}"), value);
+    }
+
+    /// Add a native function
+    pub fn register(
+        self,
+        name: &str,
+        f: impl Fn(&[CrispExpr]) -> CrispResult + Send + Sync + 'static,
+    ) -> Self {
+        self.define(name, CrispExpr::Fn(CrispFn::new(f)))
+    }
+
+    /// Bind any value to a name
+    pub fn define(mut self, name: &str, value: CrispExpr) -> Self {
+        self.symbols.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Let `load` read files with `loader`
+    pub fn loader(mut self, loader: impl Loader + 'static) -> Self {
+        self.loader = Some(Arc::new(loader));
+        self
+    }
+
+    pub fn build<'a>(self) -> CrispEnv<'a> {
+        CrispEnv {
+            builtins: Arc::new(self.symbols.keys().cloned().collect()),
+            closure: None,
+            shared: OnceLock::new(),
+            symbols: self.symbols,
+            parent: None,
+            options: self.options,
+            depth: 0,
+            steps: Arc::default(),
+            definitions: HashMap::new(),
+            docs: HashMap::new(),
+            form_span: None,
+            deprecated: HashMap::new(),
+            diagnostics: Arc::default(),
+            pending_globals: Arc::default(),
+            shadowed: HashMap::new(),
+            macros: HashMap::new(),
+            loader: self.loader,
+            loaded: vec![],
+            reloading: None,
+        }
+    }
+}
+
+impl<'a> Default for CrispEnv<'a> {
+    fn default() -> Self {
+        CrispEnvBuilder::new().pure().build()
+    }
+}
+
+/// Charge `steps` to the env, failing if that's over the step limit or
+/// evaluation has been interrupted or run past its deadline
+pub(crate) fn check_limits(env: &CrispEnv, steps: usize) -> Result<(), CrispError> {
+    env.charge(steps)?;
+    if let Some(interrupt) = &env.options.interrupt {
+        if interrupt.load(Ordering::Relaxed) {
+            return Err(CrispError::Interrupted);
+        }
+    }
+    if env
+        .options
+        .deadline
+        .is_some_and(|deadline| env.options.now() >= deadline)
+    {
+        return Err(CrispError::TimedOut);
+    }
+
+    Ok(())
+}
+
+pub fn eval(expr: &CrispExpr, env: &mut CrispEnv) -> Result<CrispExpr, CrispError> {
+    check_limits(env, 1)?;
+
+    match expr {
+        CrispExpr::List(list) => {
+            // An empty list has nothing to call, so it gives nothing
+            let Some((first, rest)) = list.split_first() else {
+                return Ok(CrispExpr::Primitive(Primitive::Nil));
+            };
+
+            let res = eval_list(expr, first, rest, env);
+            if res.is_err() {
+                env.trace_error(expr);
+            }
+            res
+        }
+        CrispExpr::Symbol(name) if is_keyword(name) => Ok(expr.clone()),
+        CrispExpr::Symbol(name) => {
+            let (scope, scopes) = env.find(name);
+            env.charge(scopes)?;
+
+            let scope = match scope {
+                Some(Binding::Env(scope)) => scope,
+                Some(Binding::Closure(value)) => return Ok(value),
+                None => {
+                    return env.pending_global(name).ok_or_else(|| {
+                        env.trace_error(expr);
+                        CrispError::EvalError(format!("Unknown symbol: {name}"))
+                    })
+                }
+            };
+            if let Some(instead) = scope.deprecated.get(name) {
+                env.warn_deprecated(name, instead);
+            }
+
+            Ok(scope.symbols[name].clone())
+        }
+        // Function values, e.g. spliced into data run with `eval`, evaluate
+        // to themselves
+        CrispExpr::Vector(items) => Ok(CrispExpr::Vector(eval_args(items, env)?)),
+        // Maps are only made by `hash-map`, so their keys and values have
+        // already been evaluated
+        CrispExpr::Primitive(_)
+        | CrispExpr::Map(_)
+        | CrispExpr::Fn(_)
+        | CrispExpr::Lambda(_)
+        | CrispExpr::Handle(_) => Ok(expr.clone()),
+        #[cfg(feature = "ndarray")]
+        CrispExpr::Array(_) => Ok(expr.clone()),
+    }
+}
+
+/// Evaluate a call, or a special form, macro or builtin
+fn eval_list(
+    expr: &CrispExpr,
+    first: &CrispExpr,
+    rest: &[CrispExpr],
+    env: &mut CrispEnv,
+) -> CrispResult {
+    match eval_built_in(first, rest, env) {
+        Some(res) => res,
+        None => {
+            if let Some(expansion) = macros::expand_once(expr, env)? {
+                let expansion = macros::expand(&expansion, env)?;
+                return eval(&expansion, env).inspect_err(|_| {
+                    if let Ok(mut diagnostics) = env.diagnostics.lock() {
+                        source_map::map_expansion(&mut diagnostics.trace, &expansion, rest);
+                    }
+                });
+            }
+
+            let first_form = eval(first, env)?;
+            let eval_args = eval_args(rest, env)?;
+
+            if let Some(tracer) = &env.options.trace {
+                trace_call(tracer.as_ref(), first, &eval_args, env.depth);
+            }
+
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!(
+                "call",
+                name = %match first {
+                    CrispExpr::Symbol(name) => name.as_str(),
+                    _ => "<anonymous>",
+                },
+                args = eval_args.len()
+            )
+            .entered();
+
+            let res = apply(first_form, eval_args, env);
+
+            if let Some(tracer) = &env.options.trace {
+                trace_result(tracer.as_ref(), &res, env.depth);
+            }
+
+            res
+        }
+    }
+}
+
+/// Call a function value with already evaluated arguments. Calls the
+/// lambda's body makes in tail position are made in a loop here rather than
+/// by recursing, so tail-recursive loops run in constant stack space.
+pub fn apply(func: CrispExpr, eval_args: Vec<CrispExpr>, env: &CrispEnv) -> CrispResult {
+    let mut lambda = match func {
+        CrispExpr::Fn(f) => return call_builtin(&f, &eval_args, env),
+        CrispExpr::Lambda(lambda) => lambda,
+        _ => return Err(not_a_function()),
+    };
+    let mut eval_args = eval_args;
+
+    if let Some(max) = env.options.max_depth {
+        if env.depth + 1 > max {
+            return Err(CrispError::EvalError(format!(
+                "maximum call depth of {max} exceeded"
+            )));
+        }
+    }
+
+    loop {
+        #[cfg(feature = "jit")]
+        if let Some(res) = crate::jit::call(&lambda, &eval_args, env.globals()) {
+            return res;
+        }
+
+        // A tail call replaces the caller's frame with a fresh one, at the
+        // same depth
+        let mut lambda_env = CrispEnv::call_frame(env, &lambda);
+        lambda_env.symbols.extend(bind_args(&lambda, eval_args)?);
+
+        let (func, args) = match eval_tail(&lambda.body, &mut lambda_env)? {
+            Tail::Value(value) => return Ok(value),
+            Tail::Call(func, args) => (func, args),
+        };
+        lambda = match func {
+            CrispExpr::Fn(f) => return call_builtin(&f, &args, &lambda_env),
+            CrispExpr::Lambda(lambda) => lambda,
+            _ => return Err(not_a_function()),
+        };
+        eval_args = args;
+    }
+}
+
+/// Call a builtin, dividing by zero the way the env's options ask for
+fn call_builtin(f: &CrispFn, args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    match (f.0(args, env), env.options.division_by_zero) {
+        (Err(CrispError::DivisionByZero), DivisionByZero::Nil) => {
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        }
+        (Err(CrispError::DivisionByZero), DivisionByZero::Infinity)
+            if math::builtin("/") == Some(f) =>
+        {
+            math::float_quotient(args)
+                .map(|q| CrispExpr::Primitive(Primitive::Number(q)))
+                .ok_or(CrispError::DivisionByZero)
+        }
+        (res, _) => res,
+    }
+}
+
+/// Pair a lambda's params with the args of a call to it: the positional
+/// params with the first args, then the keyword params with the values
+/// named by the `:keyword value` pairs after them
+fn bind_args(
+    lambda: &CrispLambda,
+    mut args: Vec<CrispExpr>,
+) -> Result<Vec<(String, CrispExpr)>, CrispError> {
+    let named = match args.len().checked_sub(lambda.params.len()) {
+        Some(0) => vec![],
+        Some(_) if !lambda.keywords.is_empty() => args.split_off(lambda.params.len()),
+        _ => {
+            return Err(CrispError::EvalError(
+                "Wrong number of arguments were supplied".to_string(),
+            ))
+        }
+    };
+
+    let mut bindings: Vec<_> = lambda.params.iter().cloned().zip(args).collect();
+    let mut keywords: Vec<Option<CrispExpr>> = vec![None; lambda.keywords.len()];
+    let mut named = named.into_iter();
+    while let Some(key) = named.next() {
+        let name = match &key {
+            CrispExpr::Symbol(key) if is_keyword(key) => &key[1..],
+            key => {
+                return Err(CrispError::EvalError(format!(
+                    "expected a keyword like :{} but got {}",
+                    lambda.keywords[0],
+                    to_source(key)
+                )))
+            }
+        };
+        let Some(i) = lambda.keywords.iter().position(|keyword| keyword == name) else {
+            let expected: Vec<String> = lambda.keywords.iter().map(|k| format!(":{k}")).collect();
+            return Err(CrispError::EvalError(format!(
+                "unknown keyword argument :{name}, expected one of {}",
+                expected.join(", ")
+            )));
+        };
+        let Some(value) = named.next() else {
+            return Err(CrispError::EvalError(format!(
+                "keyword argument :{name} has no value"
+            )));
+        };
+        if keywords[i].replace(value).is_some() {
+            return Err(CrispError::EvalError(format!(
+                "keyword argument :{name} was given twice"
+            )));
+        }
+    }
+
+    bindings.extend(
+        lambda.keywords.iter().cloned().zip(
+            keywords
+                .into_iter()
+                .map(|value| value.unwrap_or(CrispExpr::Primitive(Primitive::Nil))),
+        ),
+    );
+    Ok(bindings)
+}
+
+fn not_a_function() -> CrispError {
+    CrispError::EvalError("First form must be a function".to_string())
+}
+
+/// The result of evaluating an expression in tail position
+enum Tail {
+    Value(CrispExpr),
+    /// A call left for `apply` to make, with its function and arguments
+    /// already evaluated
+    Call(CrispExpr, Vec<CrispExpr>),
+}
+
+/// Special forms whose last expression is in tail position
+const TAIL_FORMS: &[&str] = &[
+    "and", "begin", "case", "if", "let", "let*", "or", "unless", "when",
+];
+
+/// What's left of a special form once everything before its tail position
+/// has been evaluated
+enum Rest<'e> {
+    Expr(&'e CrispExpr),
+    /// The form's value, decided without reaching its tail position
+    Value(CrispExpr),
+}
+
+/// Nothing left to evaluate means the form is nil
+impl<'e> From<Option<&'e CrispExpr>> for Rest<'e> {
+    fn from(expr: Option<&'e CrispExpr>) -> Self {
+        match expr {
+            Some(expr) => Rest::Expr(expr),
+            None => Rest::Value(CrispExpr::Primitive(Primitive::Nil)),
+        }
+    }
+}
+
+/// Evaluate `expr` like `eval`, except that a call in tail position is
+/// returned instead of made. The branches of `if`, the last expression of
+/// `begin`, `when`, `unless`, a `case` clause, `let` and `let*`, and the last
+/// value of `and` and `or` are in tail position too. Calls aren't deferred
+/// while tracing, so every call is still shown with its result.
+fn eval_tail(expr: &CrispExpr, env: &mut CrispEnv) -> Result<Tail, CrispError> {
+    let CrispExpr::List(list) = expr else {
+        return eval(expr, env).map(Tail::Value);
+    };
+
+    let tail = match list.split_first() {
+        Some((CrispExpr::Symbol(name), args)) if name == "let" || name == "let*" => {
+            check_limits(env, 1)?;
+            match let_scope(args, name == "let*", env) {
+                Ok((mut scope, body)) => match begin_last(body, &mut scope).map(Rest::from) {
+                    Ok(Rest::Expr(last)) => return eval_tail(last, &mut scope),
+                    Ok(Rest::Value(value)) => Ok(Tail::Value(value)),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            }
+        }
+        Some((CrispExpr::Symbol(name), args)) if TAIL_FORMS.contains(&name.as_str()) => {
+            check_limits(env, 1)?;
+            let rest = match name.as_str() {
+                "if" => if_branch(args, env).map(Rest::from),
+                "and" | "or" => and_or_rest(name, args, env),
+                "when" | "unless" => when_body(args, name == "when", env)
+                    .and_then(|body| begin_last(body, env))
+                    .map(Rest::from),
+                "case" => case_body(args, env)
+                    .and_then(|body| begin_last(body, env))
+                    .map(Rest::from),
+                _ => begin_last(args, env).map(Rest::from),
+            };
+            match rest {
+                Ok(Rest::Expr(expr)) => return eval_tail(expr, env),
+                Ok(Rest::Value(value)) => Ok(Tail::Value(value)),
+                Err(err) => Err(err),
+            }
+        }
+        Some((first, rest)) if env.options.trace.is_none() && is_call(first, env) => {
+            check_limits(env, 1)?;
+            eval(first, env).and_then(|func| Ok(Tail::Call(func, eval_args(rest, env)?)))
+        }
+        _ => return eval(expr, env).map(Tail::Value),
+    };
+
+    if tail.is_err() {
+        env.trace_error(expr);
+    }
+    tail
+}
+
+/// Whether a list starting with `first` is a function call, rather than a
+/// special form or macro
+fn is_call(first: &CrispExpr, env: &CrispEnv) -> bool {
+    match first {
+        CrispExpr::Symbol(name) => {
+            !SPECIAL_FORMS.contains(&name.as_str()) && env.lookup_macro(name).is_none()
+        }
+        _ => true,
+    }
+}
+
+fn trace_call(tracer: &dyn Tracer, func: &CrispExpr, args: &[CrispExpr], depth: usize) {
+    let mut call = vec![func.clone()];
+    call.extend_from_slice(args);
+
+    tracer.trace(&format!(
+        "{}-> {}",
+        "  ".repeat(depth),
+        to_source(&CrispExpr::List(call))
+    ));
+}
+
+fn trace_result(tracer: &dyn Tracer, res: &CrispResult, depth: usize) {
+    match res {
+        Ok(val) => tracer.trace(&format!("{}<- {}", "  ".repeat(depth), to_source(val))),
+        Err(err) => tracer.trace(&format!("{}<- {err}", "  ".repeat(depth))),
+    }
+}
+
+/// Names handled by the evaluator itself rather than looked up in the env
+pub const SPECIAL_FORMS: &[&str] = &[
+    "and",
+    "begin",
+    "case",
+    "def",
+    "def-global",
+    "defmacro",
+    "deprecate",
+    "eval",
+    "fn",
+    "if",
+    "let",
+    "let*",
+    "load",
+    "log",
+    "or",
+    "or-else",
+    "quote",
+    "retry",
+    "source-of",
+    "undef",
+    "unless",
+    "unshadow",
+    "use",
+    "when",
+    "with-open",
+    "with-timeout",
+];
+
+/// Evaluate a built-in expression
+fn eval_built_in(expr: &CrispExpr, args: &[CrispExpr], env: &mut CrispEnv) -> Option<CrispResult> {
+    match expr {
+        CrispExpr::Symbol(name) => match name.as_ref() {
+            "and" | "or" => Some(eval_and_or(name, args, env)),
+            "begin" => Some(eval_begin(args, env)),
+            "case" => Some(eval_case(args, env)),
+            "def" => Some(eval_def(args, env)),
+            "def-global" => Some(eval_def_global(args, env)),
+            "defmacro" => Some(eval_defmacro(args, env)),
+            "deprecate" => Some(eval_deprecate(args, env)),
+            "eval" => Some(eval_eval(args, env)),
+            "fn" => Some(eval_lambda(args, env)),
+            "if" => Some(eval_if(args, env)),
+            "let" => Some(eval_let(args, false, env)),
+            "let*" => Some(eval_let(args, true, env)),
+            "load" => Some(eval_load(args, env)),
+            "log" => Some(eval_log(args, env)),
+            "quote" => args.first().map(|list| Ok(list.clone())),
+            "source-of" => Some(eval_source_of(args, env)),
+            "unless" => Some(eval_when(args, false, env)),
+            "undef" => Some(eval_undef(args, env)),
+            "unshadow" => Some(eval_unshadow(args, env)),
+            "use" => Some(eval_use(args, env)),
+            "or-else" => Some(eval_or_else(args, env)),
+            "retry" => Some(eval_retry(args, env)),
+            "with-open" => Some(eval_with_open(args, env)),
+            "with-timeout" => Some(eval_with_timeout(args, env)),
+            "when" => Some(eval_when(args, true, env)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether a symbol is a keyword like `:info`, which evaluates to itself
+pub fn is_keyword(name: &str) -> bool {
+    name.len() > 1 && name.starts_with(':')
+}
+
+/// Evaluate a load expression, evaluating the forms of another file in the
+/// global env and returning its path. A relative path is resolved from the
+/// file doing the loading. Each file is only loaded once, so loading it
+/// again, including from a file it loads, does nothing.
+pub fn eval_load(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let name = match eval_args(args, env)?.as_slice() {
+        [CrispExpr::Primitive(Primitive::Str(name)) | CrispExpr::Symbol(name)] => name.clone(),
+        _ => {
+            return Err(CrispError::EvalError(
+                "load takes the path of a file".to_string(),
+            ))
+        }
+    };
+    if env.depth > 0 {
+        return Err(CrispError::EvalError(
+            "load can only be used at the top level, not in a function".to_string(),
+        ));
+    }
+    let loader = env.loader.clone().ok_or(CrispError::EvalError(format!(
+        "can't load {name}: this interpreter can't read files"
+    )))?;
+
+    let script = match env.lookup("*script*") {
+        Some(CrispExpr::Primitive(Primitive::Str(script))) => Some(script.clone()),
+        _ => None,
+    };
+    let (path, src) = loader.load(&name, script.as_deref())?;
+    if env.loaded.contains(&path) {
+        return Ok(CrispExpr::Primitive(Primitive::Str(path)));
+    }
+    env.loaded.push(path.clone());
+
+    if let Err(err) = eval_loaded(&path, &src, env) {
+        // Let a fixed file be loaded again
+        env.loaded.retain(|loaded| *loaded != path);
+        return Err(err);
+    }
+    Ok(CrispExpr::Primitive(Primitive::Str(path)))
+}
+
+/// Evaluate the source of the file at `path` in the global env
+fn eval_loaded(path: &str, src: &str, env: &mut CrispEnv) -> Result<(), CrispError> {
+    let script = match env.lookup("*script*") {
+        Some(CrispExpr::Primitive(Primitive::Str(script))) => Some(script.clone()),
+        _ => None,
+    };
+
+    // The file's definitions are located in the file, and its own loads
+    // resolved from it
+    let form_span = env.form_span.take();
+    env.set_script(path);
+    let res = parse_source(src, &env.options.parse).and_then(|forms| match forms.is_empty() {
+        true => Ok(()),
+        false => crate::eval_forms(&forms, env).map(|_| ()),
+    });
+    match script {
+        Some(script) => env.set_script(&script),
+        None => {
+            env.symbols.remove("*script*");
+        }
+    }
+    env.form_span = form_span;
+
+    res.map_err(|err| err.in_file(path))
+}
+
+/// Evaluate a log expression, forwarding the message to the `log` crate with
+/// the script's name as the target
+pub fn eval_log(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let level = match args.first() {
+        Some(CrispExpr::Symbol(level)) => match level.as_str() {
+            ":error" => log::Level::Error,
+            ":warn" => log::Level::Warn,
+            ":info" => log::Level::Info,
+            ":debug" => log::Level::Debug,
+            ":trace" => log::Level::Trace,
+            _ => {
+                return Err(CrispError::EvalError(format!(
+                    "unknown log level '{level}', expected one of :error, :warn, :info, :debug or :trace"
+                )))
+            }
+        },
+        _ => {
+            return Err(CrispError::EvalError(
+                "log expects a level like :info; the natural log is ln or math/log".to_string(),
+            ))
+        }
+    };
+
+    let args = eval_args(&args[1..], env)?;
+
+    let target = match env.lookup("*script*") {
+        Some(CrispExpr::Primitive(Primitive::Str(script))) => script.as_str(),
+        _ => "crisp",
+    };
+    log::log!(
+        target: target,
+        level,
+        "{}",
+        args.iter().map(to_display).collect::<Vec<String>>().join(" ")
+    );
+
+    Ok(args
+        .last()
+        .cloned()
+        .unwrap_or(CrispExpr::Primitive(Primitive::Nil)))
+}
+
+/// Evaluate each expression in order, returning the last one's value, or
+/// nil if there are none
+pub fn eval_begin(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    match begin_last(args, env)? {
+        Some(expr) => eval(expr, env),
+        None => Ok(CrispExpr::Primitive(Primitive::Nil)),
+    }
+}
+
+/// Evaluate all but the last expression of a begin, returning the last one
+fn begin_last<'e>(
+    args: &'e [CrispExpr],
+    env: &mut CrispEnv,
+) -> Result<Option<&'e CrispExpr>, CrispError> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(None);
+    };
+    for expr in init {
+        eval(expr, env)?;
+    }
+
+    Ok(Some(last))
+}
+
+/// Evaluate an and or or expression, stopping at the first value that
+/// decides the result: `and` returns the first value that isn't truthy and
+/// `or` the first that is, otherwise the last value. Without any values,
+/// `and` is true and `or` is nil.
+pub fn eval_and_or(name: &str, args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    match and_or_rest(name, args, env)? {
+        Rest::Expr(last) => eval(last, env),
+        Rest::Value(value) => Ok(value),
+    }
+}
+
+/// Evaluate all but the last value of an and or or expression, returning
+/// the last one unless an earlier one decides the result
+fn and_or_rest<'e>(
+    name: &str,
+    args: &'e [CrispExpr],
+    env: &mut CrispEnv,
+) -> Result<Rest<'e>, CrispError> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Rest::Value(CrispExpr::Primitive(match name {
+            "and" => Primitive::Bool(true),
+            _ => Primitive::Nil,
+        })));
+    };
+    for arg in init {
+        let value = eval(arg, env)?;
+        if value.is_truthy() != (name == "and") {
+            return Ok(Rest::Value(value));
+        }
+    }
+
+    Ok(Rest::Expr(last))
+}
+
+/// Evaluate an if expression. Without an else branch, a false or nil test
+/// gives nil.
+pub fn eval_if(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    match if_branch(args, env)? {
+        Some(expr) => eval(expr, env),
+        None => Ok(CrispExpr::Primitive(Primitive::Nil)),
+    }
+}
+
+/// Evaluate an if expression's test, returning the branch it picks
+fn if_branch<'e>(
+    args: &'e [CrispExpr],
+    env: &mut CrispEnv,
+) -> Result<Option<&'e CrispExpr>, CrispError> {
+    let (test_form, branches) = match args {
+        [test, branches @ ..] if (1..=2).contains(&branches.len()) => (test, branches),
+        _ => {
+            return Err(CrispError::EvalError(
+                "if takes a test, a then branch and an optional else branch".to_string(),
+            ))
+        }
+    };
+
+    let test_res = eval_test(test_form, env)?;
+
+    Ok(if test_res {
+        branches.first()
+    } else {
+        branches.get(1)
+    })
+}
+
+/// Evaluate a case expression: the body of the first clause listing a value
+/// equal to the key runs in an implicit begin. Clause values are literals,
+/// either a single value or a list of them, and aren't evaluated. An `else`
+/// clause matches anything; without a match the result is nil.
+pub fn eval_case(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let body = case_body(args, env)?;
+    eval_begin(body, env)
+}
+
+/// Evaluate a case expression's key, returning the body of the clause it
+/// matches, which is empty if none does
+fn case_body<'e>(args: &'e [CrispExpr], env: &mut CrispEnv) -> Result<&'e [CrispExpr], CrispError> {
+    let (key_form, clauses) = args
+        .split_first()
+        .ok_or(CrispError::EvalError("Expected an expression".to_string()))?;
+
+    let key = eval(key_form, env)?;
+
+    for clause in clauses {
+        let (values, body) = match clause {
+            CrispExpr::List(clause) if !clause.is_empty() => (&clause[0], &clause[1..]),
+            _ => {
+                return Err(CrispError::EvalError(
+                    "case clauses must be lists like (value body ...)".to_string(),
+                ))
+            }
+        };
+
+        let matches = match values {
+            CrispExpr::Symbol(name) if name == "else" => true,
+            CrispExpr::List(values) => values.contains(&key),
+            value => *value == key,
+        };
+
+        if matches {
+            return Ok(body);
+        }
+    }
+
+    Ok(&[])
+}
+
+fn eval_args(args: &[CrispExpr], env: &mut CrispEnv) -> Result<Vec<CrispExpr>, CrispError> {
+    args.iter().map(|arg| eval(arg, env)).collect()
+}
+
+/// Evaluate a when or unless expression: the body runs in an implicit begin
+/// if the test is `expected`, otherwise the result is nil
+pub fn eval_when(args: &[CrispExpr], expected: bool, env: &mut CrispEnv) -> CrispResult {
+    let body = when_body(args, expected, env)?;
+    eval_begin(body, env)
+}
+
+/// Evaluate a when or unless expression's test, returning the body to run,
+/// which is empty if the test isn't `expected`
+fn when_body<'e>(
+    args: &'e [CrispExpr],
+    expected: bool,
+    env: &mut CrispEnv,
+) -> Result<&'e [CrispExpr], CrispError> {
+    let (test_form, body) = args
+        .split_first()
+        .ok_or(CrispError::EvalError("Expected an expression".to_string()))?;
+
+    Ok(match eval_test(test_form, env)? == expected {
+        true => body,
+        false => &[],
+    })
+}
+
+/// Evaluate a conditional's test, which passes unless it's false or nil
+fn eval_test(test_form: &CrispExpr, env: &mut CrispEnv) -> Result<bool, CrispError> {
+    Ok(eval(test_form, env)?.is_truthy())
+}
+
+/// Evaluate a binding definition. Inside a function call the binding is
+/// local to the call, so it can shadow globals but not the call's params.
+pub fn eval_def(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let (name, value_form) = def_parts("def", args)?;
+    // In a fork, the builtin is bound in the env it was forked from
+    let shadows_builtin = env.builtins.contains(name) && env.lookup(name).is_some();
+    let rebindable = env.reloads(name) || (shadows_builtin && env.options.redefine_builtins);
+    if (env.symbols.contains_key(name) || shadows_builtin) && !rebindable {
+        return Err(CrispError::EvalError(format!(
+            "Variable with name '{name}' already exists"
+        )));
+    }
+
+    let val = eval(value_form, env)?;
+    let location = def_location(env);
+
+    let previous = env.symbols.insert(name.clone(), val);
+    if shadows_builtin {
+        let original =
+            previous.or_else(|| env.parent.and_then(|parent| parent.lookup(name).cloned()));
+        if let Some(original) = original.filter(|_| !env.shadowed.contains_key(name)) {
+            env.shadowed.insert(name.clone(), original);
+        }
+        env.warn(format!(
+            "'{name}' shadows a builtin, (unshadow (quote {name})) restores it"
+        ));
+    }
+    if let Some(location) = location {
+        env.definitions.insert(name.clone(), location);
+    }
+    env.reshare(name);
+
+    Ok(CrispExpr::Symbol(name.clone()))
+}
+
+/// Evaluate a macro definition, `(defmacro name (params ...) body ...)`.
+/// The body is the template calls to the macro expand to.
+pub fn eval_defmacro(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let (name, rest) = match args {
+        [CrispExpr::Symbol(name), rest @ ..] if rest.len() >= 2 => (name, rest),
+        _ => {
+            return Err(CrispError::EvalError(
+                "defmacro takes a name, a param list and a body".to_string(),
+            ))
+        }
+    };
+    if SPECIAL_FORMS.contains(&name.as_str()) {
+        return Err(CrispError::EvalError(format!(
+            "'{name}' is a special form and can't be redefined as a macro"
+        )));
+    }
+
+    let mac = make_lambda(rest)?;
+    if !mac.keywords.is_empty() {
+        return Err(CrispError::EvalError(
+            "macros can't take keyword params".to_string(),
+        ));
+    }
+    env.macros.insert(name.clone(), mac);
+    Ok(CrispExpr::Symbol(name.clone()))
+}
+
+/// Evaluate a global binding definition, which binds the name in the global
+/// env even inside a function call
+pub fn eval_def_global(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    if env.parent.is_none() {
+        return eval_def(args, env);
+    }
+
+    let (name, value_form) = def_parts("def-global", args)?;
+    let mut global = &*env;
+    while let Some(parent) = global.parent {
+        global = parent;
+    }
+    if global.symbols.contains_key(name) || env.pending_global(name).is_some() {
+        return Err(CrispError::EvalError(format!(
+            "Variable with name '{name}' already exists"
+        )));
+    }
+
+    let val = eval(value_form, env)?;
+    let location = def_location(env);
+
+    env.pending_globals
+        .lock()
+        .map_err(|_| CrispError::EvalError("pending globals are poisoned".to_string()))?
+        .insert(name.clone(), (val, location));
+
+    Ok(CrispExpr::Symbol(name.clone()))
+}
+
+/// The name and value expression of a def
+fn def_parts<'e>(
+    form: &str,
+    args: &'e [CrispExpr],
+) -> Result<(&'e String, &'e CrispExpr), CrispError> {
+    match args {
+        [CrispExpr::Symbol(name), value] => Ok((name, value)),
+        [_, _] => Err(CrispError::EvalError(
+            "First argument must be a symbol".to_string(),
+        )),
+        [] => Err(CrispError::EvalError("Expected a name".to_string())),
+        [_] => Err(CrispError::EvalError("Expected a value".to_string())),
+        _ => Err(CrispError::EvalError(format!(
+            "{form} takes exactly two arguments"
+        ))),
+    }
+}
+
+/// Where a definition being evaluated is, if it's in a top-level form
+fn def_location(env: &CrispEnv) -> Option<Location> {
+    let span = env.form_span?;
+    let file = match env.lookup("*script*") {
+        Some(CrispExpr::Primitive(Primitive::Str(script))) => Some(script.clone()),
+        _ => None,
+    };
+
+    Some(Location { file, span })
+}
+
+/// Evaluate a deprecate expression, marking a binding in the current scope
+/// as deprecated, optionally in favour of another name. Evaluating the
+/// binding then raises a warning, once.
+pub fn eval_deprecate(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let (name, instead) = match args {
+        [CrispExpr::Symbol(name)] => (name, None),
+        [CrispExpr::Symbol(name), CrispExpr::Symbol(instead)] => (name, Some(instead.clone())),
+        _ => {
+            return Err(CrispError::EvalError(
+                "deprecate takes a name and optionally the name to use instead".to_string(),
+            ))
+        }
+    };
+
+    if !env.symbols.contains_key(name) {
+        return Err(CrispError::EvalError(format!(
+            "can only deprecate '{name}' in the scope that defines it"
+        )));
+    }
+
+    env.deprecated.insert(name.clone(), instead);
+    Ok(CrispExpr::Symbol(name.clone()))
+}
+
+/// Evaluate a with-timeout expression, returning the value of its expression
+/// or `:timeout` if it takes longer than the given number of milliseconds
+pub fn eval_with_timeout(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let [ms, expr] = args else {
+        return Err(CrispError::EvalError(
+            "with-timeout takes a time in milliseconds and an expression".to_string(),
+        ));
+    };
+    let ms = match eval(ms, env)?.as_f32() {
+        Some(ms) if ms >= 0. => ms,
+        _ => {
+            return Err(CrispError::EvalError(
+                "with-timeout expects a time in milliseconds".to_string(),
+            ))
+        }
+    };
+
+    let outer = env.options.deadline;
+    // Times too long to represent are as good as no deadline at all
+    let deadline = Duration::try_from_secs_f32(ms / 1000.)
+        .ok()
+        .and_then(|timeout| env.options.now().checked_add(timeout));
+    env.options.deadline = match (outer, deadline) {
+        (Some(outer), Some(deadline)) => Some(outer.min(deadline)),
+        (outer, deadline) => outer.or(deadline),
+    };
+    let res = eval(expr, env);
+    env.options.deadline = outer;
+
+    match res {
+        // Leave timeouts of enclosing with-timeouts to them
+        Err(err)
+            if *err.inner() == CrispError::TimedOut
+                && outer.is_none_or(|outer| env.options.now() < outer) =>
+        {
+            Ok(CrispExpr::Symbol(":timeout".to_string()))
+        }
+        res => res,
+    }
+}
+
+/// Evaluate an eval expression, evaluating its argument to get a form and
+/// then evaluating that form in the current scope, so a `def` in it binds
+/// there. The form counts towards the same step limit, deadline and call
+/// depth as the code calling `eval`, and one nested deeper than the env's
+/// `ParseOptions::max_depth` is rejected as source would be.
+pub fn eval_eval(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let [expr] = args else {
+        return Err(CrispError::EvalError(
+            "eval takes exactly one expression".to_string(),
+        ));
+    };
+
+    let form = eval(expr, env)?;
+    if let Some(max) = env.options.parse.max_depth {
+        check_nesting(&form, max)?;
+    }
+    eval(&form, env)
+}
+
+/// Fail if `form` has lists, vectors or maps nested more than `max` deep,
+/// without recursing, so a deep form built at runtime is turned away before
+/// evaluating it can overflow the stack
+fn check_nesting(form: &CrispExpr, max: usize) -> Result<(), CrispError> {
+    let mut pending = vec![(form, 0)];
+    while let Some((expr, depth)) = pending.pop() {
+        let items: Box<dyn Iterator<Item = &CrispExpr>> = match expr {
+            CrispExpr::List(xs) | CrispExpr::Vector(xs) => Box::new(xs.iter()),
+            CrispExpr::Map(map) => {
+                Box::new(map.iter().flat_map(|(key, value)| [key.expr(), value]))
+            }
+            _ => continue,
+        };
+        if depth == max {
+            return Err(CrispError::TooLarge(format!(
+                "eval's form is nested more than {max} deep"
+            )));
+        }
+        pending.extend(items.map(|item| (item, depth + 1)));
+    }
+
+    Ok(())
+}
+
+/// Evaluate an or-else expression, returning the value of its expression or,
+/// if that fails, the value of its default
+pub fn eval_or_else(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let [expr, default] = args else {
+        return Err(CrispError::EvalError(
+            "or-else takes an expression and a default".to_string(),
+        ));
+    };
+
+    match eval(expr, env) {
+        Err(err) if is_catchable(&err, env) => {
+            env.take_trace();
+            eval(default, env)
+        }
+        res => res,
+    }
+}
+
+/// Evaluate a retry expression, evaluating its expression up to n times
+/// until it succeeds, and returning the last error if it never does
+pub fn eval_retry(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let [n, expr] = args else {
+        return Err(CrispError::EvalError(
+            "retry takes a number of attempts and an expression".to_string(),
+        ));
+    };
+    let attempts = match eval(n, env)?.as_f32() {
+        Some(n) if n >= 1. => n as usize,
+        _ => {
+            return Err(CrispError::EvalError(
+                "retry expects at least one attempt".to_string(),
+            ))
+        }
+    };
+
+    let mut res = eval(expr, env);
+    for _ in 1..attempts {
+        match res {
+            Err(err) if is_catchable(&err, env) => {
+                env.take_trace();
+                res = eval(expr, env);
+            }
+            _ => break,
+        }
+    }
+    res
+}
+
+/// Whether an error can be caught by `or-else` and `retry`. Interrupts,
+/// timeouts and running out of steps stop evaluation however deeply nested.
+fn is_catchable(err: &CrispError, env: &CrispEnv) -> bool {
+    matches!(
+        err.inner(),
+        CrispError::EvalError(_) | CrispError::DivisionByZero
+    ) && env.charge(0).is_ok()
+}
+
+/// Evaluate a with-open expression, `(with-open (name handle) body ...)`,
+/// which closes the handle once the body has been evaluated, even if that
+/// fails
+pub fn eval_with_open(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let (name, handle_form, body) = match args {
+        [CrispExpr::List(binding), body @ ..] => match binding.as_slice() {
+            [CrispExpr::Symbol(name), handle] => (name, handle, body),
+            _ => {
+                return Err(CrispError::EvalError(
+                    "with-open expects a binding like (f (open path))".to_string(),
+                ))
+            }
+        },
+        _ => {
+            return Err(CrispError::EvalError(
+                "with-open takes a binding and a body".to_string(),
+            ))
+        }
+    };
+
+    let CrispExpr::Handle(handle) = eval(handle_form, env)? else {
+        return Err(CrispError::EvalError(
+            "with-open expects a handle, like (open path)".to_string(),
+        ));
+    };
+
+    let res = {
+        let mut scope = CrispEnv::from_parent(env);
+        scope
+            .symbols
+            .insert(name.clone(), CrispExpr::Handle(handle.clone()));
+        eval_begin(body, &mut scope)
+    };
+    handle.close();
+    res
+}
+
+/// Evaluate a let expression, `(let ((name value) ...) body ...)`, which
+/// evaluates the values and runs the body in a new scope with them bound to
+/// their names. With `let*` the values are evaluated one at a time in that
+/// scope, so each can refer to the bindings before it.
+pub fn eval_let(args: &[CrispExpr], sequential: bool, env: &mut CrispEnv) -> CrispResult {
+    let (mut scope, body) = let_scope(args, sequential, env)?;
+    eval_begin(body, &mut scope)
+}
+
+/// Evaluate a let expression's bindings, returning the scope they're bound
+/// in and the body to run there
+fn let_scope<'a, 'e>(
+    args: &'e [CrispExpr],
+    sequential: bool,
+    env: &'a mut CrispEnv,
+) -> Result<(CrispEnv<'a>, &'e [CrispExpr]), CrispError> {
+    let form = if sequential { "let*" } else { "let" };
+    let Some((CrispExpr::List(bindings), body)) = args.split_first() else {
+        return Err(CrispError::EvalError(format!(
+            "{form} takes a list of bindings and a body"
+        )));
+    };
+    let bindings = bindings
+        .iter()
+        .map(|binding| match binding {
+            CrispExpr::List(binding) => match binding.as_slice() {
+                [CrispExpr::Symbol(name), value] => Ok((name, value)),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        })
+        .collect::<Result<Vec<_>, ()>>()
+        .map_err(|_| CrispError::EvalError(format!("{form} expects bindings like (x 1)")))?;
+
+    // Without let*, every value is evaluated before any name is bound
+    let values = match sequential {
+        true => vec![],
+        false => bindings
+            .iter()
+            .map(|(_, value)| eval(value, env))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    let mut scope = CrispEnv::from_parent(env);
+    for (i, (name, value)) in bindings.into_iter().enumerate() {
+        let value = match sequential {
+            true => eval(value, &mut scope)?,
+            false => values[i].clone(),
+        };
+        scope.symbols.insert(name.clone(), value);
+    }
+    Ok((scope, body))
+}
+
+/// Evaluate an undef expression, removing a binding from the current scope
+pub fn eval_undef(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let (name, force) = match args {
+        [CrispExpr::Symbol(name)] => (name, false),
+        [CrispExpr::Symbol(name), CrispExpr::Symbol(force)] if force == ":force" => (name, true),
+        _ => {
+            return Err(CrispError::EvalError(
+                "undef takes a name and optionally :force".to_string(),
+            ))
+        }
+    };
+
+    env.undef(name, force)?;
+    Ok(CrispExpr::Symbol(name.clone()))
+}
+
+/// Evaluate an unshadow expression, restoring a builtin rebound by a `def`
+pub fn eval_unshadow(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let name = match eval_args(args, env)?.as_slice() {
+        [CrispExpr::Symbol(name)] => name.clone(),
+        _ => {
+            return Err(CrispError::EvalError(
+                "unshadow takes exactly one quoted name".to_string(),
+            ))
+        }
+    };
+
+    if !env.unshadow(&name) {
+        return Err(CrispError::EvalError(format!(
+            "'{name}' doesn't shadow a builtin in this scope"
+        )));
+    }
+    Ok(CrispExpr::Symbol(name))
+}
+
+/// The warning for using a deprecated binding
+pub fn deprecation_message(name: &str, instead: &Option<String>) -> String {
+    match instead {
+        Some(instead) => format!("'{name}' is deprecated, use '{instead}' instead"),
+        None => format!("'{name}' is deprecated"),
+    }
+}
+
+/// Evaluate a use expression, binding every builtin in a namespace, like
+/// `math/+`, under its plain name, like `+`, in the current scope
+pub fn eval_use(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let ns = match args {
+        [CrispExpr::Symbol(ns)] => ns,
+        _ => {
+            return Err(CrispError::EvalError(
+                "use takes exactly one namespace".to_string(),
+            ))
+        }
+    };
+
+    let prefix = format!("{ns}/");
+    let mut imports = vec![];
+    let mut scope = Some(&*env);
+    while let Some(current) = scope {
+        for (name, value) in current.bindings() {
+            match name.strip_prefix(&prefix) {
+                // A special form of the same name would win anyway, as
+                // `log` does over `math/log`
+                Some(plain) if SPECIAL_FORMS.contains(&plain) => {}
+                Some(plain) => imports.push((plain.to_string(), value.clone())),
+                None => {}
+            }
+        }
+        scope = current.parent;
+    }
+
+    if imports.is_empty() {
+        return Err(CrispError::EvalError(format!("unknown namespace '{ns}'")));
+    }
+
+    for (name, value) in imports {
+        match env.symbols.get(&name) {
+            Some(existing) if *existing != value => {
+                return Err(CrispError::EvalError(format!(
+                    "can't use {ns}: '{name}' is already defined"
+                )))
+            }
+            _ => {
+                env.symbols.insert(name.clone(), value);
+                env.reshare(&name);
+            }
+        }
+    }
+
+    Ok(CrispExpr::Symbol(ns.clone()))
+}
+
+/// Evaluate a source-of expression: where the binding of an unevaluated
+/// symbol was defined, as a list of its file (nil if unknown), line and
+/// column, or nil if that isn't known
+pub fn eval_source_of(args: &[CrispExpr], env: &mut CrispEnv) -> CrispResult {
+    let name = match args {
+        [CrispExpr::Symbol(name)] => name,
+        _ => {
+            return Err(CrispError::EvalError(
+                "source-of takes exactly one symbol".to_string(),
+            ))
+        }
+    };
+
+    Ok(match env.location(name) {
+        Some(location) => CrispExpr::List(vec![
+            CrispExpr::Primitive(match &location.file {
+                Some(file) => Primitive::Str(file.clone()),
+                None => Primitive::Nil,
+            }),
+            CrispExpr::Primitive(Primitive::Int(location.span.line as i64)),
+            CrispExpr::Primitive(Primitive::Int(location.span.col as i64)),
+        ]),
+        None => CrispExpr::Primitive(Primitive::Nil),
+    })
+}
+
+/// Evaluate a lambda definition
+pub fn eval_lambda(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let mut lambda = make_lambda(args)?;
+    lambda.closure = env.share();
+    Ok(CrispExpr::Lambda(lambda))
+}
+
+/// Read the params and body of a lambda or macro
+fn make_lambda(args: &[CrispExpr]) -> Result<CrispLambda, CrispError> {
+    let params = args.first().ok_or(CrispError::EvalError(
+        "Expected a param expression".to_string(),
+    ))?;
+
+    let (symbol_names, keywords) = match params {
+        CrispExpr::List(xs) => {
+            let mut names = parse_param_list(xs)?;
+            let keywords = match names.iter().position(|name| name == "&key") {
+                Some(i) => names.split_off(i).split_off(1),
+                None => vec![],
+            };
+            if keywords.iter().any(|name| name == "&key") {
+                return Err(CrispError::EvalError(
+                    "&key can only appear once in a param list".to_string(),
+                ));
+            }
+            (names, keywords)
+        }
+        _ => return Err(CrispError::EvalError("Params should be a list".to_string())),
+    };
+
+    // Bodies with several expressions get an implicit begin
+    let body = match &args[1..] {
+        [body] => body.clone(),
+        body => {
+            let mut begin = vec![CrispExpr::Symbol("begin".to_string())];
+            begin.extend_from_slice(body);
+            CrispExpr::List(begin)
+        }
+    };
+
+    Ok(CrispLambda {
+        keywords,
+        ..CrispLambda::new(symbol_names, body)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_floats;
+
+    #[test]
+    fn def_in_function_is_local() {
+        let mut env = CrispEnv::default();
+        crate::run_program(
+            "(def f (fn (x) (def y (+ x 1)) (def-global z y) y))",
+            &mut env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            crate::run_program("(+ (f 1) z)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(4)))
+        );
+        assert!(env.get("y").is_none());
+        assert!(crate::run_program("(f 1)", &mut env).is_err());
+        assert!(crate::run_program("((fn (x) (def x 2)) 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn lambdas_capture_enclosing_locals() {
+        let mut env = CrispEnv::default();
+        crate::run_program(
+            "(def make-adder (fn (n) (fn (x) (+ x n)))) \
+             (def add2 (make-adder 2)) \
+             (def twice (fn (f) (fn (x) (f (f x)))))",
+            &mut env,
+        )
+        .unwrap();
+
+        for (prog, expected) in [
+            ("((make-adder 1) 2)", 3),
+            ("(add2 5)", 7),
+            ("((twice add2) 1)", 5),
+            // A caller's locals don't leak into the closure
+            ("((fn (n) (add2 0)) 100)", 2),
+        ] {
+            assert_eq!(
+                crate::run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Int(expected))),
+                "{prog}"
+            );
+        }
+    }
+
+    #[test]
+    fn lambdas_are_lexically_scoped() {
+        let mut env = CrispEnv::default();
+        crate::run_program(
+            "(def f (fn () y)) \
+             (def mk (fn () (def g (fn () (h))) (def h (fn () 42)) g)) \
+             (def evens (fn (n) \
+               (def even? (fn (n) (if (= n 0) true (odd? (- n 1))))) \
+               (def odd? (fn (n) (if (= n 0) false (even? (- n 1))))) \
+               (even? n)))",
+            &mut env,
+        )
+        .unwrap();
+
+        // A lambda doesn't see the locals of whoever calls it
+        assert!(crate::run_program("((fn (y) (f)) 5)", &mut env).is_err());
+        for (prog, expected) in [
+            // It sees the global instead
+            ("(def y 1) ((fn (y) (f)) 5)", 1),
+            // A local function sees definitions made after it in its scope
+            ("((mk))", 42),
+            ("(if (evens 10) 1 0)", 1),
+        ] {
+            assert_eq!(
+                crate::run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Int(expected))),
+                "{prog}"
+            );
+        }
+    }
+
+    #[test]
+    fn eval_quoted_list() {
+        let mut env = CrispEnv::default();
+        let expr = CrispExpr::List(vec![
+            CrispExpr::Symbol("quote".to_string()),
+            CrispExpr::List(vec![
+                CrispExpr::Symbol("+".to_string()),
+                CrispExpr::Primitive(Primitive::Int(3)),
+                CrispExpr::Primitive(Primitive::Int(4)),
+            ]),
+        ]);
+
+        assert_eq!(
+            eval(&expr, &mut env),
+            Ok(CrispExpr::List(vec![
+                CrispExpr::Symbol("+".to_string()),
+                CrispExpr::Primitive(Primitive::Int(3)),
+                CrispExpr::Primitive(Primitive::Int(4)),
+            ]),)
+        );
+    }
+
+    #[test]
+    fn eval_list() {
+        let mut env = CrispEnv::default();
+        let list = CrispExpr::List(vec![
+            CrispExpr::Symbol("+".to_string()),
+            CrispExpr::Primitive(Primitive::Int(3)),
+            CrispExpr::Primitive(Primitive::Int(4)),
+            CrispExpr::Primitive(Primitive::Int(5)),
+        ]);
+
+        assert_eq!(
+            eval(&list, &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(12)))
+        );
+    }
+
+    #[test]
+    fn eval_number() {
+        let mut env = CrispEnv::default();
+        let expr = CrispExpr::Primitive(Primitive::Int(45));
+        assert_eq!(
+            eval(&expr, &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(45)))
+        );
+    }
+
+    #[test]
+    fn eval_max_depth() {
+        let mut env = CrispEnv::with_options(EvalOptions {
+            max_depth: Some(10),
+            ..Default::default()
+        });
+        let prog = "(begin (def down (fn (n) (if (> n 0) (+ 1 (down (- n 1))) n))) (down 20))";
+
+        assert_eq!(
+            crate::run_program(prog, &mut env).map_err(|err| err.inner().clone()),
+            Err(CrispError::EvalError(
+                "maximum call depth of 10 exceeded".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn eval_evaluates_data_as_code() {
+        let run = |prog: &str, options| {
+            crate::run_program(prog, &mut CrispEnv::with_options(options))
+                .map(|value| to_source(&value))
+                .map_err(|err| err.inner().clone())
+        };
+
+        assert_eq!(
+            run("(eval '(+ 1 2))", EvalOptions::default()),
+            Ok("3".to_string())
+        );
+        assert_eq!(
+            run(
+                "(def x 2) (eval (list 'def 'y (list '* 'x 3))) y",
+                EvalOptions::default()
+            ),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            run(
+                "(def f (fn (x) (eval (list '+ 'x 1)))) (f 41)",
+                EvalOptions::default()
+            ),
+            Ok("42".to_string())
+        );
+
+        // The same limits apply to what eval evaluates
+        let deep = |max_depth| EvalOptions {
+            parse: ParseOptions {
+                max_depth: Some(max_depth),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let nest = "(def nest (fn (n) (if (> n 0) (list '+ 1 (nest (- n 1))) 0))) ";
+        assert_eq!(
+            run(&format!("{nest} (eval (nest 6))"), deep(6)),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            run(&format!("{nest} (eval (nest 7))"), deep(6)),
+            Err(CrispError::TooLarge(
+                "eval's form is nested more than 6 deep".to_string()
+            ))
+        );
+        assert_eq!(
+            run(
+                "(eval '(begin (def loop (fn (n) (+ 1 (loop n)))) (loop 0)))",
+                EvalOptions {
+                    max_depth: Some(10),
+                    ..Default::default()
+                }
+            ),
+            Err(CrispError::EvalError(
+                "maximum call depth of 10 exceeded".to_string()
+            ))
+        );
+        // Even without options, data nested too deep to evaluate is rejected
+        let wrap = "(def wrap (fn (n x) (if (> n 0) (wrap (- n 1) (list 'quote x)) x))) ";
+        assert_eq!(
+            run(
+                &format!("{wrap} (eval (wrap 1001 1))"),
+                EvalOptions::default()
+            ),
+            Err(CrispError::TooLarge(format!(
+                "eval's form is nested more than {} deep",
+                crate::parse::DEFAULT_MAX_DEPTH
+            )))
+        );
+    }
+
+    #[test]
+    fn division_by_zero_follows_the_options() {
+        let run = |prog: &str, division_by_zero| {
+            let mut env = CrispEnv::with_options(EvalOptions {
+                division_by_zero,
+                ..Default::default()
+            });
+            crate::run_program(prog, &mut env)
+                .map(|value| to_source(&value))
+                .map_err(|err| err.inner().clone())
+        };
+
+        assert_eq!(
+            run("(/ 1 0)", DivisionByZero::Error),
+            Err(CrispError::DivisionByZero)
+        );
+        for prog in ["(/ 1 0)", "(mod 1 0)", "(quotient 1 0)", "(/ 1m 0)"] {
+            assert_eq!(
+                run(prog, DivisionByZero::Nil),
+                Ok("nil".to_string()),
+                "{prog}"
+            );
+        }
+        assert_eq!(
+            run("(/ 1 0)", DivisionByZero::Infinity),
+            Ok("inf".to_string())
+        );
+        assert_eq!(
+            run("(/ -2.5 0.0)", DivisionByZero::Infinity),
+            Ok("-inf".to_string())
+        );
+        assert_eq!(
+            run("(/ 0 0)", DivisionByZero::Infinity),
+            Ok("NaN".to_string())
+        );
+        // Only float division has an infinity
+        for prog in ["(/ 1m 0)", "(mod 1 0)"] {
+            assert_eq!(
+                run(prog, DivisionByZero::Infinity),
+                Err(CrispError::DivisionByZero),
+                "{prog}"
+            );
+        }
+    }
+
+    #[test]
+    fn eval_max_steps() {
+        let mut env = CrispEnv::with_options(EvalOptions {
+            max_steps: Some(1000),
+            ..Default::default()
+        });
+        crate::run_program("(def loop (fn (n) (loop (+ n 1))))", &mut env).unwrap();
+
+        assert_eq!(
+            crate::run_program("(loop 0)", &mut env).map_err(|err| err.inner().clone()),
+            Err(CrispError::EvalError(
+                "evaluation aborted after 1000 steps".to_string()
+            ))
+        );
+
+        env.reset_steps();
+        assert_eq!(
+            crate::run_program("(+ 1 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert_eq!(env.steps(), 4);
+    }
+
+    #[test]
+    fn eval_interrupt() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut env = CrispEnvBuilder::new()
+            .pure()
+            .options(EvalOptions {
+                interrupt: Some(interrupt.clone()),
+                ..Default::default()
+            })
+            .register("interrupt", move |_| {
+                interrupt.store(true, Ordering::Relaxed);
+                Ok(CrispExpr::Primitive(Primitive::Bool(true)))
+            })
+            .build();
+
+        assert_eq!(
+            crate::run_program("(begin (interrupt) (+ 1 2))", &mut env)
+                .map_err(|err| err.inner().clone()),
+            Err(CrispError::Interrupted)
+        );
+    }
+
+    #[test]
+    fn eval_begin_and_fn_bodies() {
+        let mut env = CrispEnv::default();
+
+        assert_eq!(
+            crate::run_program("(begin)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+        assert_eq!(
+            crate::run_program("(def f (fn (x) (def y (* x 2)) (+ y 1))) (f 3)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(7)))
+        );
+        assert_eq!(
+            crate::run_program("((fn ()))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let mut env = CrispEnv::default();
+        crate::run_program(
+            "(def count (fn (n acc) (if (> n 0) (begin (def m (- n 1)) (count m (+ acc 1))) acc)))",
+            &mut env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            crate::run_program("(count 20000 0)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(20000)))
+        );
+
+        // A local helper tail-called by its definer can still call itself
+        assert_eq!(
+            crate::run_program(
+                "(def count-to (fn (n) \
+                   (def go (fn (i acc) (if (> i n) acc (go (+ i 1) (+ acc 1))))) \
+                   (go 1 0))) \
+                 (count-to 20000)",
+                &mut env
+            ),
+            Ok(CrispExpr::Primitive(Primitive::Int(20000)))
+        );
+    }
+
+    /// Runs `(f 1000000)` after `def`, deep enough to overflow unless `f`'s
+    /// self-call is in tail position.
+    fn assert_tail_call(def: &str, expected: &str) {
+        let mut env = CrispEnv::default();
+        crate::run_program(def, &mut env).unwrap();
+        let result = crate::run_program("(f 1000000)", &mut env);
+        assert_eq!(
+            result.map(|v| crate::format::to_source(&v)),
+            Ok(expected.to_string()),
+            "{def}"
+        );
+    }
+
+    #[test]
+    fn when_and_unless_bodies_are_tail_positions() {
+        assert_tail_call("(def f (fn (n) (when (> n 0) (f (- n 1)))))", "nil");
+        assert_tail_call("(def f (fn (n) (unless (= n 0) (f (- n 1)))))", "nil");
+    }
+
+    #[test]
+    fn case_bodies_are_tail_positions() {
+        assert_tail_call(
+            "(def f (fn (n) (case n (0 :done) (else (f (- n 1))))))",
+            ":done",
+        );
+    }
+
+    #[test]
+    fn let_bodies_are_tail_positions() {
+        assert_tail_call(
+            "(def f (fn (n) (let ((m (- n 1))) (if (> n 0) (f m) n))))",
+            "0",
+        );
+    }
+
+    #[test]
+    fn let_star_bodies_are_tail_positions() {
+        assert_tail_call(
+            "(def f (fn (n) (let* ((m (- n 1)) (k m)) (if (> n 0) (f k) n))))",
+            "0",
+        );
+    }
+
+    #[test]
+    fn last_and_operand_is_a_tail_position() {
+        assert_tail_call("(def f (fn (n) (and (> n 0) (f (- n 1)))))", "false");
+    }
+
+    #[test]
+    fn last_or_operand_is_a_tail_position() {
+        assert_tail_call("(def f (fn (n) (or (= n 0) (f (- n 1)))))", "true");
+    }
+
+    #[test]
+    fn eval_let_and_let_star() {
+        let mut env = CrispEnv::default();
+        let run = |prog, env: &mut CrispEnv| crate::run_program(prog, env);
+
+        assert_eq!(
+            run("(let* ((x 1) (y (+ x 1))) y)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+        // let evaluates every value before binding any of them
+        assert_eq!(
+            run("(def x 10) (let ((x 1) (y (+ x 1))) y)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(11)))
+        );
+        // Functions made in the body keep their bindings
+        assert_eq!(
+            run("((let ((n 3)) (fn (m) (* n m))) 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(6)))
+        );
+        assert!(run("(let (x 1) x)", &mut env).is_err());
+        assert!(run("(let* ((y 1)) (def z y)) z", &mut env).is_err());
+    }
+
+    #[test]
+    fn keyword_arguments() {
+        let mut env = CrispEnv::default();
+        crate::run_program(
+            "(def draw (fn (shape &key x y color) (str shape \" \" x \" \" y \" \" color))) \
+             (def area (fn (&key w h) (* w h)))",
+            &mut env,
+        )
+        .unwrap();
+        let run =
+            |prog, env: &mut CrispEnv| crate::run_program(prog, env).map(|value| to_source(&value));
+
+        assert_eq!(run("(area :h 2 :w 3)", &mut env), Ok("6".to_string()));
+        assert_eq!(
+            run("(draw 1 :y 20 :color \"red\")", &mut env),
+            Ok("\"1 nil 20 red\"".to_string())
+        );
+        assert_eq!(
+            run("((fn (&key x y) y) :x 1)", &mut env),
+            Ok("nil".to_string())
+        );
+        // The named args are locals, which make a map if one is wanted
+        assert_eq!(
+            run("((fn (&key x y) {:x x :y y}) :y 2 :x 1)", &mut env),
+            Ok("{:x 1 :y 2}".to_string())
+        );
+
+        let error = |prog, env: &mut CrispEnv| crate::run_program(prog, env).unwrap_err().message();
+        assert_eq!(
+            error("(area :w 1 :depth 2)", &mut env),
+            "unknown keyword argument :depth, expected one of :w, :h"
+        );
+        assert_eq!(
+            error("(area :w)", &mut env),
+            "keyword argument :w has no value"
+        );
+        assert_eq!(
+            error("(area :w 1 :w 2)", &mut env),
+            "keyword argument :w was given twice"
+        );
+        assert_eq!(
+            error("(area 1 2)", &mut env),
+            "expected a keyword like :w but got 1"
+        );
+        assert_eq!(
+            error("(draw)", &mut env),
+            "Wrong number of arguments were supplied"
+        );
+    }
+
+    #[test]
+    fn eval_if_arity() {
+        let mut env = CrispEnv::default();
+
+        assert_eq!(
+            crate::run_program("(if false 1)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+        assert_eq!(
+            crate::run_program("(if true 1)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(1)))
+        );
+        assert!(crate::run_program("(if true 1 2 3)", &mut env).is_err());
+        assert!(crate::run_program("(if true)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_when_unless() {
+        let mut env = CrispEnv::default();
+
+        assert_eq!(
+            crate::run_program("(when (> 2 1) (def x 1) (+ x 1))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+        assert_eq!(
+            crate::run_program("(unless true (undefined))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+        assert_eq!(
+            crate::run_program("(when 1 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+    }
+
+    #[test]
+    fn only_false_and_nil_are_falsy() {
+        let mut env = CrispEnv::default();
+
+        for (test, branch) in [
+            ("nil", 2),
+            ("false", 2),
+            ("0", 1),
+            ("\"\"", 1),
+            ("(quote ())", 1),
+        ] {
+            assert_eq!(
+                crate::run_program(&format!("(if {test} 1 2)"), &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Int(branch))),
+                "{test}"
+            );
+        }
+        assert_eq!(
+            crate::run_program("()", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+        assert_eq!(
+            crate::run_program("(unless () 1)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(1)))
+        );
+        assert_eq!(
+            crate::run_program(
+                "(count-if (fn (x) (when (> x 1) x)) (quote (1 2 3)))",
+                &mut env
+            ),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+        // Errors in the test aren't mistaken for a non-boolean result
+        let err = crate::run_program("(if (undefined) 1 2)", &mut env).unwrap_err();
+        assert!(err.message().contains("undefined"), "{err}");
+    }
+
+    #[test]
+    fn eval_case_clauses() {
+        let mut env = CrispEnv::default();
+        let prog = "(def classify (fn (x) (case x (1 (quote one)) ((2 3) (quote few)) (else (quote many)))))";
+        crate::run_program(prog, &mut env).unwrap();
+
+        for (x, expected) in [(1, "one"), (3, "few"), (7, "many")] {
+            assert_eq!(
+                crate::run_program(&format!("(classify {x})"), &mut env),
+                Ok(CrispExpr::Symbol(expected.to_string()))
+            );
+        }
+        assert_eq!(
+            crate::run_program("(case true (false 1))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+    }
+
+    #[test]
+    fn eval_combinators() {
+        let mut env = CrispEnv::default();
+        let prog = "(def inc (fn (x) (+ x 1))) (def double (fn (x) (* x 2)))";
+        crate::run_program(prog, &mut env).unwrap();
+
+        for (prog, expected) in [
+            ("(identity 4)", 4),
+            ("((const 4) 1 2)", 4),
+            ("((flip -) 1 10)", 9),
+            ("((comp inc double) 5)", 11),
+            ("((comp) 5)", 5),
+            ("(pipe 5 inc double)", 12),
+            ("(pipe 5 (comp inc double) inc)", 12),
+            ("(pipe 5)", 5),
+        ] {
+            assert_eq!(
+                crate::run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Int(expected))),
+                "{prog}"
+            );
+        }
+    }
+
+    #[test]
+    fn version_and_features() {
+        let mut env = CrispEnv::default();
+        assert_eq!(
+            crate::run_program("(crisp-version)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Str(
+                crate::VERSION.to_string()
+            )))
+        );
+        assert_eq!(
+            crate::run_program("(feature? :ndarray)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Bool(cfg!(
+                feature = "ndarray"
+            ))))
+        );
+        assert_eq!(
+            crate::run_program("(feature? \"json\")", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Bool(false)))
+        );
+    }
+
+    #[test]
+    fn eval_predicates_short_circuit() {
+        let mut env = CrispEnv::default();
+        let prog = "(def big (fn (x) (> x 2))) (def xs (quote (1 3 5 oops)))";
+        crate::run_program(prog, &mut env).unwrap();
+
+        // Stopping at 1 and 3 means big is never called with oops
+        for (prog, expected) in [
+            (
+                "(every? big xs)",
+                CrispExpr::Primitive(Primitive::Bool(false)),
+            ),
+            ("(some big xs)", CrispExpr::Primitive(Primitive::Int(3))),
+            (
+                "(count-if big (quote (1 3 5)))",
+                CrispExpr::Primitive(Primitive::Int(2)),
+            ),
+            (
+                "(some big (quote ()))",
+                CrispExpr::Primitive(Primitive::Nil),
+            ),
+        ] {
+            assert_eq!(crate::run_program(prog, &mut env), Ok(expected), "{prog}");
+        }
+        assert!(crate::run_program("(count-if big xs)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_and_or() {
+        let mut env = CrispEnv::default();
+        let run =
+            |prog, env: &mut CrispEnv| crate::run_program(prog, env).map(|value| to_source(&value));
+
+        for (prog, expected) in [
+            ("(and 1 2 3)", "3"),
+            ("(and 1 false oops)", "false"),
+            ("(and)", "true"),
+            ("(or false (begin) 2 oops)", "2"),
+            ("(or false (begin))", "nil"),
+            ("(or)", "nil"),
+        ] {
+            assert_eq!(run(prog, &mut env), Ok(expected.to_string()), "{prog}");
+        }
+        assert!(run("(and true oops)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_reductions() {
+        let mut env = CrispEnv::default();
+        let prog = "(def neg (fn (x) (* x -1))) (def xs (quote (3 -4 2)))";
+        crate::run_program(prog, &mut env).unwrap();
+
+        for (prog, expected) in [
+            ("(sum xs)", 1),
+            ("(product xs)", -24),
+            ("(sum (quote ()))", 0),
+            ("(product (quote ()))", 1),
+            ("(max-by neg xs)", -4),
+            ("(min-by neg xs)", 3),
+            ("(max-by neg (quote ()) 0)", 0),
+        ] {
+            assert_eq!(
+                crate::run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Int(expected))),
+                "{prog}"
+            );
+        }
+        assert!(crate::run_program("(min-by neg (quote ()))", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_source_of_definitions() {
+        let mut env = CrispEnv::default();
+        env.set_script("lib.crisp");
+        crate::run_program("(def x 1)\n  (def f (fn () (def local 2) local))", &mut env).unwrap();
+
+        assert_eq!(
+            crate::run_program("(source-of f)", &mut env),
+            Ok(CrispExpr::List(vec![
+                CrispExpr::Primitive(Primitive::Str("lib.crisp".to_string())),
+                CrispExpr::Primitive(Primitive::Int(2)),
+                CrispExpr::Primitive(Primitive::Int(3)),
+            ]))
+        );
+        assert_eq!(env.location("x").map(|loc| loc.span.line), Some(1));
+        assert_eq!(
+            crate::run_program("(source-of +)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Nil))
+        );
+    }
+
+    #[test]
+    fn eval_deprecated_binding_warns_once() {
+        let mut env = CrispEnv::default();
+        let prog = "(def old 1) (def new 1) (deprecate old new) (def f (fn () old)) (f) (f)";
+        crate::run_program(prog, &mut env).unwrap();
+
+        assert_eq!(
+            env.take_warnings(),
+            vec!["'old' is deprecated, use 'new' instead"]
+        );
+        assert_eq!(
+            crate::run_program("old", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(1)))
+        );
+        assert!(env.take_warnings().is_empty());
+        assert!(crate::run_program("(deprecate missing)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_use_namespace() {
+        let mut env = CrispEnvBuilder::new().namespaced().core().math().build();
+
+        assert_eq!(
+            crate::run_program("(math/+ 1 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert!(crate::run_program("(+ 1 2)", &mut env).is_err());
+        assert_eq!(
+            crate::run_program("(use math) (+ 1 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert!(crate::run_program("(use nothing)", &mut env).is_err());
+        // The natural log is only math/log, even after using math, since
+        // plain log is the logging form
+        assert_eq!(
+            crate::run_program("(math/log 1)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Number(0.)))
+        );
+        assert!(env.get("log").is_none());
+        assert!(crate::run_program("(def identity 1) (use core)", &mut env).is_err());
+    }
+
+    #[test]
+    fn bindings_are_sorted() {
+        let global = CrispEnv::default();
+        let mut env = CrispEnv::from_parent(&global);
+        crate::run_program("(def b 2) (def c 3) (def a 1)", &mut env).unwrap();
+
+        let names: Vec<_> = env.bindings().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn shadow_and_unshadow_builtins() {
+        let mut env = CrispEnv::default();
+        assert!(crate::run_program("(def + 1)", &mut env).is_err());
+
+        env.options.redefine_builtins = true;
+        crate::run_program("(def + (fn (a b) (* a b))) (def + -)", &mut env).unwrap();
+        assert_eq!(
+            crate::run_program("(+ 3 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(1)))
+        );
+        assert_eq!(env.take_warnings().len(), 2);
+
+        assert_eq!(
+            crate::run_program("(unshadow (quote +)) (+ 3 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(5)))
+        );
+        assert!(crate::run_program("(unshadow (quote +))", &mut env).is_err());
+
+        crate::run_program("(def * 1) (def x 1)", &mut env).unwrap();
+        assert!(crate::run_program("(def x 2)", &mut env).is_err());
+        assert_eq!(env.reset_builtins(), vec!["*"]);
+        assert_eq!(
+            crate::run_program("(* 3 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(6)))
+        );
+    }
+
+    #[test]
+    fn undef_bindings() {
+        let mut env = CrispEnv::default();
+        crate::run_program("(def x 1) (undef x)", &mut env).unwrap();
+        assert!(env.get("x").is_none());
+        assert!(crate::run_program("(undef x)", &mut env).is_err());
+
+        assert!(crate::run_program("(undef +)", &mut env).is_err());
+        env.options.redefine_builtins = true;
+        crate::run_program("(def + -) (undef +)", &mut env).unwrap();
+        assert_eq!(
+            crate::run_program("(+ 3 2)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(5)))
+        );
+
+        crate::run_program("(undef + :force)", &mut env).unwrap();
+        assert!(env.get("+").is_none());
+    }
+
+    #[test]
+    fn with_timeout_gives_up() {
+        let mut env = CrispEnv::default();
+        crate::run_program("(def loop (fn (n) (loop (+ n 1))))", &mut env).unwrap();
+
+        assert_eq!(
+            crate::run_program("(with-timeout 0 (loop 0))", &mut env),
+            Ok(CrispExpr::Symbol(":timeout".to_string()))
+        );
+        assert_eq!(
+            crate::run_program("(with-timeout 1000 (+ 1 2))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        // The outer deadline still applies inside a longer inner one
+        assert_eq!(
+            crate::run_program(
+                "(with-timeout 0 (begin (with-timeout 100000 (+ 1 2)) 1))",
+                &mut env
+            ),
+            Ok(CrispExpr::Symbol(":timeout".to_string()))
+        );
+        // Times too long to represent don't time out
+        assert_eq!(
+            crate::run_program("(with-timeout 1e38 (+ 1 2))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert!(env.options.deadline.is_none());
+    }
+
+    #[test]
+    fn deadlines_follow_the_env_clock() {
+        /// A clock an hour further on each time it's read
+        struct Hourly(Instant, AtomicUsize);
+
+        impl Clock for Hourly {
+            fn now(&self) -> Instant {
+                let hours = self.1.fetch_add(1, Ordering::Relaxed) as u64;
+                self.0 + Duration::from_secs(hours * 3600)
+            }
+        }
+
+        let mut env = CrispEnv::default();
+        env.options.clock = Some(Arc::new(Hourly(Instant::now(), AtomicUsize::new(0))));
+        assert_eq!(
+            crate::run_program("(with-timeout 60000 (+ 1 2))", &mut env),
+            Ok(CrispExpr::Symbol(":timeout".to_string()))
+        );
+    }
+
+    #[test]
+    fn traces_go_to_the_tracer() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let traced = lines.clone();
+        let mut env = CrispEnv::default();
+        env.options.trace = Some(Arc::new(move |line: &str| {
+            traced.lock().unwrap().push(line.to_string())
+        }));
+
+        crate::run_program("(def sq (fn (x) (* x x))) (sq 3)", &mut env).unwrap();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["-> (sq 3)", "  -> (* 3 3)", "  <- 9", "<- 9"]
+        );
+    }
+
+    #[test]
+    fn or_else_and_retry() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let mut env = CrispEnvBuilder::new()
+            .pure()
+            .register("flaky", move |_| {
+                match counter.fetch_add(1, Ordering::Relaxed) {
+                    0..=2 => Err(CrispError::EvalError("not yet".to_string())),
+                    n => Ok(CrispExpr::Primitive(Primitive::Number(n as f32))),
+                }
+            })
+            .build();
+
+        assert_eq!(
+            crate::run_program("(or-else (+ 1 x) 0)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(0)))
+        );
+        assert!(crate::run_program("(retry 2 (flaky))", &mut env).is_err());
+        assert_eq!(
+            crate::run_program("(retry 3 (flaky))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Number(3.)))
+        );
+        assert_eq!(attempts.load(Ordering::Relaxed), 4);
+        assert_eq!(
+            crate::run_program("(or-else (with-timeout 0 (+ 1 x)) 0)", &mut env),
+            Ok(CrispExpr::Symbol(":timeout".to_string()))
+        );
+
+        assert_eq!(
+            crate::run_program("(or-else (/ 1 0) 0)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(0)))
+        );
+        assert_eq!(
+            crate::run_program("(or-else (retry 3 (mod 1 0)) :gave-up)", &mut env),
+            Ok(CrispExpr::Symbol(":gave-up".to_string()))
+        );
+        // Fails on the first attempt only
+        crate::run_program("(def tries (stack))", &mut env).unwrap();
+        assert_eq!(
+            crate::run_program(
+                "(retry 3 (begin (push tries 1) (/ 6 (- 1 (length tries)))))",
+                &mut env
+            ),
+            Ok(CrispExpr::Primitive(Primitive::Number(-6.)))
+        );
+    }
+
+    #[test]
+    fn eval_decimal_arithmetic() {
+        let mut env = CrispEnv::default();
+        let eval_str =
+            |src: &str, env: &mut CrispEnv| crate::run_program(src, env).map(|val| to_source(&val));
+
+        assert_eq!(eval_str("(+ 0.1m 0.2m)", &mut env), Ok("0.3m".to_string()));
+        assert_eq!(eval_str("(* 1.50m 3)", &mut env), Ok("4.50m".to_string()));
+        assert_eq!(eval_str("(- 10m 0.01m)", &mut env), Ok("9.99m".to_string()));
+        assert_eq!(
+            eval_str("(sum (quote (19.99m 0.01m 5)))", &mut env),
+            Ok("25.00m".to_string())
+        );
+        assert_eq!(
+            eval_str("(> 2.00m 1.99m)", &mut env),
+            Ok("true".to_string())
+        );
+        // A float makes the result inexact
+        assert_eq!(eval_str("(+ 1m 0.5)", &mut env), Ok("1.5".to_string()));
+    }
+
+    #[test]
+    fn rollback_to_checkpoint() {
+        let mut env = CrispEnv::default();
+        crate::run_program("(def x 1)", &mut env).unwrap();
+
+        let checkpoint = env.checkpoint();
+        assert!(crate::run_program("(def y 2) (def z (undefined))", &mut env).is_err());
+        assert!(env.get("y").is_some());
+
+        env.rollback(checkpoint);
+        assert!(env.get("y").is_none());
+        assert!(env.location("y").is_none());
+        assert!(env.get("x").is_some());
+    }
+
+    #[test]
+    fn eval_script_args() {
+        let mut env = CrispEnv::default();
+        env.set_args(&["--input".to_string(), "data.csv".to_string()]);
+
+        assert_eq!(
+            eval(&CrispExpr::Symbol("*args*".to_string()), &mut env),
+            Ok(CrispExpr::List(vec![
+                CrispExpr::Primitive(Primitive::Str("--input".to_string())),
+                CrispExpr::Primitive(Primitive::Str("data.csv".to_string())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn eval_log_uses_script_as_target() {
+        use std::sync::Mutex;
+
+        struct Recorder(Mutex<Vec<(String, log::Level, String)>>);
+
+        impl log::Log for Recorder {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push((
+                    record.target().to_string(),
+                    record.level(),
+                    record.args().to_string(),
+                ));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static RECORDER: Recorder = Recorder(Mutex::new(vec![]));
+        log::set_logger(&RECORDER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut env = CrispEnv::default();
+        env.set_script("jobs.crisp");
+        assert_eq!(
+            crate::run_program("(log :warn (quote retrying) (+ 1 2))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert!(crate::run_program("(log :loud 1)", &mut env).is_err());
+        assert_eq!(
+            crate::run_program("(log 1)", &mut env)
+                .unwrap_err()
+                .message(),
+            "log expects a level like :info; the natural log is ln or math/log"
+        );
+
+        assert_eq!(
+            *RECORDER.0.lock().unwrap(),
+            vec![(
+                "jobs.crisp".to_string(),
+                log::Level::Warn,
+                "retrying 3".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn build_env_from_groups() {
+        let mut env = CrispEnvBuilder::new()
+            .math()
+            .register("double", |args| {
+                let floats = parse_floats(args)?;
+                Ok(CrispExpr::Primitive(Primitive::Number(floats[0] * 2.)))
+            })
+            .build();
+
+        assert!(env.get("assert").is_none());
+        assert_eq!(
+            crate::run_program("(double (+ 1 2))", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Number(6.)))
+        );
+    }
+
+    #[test]
+    fn share_env_between_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CrispExpr>();
+        assert_send_sync::<CrispEnv>();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let base = CrispEnvBuilder::new()
+            .pure()
+            .register("tick", move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(CrispExpr::Primitive(Primitive::Bool(true)))
+            })
+            .build();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let base = &base;
+                scope.spawn(move || {
+                    let mut env = CrispEnv::from_parent(base);
+                    crate::run_program("(begin (def x 1) (tick))", &mut env).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert!(base.get("x").is_none());
+    }
+
+    #[test]
+    fn forked_env_is_isolated() {
+        let mut base = CrispEnv::default();
+        crate::run_program("(def x 1)", &mut base).unwrap();
+
+        let mut fork = base.fork();
+        assert_eq!(
+            crate::run_program("(begin (def x 2) (def y x) (+ x y))", &mut fork),
+            Ok(CrispExpr::Primitive(Primitive::Int(4)))
+        );
+        assert_eq!(fork.depth, base.depth);
+
+        assert_eq!(base.get("x"), Some(CrispExpr::Primitive(Primitive::Int(1))));
+        assert!(base.get("y").is_none());
+    }
+
+    #[test]
+    fn forked_env_keeps_builtins() {
+        let base = CrispEnv::default();
+        let mut fork = base.fork();
+        assert!(crate::run_program("(def + 1)", &mut fork).is_err());
+        assert!(crate::run_program("(undef +)", &mut fork).is_err());
+
+        let base = CrispEnv::with_options(EvalOptions {
+            redefine_builtins: true,
+            ..Default::default()
+        });
+        let mut fork = base.fork();
+        crate::run_program("(def + (fn (a b) 0))", &mut fork).unwrap();
+        assert_eq!(fork.take_warnings().len(), 1);
+        assert_eq!(
+            crate::run_program("(begin (unshadow (quote +)) (+ 1 2))", &mut fork),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+        assert_eq!(
+            crate::run_program("(+ 1 2)", &mut base.fork()),
+            Ok(CrispExpr::Primitive(Primitive::Int(3)))
+        );
+    }
+
+    /// Files kept in memory, which tests can change between loads
+    #[derive(Clone, Default)]
+    struct Files(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Files {
+        fn write(&self, path: &str, src: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), src.to_string());
+        }
+    }
+
+    impl Loader for Files {
+        fn load(&self, name: &str, _from: Option<&str>) -> Result<(String, String), CrispError> {
+            let src = self.0.lock().unwrap().get(name).cloned();
+            let src = src.ok_or(CrispError::EvalError(format!("no such file {name}")))?;
+            Ok((name.to_string(), src))
+        }
+    }
+
+    #[test]
+    fn reload_rebinds_the_files_definitions() {
+        let files = Files::default();
+        files.write(
+            "lib/shapes.crisp",
+            "(def sides 3) (def area (fn (x) (* x x)))",
+        );
+        let mut env = CrispEnvBuilder::new().pure().loader(files.clone()).build();
+        crate::run_program("(load \"lib/shapes.crisp\") (def total (area 2))", &mut env).unwrap();
+
+        files.write("lib/shapes.crisp", "(def area (fn (x) (* x x x)))");
+        assert_eq!(
+            env.reload("shapes"),
+            Ok(CrispExpr::Primitive(Primitive::Str(
+                "lib/shapes.crisp".to_string()
+            )))
+        );
+        assert_eq!(
+            crate::run_program("(+ (area 2) sides total)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(15)))
+        );
+
+        // Names defined outside the file still can't be rebound
+        files.write("lib/shapes.crisp", "(def total 0)");
+        let err = env.reload("lib/shapes.crisp").unwrap_err();
+        assert!(err.message().contains("'total' already exists"), "{err}");
+        assert!(env.reload("circles").is_err());
+    }
+
+    #[test]
+    fn rollback_forgets_loaded_files() {
+        let files = Files::default();
+        files.write("lib.crisp", "(def libval 1)");
+        let mut env = CrispEnvBuilder::new().pure().loader(files).build();
+
+        let checkpoint = env.checkpoint();
+        assert!(
+            crate::run_program("(begin (load \"lib.crisp\") (car (quote ())))", &mut env).is_err()
+        );
+        env.rollback(checkpoint);
+        assert!(env.get("libval").is_none());
+
+        crate::run_program("(load \"lib.crisp\")", &mut env).unwrap();
+        assert_eq!(
+            env.get("libval"),
+            Some(CrispExpr::Primitive(Primitive::Int(1)))
+        );
+    }
+}
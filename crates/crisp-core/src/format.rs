@@ -0,0 +1,355 @@
+use std::time::Duration;
+
+use crate::{
+    lang::{CrispError, CrispExpr, Primitive},
+    lex_with_comments,
+    parse::parse_forms,
+    Token,
+};
+
+/// Line width the formatter tries to keep forms within
+pub const MAX_WIDTH: usize = 80;
+
+/// Forms whose first argument stays on the same line as the form name
+const HEADER_FORMS: [&str; 9] = [
+    "case", "def", "fn", "if", "let", "let*", "quote", "unless", "when",
+];
+
+/// Print an expression on a single line as it would be written in source.
+/// This is how `write` and the REPL print values.
+pub fn to_source(expr: &CrispExpr) -> String {
+    match expr {
+        CrispExpr::Primitive(Primitive::Nil) => "nil".to_string(),
+        // Whole floats keep their point, so they don't read back as ints
+        CrispExpr::Primitive(Primitive::Number(n)) if n.fract() == 0. => format!("{n:.1}"),
+        CrispExpr::Primitive(Primitive::Number(n)) => n.to_string(),
+        CrispExpr::Primitive(Primitive::Int(n)) => n.to_string(),
+        CrispExpr::Primitive(Primitive::Decimal(d)) => format!("{d}m"),
+        CrispExpr::Primitive(Primitive::Bool(b)) => b.to_string(),
+        CrispExpr::Primitive(Primitive::Str(s)) => quote_str(s),
+        CrispExpr::Symbol(name) => name.clone(),
+        CrispExpr::List(xs) => format!(
+            "({})",
+            xs.iter().map(to_source).collect::<Vec<String>>().join(" ")
+        ),
+        CrispExpr::Vector(xs) => format!(
+            "[{}]",
+            xs.iter().map(to_source).collect::<Vec<String>>().join(" ")
+        ),
+        CrispExpr::Map(map) => format!(
+            "{{{}}}",
+            map.iter()
+                .map(|(key, value)| format!("{} {}", to_source(key.expr()), to_source(value)))
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+        CrispExpr::Fn(_) => "#<builtin>".to_string(),
+        CrispExpr::Handle(handle) => format!("#<handle {}>", handle.name()),
+        CrispExpr::Lambda(lambda) => {
+            format!("(fn ({}) {})", lambda.param_list(), to_source(&lambda.body))
+        }
+        #[cfg(feature = "ndarray")]
+        CrispExpr::Array(a) => format!("#<array {}>", to_source(&crate::array::to_list(a))),
+    }
+}
+
+/// Print an expression for people rather than the reader: like `to_source`,
+/// except strings are written as their raw contents. This is how `display`
+/// and `print` print values.
+pub fn to_display(expr: &CrispExpr) -> String {
+    match expr {
+        CrispExpr::Primitive(Primitive::Str(s)) => s.clone(),
+        CrispExpr::Primitive(Primitive::Decimal(d)) => d.to_string(),
+        expr => to_source(expr),
+    }
+}
+
+/// How much of a value to print before eliding the rest with `...`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PrintLimits {
+    /// Lists and vectors nested deeper than this are printed as `...`
+    pub depth: Option<usize>,
+    /// Only this many items of each list or vector are printed, followed
+    /// by `...`
+    pub length: Option<usize>,
+}
+
+impl PrintLimits {
+    /// A copy of `expr` with the parts beyond the limits replaced by the
+    /// symbol `...`, ready to pass to any of the printers
+    pub fn elide(&self, expr: &CrispExpr) -> CrispExpr {
+        self.elide_within(expr, 0)
+    }
+
+    fn elide_within(&self, expr: &CrispExpr, depth: usize) -> CrispExpr {
+        let (CrispExpr::List(xs) | CrispExpr::Vector(xs)) = expr else {
+            return expr.clone();
+        };
+        if self.depth.is_some_and(|max| depth >= max) {
+            return CrispExpr::Symbol("...".to_string());
+        }
+
+        let shown = self.length.map_or(xs.len(), |max| max.min(xs.len()));
+        let mut elided = xs[..shown]
+            .iter()
+            .map(|x| self.elide_within(x, depth + 1))
+            .collect::<Vec<CrispExpr>>();
+        if shown < xs.len() {
+            elided.push(CrispExpr::Symbol("...".to_string()));
+        }
+
+        match expr {
+            CrispExpr::Vector(_) => CrispExpr::Vector(elided),
+            _ => CrispExpr::List(elided),
+        }
+    }
+}
+
+/// Render a list of rows, each a list of cells, as an aligned ASCII table.
+/// Short rows are padded with empty cells. Returns `None` for anything else,
+/// including a table without any cells.
+pub fn to_table(expr: &CrispExpr) -> Option<String> {
+    let CrispExpr::List(rows) = expr else {
+        return None;
+    };
+    let rows = rows
+        .iter()
+        .map(|row| match row {
+            CrispExpr::List(cells) => Some(cells.iter().map(to_display).collect::<Vec<String>>()),
+            _ => None,
+        })
+        .collect::<Option<Vec<Vec<String>>>>()?;
+
+    let columns = rows.iter().map(Vec::len).max().filter(|&n| n > 0)?;
+    let widths = (0..columns)
+        .map(|col| {
+            rows.iter()
+                .filter_map(|row| row.get(col))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<usize>>();
+
+    let border = widths
+        .iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<String>>()
+        .join("+");
+    let border = format!("+{border}+");
+
+    let mut lines = vec![border.clone()];
+    for row in &rows {
+        let cells = widths
+            .iter()
+            .enumerate()
+            .map(|(col, width)| format!("{:<width$}", row.get(col).map_or("", String::as_str)))
+            .collect::<Vec<String>>()
+            .join(" | ");
+        lines.push(format!("| {cells} |"));
+    }
+    lines.push(border);
+
+    Some(lines.join("\n"))
+}
+
+/// Write a string as a double-quoted literal, escaping special characters
+pub fn quote_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Write a duration for people, in the largest unit it has at least one of,
+/// e.g. `850µs`, `12.3ms` or `2.05s`. This is how the REPL shows timings.
+pub fn duration(d: Duration) -> String {
+    let secs = d.as_secs_f64();
+    match secs {
+        s if s >= 1. => format!("{s:.2}s"),
+        s if s >= 1e-3 => format!("{:.1}ms", s * 1e3),
+        s if s >= 1e-6 => format!("{:.0}µs", s * 1e6),
+        _ => format!("{}ns", d.as_nanos()),
+    }
+}
+
+/// Print an expression, breaking lists over several lines when they don't
+/// fit within `MAX_WIDTH` columns starting from `indent`
+pub fn pretty(expr: &CrispExpr, indent: usize) -> String {
+    pretty_within(expr, indent, 0)
+}
+
+/// Like `pretty`, but leaves room for `trail` closing parens from the
+/// enclosing forms after the expression
+fn pretty_within(expr: &CrispExpr, indent: usize, trail: usize) -> String {
+    let flat = to_source(expr);
+    if indent + flat.len() + trail <= MAX_WIDTH {
+        return flat;
+    }
+
+    let xs = match expr {
+        CrispExpr::List(xs) if xs.len() > 1 => xs,
+        _ => return flat,
+    };
+
+    let head = to_source(&xs[0]);
+    let inline = match &xs[0] {
+        CrispExpr::Symbol(name) if HEADER_FORMS.contains(&name.as_str()) => 1,
+        _ => 0,
+    };
+    let last = xs.len() - 1;
+    let trail_of = |i: usize| if i == last { trail + 1 } else { 0 };
+
+    let mut out = format!("({head}");
+    let mut col = indent + 1 + head.len();
+    for (i, arg) in xs.iter().enumerate().take(inline + 1).skip(1) {
+        let arg = pretty_within(arg, col + 1, trail_of(i));
+        col += 1 + arg.len();
+        out.push(' ');
+        out.push_str(&arg);
+    }
+
+    let body_indent = indent + 2;
+    for (i, arg) in xs.iter().enumerate().skip(inline + 1) {
+        out.push('\n');
+        out.push_str(&" ".repeat(body_indent));
+        out.push_str(&pretty_within(arg, body_indent, trail_of(i)));
+    }
+    out.push(')');
+
+    out
+}
+
+/// Reformat crisp source into its canonical layout. Comments between forms
+/// are kept, with those after a form on the same line staying there; comments
+/// inside a form can't be placed yet, so they're an error rather than lost.
+pub fn format_source(src: &str) -> Result<String, CrispError> {
+    let (tokens, comments) = lex_with_comments(src);
+    let forms = parse_forms(&tokens)?;
+
+    let inside = |comment: &&Token| {
+        forms
+            .iter()
+            .any(|form| form.span.start < comment.span.start && comment.span.start < form.span.end)
+    };
+    if let Some(comment) = comments.iter().find(inside) {
+        return Err(CrispError::FormatError(format!(
+            "can't keep the comment inside a form at line {}; move it between forms",
+            comment.span.line
+        )));
+    }
+
+    let mut chunks: Vec<String> = vec![];
+    let mut comments = comments.iter().peekable();
+    let mut prev_end = 0;
+    for form in forms.iter().map(Some).chain([None]) {
+        let end = form.map_or(src.len(), |form| form.span.start);
+        let mut chunk = String::new();
+        while let Some(comment) = comments.next_if(|comment| comment.span.start < end) {
+            let text = comment.text.trim_end();
+            match chunks.last_mut() {
+                Some(last) if !src[prev_end..comment.span.start].contains('\n') => {
+                    last.pop();
+                    *last += &format!(" {text}\n");
+                }
+                _ => chunk += &format!("{text}\n"),
+            }
+            prev_end = comment.span.end;
+        }
+
+        if let Some(form) = form {
+            chunk += &pretty(&form.expr, 0);
+            chunk.push('\n');
+            prev_end = form.span.end;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+    }
+
+    Ok(chunks.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parse::parse_all};
+
+    #[test]
+    fn elide_deep_and_long_lists() {
+        let expr = parse_all(&lexer("(1 (2 (3 (4))) 5 6)")).unwrap().remove(0);
+        let limits = PrintLimits {
+            depth: Some(2),
+            length: Some(3),
+        };
+
+        assert_eq!(to_source(&limits.elide(&expr)), "(1 (2 ...) 5 ...)");
+        assert_eq!(PrintLimits::default().elide(&expr), expr);
+    }
+
+    #[test]
+    fn table_pads_columns() {
+        let rows = parse_all(&lexer("((name age) (ada 36) (grace))")).unwrap();
+
+        assert_eq!(
+            to_table(&rows[0]).unwrap(),
+            "+-------+-----+\n\
+             | name  | age |\n\
+             | ada   | 36  |\n\
+             | grace |     |\n\
+             +-------+-----+"
+        );
+        assert_eq!(to_table(&CrispExpr::List(vec![])), None);
+        let flat = parse_all(&lexer("(1 2)")).unwrap();
+        assert_eq!(to_table(&flat[0]), None);
+    }
+
+    #[test]
+    fn format_short_forms_on_one_line() {
+        let formatted = format_source("(def  x\n  (+ 1   2))\n(fn (a) a)").unwrap();
+
+        assert_eq!(formatted, "(def x (+ 1 2))\n\n(fn (a) a)\n");
+    }
+
+    #[test]
+    fn format_breaks_long_forms() {
+        let src = "(def add (fn (a b) (if (> b 0) (+ 1 (add a (- b 1))) (* a a a a a a a a a a a a a a a a a a a a))))";
+        let formatted = format_source(src).unwrap();
+
+        assert_eq!(
+            formatted,
+            "(def add\n  (fn (a b)\n    (if (> b 0)\n      (+ 1 (add a (- b 1)))\n      (* a a a a a a a a a a a a a a a a a a a a))))\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_comments_between_forms() {
+        let src = "; setup\n(def  x 1) ; one\n\n; twice x\n(* x 2)\n; done";
+        let formatted = format_source(src).unwrap();
+
+        assert_eq!(
+            formatted,
+            "; setup\n(def x 1) ; one\n\n; twice x\n(* x 2)\n\n; done\n"
+        );
+        assert!(format_source("(+ 1 ; one\n 2)").is_err());
+    }
+
+    #[test]
+    fn durations_use_a_fitting_unit() {
+        assert_eq!(duration(Duration::from_nanos(420)), "420ns");
+        assert_eq!(duration(Duration::from_micros(850)), "850µs");
+        assert_eq!(duration(Duration::from_micros(12_340)), "12.3ms");
+        assert_eq!(duration(Duration::from_millis(2_050)), "2.05s");
+    }
+}
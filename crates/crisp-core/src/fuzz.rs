@@ -0,0 +1,287 @@
+//! `Arbitrary` impls and entry points for fuzzing, enabled by the
+//! `arbitrary` feature.
+//!
+//! Each entry point checks an invariant and panics when it doesn't hold, so
+//! they can be called directly from `cargo fuzz` targets:
+//!
+//! ```ignore
+//! fuzz_target!(|expr: CrispExpr| crisp_core::fuzz::print_parse_round_trip(&expr));
+//! ```
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{
+    decimal::Decimal,
+    eval::{CrispEnv, EvalOptions},
+    format::{format_source, pretty, to_source},
+    lang::{CrispExpr, Primitive},
+    lexer,
+    parse::parse_all,
+    run_program,
+};
+
+/// How deeply generated lists can nest
+const MAX_DEPTH: usize = 4;
+
+/// Characters generated symbols are made of
+const SYMBOL_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789+-*/<>=!?_";
+
+/// Generates the values that can be written in source: floats (other than
+/// NaN, which never equals itself), ints, decimals, bools, nil, strings, symbols
+/// and lists of them
+impl<'a> Arbitrary<'a> for CrispExpr {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        arbitrary_expr(u, MAX_DEPTH)
+    }
+}
+
+fn arbitrary_expr(u: &mut Unstructured, depth: usize) -> Result<CrispExpr> {
+    let kinds = if depth == 0 { 2 } else { 3 };
+
+    Ok(match u.int_in_range(0..=kinds)? {
+        0 => {
+            let n = f32::arbitrary(u)?;
+            CrispExpr::Primitive(Primitive::Number(if n.is_nan() { 0. } else { n }))
+        }
+        1 => CrispExpr::Primitive(match u.int_in_range(0..=5)? {
+            0 => Primitive::Nil,
+            5 => Primitive::Int(i64::arbitrary(u)?),
+            4 => Primitive::Str(String::arbitrary(u)?),
+            3 => Primitive::Decimal(
+                Decimal::new(i64::arbitrary(u)?.into(), u.int_in_range(0..=4)?)
+                    .unwrap_or(Decimal::ZERO),
+            ),
+            n => Primitive::Bool(n == 1),
+        }),
+        2 => CrispExpr::Symbol(arbitrary_symbol(u)?),
+        _ => {
+            let len = u.int_in_range(0..=4)?;
+            CrispExpr::List(
+                (0..len)
+                    .map(|_| arbitrary_expr(u, depth - 1))
+                    .collect::<Result<_>>()?,
+            )
+        }
+    })
+}
+
+/// A symbol that won't be read back as a number, decimal, bool or nil
+fn arbitrary_symbol(u: &mut Unstructured) -> Result<String> {
+    let len = u.int_in_range(1..=8)?;
+    let name = (0..len)
+        .map(|_| u.choose(SYMBOL_CHARS).map(|&c| c as char))
+        .collect::<Result<String>>()?;
+
+    let is_decimal = name.strip_suffix('m').and_then(Decimal::parse).is_some();
+    if name.parse::<f32>().is_ok()
+        || is_decimal
+        || ["true", "false", "nil"].contains(&name.as_str())
+    {
+        return Ok(format!("_{name}"));
+    }
+
+    Ok(name)
+}
+
+/// A well-formed program: every symbol is defined before it's used, every
+/// call has the right number of arguments and every value has the right
+/// type, so it always evaluates without errors
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub forms: Vec<CrispExpr>,
+}
+
+impl Program {
+    pub fn source(&self) -> String {
+        self.forms
+            .iter()
+            .map(to_source)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl<'a> Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut gen = ProgramGen::default();
+        let len = u.int_in_range(1..=8)?;
+        let forms = (0..len).map(|_| gen.form(u)).collect::<Result<_>>()?;
+
+        Ok(Program { forms })
+    }
+}
+
+#[derive(Default)]
+struct ProgramGen {
+    /// Variables defined so far, all numbers
+    vars: Vec<String>,
+    /// Functions defined so far and their arity
+    fns: Vec<(String, usize)>,
+}
+
+fn symbol(name: &str) -> CrispExpr {
+    CrispExpr::Symbol(name.to_string())
+}
+
+fn call(name: &str, args: Vec<CrispExpr>) -> CrispExpr {
+    let mut list = vec![symbol(name)];
+    list.extend(args);
+    CrispExpr::List(list)
+}
+
+impl ProgramGen {
+    fn form(&mut self, u: &mut Unstructured) -> Result<CrispExpr> {
+        match u.int_in_range(0..=2)? {
+            0 => {
+                let name = format!("v{}", self.vars.len());
+                let value = self.number(u, &[], MAX_DEPTH)?;
+                self.vars.push(name.clone());
+
+                Ok(call("def", vec![symbol(&name), value]))
+            }
+            1 => {
+                let name = format!("f{}", self.fns.len());
+                let params = (0..u.int_in_range(0..=3)?)
+                    .map(|i| format!("p{i}"))
+                    .collect::<Vec<String>>();
+                let body = self.number(u, &params, MAX_DEPTH)?;
+                self.fns.push((name.clone(), params.len()));
+
+                let params = CrispExpr::List(params.iter().map(|p| symbol(p)).collect());
+                Ok(call(
+                    "def",
+                    vec![symbol(&name), call("fn", vec![params, body])],
+                ))
+            }
+            _ => self.number(u, &[], MAX_DEPTH),
+        }
+    }
+
+    /// An expression that evaluates to a number
+    fn number(&self, u: &mut Unstructured, locals: &[String], depth: usize) -> Result<CrispExpr> {
+        let kinds = if depth == 0 { 1 } else { 6 };
+
+        match u.int_in_range(0..=kinds)? {
+            0 => Ok(CrispExpr::Primitive(Primitive::Number(
+                u.int_in_range(-100..=100)? as f32,
+            ))),
+            1 => {
+                let names = locals.iter().chain(&self.vars).collect::<Vec<_>>();
+                match u.choose(&names) {
+                    Ok(name) => Ok(symbol(name)),
+                    Err(_) => Ok(CrispExpr::Primitive(Primitive::Number(0.))),
+                }
+            }
+            2 | 3 => {
+                let op = u.choose(&["+", "-", "*"])?;
+                let min = if *op == "-" { 1 } else { 0 };
+                let args = (0..u.int_in_range(min..=3)?)
+                    .map(|_| self.number(u, locals, depth - 1))
+                    .collect::<Result<_>>()?;
+
+                Ok(call(op, args))
+            }
+            4 => Ok(call(
+                "if",
+                vec![
+                    self.boolean(u, locals, depth - 1)?,
+                    self.number(u, locals, depth - 1)?,
+                    self.number(u, locals, depth - 1)?,
+                ],
+            )),
+            _ => match u.choose(&self.fns) {
+                Ok((name, arity)) => {
+                    let args = (0..*arity)
+                        .map(|_| self.number(u, locals, depth - 1))
+                        .collect::<Result<_>>()?;
+                    Ok(call(name, args))
+                }
+                Err(_) => self.number(u, locals, 0),
+            },
+        }
+    }
+
+    /// An expression that evaluates to a bool
+    fn boolean(&self, u: &mut Unstructured, locals: &[String], depth: usize) -> Result<CrispExpr> {
+        if depth == 0 || bool::arbitrary(u)? {
+            return Ok(CrispExpr::Primitive(Primitive::Bool(bool::arbitrary(u)?)));
+        }
+
+        Ok(call(
+            ">",
+            vec![
+                self.number(u, locals, depth - 1)?,
+                self.number(u, locals, depth - 1)?,
+            ],
+        ))
+    }
+}
+
+/// Lexing and parsing any input returns rather than panicking
+pub fn parse_bytes(data: &[u8]) {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = parse_all(&lexer(src));
+    }
+}
+
+/// Printing an expression and parsing it back gives the same expression,
+/// whether it's printed on one line or pretty-printed
+pub fn print_parse_round_trip(expr: &CrispExpr) {
+    for printed in [to_source(expr), pretty(expr, 0)] {
+        let parsed = parse_all(&lexer(&printed));
+        assert_eq!(parsed, Ok(vec![expr.clone()]), "printed as {printed}");
+    }
+}
+
+/// Well-formed programs evaluate without errors, and formatting them
+/// doesn't change their result
+pub fn eval_program(program: &Program) {
+    let options = EvalOptions {
+        max_depth: Some(64),
+        ..Default::default()
+    };
+    let src = program.source();
+    let formatted = format_source(&src).expect("well-formed programs parse");
+
+    let res = run_program(&src, &mut CrispEnv::with_options(options.clone()));
+    assert!(res.is_ok(), "{src}\nfailed with {res:?}");
+
+    let formatted_res = run_program(&formatted, &mut CrispEnv::with_options(options));
+    assert_eq!(
+        format!("{res:?}"),
+        format!("{formatted_res:?}"),
+        "formatting changed the result of\n{src}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes for driving the generators without a fuzzer
+    fn seeded_bytes(seed: u64) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        (0..512)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fuzz_entry_points_hold_on_generated_input() {
+        for seed in 0..200 {
+            let bytes = seeded_bytes(seed);
+            parse_bytes(&bytes);
+
+            let expr = CrispExpr::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            print_parse_round_trip(&expr);
+
+            let program = Program::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            eval_program(&program);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Directed graphs, kept as adjacency maps: a map from each node to the
+//! list of nodes it has edges to, like `{:a (:b :c) :b (:c) :c ()}`. Nodes
+//! can be any data a map key can be. Every node is a key, even one without
+//! edges of its own, so `keys` lists them all.
+//!
+//! Graphs are plain maps, so they're immutable like every other value and
+//! `add-edge` gives a changed copy. `bfs` and `topo-sort` visit neighbors in
+//! the order their edges were added, so their results don't vary from run
+//! to run.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use indexmap::IndexMap;
+
+use crate::{
+    format::to_source,
+    lang::{CrispError, CrispExpr, CrispFn, CrispResult, MapKey},
+};
+
+type Graph = IndexMap<MapKey, CrispExpr>;
+
+/// The graph builtins, registered under `graph/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "graph",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let mut graph = Graph::new();
+                for edge in args {
+                    match edge {
+                        CrispExpr::List(pair) | CrispExpr::Vector(pair) if pair.len() == 2 => {
+                            add_edge(&mut graph, &pair[0], &pair[1])?
+                        }
+                        _ => {
+                            return Err(CrispError::EvalError(format!(
+                                "graph takes edges as (from to) pairs, not {}",
+                                to_source(edge)
+                            )))
+                        }
+                    }
+                }
+                Ok(CrispExpr::Map(graph))
+            }),
+        ),
+        (
+            "add-edge",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(graph), from, to] => {
+                    let mut graph = graph.clone();
+                    add_edge(&mut graph, from, to)?;
+                    Ok(CrispExpr::Map(graph))
+                }
+                _ => Err(CrispError::EvalError(
+                    "add-edge takes a graph and the nodes the edge goes from and to".to_string(),
+                )),
+            }),
+        ),
+        (
+            "neighbors",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(graph), node] => Ok(CrispExpr::List(
+                    neighbors("neighbors", graph, &MapKey::new(node.clone())?)?.to_vec(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "neighbors takes a graph and a node".to_string(),
+                )),
+            }),
+        ),
+        (
+            "bfs",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(graph), start] => bfs(graph, start),
+                _ => Err(CrispError::EvalError(
+                    "bfs takes a graph and the node to start from".to_string(),
+                )),
+            }),
+        ),
+        (
+            "topo-sort",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(graph)] => topo_sort(graph),
+                _ => Err(CrispError::EvalError(
+                    "topo-sort takes exactly one graph".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// Add an edge to a graph, and its nodes if they're new. An edge that's
+/// already there isn't added twice.
+fn add_edge(graph: &mut Graph, from: &CrispExpr, to: &CrispExpr) -> Result<(), CrispError> {
+    let to_key = MapKey::new(to.clone())?;
+    let from = graph
+        .entry(MapKey::new(from.clone())?)
+        .or_insert(CrispExpr::List(vec![]));
+    let CrispExpr::List(edges) = from else {
+        return Err(not_a_graph("add-edge"));
+    };
+    if !edges.contains(to) {
+        edges.push(to.clone());
+    }
+
+    graph.entry(to_key).or_insert(CrispExpr::List(vec![]));
+    Ok(())
+}
+
+/// The nodes `node` has edges to, none if it isn't in the graph
+fn neighbors<'g>(
+    builtin: &str,
+    graph: &'g Graph,
+    node: &MapKey,
+) -> Result<&'g [CrispExpr], CrispError> {
+    match graph.get(node) {
+        Some(CrispExpr::List(edges)) => Ok(edges),
+        Some(_) => Err(not_a_graph(builtin)),
+        None => Ok(&[]),
+    }
+}
+
+/// The nodes reachable from `start`, nearest first, starting with `start`
+fn bfs(graph: &Graph, start: &CrispExpr) -> CrispResult {
+    let start = MapKey::new(start.clone())?;
+    let mut seen = HashSet::from([start.clone()]);
+    let mut pending = VecDeque::from([start]);
+    let mut visited = vec![];
+    while let Some(node) = pending.pop_front() {
+        for next in neighbors("bfs", graph, &node)? {
+            let next = MapKey::new(next.clone())?;
+            if seen.insert(next.clone()) {
+                pending.push_back(next);
+            }
+        }
+        visited.push(node.into_expr());
+    }
+
+    Ok(CrispExpr::List(visited))
+}
+
+/// Every node, each before the nodes it has edges to, failing if a cycle
+/// means there's no such order
+fn topo_sort(graph: &Graph) -> CrispResult {
+    // How many edges into each node come from nodes not yet sorted
+    let mut incoming: HashMap<MapKey, usize> = HashMap::new();
+    for node in graph.keys() {
+        for next in neighbors("topo-sort", graph, node)? {
+            *incoming.entry(MapKey::new(next.clone())?).or_default() += 1;
+        }
+    }
+
+    let mut ready: VecDeque<MapKey> = graph
+        .keys()
+        .filter(|node| !incoming.contains_key(*node))
+        .cloned()
+        .collect();
+    let mut sorted = vec![];
+    while let Some(node) = ready.pop_front() {
+        for next in neighbors("topo-sort", graph, &node)? {
+            let next = MapKey::new(next.clone())?;
+            let count = incoming.get_mut(&next).expect("every neighbor was counted");
+            *count -= 1;
+            if *count == 0 {
+                ready.push_back(next);
+            }
+        }
+        sorted.push(node.into_expr());
+    }
+
+    match graph
+        .keys()
+        .find(|node| incoming.get(*node).is_some_and(|n| *n > 0))
+    {
+        Some(node) => Err(CrispError::EvalError(format!(
+            "topo-sort found a cycle through {}",
+            to_source(node.expr())
+        ))),
+        None => Ok(CrispExpr::List(sorted)),
+    }
+}
+
+fn not_a_graph(builtin: &str) -> CrispError {
+    CrispError::EvalError(format!(
+        "{builtin} takes a graph, a map from each node to a list of the nodes it has edges to"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn build_and_walk_graphs() {
+        let g = "(def g (add-edge (graph '(:a :b) '(:a :c) [:b :d]) :c :d)) ";
+        assert_eq!(
+            eval_source(&format!("{g} g")),
+            Ok("{:a (:b :c) :b (:d) :c (:d) :d ()}".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{g} (neighbors g :a)")),
+            Ok("(:b :c)".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{g} (neighbors g :z)")),
+            Ok("()".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{g} (bfs g :a)")),
+            Ok("(:a :b :c :d)".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{g} (bfs g :c)")),
+            Ok("(:c :d)".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{g} (topo-sort (add-edge g :e :a))")),
+            Ok("(:e :a :b :c :d)".to_string())
+        );
+        // Adding an edge twice doesn't duplicate it
+        assert_eq!(
+            eval_source("(add-edge (graph '(1 2)) 1 2)"),
+            Ok("{1 (2) 2 ()}".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(topo-sort (graph '(:a :b) '(:b :c) '(:c :b)))"),
+            Err("topo-sort found a cycle through :b".to_string())
+        );
+        assert!(eval_source("(graph '(:a))").is_err());
+        assert!(eval_source("(neighbors {:a 1} :a)").is_err());
+    }
+}
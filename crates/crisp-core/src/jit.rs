@@ -0,0 +1,702 @@
+//! Compiles hot functions to native code with Cranelift.
+//!
+//! Only numeric kernels are compiled: functions whose body uses nothing but
+//! number and bool literals, the params, `if`, the math builtins and calls
+//! to the function itself. That's the subset `kernel::recognize` accepts,
+//! which the JIT lowers from the same tree the `map` and `reduce` kernels
+//! run. Each function gets code for calls with all float arguments and
+//! code for calls with all int arguments, which does int math and hands a
+//! call that overflows back to the interpreter to report. Anything else,
+//! including calls mixing ints and floats, keeps running in the interpreter.
+//!
+//! Symbols resolve dynamically, so the code is only used while the names it
+//! refers to are still bound to what they were when it was compiled.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, BlockArg, FuncRef, InstBuilder, MemFlagsData, Type, UserFuncName, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Module};
+
+use crate::{
+    eval::CrispEnv,
+    kernel::{self, Body, Bool, Callee, Number},
+    lang::{CrispExpr, CrispLambda, CrispResult, Primitive},
+};
+
+/// Per-lambda JIT state
+#[derive(Default)]
+pub struct JitSlot {
+    calls: AtomicUsize,
+    /// Set once compilation has been tried, to `None` if the lambda can't be
+    /// compiled
+    compiled: OnceLock<Option<Compiled>>,
+}
+
+impl JitSlot {
+    /// Whether the lambda is running as native code
+    pub fn is_compiled(&self) -> bool {
+        matches!(self.compiled.get(), Some(Some(_)))
+    }
+}
+
+impl std::fmt::Debug for JitSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JitSlot")
+    }
+}
+
+/// Copies of a lambda are equal whatever their JIT state
+impl PartialEq for JitSlot {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Run `lambda` as native code if it's hot enough and can be compiled,
+/// otherwise return `None` so it's interpreted
+pub(crate) fn call(
+    lambda: &CrispLambda,
+    args: &[CrispExpr],
+    env: &CrispEnv,
+) -> Option<CrispResult> {
+    let threshold = env.options.jit_threshold?;
+    if env.options.trace.is_some()
+        || env.options.max_depth.is_some()
+        || env.options.max_steps.is_some()
+        || env.options.deadline.is_some()
+    {
+        return None;
+    }
+
+    let slot = &lambda.jit;
+    let compiled = match slot.compiled.get() {
+        Some(compiled) => compiled.as_ref()?,
+        None => {
+            if slot.calls.fetch_add(1, Ordering::Relaxed) + 1 < threshold {
+                return None;
+            }
+            slot.compiled
+                .get_or_init(|| compile(lambda, env))
+                .as_ref()?
+        }
+    };
+
+    compiled.call(args, env, lambda)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Ty {
+    Number,
+    Bool,
+}
+
+impl Ty {
+    fn ir(self, mode: Mode) -> Type {
+        match self {
+            Ty::Number => mode.ir(),
+            Ty::Bool => types::I8,
+        }
+    }
+}
+
+/// The arguments code is compiled for: all floats, or all ints, which are
+/// checked for overflow like the interpreter does
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Float,
+    Int,
+}
+
+impl Mode {
+    fn ir(self) -> Type {
+        match self {
+            Mode::Float => types::F32,
+            Mode::Int => types::I64,
+        }
+    }
+}
+
+struct Compiled {
+    returns: Ty,
+    arity: usize,
+    /// The names the code calls, which must still be bound to what they
+    /// were when it was compiled
+    guards: Vec<(String, Callee)>,
+    /// For calls with float arguments, unless the result would be an int
+    floats: Option<Code<f32>>,
+    /// For calls with int arguments, unless the body has float literals
+    ints: Option<Code<i64>>,
+}
+
+impl Compiled {
+    fn call(
+        &self,
+        args: &[CrispExpr],
+        env: &CrispEnv,
+        lambda: &CrispLambda,
+    ) -> Option<CrispResult> {
+        if args.len() != self.arity {
+            return None;
+        }
+
+        let ints = args
+            .iter()
+            .map(|arg| match arg {
+                CrispExpr::Primitive(Primitive::Int(n)) => Some(*n),
+                _ => None,
+            })
+            .collect::<Option<Vec<i64>>>();
+        let floats = || {
+            args.iter()
+                .map(|arg| match arg {
+                    CrispExpr::Primitive(Primitive::Number(n)) => Some(*n),
+                    _ => None,
+                })
+                .collect::<Option<Vec<f32>>>()
+        };
+        let run = match (ints, &self.ints) {
+            (Some(ints), Some(code)) => Run::Int(code, ints),
+            _ => Run::Float(self.floats.as_ref()?, floats()?),
+        };
+
+        // The locals the lambda closed over can come to bind a name too,
+        // through a `def` made after the lambda
+        let closure_binds = |name: &str| lambda.closure.as_ref().is_some_and(|c| c.binds(name));
+        let guards_hold = self.guards.iter().all(|(name, guard)| {
+            !closure_binds(name)
+                && match (env.lookup(name), guard) {
+                    (Some(CrispExpr::Fn(f)), Callee::Builtin(expected)) => f == *expected,
+                    (Some(CrispExpr::Lambda(callee)), Callee::Recursive) => {
+                        Arc::ptr_eq(&callee.jit, &lambda.jit)
+                    }
+                    _ => false,
+                }
+        });
+        if !guards_hold {
+            return None;
+        }
+
+        // An int that overflowed is left to the interpreter to report
+        let res = match (run, self.returns) {
+            (Run::Float(code, args), Ty::Number) => Primitive::Number(code.run(&args)?),
+            (Run::Float(code, args), Ty::Bool) => Primitive::Bool(code.run(&args)? != 0.),
+            (Run::Int(code, args), Ty::Number) => Primitive::Int(code.run(&args)?),
+            (Run::Int(code, args), Ty::Bool) => Primitive::Bool(code.run(&args)? != 0),
+        };
+        Some(Ok(CrispExpr::Primitive(res)))
+    }
+}
+
+enum Run<'c> {
+    Float(&'c Code<f32>, Vec<f32>),
+    Int(&'c Code<i64>, Vec<i64>),
+}
+
+/// Native code for one `Mode`
+struct Code<T> {
+    /// Takes a pointer to the arguments and one to a flag that's set if an
+    /// int overflows, and returns the result, with bools as 1 or 0
+    entry: extern "C" fn(*const T, *mut u8) -> T,
+    /// Owns the code, which is freed along with the last copy of the lambda
+    module: Mutex<Option<JITModule>>,
+}
+
+impl<T> Code<T> {
+    /// Run the code, or return `None` if an int overflowed
+    fn run(&self, args: &[T]) -> Option<T> {
+        let mut overflowed = 0;
+        let res = (self.entry)(args.as_ptr(), &mut overflowed);
+        (overflowed == 0).then_some(res)
+    }
+}
+
+impl<T> Drop for Code<T> {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.get_mut().ok().and_then(Option::take) {
+            // SAFETY: the code only runs through `Code::run`, which borrows
+            // `self`, so nothing is executing it any more
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn compile(lambda: &CrispLambda, env: &CrispEnv) -> Option<Compiled> {
+    let is_self = |callee: &CrispLambda| Arc::ptr_eq(&callee.jit, &lambda.jit);
+    let recognized = kernel::recognize(lambda, env, Some(&is_self))?;
+    let (returns, floats) = match &recognized.body {
+        // A number worked out from int literals alone is an int to the
+        // interpreter
+        Body::Number(number) if !number.floating() => (Ty::Number, None),
+        Body::Number(_) => (Ty::Number, native(lambda, &recognized.body, Mode::Float)),
+        Body::Bool(_) => (Ty::Bool, native(lambda, &recognized.body, Mode::Float)),
+    };
+    let ints = native(lambda, &recognized.body, Mode::Int);
+    if floats.is_none() && ints.is_none() {
+        return None;
+    }
+
+    Some(Compiled {
+        returns,
+        arity: lambda.params.len(),
+        guards: recognized.callees,
+        floats,
+        ints,
+    })
+}
+
+/// Compile the body for `mode`, whose arguments must be `T`s
+fn native<T>(lambda: &CrispLambda, body: &Body, mode: Mode) -> Option<Code<T>> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let Some(entry_id) = define(&mut module, lambda, body, mode) else {
+        // SAFETY: none of the module's code has been handed out
+        unsafe { module.free_memory() };
+        return None;
+    };
+    let code = module.get_finalized_function(entry_id);
+
+    Some(Code {
+        // SAFETY: the entry was declared with this signature, with `T` as
+        // the type `mode` works on
+        entry: unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn(*const T, *mut u8) -> T>(code)
+        },
+        module: Mutex::new(Some(module)),
+    })
+}
+
+/// Define the kernel and its entry point, returning the entry
+fn define(module: &mut JITModule, lambda: &CrispLambda, body: &Body, mode: Mode) -> Option<FuncId> {
+    let returns = match body {
+        Body::Number(_) => Ty::Number,
+        Body::Bool(_) => Ty::Bool,
+    };
+    let pointer = module.target_config().pointer_type();
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut ctx = module.make_context();
+
+    let mut sig = module.make_signature();
+    for _ in &lambda.params {
+        sig.params.push(AbiParam::new(mode.ir()));
+    }
+    sig.params.push(AbiParam::new(pointer));
+    sig.returns.push(AbiParam::new(returns.ir(mode)));
+    let kernel_id = module.declare_anonymous_function(&sig).ok()?;
+
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(0, kernel_id.as_u32());
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = builder.create_block();
+        builder.append_block_params_for_function_params(block);
+        builder.switch_to_block(block);
+
+        let recurse = module.declare_func_in_func(kernel_id, builder.func);
+        let (flag, params) = builder.block_params(block).split_last()?;
+        let mut lower = Lower {
+            mode,
+            params: params.to_vec(),
+            flag: *flag,
+            recurse,
+            overflow: None,
+            builder,
+        };
+
+        let res = match body {
+            Body::Number(number) => lower.number(number)?,
+            Body::Bool(b) => lower.bool(b)?,
+        };
+        lower.builder.ins().return_(&[res]);
+
+        if let Some(overflow) = lower.overflow {
+            lower.builder.switch_to_block(overflow);
+            let one = lower.builder.ins().iconst(types::I8, 1);
+            lower
+                .builder
+                .ins()
+                .store(MemFlagsData::trusted(), one, lower.flag, 0);
+            let zero = lower.builder.ins().iconst(returns.ir(mode), 0);
+            lower.builder.ins().return_(&[zero]);
+        }
+        lower.builder.seal_all_blocks();
+        lower.builder.finalize(module.target_config());
+    }
+    module.define_function(kernel_id, &mut ctx).ok()?;
+    module.clear_context(&mut ctx);
+
+    let entry_id = define_entry(
+        module,
+        &mut ctx,
+        &mut func_ctx,
+        kernel_id,
+        lambda,
+        returns,
+        mode,
+    )?;
+    module.finalize_definitions().ok()?;
+
+    Some(entry_id)
+}
+
+/// Define the function the interpreter calls, which loads the arguments
+/// from memory and calls the kernel
+fn define_entry(
+    module: &mut JITModule,
+    ctx: &mut cranelift_codegen::Context,
+    func_ctx: &mut FunctionBuilderContext,
+    kernel_id: FuncId,
+    lambda: &CrispLambda,
+    returns: Ty,
+    mode: Mode,
+) -> Option<FuncId> {
+    let pointer = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(pointer));
+    sig.params.push(AbiParam::new(pointer));
+    sig.returns.push(AbiParam::new(mode.ir()));
+    let entry_id = module.declare_anonymous_function(&sig).ok()?;
+
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(0, entry_id.as_u32());
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, func_ctx);
+        let block = builder.create_block();
+        builder.append_block_params_for_function_params(block);
+        builder.switch_to_block(block);
+
+        let [args_ptr, flag] = builder.block_params(block) else {
+            return None;
+        };
+        let (args_ptr, flag) = (*args_ptr, *flag);
+        let size = mode.ir().bytes() as i32;
+        let mut args = (0..lambda.params.len())
+            .map(|i| {
+                builder.ins().load(
+                    mode.ir(),
+                    MemFlagsData::trusted(),
+                    args_ptr,
+                    size * i as i32,
+                )
+            })
+            .collect::<Vec<Value>>();
+        args.push(flag);
+
+        let kernel = module.declare_func_in_func(kernel_id, builder.func);
+        let call = builder.ins().call(kernel, &args);
+        let res = builder.inst_results(call)[0];
+        let res = match (returns, mode) {
+            (Ty::Number, _) => res,
+            (Ty::Bool, Mode::Float) => {
+                let one = builder.ins().f32const(1.);
+                let zero = builder.ins().f32const(0.);
+                builder.ins().select(res, one, zero)
+            }
+            (Ty::Bool, Mode::Int) => builder.ins().uextend(types::I64, res),
+        };
+
+        builder.ins().return_(&[res]);
+        builder.seal_all_blocks();
+        builder.finalize(module.target_config());
+    }
+    module.define_function(entry_id, ctx).ok()?;
+    module.clear_context(ctx);
+
+    Some(entry_id)
+}
+
+#[derive(Clone, Copy)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+}
+
+/// Lowers a recognized body to Cranelift IR, working out numbers the way
+/// `Number::eval` and `Number::eval_int` do so results match the interpreter
+/// exactly
+struct Lower<'f> {
+    mode: Mode,
+    params: Vec<Value>,
+    /// Where to record that an int overflowed
+    flag: Value,
+    recurse: FuncRef,
+    /// The block that sets the flag and returns, once something can
+    /// overflow
+    overflow: Option<Block>,
+    builder: FunctionBuilder<'f>,
+}
+
+impl Lower<'_> {
+    fn number(&mut self, number: &Number) -> Option<Value> {
+        Some(match number {
+            Number::Const(n) => match self.mode {
+                Mode::Float => self.builder.ins().f32const(*n),
+                Mode::Int => return None,
+            },
+            Number::Int(n) => self.int(*n),
+            Number::Param(i) => self.params[*i],
+            Number::Add(xs) => {
+                let zero = self.int(0);
+                self.fold(Arith::Add, zero, xs)?
+            }
+            Number::Sub(first, rest) => {
+                let first = self.number(first)?;
+                self.fold(Arith::Sub, first, rest)?
+            }
+            Number::Mul(xs) => {
+                let one = self.int(1);
+                self.fold(Arith::Mul, one, xs)?
+            }
+            // With floats, both branches have to give a float, or the
+            // interpreter would give an int for one of them
+            Number::If(_, then, otherwise)
+                if self.mode == Mode::Float && !(then.floating() && otherwise.floating()) =>
+            {
+                return None
+            }
+            Number::If(test, then, otherwise) => {
+                let ty = self.mode.ir();
+                self.branch(
+                    test,
+                    ty,
+                    |lower| lower.number(then),
+                    |lower| lower.number(otherwise),
+                )?
+            }
+            Number::Recurse(args) => self.recurse(args)?,
+        })
+    }
+
+    fn bool(&mut self, b: &Bool) -> Option<Value> {
+        Some(match b {
+            Bool::Const(b) => self.builder.ins().iconst(types::I8, *b as i64),
+            Bool::Gt(a, b) => {
+                let a = self.number(a)?;
+                let b = self.number(b)?;
+                match self.mode {
+                    Mode::Float => self.builder.ins().fcmp(FloatCC::GreaterThan, a, b),
+                    Mode::Int => self.builder.ins().icmp(IntCC::SignedGreaterThan, a, b),
+                }
+            }
+            Bool::If(test, then, otherwise) => self.branch(
+                test,
+                types::I8,
+                |lower| lower.bool(then),
+                |lower| lower.bool(otherwise),
+            )?,
+            Bool::Recurse(args) => self.recurse(args)?,
+        })
+    }
+
+    /// An int literal, which is a float when the arguments are
+    fn int(&mut self, n: i64) -> Value {
+        match self.mode {
+            Mode::Float => self.builder.ins().f32const(n as f32),
+            Mode::Int => self.builder.ins().iconst(types::I64, n),
+        }
+    }
+
+    fn fold(&mut self, op: Arith, mut acc: Value, xs: &[Number]) -> Option<Value> {
+        for x in xs {
+            let x = self.number(x)?;
+            acc = match (self.mode, op) {
+                (Mode::Float, Arith::Add) => self.builder.ins().fadd(acc, x),
+                (Mode::Float, Arith::Sub) => self.builder.ins().fsub(acc, x),
+                (Mode::Float, Arith::Mul) => self.builder.ins().fmul(acc, x),
+                (Mode::Int, op) => {
+                    let (res, overflowed) = match op {
+                        Arith::Add => self.builder.ins().sadd_overflow(acc, x),
+                        Arith::Sub => self.builder.ins().ssub_overflow(acc, x),
+                        Arith::Mul => self.builder.ins().smul_overflow(acc, x),
+                    };
+                    self.bail_if(overflowed);
+                    res
+                }
+            };
+        }
+        Some(acc)
+    }
+
+    /// A call to the kernel itself, which only takes arguments of its mode
+    fn recurse(&mut self, args: &[Number]) -> Option<Value> {
+        if self.mode == Mode::Float && !args.iter().all(Number::floating) {
+            return None;
+        }
+        let mut args = args
+            .iter()
+            .map(|arg| self.number(arg))
+            .collect::<Option<Vec<_>>>()?;
+        if args.len() != self.params.len() {
+            return None;
+        }
+        args.push(self.flag);
+        let call = self.builder.ins().call(self.recurse, &args);
+        let res = self.builder.inst_results(call)[0];
+
+        if self.mode == Mode::Int {
+            let overflowed =
+                self.builder
+                    .ins()
+                    .load(types::I8, MemFlagsData::trusted(), self.flag, 0);
+            self.bail_if(overflowed);
+        }
+        Some(res)
+    }
+
+    /// Stop and report an overflow if `cond` is set
+    fn bail_if(&mut self, cond: Value) {
+        let overflow = match self.overflow {
+            Some(block) => block,
+            None => *self.overflow.insert(self.builder.create_block()),
+        };
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, overflow, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn branch(
+        &mut self,
+        test: &Bool,
+        ty: Type,
+        then: impl FnOnce(&mut Self) -> Option<Value>,
+        otherwise: impl FnOnce(&mut Self) -> Option<Value>,
+    ) -> Option<Value> {
+        let test = self.bool(test)?;
+
+        let then_block = self.builder.create_block();
+        let else_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(test, then_block, &[], else_block, &[]);
+
+        self.builder.switch_to_block(then_block);
+        let then = then(self)?;
+        self.builder
+            .ins()
+            .jump(merge_block, &[BlockArg::Value(then)]);
+
+        self.builder.switch_to_block(else_block);
+        let otherwise = otherwise(self)?;
+        self.builder
+            .ins()
+            .jump(merge_block, &[BlockArg::Value(otherwise)]);
+
+        self.builder.switch_to_block(merge_block);
+        Some(self.builder.append_block_param(merge_block, ty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::EvalOptions, run_program};
+
+    const FIB: &str = "(def fib (fn (n) (if (> 2 n) n (+ (fib (- n 1)) (fib (- n 2))))))";
+
+    fn jit_env<'a>() -> CrispEnv<'a> {
+        CrispEnv::with_options(EvalOptions {
+            jit_threshold: Some(2),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn jit_matches_interpreter() {
+        let mut jit = jit_env();
+        let mut interpreted = CrispEnv::default();
+        run_program(FIB, &mut jit).unwrap();
+        run_program(FIB, &mut interpreted).unwrap();
+
+        for n in ["0.0", "1.0", "5.0", "15.5", "5"] {
+            let prog = format!("(fib {n})");
+            assert_eq!(
+                run_program(&prog, &mut jit),
+                run_program(&prog, &mut interpreted)
+            );
+        }
+
+        let Some(CrispExpr::Lambda(fib)) = jit.get("fib") else {
+            panic!("fib should be a lambda");
+        };
+        assert!(fib.jit.is_compiled());
+    }
+
+    #[test]
+    fn jit_skips_non_numeric_functions() {
+        let mut env = jit_env();
+        run_program("(def check (fn (x) (assert x)))", &mut env).unwrap();
+        for _ in 0..3 {
+            run_program("(check true)", &mut env).unwrap();
+        }
+
+        let Some(CrispExpr::Lambda(check)) = env.get("check") else {
+            panic!("check should be a lambda");
+        };
+        assert!(!check.jit.is_compiled());
+    }
+
+    #[test]
+    fn jit_falls_back_when_builtins_are_shadowed() {
+        let mut env = jit_env();
+        run_program("(def gt (fn (a b) (> a b)))", &mut env).unwrap();
+        for _ in 0..3 {
+            run_program("(gt 2 1)", &mut env).unwrap();
+        }
+
+        let mut shadowed = env.fork();
+        shadowed.options.redefine_builtins = true;
+        run_program("(def > (fn (a b) false))", &mut shadowed).unwrap();
+        assert_eq!(
+            run_program("(gt 2 1)", &mut shadowed),
+            Ok(CrispExpr::Primitive(Primitive::Bool(false)))
+        );
+    }
+
+    #[test]
+    fn jit_compiles_int_calls() {
+        let mut env = jit_env();
+        run_program(FIB, &mut env).unwrap();
+        run_program(
+            "(def fact (fn (n) (if (> 2 n) 1 (* n (fact (- n 1))))))",
+            &mut env,
+        )
+        .unwrap();
+        for _ in 0..3 {
+            run_program("(fib 2) (fact 2)", &mut env).unwrap();
+        }
+
+        assert_eq!(
+            run_program("(fib 20)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(6765)))
+        );
+        assert_eq!(
+            run_program("(fact 20)", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(2432902008176640000)))
+        );
+        for name in ["fib", "fact"] {
+            let Some(CrispExpr::Lambda(lambda)) = env.get(name) else {
+                panic!("{name} should be a lambda");
+            };
+            assert!(matches!(lambda.jit.compiled.get(), Some(Some(c)) if c.ints.is_some()));
+        }
+
+        // Overflowing part way down the recursion is reported as if the
+        // call had been interpreted
+        let err = run_program("(fact 21)", &mut env).unwrap_err();
+        assert_eq!(err.message(), "integer overflow");
+    }
+}
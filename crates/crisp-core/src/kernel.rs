@@ -0,0 +1,471 @@
+//! A fast path for `map`, `filter`, `reduce`, `sum` and folds like
+//! `count-if` and `max-by` over lists of numbers.
+//!
+//! When the function passed to one of them is a lambda whose body only does
+//! arithmetic and comparisons on its params, it's compiled once per fold into
+//! a `Kernel`, which works on unboxed ints or floats instead of making an env
+//! and a `CrispExpr` for every intermediate value. Like the JIT, it only
+//! accepts number and bool literals, the params, `if` and the math builtins
+//! `+`, `-`, `*` and `>`, and only while those names are bound to the
+//! builtins. Ints are added up exactly, and lists mixing ints and floats are
+//! left to the interpreter.
+//!
+//! The JIT compiles the same subset, plus calls a lambda makes to itself,
+//! so `recognize` is shared: it turns a body into the `Body` tree that
+//! kernels evaluate and the JIT lowers to native code.
+
+use crate::{
+    eval::{apply, check_limits, CrispEnv, SPECIAL_FORMS},
+    lang::{CrispExpr, CrispFn, CrispLambda, CrispResult, Primitive},
+    math,
+};
+
+/// A compiled lambda body, which can't fail once compiled
+pub(crate) struct Kernel {
+    body: Body,
+    arity: usize,
+    /// Steps charged per call, roughly what interpreting the body would take
+    cost: usize,
+}
+
+pub(crate) enum Body {
+    Number(Number),
+    Bool(Bool),
+}
+
+/// An expression evaluating to a number
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) enum Number {
+    Const(f32),
+    Int(i64),
+    Param(usize),
+    Add(Vec<Number>),
+    Sub(Box<Number>, Vec<Number>),
+    Mul(Vec<Number>),
+    If(Box<Bool>, Box<Number>, Box<Number>),
+    /// A call the lambda makes to itself, only recognized for the JIT
+    Recurse(Vec<Number>),
+}
+
+/// An expression evaluating to a bool
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) enum Bool {
+    Const(bool),
+    Gt(Box<Number>, Box<Number>),
+    If(Box<Bool>, Box<Bool>, Box<Bool>),
+    Recurse(Vec<Number>),
+}
+
+/// What a name a recognized body calls has to stay bound to for the body
+/// to mean what the interpreter would make of it
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) enum Callee {
+    Builtin(&'static CrispFn),
+    /// The lambda itself
+    Recursive,
+}
+
+/// A lambda body in the numeric subset, along with the names it calls
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) struct Recognized {
+    pub(crate) body: Body,
+    pub(crate) callees: Vec<(String, Callee)>,
+}
+
+/// Recognize `lambda`'s body if it's in the numeric subset, trying it as a
+/// number first and then as a bool. Calls to a lambda `is_self` accepts are
+/// taken as recursive calls; without it, they aren't recognized.
+pub(crate) fn recognize(
+    lambda: &CrispLambda,
+    env: &CrispEnv,
+    is_self: Option<&dyn Fn(&CrispLambda) -> bool>,
+) -> Option<Recognized> {
+    if !lambda.keywords.is_empty() {
+        return None;
+    }
+
+    let compiler = || Compiler {
+        lambda,
+        env: env.globals(),
+        is_self,
+        returns_number: true,
+        callees: vec![],
+    };
+    let mut numbers = compiler();
+    if let Some(number) = numbers.number(&lambda.body) {
+        return Some(Recognized {
+            body: Body::Number(number),
+            callees: numbers.callees,
+        });
+    }
+
+    let mut bools = Compiler {
+        returns_number: false,
+        ..compiler()
+    };
+    let b = bools.bool(&lambda.body)?;
+    Some(Recognized {
+        body: Body::Bool(b),
+        callees: bools.callees,
+    })
+}
+
+impl Kernel {
+    /// Compile `f` if it's a lambda simple enough to run as a kernel
+    pub(crate) fn compile(f: &CrispExpr, env: &CrispEnv) -> Option<Kernel> {
+        let CrispExpr::Lambda(lambda) = f else {
+            return None;
+        };
+        if env.options.trace.is_some() {
+            return None;
+        }
+
+        Some(Kernel {
+            body: recognize(lambda, env, None)?.body,
+            arity: lambda.params.len(),
+            cost: size(&lambda.body),
+        })
+    }
+
+    /// Call the kernel, or return `None` if the lambda has to be
+    /// interpreted: when the arguments aren't all ints or all floats, or the
+    /// result wouldn't have the type the interpreter gives
+    pub(crate) fn call(&self, args: &[&CrispExpr], env: &CrispEnv) -> Option<CrispResult> {
+        if args.len() != self.arity {
+            return None;
+        }
+
+        let res = match ints(args) {
+            Some(args) => match &self.body {
+                Body::Number(number) => Primitive::Int(number.eval_int(&args)?),
+                Body::Bool(b) => Primitive::Bool(b.eval_int(&args)?),
+            },
+            None => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        CrispExpr::Primitive(Primitive::Number(n)) => Some(*n),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<f32>>>()?;
+                match &self.body {
+                    Body::Number(number) if number.floating() => {
+                        Primitive::Number(number.eval(&args))
+                    }
+                    Body::Number(_) => return None,
+                    Body::Bool(b) => Primitive::Bool(b.eval(&args)),
+                }
+            }
+        };
+
+        if let Err(err) = check_limits(env, self.cost) {
+            return Some(Err(err));
+        }
+        Some(Ok(CrispExpr::Primitive(res)))
+    }
+}
+
+/// Call `f`, which `kernel` was compiled from if it could be, on `args`:
+/// through the kernel if it takes them, otherwise through the interpreter
+pub(crate) fn call(
+    f: &CrispExpr,
+    kernel: Option<&Kernel>,
+    args: &[&CrispExpr],
+    env: &CrispEnv,
+) -> CrispResult {
+    match kernel.and_then(|kernel| kernel.call(args, env)) {
+        Some(res) => res,
+        None => apply(
+            f.clone(),
+            args.iter().map(|&arg| arg.clone()).collect(),
+            env,
+        ),
+    }
+}
+
+fn ints(args: &[&CrispExpr]) -> Option<Vec<i64>> {
+    args.iter()
+        .map(|arg| match arg {
+            CrispExpr::Primitive(Primitive::Int(n)) => Some(*n),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Number {
+    fn eval(&self, args: &[f32]) -> f32 {
+        match self {
+            Number::Const(n) => *n,
+            Number::Int(n) => *n as f32,
+            Number::Param(i) => args.get(*i).copied().unwrap_or_default(),
+            Number::Add(xs) => xs.iter().fold(0., |acc, x| acc + x.eval(args)),
+            Number::Sub(first, rest) => rest
+                .iter()
+                .fold(first.eval(args), |acc, x| acc - x.eval(args)),
+            Number::Mul(xs) => xs.iter().fold(1., |acc, x| acc * x.eval(args)),
+            Number::If(test, then, otherwise) => match test.eval(args) {
+                true => then.eval(args),
+                false => otherwise.eval(args),
+            },
+            Number::Recurse(_) => unreachable!("kernels don't recurse"),
+        }
+    }
+
+    /// The value with int arguments, or `None` on overflow or if there's a
+    /// float literal
+    fn eval_int(&self, args: &[i64]) -> Option<i64> {
+        match self {
+            Number::Const(_) => None,
+            Number::Int(n) => Some(*n),
+            Number::Param(i) => args.get(*i).copied(),
+            Number::Add(xs) => xs
+                .iter()
+                .try_fold(0i64, |acc, x| acc.checked_add(x.eval_int(args)?)),
+            Number::Sub(first, rest) => rest.iter().try_fold(first.eval_int(args)?, |acc, x| {
+                acc.checked_sub(x.eval_int(args)?)
+            }),
+            Number::Mul(xs) => xs
+                .iter()
+                .try_fold(1i64, |acc, x| acc.checked_mul(x.eval_int(args)?)),
+            Number::If(test, then, otherwise) => match test.eval_int(args)? {
+                true => then.eval_int(args),
+                false => otherwise.eval_int(args),
+            },
+            Number::Recurse(_) => None,
+        }
+    }
+
+    /// Whether the value is always a float when the arguments are, rather
+    /// than an int worked out from int literals alone
+    pub(crate) fn floating(&self) -> bool {
+        match self {
+            Number::Const(_) | Number::Param(_) | Number::Recurse(_) => true,
+            Number::Int(_) => false,
+            Number::Add(xs) | Number::Mul(xs) => xs.iter().any(Number::floating),
+            Number::Sub(first, rest) => first.floating() || rest.iter().any(Number::floating),
+            Number::If(_, then, otherwise) => then.floating() && otherwise.floating(),
+        }
+    }
+}
+
+impl Bool {
+    fn eval(&self, args: &[f32]) -> bool {
+        match self {
+            Bool::Const(b) => *b,
+            Bool::Gt(a, b) => a.eval(args) > b.eval(args),
+            Bool::If(test, then, otherwise) => match test.eval(args) {
+                true => then.eval(args),
+                false => otherwise.eval(args),
+            },
+            Bool::Recurse(_) => unreachable!("kernels don't recurse"),
+        }
+    }
+
+    fn eval_int(&self, args: &[i64]) -> Option<bool> {
+        match self {
+            Bool::Const(b) => Some(*b),
+            Bool::Gt(a, b) => Some(a.eval_int(args)? > b.eval_int(args)?),
+            Bool::If(test, then, otherwise) => match test.eval_int(args)? {
+                true => then.eval_int(args),
+                false => otherwise.eval_int(args),
+            },
+            Bool::Recurse(_) => None,
+        }
+    }
+}
+
+enum Op<'e> {
+    If,
+    Math(&'e str),
+    Recurse,
+}
+
+struct Compiler<'c> {
+    lambda: &'c CrispLambda,
+    /// The global env, where names the lambda didn't close over are bound
+    env: &'c CrispEnv<'c>,
+    is_self: Option<&'c dyn Fn(&CrispLambda) -> bool>,
+    /// Whether recursive calls give a number rather than a bool
+    returns_number: bool,
+    callees: Vec<(String, Callee)>,
+}
+
+impl Compiler<'_> {
+    fn number(&mut self, expr: &CrispExpr) -> Option<Number> {
+        match expr {
+            CrispExpr::Primitive(Primitive::Number(n)) => Some(Number::Const(*n)),
+            CrispExpr::Primitive(Primitive::Int(n)) => Some(Number::Int(*n)),
+            CrispExpr::Symbol(name) => self.param(name).map(Number::Param),
+            CrispExpr::List(list) => match self.call(list)? {
+                (Op::Math("+"), args) => Some(Number::Add(self.numbers(args)?)),
+                (Op::Math("*"), args) => Some(Number::Mul(self.numbers(args)?)),
+                (Op::Math("-"), [first, rest @ ..]) => Some(Number::Sub(
+                    Box::new(self.number(first)?),
+                    self.numbers(rest)?,
+                )),
+                (Op::If, [test, then, otherwise]) => Some(Number::If(
+                    Box::new(self.bool(test)?),
+                    Box::new(self.number(then)?),
+                    Box::new(self.number(otherwise)?),
+                )),
+                (Op::Recurse, args) if self.returns_number => {
+                    Some(Number::Recurse(self.numbers(args)?))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn bool(&mut self, expr: &CrispExpr) -> Option<Bool> {
+        match expr {
+            CrispExpr::Primitive(Primitive::Bool(b)) => Some(Bool::Const(*b)),
+            CrispExpr::List(list) => match self.call(list)? {
+                (Op::Math(">"), [a, b]) => Some(Bool::Gt(
+                    Box::new(self.number(a)?),
+                    Box::new(self.number(b)?),
+                )),
+                (Op::If, [test, then, otherwise]) => Some(Bool::If(
+                    Box::new(self.bool(test)?),
+                    Box::new(self.bool(then)?),
+                    Box::new(self.bool(otherwise)?),
+                )),
+                (Op::Recurse, args) if !self.returns_number => {
+                    Some(Bool::Recurse(self.numbers(args)?))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn numbers(&mut self, exprs: &[CrispExpr]) -> Option<Vec<Number>> {
+        exprs.iter().map(|expr| self.number(expr)).collect()
+    }
+
+    /// Split a call into what it calls and its arguments, if the name means
+    /// `if`, a math builtin or the lambda itself to the interpreter too
+    fn call<'e>(&mut self, list: &'e [CrispExpr]) -> Option<(Op<'e>, &'e [CrispExpr])> {
+        let (CrispExpr::Symbol(name), args) = list.split_first()? else {
+            return None;
+        };
+        if name == "if" {
+            return Some((Op::If, args));
+        }
+        if SPECIAL_FORMS.contains(&name.as_str())
+            || self.param(name).is_some()
+            || self.lambda.closure.as_ref().is_some_and(|c| c.binds(name))
+            || self.env.lookup_macro(name).is_some()
+        {
+            return None;
+        }
+
+        let (op, callee) = match (self.env.lookup(name)?, math::builtin(name)) {
+            (CrispExpr::Fn(f), Some(builtin)) if f == builtin => {
+                (Op::Math(name), Callee::Builtin(builtin))
+            }
+            (CrispExpr::Lambda(callee), _)
+                if self.is_self.is_some_and(|is_self| is_self(callee)) =>
+            {
+                (Op::Recurse, Callee::Recursive)
+            }
+            _ => return None,
+        };
+        self.callees.push((name.clone(), callee));
+        Some((op, args))
+    }
+
+    fn param(&self, name: &str) -> Option<usize> {
+        self.lambda.params.iter().position(|param| param == name)
+    }
+}
+
+/// Number of expressions in `expr`
+fn size(expr: &CrispExpr) -> usize {
+    match expr {
+        CrispExpr::List(items) => 1 + items.iter().map(size).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eval::CrispEnv,
+        lang::{CrispExpr, Primitive},
+        run_program,
+    };
+
+    fn run(prog: &str, env: &mut CrispEnv) -> CrispExpr {
+        run_program(prog, env).unwrap()
+    }
+
+    #[test]
+    fn kernels_agree_with_the_interpreter() {
+        let mut env = CrispEnv::default();
+        run(
+            "(def big (fn (x) (> (* x x) 10))) \
+             (def score (fn (x) (if (> x 0) (- x 1 2) (+ x 0.5)))) \
+             (def xs (quote (-3 1 4 -1 5 9)))",
+            &mut env,
+        );
+
+        for (fold, f) in [
+            ("count-if", "big"),
+            ("every?", "big"),
+            ("some", "big"),
+            ("max-by", "score"),
+            ("min-by", "score"),
+            ("map", "score"),
+            ("filter", "big"),
+            ("sum", "score"),
+        ] {
+            // Calling through another lambda keeps the fold interpreted
+            let fast = run(&format!("({fold} {f} xs)"), &mut env);
+            let slow = run(&format!("({fold} (fn (x) ({f} x)) xs)"), &mut env);
+            assert_eq!(fast, slow, "{fold}");
+        }
+        assert_eq!(
+            run("(reduce (fn (acc x) (- (* acc 2) x)) 1 xs)", &mut env),
+            run(
+                "(reduce (fn (acc x) (- (* acc 2) (identity x))) 1 xs)",
+                &mut env
+            )
+        );
+
+        // Rebinding a builtin the lambda uses sends it back through the
+        // interpreter
+        let mut scope = CrispEnv::from_parent(&env);
+        run("(def * (fn (a b) 0))", &mut scope);
+        assert_eq!(
+            run("(count-if (fn (x) (> (* x x) 10)) xs)", &mut scope),
+            CrispExpr::Primitive(Primitive::Int(0))
+        );
+    }
+
+    #[test]
+    fn kernels_fall_back_on_other_items() {
+        let mut env = CrispEnv::default();
+        run("(def big (fn (x) (> x 2)))", &mut env);
+
+        assert!(run_program("(count-if big (quote (1 3 oops)))", &mut env).is_err());
+
+        // Ints that overflow are left to the interpreter to report
+        let err = run_program(
+            "(count-if (fn (x) (> (* x x) 0)) (quote (9223372036854775807)))",
+            &mut env,
+        )
+        .unwrap_err();
+        assert_eq!(err.message(), "integer overflow");
+
+        // Only the JIT runs calls a lambda makes to itself
+        run(
+            "(def fact (fn (n) (if (> 2 n) 1 (* n (fact (- n 1))))))",
+            &mut env,
+        );
+        assert_eq!(
+            run("(map fact (quote (1 5 10)))", &mut env),
+            run("(quote (1 120 3628800))", &mut env)
+        );
+    }
+}
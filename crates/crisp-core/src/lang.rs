@@ -0,0 +1,588 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Instant;
+
+use indexmap::IndexMap;
+
+use crate::decimal::Decimal;
+use crate::eval::CrispEnv;
+
+/// A region of source text
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset just past the last character
+    pub end: usize,
+    /// Line of the first character, starting from 1
+    pub line: u32,
+    /// Column of the first character, starting from 1
+    pub col: u32,
+}
+
+impl Span {
+    /// A span covering everything from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CrispError {
+    SyntaxError(String),
+    MissingParen(u32, u32),
+    EvalError(String),
+    /// A compiled program or snapshot that can't be written or loaded
+    FormatError(String),
+    /// Evaluation was cancelled through `EvalOptions::interrupt`
+    Interrupted,
+    /// Evaluation ran past `EvalOptions::deadline`
+    TimedOut,
+    /// Source over one of the limits in `ParseOptions`
+    TooLarge(String),
+    /// `/`, `mod` or `rem` with a zero divisor
+    DivisionByZero,
+    /// A bug in crisp or a native function: a panic, caught before it
+    /// could reach the embedder
+    Internal(String),
+    /// An error tied to the region of source that caused it
+    Spanned(Box<CrispError>, Span),
+}
+
+impl CrispError {
+    /// Attach a span to the error, unless it already points somewhere more precise
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::Spanned(..) => self,
+            err => Self::Spanned(Box::new(err), span),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Spanned(_, span) => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// A short name for the kind of error, for tools that consume errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::SyntaxError(_) | Self::MissingParen(..) => "syntax",
+            Self::EvalError(_) => "eval",
+            Self::FormatError(_) => "format",
+            Self::Interrupted => "interrupted",
+            Self::TimedOut => "timeout",
+            Self::TooLarge(_) => "limit",
+            Self::DivisionByZero => "arithmetic",
+            Self::Internal(_) => "internal",
+            Self::Spanned(err, _) => err.kind(),
+        }
+    }
+
+    /// The error's message, without the description of its kind
+    pub fn message(&self) -> String {
+        match self {
+            Self::SyntaxError(msg)
+            | Self::EvalError(msg)
+            | Self::FormatError(msg)
+            | Self::TooLarge(msg)
+            | Self::Internal(msg) => msg.clone(),
+            Self::MissingParen(..) => "missing paren".to_string(),
+            Self::Interrupted => "evaluation interrupted".to_string(),
+            Self::TimedOut => "evaluation timed out".to_string(),
+            Self::DivisionByZero => "division by zero".to_string(),
+            Self::Spanned(err, _) => err.message(),
+        }
+    }
+
+    /// The error without any span attached
+    pub fn inner(&self) -> &CrispError {
+        match self {
+            Self::Spanned(err, _) => err.inner(),
+            err => err,
+        }
+    }
+
+    /// The error as raised by `path`, a file other than the one being run,
+    /// with the file and line in the message since a span would point into
+    /// the wrong source. Interruptions and timeouts are kept as they are.
+    pub fn in_file(self, path: &str) -> Self {
+        let line = match (self.span(), self.inner()) {
+            (Some(span), _) => Some(span.line),
+            (None, Self::MissingParen(line, _)) => Some(*line),
+            _ => None,
+        };
+
+        match self.inner() {
+            Self::Interrupted | Self::TimedOut => self.inner().clone(),
+            _ => Self::EvalError(match line {
+                Some(line) => format!("{path}, line {line}: {}", self.message()),
+                None => format!("{path}: {}", self.message()),
+            }),
+        }
+    }
+}
+
+impl std::error::Error for CrispError {}
+
+impl Display for CrispError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            Self::SyntaxError(msg) => format!("syntax error: {msg}"),
+            Self::MissingParen(line, char) => format!("missing paren at line {line}, char {char}"),
+            Self::EvalError(msg) => format!("error evaluating expr: {msg}"),
+            Self::FormatError(msg) => format!("can't load compiled program: {msg}"),
+            Self::Interrupted => "evaluation interrupted".to_string(),
+            Self::TimedOut => "evaluation timed out".to_string(),
+            Self::TooLarge(msg) => format!("input too large: {msg}"),
+            Self::DivisionByZero => "division by zero".to_string(),
+            Self::Internal(msg) => format!("internal error: {msg}"),
+            Self::Spanned(err, _) => err.to_string(),
+        };
+
+        write!(f, "{msg}")
+    }
+}
+
+/// Signature of a native function. It's given the env it's called in, so
+/// builtins like `map` can call the functions they're passed.
+pub type NativeFn = dyn Fn(&[CrispExpr], &CrispEnv) -> CrispResult + Send + Sync;
+
+/// A native function. Kept behind an `Arc` so builtins can capture state and
+/// values can be shared between threads.
+#[derive(Clone)]
+pub struct CrispFn(pub Arc<NativeFn>);
+
+impl CrispFn {
+    /// A native function of its arguments alone
+    pub fn new(f: impl Fn(&[CrispExpr]) -> CrispResult + Send + Sync + 'static) -> Self {
+        Self(Arc::new(move |args: &[CrispExpr], _: &CrispEnv| f(args)))
+    }
+
+    /// A native function that can call back into the interpreter, e.g. with
+    /// `eval::apply`, in the env it's called in
+    pub fn with_env(
+        f: impl Fn(&[CrispExpr], &CrispEnv) -> CrispResult + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrispLambda {
+    pub params: Vec<String>,
+    /// Params declared after `&key`, which calls pass by name after the
+    /// positional args, like `:color "red"`. Those left out are nil. Each is
+    /// bound as a local of its own, not collected into a map of named args.
+    pub keywords: Vec<String>,
+    pub body: Box<CrispExpr>,
+    /// The locals of the function call or `let` the lambda was made in,
+    /// which calls to it see instead of their caller's, even once that
+    /// call has returned. `None` for lambdas made in the global env.
+    pub closure: Option<Arc<Closure>>,
+    /// Call count and native code, shared by every copy of the lambda
+    #[cfg(feature = "jit")]
+    pub jit: Arc<crate::jit::JitSlot>,
+}
+
+impl CrispLambda {
+    pub fn new(params: Vec<String>, body: CrispExpr) -> Self {
+        Self {
+            params,
+            keywords: vec![],
+            body: Box::new(body),
+            closure: None,
+            #[cfg(feature = "jit")]
+            jit: Arc::default(),
+        }
+    }
+
+    /// The params as they're written in the lambda's source, without the
+    /// parens, e.g. `shape &key color`
+    pub fn param_list(&self) -> String {
+        let mut params = self.params.clone();
+        if !self.keywords.is_empty() {
+            params.push("&key".to_string());
+            params.extend(self.keywords.iter().cloned());
+        }
+        params.join(" ")
+    }
+}
+
+/// The locals of a function call or `let` that lambdas were made in,
+/// shared by those lambdas. Definitions the scope makes after a lambda was
+/// made are shared too, so a local function can call a helper defined below
+/// it.
+#[derive(Default)]
+pub struct Closure {
+    symbols: Mutex<HashMap<String, CrispExpr>>,
+    /// The locals of the scope enclosing this one, if it isn't global
+    parent: Option<Arc<Closure>>,
+}
+
+impl Closure {
+    pub fn new(symbols: HashMap<String, CrispExpr>, parent: Option<Arc<Closure>>) -> Self {
+        Self {
+            symbols: Mutex::new(symbols),
+            parent,
+        }
+    }
+
+    /// The value `name` is bound to in this scope or an enclosing one,
+    /// along with the number of enclosing scopes searched
+    pub fn find(&self, name: &str) -> (Option<CrispExpr>, usize) {
+        let mut closure = self;
+        let mut scopes = 0;
+        loop {
+            if let Some(value) = closure.symbols().get(name) {
+                return (Some(value.clone()), scopes);
+            }
+            match &closure.parent {
+                Some(outer) => closure = outer,
+                None => return (None, scopes),
+            }
+            scopes += 1;
+        }
+    }
+
+    /// Whether `name` is bound in this scope or an enclosing one
+    pub fn binds(&self, name: &str) -> bool {
+        self.symbols().contains_key(name) || self.parent.as_ref().is_some_and(|p| p.binds(name))
+    }
+
+    pub fn insert(&self, name: String, value: CrispExpr) {
+        self.symbols().insert(name, value);
+    }
+
+    pub fn remove(&self, name: &str) {
+        self.symbols().remove(name);
+    }
+
+    /// Every binding visible from this scope, sorted by name
+    pub fn bindings(&self) -> Vec<(String, CrispExpr)> {
+        let mut bindings = match &self.parent {
+            Some(parent) => parent.bindings().into_iter().collect(),
+            None => HashMap::new(),
+        };
+        bindings.extend(self.symbols().clone());
+
+        let mut bindings: Vec<_> = bindings.into_iter().collect();
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings
+    }
+
+    fn symbols(&self) -> MutexGuard<'_, HashMap<String, CrispExpr>> {
+        // A panic while the lock was held can't leave the map half changed
+        self.symbols.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Lambdas in a closure can refer back to it, so neither of these looks
+// inside
+impl Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Closure")
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for CrispFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Function")
+    }
+}
+
+impl PartialEq for CrispFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Finds and reads the files `load` evaluates. The core can't read files,
+/// so `load` fails unless the env was built with a loader, like the one the
+/// `fs` group adds.
+pub trait Loader: Send + Sync {
+    /// Resolve `name`, as written in a `load` in the file `from` or outside
+    /// any file if `from` is `None`, returning the path it resolves to and
+    /// the file's source. Loading the same file must give the same path.
+    fn load(&self, name: &str, from: Option<&str>) -> Result<(String, String), CrispError>;
+}
+
+/// Where the calls and results traced with `EvalOptions::trace` go, one
+/// line each. The core can't print, so the tracer does; crisp-std's
+/// `io::StderrTracer` writes the lines to stderr.
+pub trait Tracer: Send + Sync {
+    fn trace(&self, line: &str);
+}
+
+impl<F: Fn(&str) + Send + Sync> Tracer for F {
+    fn trace(&self, line: &str) {
+        self(line)
+    }
+}
+
+impl Debug for dyn Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+/// Tells the time for `with-timeout` deadlines, so embedders can run
+/// scripts against a clock they control. Envs without one use the
+/// system's monotonic clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+impl Debug for dyn Clock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+/// Something outside the interpreter that a value can refer to, like an
+/// open file, or state that changes, like a string builder. The core only
+/// knows how to name and close resources; the builtins that make them know
+/// what else they can do.
+pub trait Resource: Any + Send + Sync {
+    /// What the resource is, shown when it's printed, e.g. a file's path
+    fn name(&self) -> &str;
+
+    /// Release the resource. Closing it again does nothing.
+    fn close(&self);
+}
+
+/// A value referring to a resource. Clones share the resource, and a
+/// handle is only equal to its clones.
+#[derive(Clone)]
+pub struct Handle(Arc<dyn Resource>);
+
+impl Handle {
+    pub fn new(resource: impl Resource) -> Self {
+        Self(Arc::new(resource))
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    pub fn close(&self) {
+        self.0.close()
+    }
+
+    /// A reference to the resource that doesn't keep it alive
+    pub fn downgrade(&self) -> Weak<dyn Resource> {
+        Arc::downgrade(&self.0)
+    }
+
+    /// A handle to the resource `weak` refers to, if another handle still
+    /// keeps it alive
+    pub fn upgrade(weak: &Weak<dyn Resource>) -> Option<Self> {
+        weak.upgrade().map(Self)
+    }
+
+    /// The resource, if it's a `T`
+    pub fn downcast<T: Resource>(&self) -> Option<&T> {
+        (&*self.0 as &dyn Any).downcast_ref()
+    }
+}
+
+impl Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({})", self.name())
+    }
+}
+
+impl PartialEq for Handle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    /// The absence of a value, e.g. from `(begin)`
+    Nil,
+    Number(f32),
+    /// A whole number, written without a decimal point like `3`
+    Int(i64),
+    /// An exact decimal, written like `1.50m`
+    Decimal(Decimal),
+    Bool(bool),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CrispExpr {
+    Symbol(String),
+    Primitive(Primitive),
+    List(Vec<CrispExpr>),
+    /// Values indexed in constant time, written like `[1 2 3]`
+    Vector(Vec<CrispExpr>),
+    /// Values looked up by key, written like `{:name "jim" :age 4}`. Keys
+    /// keep the order they were first added in.
+    Map(IndexMap<MapKey, CrispExpr>),
+    Fn(CrispFn),
+    Lambda(CrispLambda),
+    /// A resource like an open file, made by a builtin such as `open`
+    Handle(Handle),
+    /// A dense numeric array
+    #[cfg(feature = "ndarray")]
+    Array(crate::array::Array),
+}
+
+impl CrispExpr {
+    pub fn is_symbol(&self) -> bool {
+        matches!(self, Self::Symbol(_))
+    }
+
+    /// The value as a float if it's a number, converting ints
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Self::Primitive(Primitive::Number(n)) => Some(*n),
+            Self::Primitive(Primitive::Int(n)) => Some(*n as f32),
+            _ => None,
+        }
+    }
+
+    /// Whether conditionals, `and` and `or` count the value as true:
+    /// anything but false and nil
+    pub fn is_truthy(&self) -> bool {
+        !matches!(
+            self,
+            Self::Primitive(Primitive::Bool(false) | Primitive::Nil)
+        )
+    }
+}
+
+pub type CrispResult = Result<CrispExpr, CrispError>;
+
+/// A value used as a key in a map. Keys are data: nil, bools, numbers,
+/// strings, symbols and keywords, and lists and vectors of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapKey(CrispExpr);
+
+impl MapKey {
+    /// `expr` as a key, if it's data. NaN can't be a key, since it isn't
+    /// equal to itself.
+    pub fn new(expr: CrispExpr) -> Result<Self, CrispError> {
+        fn is_key(expr: &CrispExpr) -> bool {
+            match expr {
+                CrispExpr::Primitive(Primitive::Number(n)) => !n.is_nan(),
+                CrispExpr::Primitive(_) | CrispExpr::Symbol(_) => true,
+                CrispExpr::List(xs) | CrispExpr::Vector(xs) => xs.iter().all(is_key),
+                _ => false,
+            }
+        }
+
+        match is_key(&expr) {
+            true => Ok(Self(expr)),
+            false => Err(CrispError::EvalError(format!(
+                "{} can't be a map key, only data like keywords, strings and numbers",
+                crate::format::to_source(&expr)
+            ))),
+        }
+    }
+
+    pub fn expr(&self) -> &CrispExpr {
+        &self.0
+    }
+
+    pub fn into_expr(self) -> CrispExpr {
+        self.0
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Consistent with `==`, so e.g. `0.0` and `-0.0` hash alike, as do
+        // `1.5m` and `1.50m`
+        fn hash_expr<H: Hasher>(expr: &CrispExpr, state: &mut H) {
+            std::mem::discriminant(expr).hash(state);
+            match expr {
+                CrispExpr::Primitive(p) => {
+                    std::mem::discriminant(p).hash(state);
+                    match p {
+                        Primitive::Nil => {}
+                        Primitive::Number(n) if *n == 0. => 0u32.hash(state),
+                        Primitive::Number(n) => n.to_bits().hash(state),
+                        Primitive::Int(n) => n.hash(state),
+                        Primitive::Decimal(d) => {
+                            let digits = d.to_string();
+                            match digits.contains('.') {
+                                true => digits.trim_end_matches('0').trim_end_matches('.'),
+                                false => &digits,
+                            }
+                            .hash(state)
+                        }
+                        Primitive::Bool(b) => b.hash(state),
+                        Primitive::Str(s) => s.hash(state),
+                    }
+                }
+                CrispExpr::Symbol(name) => name.hash(state),
+                CrispExpr::List(xs) | CrispExpr::Vector(xs) => {
+                    xs.len().hash(state);
+                    for x in xs {
+                        hash_expr(x, state);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        hash_expr(&self.0, state)
+    }
+}
+
+impl Display for CrispExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            Self::Primitive(val) => match val {
+                Primitive::Nil => "nil".to_string(),
+                Primitive::Bool(b) => format!("{}", b),
+                Primitive::Number(n) => format!("{}", n),
+                Primitive::Int(n) => n.to_string(),
+                Primitive::Decimal(d) => d.to_string(),
+                Primitive::Str(s) => s.clone(),
+            },
+            Self::Symbol(name) => format!("Symbol: {name}"),
+            Self::List(exps) => format!(
+                "List: ({:?})",
+                exps.iter()
+                    .map(|expr| expr.to_string())
+                    .collect::<Vec<String>>()
+            ),
+            Self::Vector(items) => format!(
+                "Vector: [{:?}]",
+                items
+                    .iter()
+                    .map(|expr| expr.to_string())
+                    .collect::<Vec<String>>()
+            ),
+            Self::Map(_) => format!("Map: {}", crate::format::to_source(self)),
+            Self::Fn(_) => "Fn: #<builtin>".to_string(),
+            Self::Lambda(lambda) => {
+                format!("Lambda: ({}) {}", lambda.param_list(), lambda.body)
+            }
+            Self::Handle(handle) => format!("Handle: {}", handle.name()),
+            #[cfg(feature = "ndarray")]
+            Self::Array(a) => format!("Array: {a}"),
+        };
+
+        write!(f, "{msg}")
+    }
+}
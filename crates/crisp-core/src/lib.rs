@@ -0,0 +1,420 @@
+//! The core of the crisp interpreter: the lexer, parser, evaluator and value
+//! types, with builtins that only compute. Nothing here reaches outside the
+//! interpreter but to read the monotonic clock for `with-timeout`, and
+//! `EvalOptions::clock` can replace that; traces go to whatever
+//! `EvalOptions::trace` is given. `crisp-std` layers printing, files and
+//! other capabilities on top through `CrispEnvBuilder`.
+
+use eval::{eval, CrispEnv};
+use lang::{CrispError, CrispExpr, CrispResult, Primitive, Span};
+use parse::{parse_source, Form};
+use source_map::SourceMap;
+
+#[cfg(feature = "ndarray")]
+pub mod array;
+pub mod bundle;
+pub mod cache;
+pub mod compile;
+pub mod decimal;
+pub mod diff;
+pub mod doc;
+pub mod eval;
+pub mod format;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+mod graph;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod kernel;
+pub mod lang;
+pub mod lint;
+mod list;
+pub mod macros;
+mod map;
+mod math;
+pub mod parse;
+pub mod pool;
+mod queue;
+pub mod snapshot;
+mod source_map;
+mod string_builder;
+mod template;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod vector;
+pub mod visit;
+mod weak;
+
+pub use diff::{diff, Edit};
+
+/// The version of crisp, like `0.1.0`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The optional features crisp was built with, which scripts can check for
+/// with `feature?`
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "arbitrary")]
+    "arbitrary",
+    #[cfg(feature = "jit")]
+    "jit",
+    #[cfg(feature = "ndarray")]
+    "ndarray",
+    #[cfg(feature = "testing")]
+    "testing",
+    #[cfg(feature = "tracing")]
+    "tracing",
+];
+
+/// A piece of source text along with where it came from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+}
+
+impl PartialEq<&str> for Token {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+pub fn lexer(s: &str) -> Vec<Token> {
+    lex_with_comments(s).0
+}
+
+/// Split source into tokens like `lexer`, also returning the `;` comments it
+/// skipped, each running to the end of its line
+pub fn lex_with_comments(s: &str) -> (Vec<Token>, Vec<Token>) {
+    let mut tokens = vec![];
+    let mut comments = vec![];
+    // Start offset, line and column of the token being read
+    let mut current: Option<(usize, u32, u32)> = None;
+    let (mut line, mut col) = (1, 1);
+
+    let mut finish = |current: &mut Option<(usize, u32, u32)>, end: usize| {
+        if let Some((start, line, col)) = current.take() {
+            tokens.push(Token {
+                text: s[start..end].to_string(),
+                span: Span {
+                    start,
+                    end,
+                    line,
+                    col,
+                },
+            });
+        }
+    };
+
+    // Whether the token being read is a string literal, and whether the
+    // last character in it was an unescaped backslash
+    let (mut in_str, mut escaped) = (false, false);
+    // Start offset, line and column of the comment being skipped
+    let mut comment: Option<(usize, u32, u32)> = None;
+
+    for (i, c) in s.char_indices() {
+        if let Some((start, line, col)) = comment {
+            if c == '\n' {
+                comments.push(Token {
+                    text: s[start..i].to_string(),
+                    span: Span {
+                        start,
+                        end: i,
+                        line,
+                        col,
+                    },
+                });
+                comment = None;
+            }
+        } else if in_str {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_str = false;
+                finish(&mut current, i + 1);
+            }
+        } else {
+            match c {
+                ';' => {
+                    finish(&mut current, i);
+                    comment = Some((i, line, col));
+                }
+                '"' => {
+                    finish(&mut current, i);
+                    current = Some((i, line, col));
+                    in_str = true;
+                }
+                '(' | ')' | '[' | ']' | '{' | '}' | '\'' => {
+                    finish(&mut current, i);
+                    current = Some((i, line, col));
+                    finish(&mut current, i + 1);
+                }
+                c if c.is_whitespace() => finish(&mut current, i),
+                _ => {
+                    current.get_or_insert((i, line, col));
+                }
+            }
+        }
+
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    finish(&mut current, s.len());
+    if let Some((start, line, col)) = comment {
+        comments.push(Token {
+            text: s[start..].to_string(),
+            span: Span {
+                start,
+                end: s.len(),
+                line,
+                col,
+            },
+        });
+    }
+
+    (tokens, comments)
+}
+
+/// The value of a program along with the top-level form that produced it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Evaluation {
+    pub value: CrispExpr,
+    /// Index of the form among the program's top-level forms
+    pub form: usize,
+    /// Where the form is in the source
+    pub span: Span,
+}
+
+/// Evaluate every top-level form of a program in order, returning the value
+/// of the last one, or nil if there are none. Errors point at the span of the form that raised them.
+/// Source over the env's `ParseOptions` limits is rejected unparsed.
+pub fn eval_str(prog: &str, env: &mut CrispEnv) -> Result<Evaluation, CrispError> {
+    let forms = catch_panics(|| parse_source(prog, &env.options.parse))?;
+    eval_forms(&forms, env)
+}
+
+/// Evaluate already parsed top-level forms in order, like `eval_str`
+pub fn eval_forms(forms: &[Form], env: &mut CrispEnv) -> Result<Evaluation, CrispError> {
+    let res = catch_panics(|| eval_each(forms, env));
+    // A panic can leave the form being evaluated behind
+    env.form_span = None;
+    res
+}
+
+fn eval_each(forms: &[Form], env: &mut CrispEnv) -> Result<Evaluation, CrispError> {
+    let mut last = None;
+
+    for (i, form) in forms.iter().enumerate() {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("form", index = i, line = form.span.line).entered();
+
+        env.form_span = Some(form.span);
+        env.take_trace();
+        let value = eval(&form.expr, env).map_err(|err| {
+            let trace = env.take_trace();
+            let span = SourceMap::new(form).span(&trace);
+            err.at(span.unwrap_or(form.span))
+        });
+        env.form_span = None;
+        env.flush_globals();
+        let value = value?;
+
+        if let Some(name) = form.defines() {
+            match &form.doc {
+                Some(doc) => env.docs.insert(name.to_string(), doc.clone()),
+                None => env.docs.remove(name),
+            };
+        }
+
+        last = Some(Evaluation {
+            value,
+            form: i,
+            span: form.span,
+        });
+    }
+
+    // A program of nothing but whitespace and comments does nothing
+    Ok(last.unwrap_or(Evaluation {
+        value: CrispExpr::Primitive(Primitive::Nil),
+        form: 0,
+        span: Span {
+            line: 1,
+            col: 1,
+            ..Span::default()
+        },
+    }))
+}
+
+/// Run `f`, turning a panic into `CrispError::Internal` so that a bug can't
+/// take down the program embedding crisp
+fn catch_panics<T>(f: impl FnOnce() -> Result<T, CrispError>) -> Result<T, CrispError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let msg = match panic.downcast::<String>() {
+            Ok(msg) => *msg,
+            Err(panic) => match panic.downcast::<&str>() {
+                Ok(msg) => msg.to_string(),
+                Err(_) => "panicked".to_string(),
+            },
+        };
+        Err(CrispError::Internal(msg))
+    })
+}
+
+pub fn run_program(prog: &str, env: &mut CrispEnv) -> CrispResult {
+    eval_str(prog, env).map(|res| res.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lex_basic() {
+        let text = "(3 4 5)";
+        let tokens = lexer(text);
+
+        assert_eq!(tokens, vec!["(", "3", "4", "5", ")"]);
+    }
+
+    #[test]
+    fn lex_spans() {
+        let tokens = lexer("(def x\n  10)");
+
+        assert_eq!(
+            tokens[3].span,
+            Span {
+                start: 9,
+                end: 11,
+                line: 2,
+                col: 3
+            }
+        );
+    }
+
+    #[test]
+    fn lex_comments() {
+        let (tokens, comments) = lex_with_comments("(+ 1 ; one\n   2) ; \"three\" (\n; end");
+
+        assert_eq!(tokens, vec!["(", "+", "1", "2", ")"]);
+        assert_eq!(tokens[3].span.line, 2);
+        assert_eq!(comments, vec!["; one", "; \"three\" (", "; end"]);
+        assert_eq!(lexer("(f \"a;b\")"), vec!["(", "f", "\"a;b\"", ")"]);
+    }
+
+    #[test]
+    fn run_program_error_span() {
+        let mut env = CrispEnv::default();
+        let err = run_program("  (+ 1 x)", &mut env).unwrap_err();
+        let form_err = run_program("  (+ 1 (quote x))", &mut env).unwrap_err();
+
+        assert_eq!(
+            err.inner(),
+            &lang::CrispError::EvalError("Unknown symbol: x".to_string())
+        );
+        assert_eq!(err.span().map(|span| (span.start, span.end)), Some((7, 8)));
+        assert_eq!(
+            form_err.span().map(|span| (span.start, span.end)),
+            Some((2, 17))
+        );
+        assert_eq!(err.kind(), "eval");
+        assert_eq!(err.message(), "Unknown symbol: x");
+    }
+
+    #[test]
+    fn definitions_keep_their_docs() {
+        let mut env = CrispEnv::default();
+        run_program(
+            ";; Twice x\n(def double (fn (x) (* 2 x)))\n;; Swap args\n(defmacro swap (f a b) (f b a))",
+            &mut env,
+        )
+        .unwrap();
+
+        assert_eq!(env.doc("double"), Some("Twice x"));
+        assert_eq!(env.doc("swap"), Some("Swap args"));
+
+        env.undef("double", false).unwrap();
+        assert_eq!(env.doc("double"), None);
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let mut env = CrispEnv::default();
+        env.symbols.insert(
+            "boom".to_string(),
+            CrispExpr::Fn(lang::CrispFn::new(|_| panic!("boom"))),
+        );
+
+        let err = run_program("(+ 1 (boom))", &mut env).unwrap_err();
+        assert_eq!(err.inner(), &CrispError::Internal("boom".to_string()));
+        assert_eq!(err.kind(), "internal");
+        assert_eq!(
+            run_program("(+ 1 2)", &mut env),
+            Ok(CrispExpr::Primitive(lang::Primitive::Int(3)))
+        );
+    }
+
+    #[test]
+    fn eval_str_last_form() {
+        let mut env = CrispEnv::default();
+        let res = eval_str("(def x 2)\n(+ x 1)", &mut env).unwrap();
+
+        assert_eq!(res.value, CrispExpr::Primitive(lang::Primitive::Int(3)));
+        assert_eq!(res.form, 1);
+        assert_eq!((res.span.start, res.span.end, res.span.line), (10, 17, 2));
+    }
+
+    #[test]
+    fn empty_programs_are_nil() {
+        let mut env = CrispEnv::default();
+        for prog in ["", "   \n", "; just a comment"] {
+            assert_eq!(
+                run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(lang::Primitive::Nil)),
+                "{prog:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn eval_str_emits_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{span, Event, Metadata, Subscriber};
+
+        /// Records the name of every span created
+        #[derive(Clone, Default)]
+        struct Spans(Arc<Mutex<Vec<String>>>);
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, attrs: &span::Attributes) -> span::Id {
+                let mut spans = self.0.lock().unwrap();
+                spans.push(attrs.metadata().name().to_string());
+                span::Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, _: &Event) {}
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let spans = Spans::default();
+        tracing::subscriber::with_default(spans.clone(), || {
+            eval_str("(def x 2)\n(+ x 1)", &mut CrispEnv::default()).unwrap();
+        });
+
+        assert_eq!(*spans.0.lock().unwrap(), ["parse", "form", "form", "call"]);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::{
+    eval::{deprecation_message, is_keyword, CrispEnv, SPECIAL_FORMS},
+    lang::CrispExpr,
+    visit::{walk, Visitor, Walk},
+};
+
+/// A likely mistake found without running the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub message: String,
+}
+
+/// Check a program's top-level forms for unknown symbols and malformed
+/// special forms, using `env` to resolve builtins
+pub fn lint(forms: &[CrispExpr], env: &CrispEnv) -> Vec<Lint> {
+    let mut linter = Linter {
+        env,
+        globals: vec![],
+        lints: vec![],
+    };
+
+    // Collect every top-level definition up front so functions can refer to
+    // each other regardless of the order they're defined in
+    let mut names = vec![];
+    collect_defs(forms, &mut names);
+    collect_global_defs(forms, &mut names);
+
+    for name in names {
+        if linter.globals.contains(&name) || env.get(&name).is_some() {
+            linter.warn(format!("'{name}' is already defined"));
+        } else {
+            linter.globals.push(name);
+        }
+    }
+
+    for form in forms {
+        linter.check(form, &[]);
+    }
+
+    linter.lints
+}
+
+/// Find the names bound by `def`s at the top level, including inside `begin`
+fn collect_defs(forms: &[CrispExpr], names: &mut Vec<String>) {
+    for form in forms {
+        if let CrispExpr::List(xs) = form {
+            match xs.as_slice() {
+                [CrispExpr::Symbol(def), CrispExpr::Symbol(name), ..]
+                    if def == "def" || def == "defmacro" =>
+                {
+                    names.push(name.clone())
+                }
+                [CrispExpr::Symbol(begin), body @ ..] if begin == "begin" => {
+                    collect_defs(body, names)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Find the names bound by `def-global`s anywhere in the program, since they
+/// can be defined from inside functions
+fn collect_global_defs(forms: &[CrispExpr], names: &mut Vec<String>) {
+    struct GlobalDefs<'n>(&'n mut Vec<String>);
+
+    impl Visitor for GlobalDefs<'_> {
+        fn enter(&mut self, expr: &CrispExpr) -> Walk {
+            let CrispExpr::List(xs) = expr else {
+                return Walk::Continue;
+            };
+            match xs.as_slice() {
+                [CrispExpr::Symbol(quote), ..] if quote == "quote" => Walk::SkipChildren,
+                [CrispExpr::Symbol(def), CrispExpr::Symbol(name), ..] if def == "def-global" => {
+                    self.0.push(name.clone());
+                    Walk::Continue
+                }
+                _ => Walk::Continue,
+            }
+        }
+    }
+
+    for form in forms {
+        walk(form, &mut GlobalDefs(names));
+    }
+}
+
+struct Linter<'a, 'e> {
+    env: &'a CrispEnv<'e>,
+    globals: Vec<String>,
+    lints: Vec<Lint>,
+}
+
+impl<'a, 'e> Linter<'a, 'e> {
+    fn warn(&mut self, message: String) {
+        self.lints.push(Lint { message });
+    }
+
+    fn check(&mut self, expr: &CrispExpr, locals: &[String]) {
+        match expr {
+            CrispExpr::Symbol(name) => self.check_symbol(name, locals),
+            CrispExpr::List(xs) => self.check_list(xs, locals),
+            CrispExpr::Vector(items) => {
+                for item in items {
+                    self.check(item, locals);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_symbol(&mut self, name: &str, locals: &[String]) {
+        let defined_here = SPECIAL_FORMS.contains(&name)
+            || is_keyword(name)
+            || locals.iter().any(|local| local == name)
+            || self.globals.iter().any(|global| global == name);
+        if defined_here {
+            return;
+        }
+
+        match self.env.deprecation(name) {
+            Some(instead) => self.warn(deprecation_message(name, instead)),
+            None if self.env.lookup(name).is_none() => {
+                self.warn(format!("unknown symbol '{name}'"))
+            }
+            None => {}
+        }
+    }
+
+    fn check_list(&mut self, xs: &[CrispExpr], locals: &[String]) {
+        let head = match xs.first() {
+            Some(CrispExpr::Symbol(name)) => name.as_str(),
+            Some(_) => "",
+            None => return,
+        };
+
+        match head {
+            // Macro bodies are templates, which aren't evaluated as they are
+            "quote" | "use" | "defmacro" => {}
+            "case" => {
+                if let Some(key) = xs.get(1) {
+                    self.check(key, locals);
+                }
+                for clause in xs.iter().skip(2) {
+                    match clause {
+                        // The clause's values are literals, so only its
+                        // body is checked
+                        CrispExpr::List(clause) if !clause.is_empty() => {
+                            for body in &clause[1..] {
+                                self.check(body, locals);
+                            }
+                        }
+                        _ => self
+                            .warn("case clauses must be lists like (value body ...)".to_string()),
+                    }
+                }
+            }
+            "def" | "def-global" => {
+                if xs.len() != 3 {
+                    self.warn(format!("{head} takes exactly two arguments"));
+                }
+                if !xs.get(1).is_some_and(CrispExpr::is_symbol) {
+                    self.warn(format!("{head} expects a symbol as its first argument"));
+                }
+                for arg in xs.iter().skip(2) {
+                    self.check(arg, locals);
+                }
+            }
+            "fn" => {
+                let mut scope = locals.to_vec();
+                match xs.get(1) {
+                    Some(CrispExpr::List(params)) => {
+                        for param in params {
+                            match param {
+                                CrispExpr::Symbol(name) => scope.push(name.clone()),
+                                _ => self.warn("fn params must be symbols".to_string()),
+                            }
+                        }
+                    }
+                    _ => self.warn("fn expects a list of params".to_string()),
+                }
+                // Defs in the body are local to the call
+                collect_defs(&xs[2.min(xs.len())..], &mut scope);
+
+                for body in xs.iter().skip(2) {
+                    self.check(body, &scope);
+                }
+            }
+            "let" | "let*" => {
+                let mut scope = locals.to_vec();
+                match xs.get(1) {
+                    Some(CrispExpr::List(bindings)) => {
+                        for binding in bindings {
+                            match binding {
+                                CrispExpr::List(binding) => match binding.as_slice() {
+                                    [CrispExpr::Symbol(name), value] => {
+                                        // Only let* values can see the names
+                                        // bound before them
+                                        match head {
+                                            "let*" => self.check(value, &scope),
+                                            _ => self.check(value, locals),
+                                        }
+                                        scope.push(name.clone());
+                                    }
+                                    _ => self.warn(format!("{head} expects bindings like (x 1)")),
+                                },
+                                _ => self.warn(format!("{head} expects bindings like (x 1)")),
+                            }
+                        }
+                    }
+                    _ => self.warn(format!("{head} takes a list of bindings and a body")),
+                }
+                collect_defs(&xs[2.min(xs.len())..], &mut scope);
+
+                for body in xs.iter().skip(2) {
+                    self.check(body, &scope);
+                }
+            }
+            "with-open" => {
+                let mut scope = locals.to_vec();
+                match xs.get(1) {
+                    Some(CrispExpr::List(binding)) => match binding.as_slice() {
+                        [CrispExpr::Symbol(name), handle] => {
+                            self.check(handle, locals);
+                            scope.push(name.clone());
+                        }
+                        _ => self
+                            .warn("with-open expects a binding like (f (open path))".to_string()),
+                    },
+                    _ => self.warn("with-open expects a binding like (f (open path))".to_string()),
+                }
+
+                for body in xs.iter().skip(2) {
+                    self.check(body, &scope);
+                }
+            }
+            "if" => {
+                if !(3..=4).contains(&xs.len()) {
+                    self.warn(
+                        "if takes a test, a then branch and an optional else branch".to_string(),
+                    );
+                }
+                for arg in &xs[1..] {
+                    self.check(arg, locals);
+                }
+            }
+            _ => {
+                for x in xs {
+                    self.check(x, locals);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parse::parse_all};
+
+    fn lint_str(src: &str) -> Vec<String> {
+        let forms = parse_all(&lexer(src)).unwrap();
+        lint(&forms, &CrispEnv::default())
+            .into_iter()
+            .map(|lint| lint.message)
+            .collect()
+    }
+
+    #[test]
+    fn lint_clean_program() {
+        let src = "(begin (def fac (fn (a) (if (> a 2) (* a (fac (- a 1))) a))) (fac 4))";
+
+        assert!(lint_str(src).is_empty());
+    }
+
+    #[test]
+    fn lint_unknown_symbols() {
+        assert_eq!(
+            lint_str("(def f (fn (a) (+ a b))) (g 1)"),
+            vec!["unknown symbol 'b'", "unknown symbol 'g'"]
+        );
+    }
+
+    #[test]
+    fn lint_let_bindings() {
+        assert_eq!(
+            lint_str("(let* ((a 1) (b a)) (+ a b)) (let ((c 1) (d c)) d) c"),
+            vec!["unknown symbol 'c'", "unknown symbol 'c'"]
+        );
+    }
+
+    #[test]
+    fn lint_deprecated_symbols() {
+        let mut env = CrispEnv::default();
+        crate::run_program("(def old 1) (deprecate old)", &mut env).unwrap();
+        let forms = parse_all(&lexer("(+ old ((fn (old) old) 2))")).unwrap();
+
+        assert_eq!(
+            lint(&forms, &env)
+                .into_iter()
+                .map(|lint| lint.message)
+                .collect::<Vec<String>>(),
+            vec!["'old' is deprecated"]
+        );
+    }
+
+    #[test]
+    fn lint_macros() {
+        assert_eq!(
+            lint_str("(defmacro swap (f a b) (f b a)) (swap - 1 x)"),
+            vec!["unknown symbol 'x'"]
+        );
+    }
+
+    #[test]
+    fn lint_defs_in_functions() {
+        assert_eq!(
+            lint_str("(def f (fn () (def y 1) (def-global g y) y)) (+ g y)"),
+            vec!["unknown symbol 'y'"]
+        );
+    }
+
+    #[test]
+    fn lint_malformed_special_forms() {
+        assert_eq!(
+            lint_str("(if true 1) (if true 1 2 3) (def + 2)"),
+            vec![
+                "'+' is already defined",
+                "if takes a test, a then branch and an optional else branch"
+            ]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Lists, and the primitives recursive list code is written with: `cons` to
+//! build a list from its head and tail, `car` and `cdr` (or `first` and
+//! `rest`) to take it apart, and `empty?` to find the end. Since `()`
+//! evaluates to nil, nil counts as the empty list everywhere a list is taken.
+//!
+//! `empty?` and `length` also take the stacks and queues in `queue`.
+//!
+//! `map`, `filter`, `reduce` and the folds `count-if`, `every?`, `some`,
+//! `max-by` and `min-by` take lambdas and native functions alike, calling
+//! them through `eval::apply` in the env they're called in, or as kernels
+//! when they're simple numeric lambdas, see `kernel`.
+
+use std::cmp::Ordering;
+
+use crate::{
+    eval::{apply, check_limits, CrispEnv},
+    kernel::{self, Kernel},
+    lang::{CrispError, CrispExpr, CrispFn, CrispResult, Primitive},
+    math,
+};
+
+/// The list builtins, registered under `list/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "list",
+            CrispFn::new(|args: &[CrispExpr]| Ok(CrispExpr::List(args.to_vec()))),
+        ),
+        (
+            "cons",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [head, tail] => {
+                    let tail = items("cons", tail)?;
+                    Ok(CrispExpr::List(
+                        std::iter::once(head.clone())
+                            .chain(tail.iter().cloned())
+                            .collect(),
+                    ))
+                }
+                _ => Err(CrispError::EvalError(
+                    "cons takes a value and a list".to_string(),
+                )),
+            }),
+        ),
+        ("car", head("car")),
+        ("first", head("first")),
+        ("cdr", tail("cdr")),
+        ("rest", tail("rest")),
+        (
+            "empty?",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [list] => Ok(CrispExpr::Primitive(Primitive::Bool(
+                    count("empty?", list)? == 0,
+                ))),
+                _ => Err(CrispError::EvalError(
+                    "empty? takes exactly one list".to_string(),
+                )),
+            }),
+        ),
+        (
+            "length",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [list] => Ok(CrispExpr::Primitive(Primitive::Int(
+                    count("length", list)? as i64
+                ))),
+                _ => Err(CrispError::EvalError(
+                    "length takes exactly one list".to_string(),
+                )),
+            }),
+        ),
+        ("nth", CrispFn::new(nth)),
+        ("range", CrispFn::with_env(range)),
+        ("map", CrispFn::with_env(map)),
+        ("filter", CrispFn::with_env(filter)),
+        ("reduce", CrispFn::with_env(reduce)),
+        ("count-if", predicate("count-if")),
+        ("every?", predicate("every?")),
+        ("some", predicate("some")),
+        ("max-by", extreme_by("max-by")),
+        ("min-by", extreme_by("min-by")),
+        (
+            "append",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let mut appended = vec![];
+                for list in args {
+                    appended.extend_from_slice(items("append", list)?);
+                }
+                Ok(CrispExpr::List(appended))
+            }),
+        ),
+        (
+            "reverse",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [list] => Ok(CrispExpr::List(
+                    items("reverse", list)?.iter().rev().cloned().collect(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "reverse takes exactly one list".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// `(map f list ...)`, a list of the results of calling `f` on the items of
+/// the lists at each index in turn, as long as the shortest list
+fn map(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let [f, lists @ ..] = args else {
+        return Err(CrispError::EvalError(
+            "map takes a function and one or more lists".to_string(),
+        ));
+    };
+    if lists.is_empty() {
+        return Err(CrispError::EvalError(
+            "map takes a function and one or more lists".to_string(),
+        ));
+    }
+
+    let lists = lists
+        .iter()
+        .map(|list| items("map", list))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(|items| items.len()).min().unwrap_or(0);
+    let kernel = Kernel::compile(f, env);
+    (0..len)
+        .map(|i| {
+            let args: Vec<&CrispExpr> = lists.iter().map(|items| &items[i]).collect();
+            kernel::call(f, kernel.as_ref(), &args, env)
+        })
+        .collect::<Result<_, _>>()
+        .map(CrispExpr::List)
+}
+
+/// `(filter pred list)`, the items of a list `pred` gives a true value for
+fn filter(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let [pred, list] = args else {
+        return Err(CrispError::EvalError(
+            "filter takes a predicate and a list".to_string(),
+        ));
+    };
+
+    let kernel = Kernel::compile(pred, env);
+    let mut kept = vec![];
+    for item in items("filter", list)? {
+        if kernel::call(pred, kernel.as_ref(), &[item], env)?.is_truthy() {
+            kept.push(item.clone());
+        }
+    }
+    Ok(CrispExpr::List(kept))
+}
+
+/// `(reduce f init list)`, combining `init` with each item in turn by
+/// calling `f` on the result so far and the item. Without `init`, the first
+/// item starts, so the list can't be empty.
+fn reduce(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let (f, init, items) = match args {
+        [f, list] => match items("reduce", list)? {
+            [first, rest @ ..] => (f, first, rest),
+            [] => {
+                return Err(CrispError::EvalError(
+                    "reduce of an empty list needs an initial value".to_string(),
+                ))
+            }
+        },
+        [f, init, list] => (f, init, items("reduce", list)?),
+        _ => {
+            return Err(CrispError::EvalError(
+                "reduce takes a function, an optional initial value and a list".to_string(),
+            ))
+        }
+    };
+
+    let kernel = Kernel::compile(f, env);
+    items.iter().try_fold(init.clone(), |acc, item| {
+        kernel::call(f, kernel.as_ref(), &[&acc, item], env)
+    })
+}
+
+/// `every?`, `some` or `count-if`, calling the predicate on each item of
+/// the list in turn. `every?` and `some` stop as soon as the answer is
+/// known.
+fn predicate(name: &'static str) -> CrispFn {
+    CrispFn::with_env(move |args: &[CrispExpr], env: &CrispEnv| {
+        let [pred, list] = args else {
+            return Err(CrispError::EvalError(format!(
+                "{name} takes a predicate and a list"
+            )));
+        };
+
+        let kernel = Kernel::compile(pred, env);
+        let mut count = 0;
+        for item in items(name, list)? {
+            let matched = kernel::call(pred, kernel.as_ref(), &[item], env)?.is_truthy();
+
+            match name {
+                "every?" if !matched => return Ok(CrispExpr::Primitive(Primitive::Bool(false))),
+                "some" if matched => return Ok(item.clone()),
+                _ if matched => count += 1,
+                _ => {}
+            }
+        }
+
+        Ok(CrispExpr::Primitive(match name {
+            "every?" => Primitive::Bool(true),
+            "some" => Primitive::Nil,
+            _ => Primitive::Int(count as i64),
+        }))
+    })
+}
+
+/// `max-by` or `min-by`: the item of the list `f` gives the largest or
+/// smallest number for, preferring the first on ties. An empty list gives
+/// the default if there is one, and is an error otherwise.
+fn extreme_by(name: &'static str) -> CrispFn {
+    CrispFn::with_env(move |args: &[CrispExpr], env: &CrispEnv| {
+        let (f, list, default) = match args {
+            [f, list] => (f, list, None),
+            [f, list, default] => (f, list, Some(default)),
+            _ => {
+                return Err(CrispError::EvalError(format!(
+                    "{name} takes a function, a list and an optional default"
+                )))
+            }
+        };
+
+        let kernel = Kernel::compile(f, env);
+        let mut best: Option<(f32, &CrispExpr)> = None;
+        for item in items(name, list)? {
+            let Some(key) = kernel::call(f, kernel.as_ref(), &[item], env)?.as_f32() else {
+                return Err(CrispError::EvalError(format!(
+                    "{name}'s function must return a number"
+                )));
+            };
+
+            let better = match &best {
+                None => true,
+                Some((best, _)) if name == "max-by" => key > *best,
+                Some((best, _)) => key < *best,
+            };
+            if better {
+                best = Some((key, item));
+            }
+        }
+
+        best.map(|(_, item)| item)
+            .or(default)
+            .cloned()
+            .ok_or(CrispError::EvalError(format!(
+                "{name} of an empty list needs a default"
+            )))
+    })
+}
+
+/// `(nth list i)`, the item at index `i` of a list, counting from 0
+fn nth(args: &[CrispExpr]) -> CrispResult {
+    let [list, i] = args else {
+        return Err(CrispError::EvalError(
+            "nth takes a list and an index".to_string(),
+        ));
+    };
+    let items = items("nth", list)?;
+
+    match i {
+        CrispExpr::Primitive(Primitive::Int(i)) if (0..items.len() as i64).contains(i) => {
+            Ok(items[*i as usize].clone())
+        }
+        CrispExpr::Primitive(Primitive::Int(i)) => Err(CrispError::EvalError(format!(
+            "index {i} is out of bounds for a list of length {}",
+            items.len()
+        ))),
+        _ => Err(CrispError::EvalError(
+            "list indexes must be ints".to_string(),
+        )),
+    }
+}
+
+/// `(range end)`, `(range start end)` or `(range start end step)`: the
+/// numbers from start, 0 by default, up to but not including end, counting
+/// by step, 1 by default, or down to end when step is negative. The nth
+/// number is worked out as `start + n * step`, so float steps don't drift.
+/// Each number counts towards the env's step limit, so a huge range can be
+/// stopped.
+fn range(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let int = |n| CrispExpr::Primitive(Primitive::Int(n));
+    let (start, end, step) = match args {
+        [end] => (int(0), end, int(1)),
+        [start, end] => (start.clone(), end, int(1)),
+        [start, end, step] => (start.clone(), end, step.clone()),
+        _ => {
+            return Err(CrispError::EvalError(
+                "range takes an end, or a start, an end and optionally a step".to_string(),
+            ))
+        }
+    };
+    let not_numbers = || CrispError::EvalError("range takes numbers".to_string());
+
+    // The ordering of each number and end that keeps the range going
+    let before_end = match math::compare(&step, &int(0)).ok_or_else(not_numbers)? {
+        Ordering::Greater => Ordering::Less,
+        Ordering::Less => Ordering::Greater,
+        Ordering::Equal => {
+            return Err(CrispError::EvalError(
+                "range's step can't be zero".to_string(),
+            ))
+        }
+    };
+    let arith = |name, x, y| {
+        let f = math::builtin(name).expect("range only uses math builtins");
+        apply(CrispExpr::Fn(f.clone()), vec![x, y], env)
+    };
+
+    let mut numbers = vec![];
+    for n in 0.. {
+        let number = arith("+", start.clone(), arith("*", int(n), step.clone())?)?;
+        if math::compare(&number, end).ok_or_else(not_numbers)? != before_end {
+            break;
+        }
+        check_limits(env, 1)?;
+        numbers.push(number);
+    }
+    Ok(CrispExpr::List(numbers))
+}
+
+/// `car`, or `first`: the first item of a non-empty list
+fn head(name: &'static str) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult { Ok(non_empty(name, args)?[0].clone()) })
+}
+
+/// `cdr`, or `rest`: every item of a non-empty list after the first
+fn tail(name: &'static str) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        Ok(CrispExpr::List(non_empty(name, args)?[1..].to_vec()))
+    })
+}
+
+/// The items of the single list argument to `name`, which has to have some
+fn non_empty<'a>(name: &str, args: &'a [CrispExpr]) -> Result<&'a [CrispExpr], CrispError> {
+    let [list] = args else {
+        return Err(CrispError::EvalError(format!(
+            "{name} takes exactly one list"
+        )));
+    };
+
+    match items(name, list)? {
+        [] => Err(CrispError::EvalError(format!("{name} of an empty list"))),
+        items => Ok(items),
+    }
+}
+
+/// The items of a list argument to `name`, with nil as the empty list
+fn items<'a>(name: &str, list: &'a CrispExpr) -> Result<&'a [CrispExpr], CrispError> {
+    match list {
+        CrispExpr::List(items) => Ok(items),
+        CrispExpr::Primitive(Primitive::Nil) => Ok(&[]),
+        _ => Err(CrispError::EvalError(format!("{name} takes a list"))),
+    }
+}
+
+/// The number of items in a list, or in a stack or queue
+fn count(name: &str, list: &CrispExpr) -> Result<usize, CrispError> {
+    match list {
+        CrispExpr::Handle(handle) => crate::queue::len(handle).unwrap_or(Err(
+            CrispError::EvalError(format!("{name} takes a list, stack or queue")),
+        )),
+        list => Ok(items(name, list)?.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn build_and_take_apart_lists() {
+        assert_eq!(eval_source("(cons 1 '(2 3))"), Ok("(1 2 3)".to_string()));
+        assert_eq!(eval_source("(cons 1 ())"), Ok("(1)".to_string()));
+        assert_eq!(eval_source("(car '(1 2 3))"), Ok("1".to_string()));
+        assert_eq!(eval_source("(first '((a) b))"), Ok("(a)".to_string()));
+        assert_eq!(eval_source("(cdr '(1 2 3))"), Ok("(2 3)".to_string()));
+        assert_eq!(eval_source("(rest '(1))"), Ok("()".to_string()));
+        assert_eq!(eval_source("(empty? (rest '(1)))"), Ok("true".to_string()));
+        assert_eq!(eval_source("(empty? ())"), Ok("true".to_string()));
+        assert_eq!(eval_source("(empty? '(nil))"), Ok("false".to_string()));
+
+        assert_eq!(
+            eval_source("(car ())"),
+            Err("car of an empty list".to_string())
+        );
+        assert!(eval_source("(cdr [1 2])").is_err());
+        assert!(eval_source("(cons 1 2)").is_err());
+    }
+
+    #[test]
+    fn list_library() {
+        assert_eq!(
+            eval_source("(list 1 (+ 1 1) '(3))"),
+            Ok("(1 2 (3))".to_string())
+        );
+        assert_eq!(eval_source("(list)"), Ok("()".to_string()));
+        assert_eq!(eval_source("(length '(1 (2 3) ()))"), Ok("3".to_string()));
+        assert_eq!(eval_source("(length ())"), Ok("0".to_string()));
+        assert_eq!(eval_source("(nth '(a (b c) d) 1)"), Ok("(b c)".to_string()));
+        assert_eq!(
+            eval_source("(nth (nth '(a (b c)) 1) 0)"),
+            Ok("b".to_string())
+        );
+        assert_eq!(
+            eval_source("(append '(1) () '((2 3)) (list 4))"),
+            Ok("(1 (2 3) 4)".to_string())
+        );
+        assert_eq!(eval_source("(append)"), Ok("()".to_string()));
+        assert_eq!(
+            eval_source("(reverse '(1 (2 3) 4))"),
+            Ok("(4 (2 3) 1)".to_string())
+        );
+
+        assert_eq!(eval_source("(range 4)"), Ok("(0 1 2 3)".to_string()));
+        assert_eq!(eval_source("(range 2 5)"), Ok("(2 3 4)".to_string()));
+        assert_eq!(eval_source("(range 10 0 -3)"), Ok("(10 7 4 1)".to_string()));
+        assert_eq!(
+            eval_source("(range 0 1 0.25)"),
+            Ok("(0.0 0.25 0.5 0.75)".to_string())
+        );
+        assert_eq!(
+            eval_source("(range 1m 2 0.5m)"),
+            Ok("(1.0m 1.5m)".to_string())
+        );
+        assert_eq!(eval_source("(range 5 2)"), Ok("()".to_string()));
+        assert_eq!(
+            eval_source("(reduce + (map (fn (x) (* x x)) (range 1 4)))"),
+            Ok("14".to_string())
+        );
+        assert_eq!(
+            eval_source("(range 0 3 0)"),
+            Err("range's step can't be zero".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(nth '(1 2) 2)"),
+            Err("index 2 is out of bounds for a list of length 2".to_string())
+        );
+        for prog in [
+            "(nth '(1 2) 1.0)",
+            "(nth '(1 2))",
+            "(length 1)",
+            "(length '(1) '(2))",
+            "(append '(1) 2)",
+            "(reverse [1 2])",
+            "(range :a)",
+            "(range 1 2 3 4)",
+        ] {
+            assert!(eval_source(prog).is_err(), "{prog}");
+        }
+    }
+
+    #[test]
+    fn folds_are_values() {
+        assert_eq!(
+            eval_source("(map (fn (fold) (fold odd? '(1 2 3))) (list count-if every? some))"),
+            Ok("(2 false 1)".to_string())
+        );
+        assert_eq!(
+            eval_source("(def biggest (fn (xs) (max-by identity xs))) (biggest '(3 9 2))"),
+            Ok("9".to_string())
+        );
+        assert_eq!(
+            eval_source("(pipe '(1 2 3) (fn (xs) (min-by (fn (x) (- 0 x)) xs)))"),
+            Ok("3".to_string())
+        );
+        // Like any builtin, they can be shadowed
+        assert_eq!(
+            eval_source("((fn (some) (some 1)) (fn (x) (+ x 1)))"),
+            Ok("2".to_string())
+        );
+        assert!(eval_source("(count-if odd? 1)").is_err());
+    }
+
+    #[test]
+    fn higher_order_functions() {
+        assert_eq!(
+            eval_source("(map (fn (x) (* x x)) '(1 2 3))"),
+            Ok("(1 4 9)".to_string())
+        );
+        assert_eq!(
+            eval_source("(map + '(1 2 3) '(10 20))"),
+            Ok("(11 22)".to_string())
+        );
+        assert_eq!(
+            eval_source("(map car '((a b) (c)))"),
+            Ok("(a c)".to_string())
+        );
+        assert_eq!(
+            eval_source("(filter (fn (x) (> x 1)) '(1 2 3))"),
+            Ok("(2 3)".to_string())
+        );
+        assert_eq!(
+            eval_source("(filter empty? '(() (1) nil))"),
+            Ok("(() nil)".to_string())
+        );
+        assert_eq!(eval_source("(reduce + 0 '(1 2 3))"), Ok("6".to_string()));
+        assert_eq!(
+            eval_source("(reduce (fn (acc x) (cons x acc)) () '(1 2 3))"),
+            Ok("(3 2 1)".to_string())
+        );
+        assert_eq!(eval_source("(reduce max '(3 9 2))"), Ok("9".to_string()));
+        // Functions close over the env they're made in, as with any call
+        assert_eq!(
+            eval_source("(def scale (fn (k xs) (map (fn (x) (* k x)) xs))) (scale 2 '(1 2))"),
+            Ok("(2 4)".to_string())
+        );
+        assert_eq!(
+            eval_source("(map (fn (f) (f -4)) (list (fn (x) (+ x 1)) abs))"),
+            Ok("(-3 4)".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(map (fn (x) (car x)) '((1) ()))"),
+            Err("car of an empty list".to_string())
+        );
+        for prog in [
+            "(map car)",
+            "(filter car '(1) '(2))",
+            "(reduce + ())",
+            "(map 1 '(1))",
+        ] {
+            assert!(eval_source(prog).is_err(), "{prog}");
+        }
+    }
+
+    #[test]
+    fn recursive_list_functions() {
+        assert_eq!(
+            eval_source(
+                "(def len (fn (xs) (if (empty? xs) 0 (+ 1 (len (cdr xs))))))\n\
+                 (def double (fn (xs) (if (empty? xs) () (cons (* 2 (car xs)) (double (cdr xs))))))\n\
+                 (cons (len '(a b c)) (double '(1 2 3)))"
+            ),
+            Ok("(3 2 4 6)".to_string())
+        );
+    }
+}
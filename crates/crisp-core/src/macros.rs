@@ -0,0 +1,136 @@
+//! Macros defined with `defmacro`. A macro is a template: a call to it
+//! expands to the macro's body with the call's unevaluated args substituted
+//! for its params, and the expansion is evaluated in place of the call.
+
+use crate::{
+    eval::CrispEnv,
+    lang::{CrispError, CrispExpr, CrispLambda, CrispResult},
+    visit::rewrite,
+};
+
+/// Most times the head of a form is expanded before giving up, so a macro
+/// that expands to a call to itself fails instead of looping forever
+const MAX_EXPANSIONS: usize = 1000;
+
+/// Expand `expr` once if it's a call to a macro, or return `None` if it isn't
+pub fn expand_once(expr: &CrispExpr, env: &CrispEnv) -> Result<Option<CrispExpr>, CrispError> {
+    let CrispExpr::List(list) = expr else {
+        return Ok(None);
+    };
+    let Some((CrispExpr::Symbol(name), args)) = list.split_first() else {
+        return Ok(None);
+    };
+    let Some(CrispLambda { params, body, .. }) = env.lookup_macro(name) else {
+        return Ok(None);
+    };
+
+    if args.len() != params.len() {
+        return Err(CrispError::EvalError(format!(
+            "macro '{name}' takes {} arguments but was given {}",
+            params.len(),
+            args.len()
+        )));
+    }
+
+    Ok(Some(substitute(body, params, args)))
+}
+
+/// Expand `expr` until it's no longer a call to a macro. Its subforms are
+/// left as they are.
+pub fn expand(expr: &CrispExpr, env: &CrispEnv) -> CrispResult {
+    let mut expr = expr.clone();
+    for _ in 0..MAX_EXPANSIONS {
+        match expand_once(&expr, env)? {
+            Some(expansion) => expr = expansion,
+            None => return Ok(expr),
+        }
+    }
+
+    Err(CrispError::EvalError(format!(
+        "macro expansion didn't finish after {MAX_EXPANSIONS} steps"
+    )))
+}
+
+/// Expand every macro call in `expr`, including in its subforms. Quoted
+/// data, macro definitions and `fn` params are left unexpanded.
+pub fn expand_all(expr: &CrispExpr, env: &CrispEnv) -> CrispResult {
+    let expr = expand(expr, env)?;
+    let list = match &expr {
+        CrispExpr::List(list) => list,
+        CrispExpr::Vector(items) => {
+            let items = items.iter().map(|item| expand_all(item, env));
+            return Ok(CrispExpr::Vector(items.collect::<Result<_, _>>()?));
+        }
+        _ => return Ok(expr),
+    };
+
+    let keep = match list.first() {
+        Some(CrispExpr::Symbol(head)) => match head.as_str() {
+            "quote" | "defmacro" => return Ok(expr),
+            "fn" => 2,
+            _ => 0,
+        },
+        _ => 0,
+    };
+
+    let mut items = list[..keep.min(list.len())].to_vec();
+    for item in list.iter().skip(keep) {
+        items.push(expand_all(item, env)?);
+    }
+    Ok(CrispExpr::List(items))
+}
+
+/// `body` with every symbol that's one of `params` replaced by its arg
+fn substitute(body: &CrispExpr, params: &[String], args: &[CrispExpr]) -> CrispExpr {
+    rewrite(body.clone(), |expr| match &expr {
+        CrispExpr::Symbol(name) => match params.iter().position(|param| param == name) {
+            Some(i) => args[i].clone(),
+            None => expr,
+        },
+        _ => expr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::to_source, lexer, parse::parse, run_program};
+
+    fn expr(src: &str) -> CrispExpr {
+        parse(&lexer(src)).unwrap().0
+    }
+
+    #[test]
+    fn macros_expand_before_evaluating() {
+        let mut env = CrispEnv::default();
+        let prog = "(defmacro my-unless (c then else) (if c else then))
+                    (my-unless (> 1 2) (quote yes) (assert false))";
+
+        assert_eq!(
+            run_program(prog, &mut env).map(|val| to_source(&val)),
+            Ok("yes".to_string())
+        );
+        assert!(run_program("(my-unless true 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn expand_all_skips_quoted_forms() {
+        let mut env = CrispEnv::default();
+        run_program("(defmacro twice (x) (begin x x))", &mut env).unwrap();
+
+        let once = expand_once(&expr("(twice (twice 1))"), &env).unwrap();
+        assert_eq!(
+            once.map(|e| to_source(&e)),
+            Some("(begin (twice 1) (twice 1))".to_string())
+        );
+
+        let all = expand_all(&expr("(fn (twice) (twice (quote (twice 2))))"), &env).unwrap();
+        assert_eq!(
+            to_source(&all),
+            "(fn (twice) (begin (quote (twice 2)) (quote (twice 2))))"
+        );
+
+        run_program("(defmacro forever (x) (forever x))", &mut env).unwrap();
+        assert!(expand(&expr("(forever 1)"), &env).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Maps from keys to values, written like `{:name "jim" :age 4}`, which
+//! reads as `(hash-map :name "jim" :age 4)`. Keys can be any data, see
+//! `MapKey`. Like every other value maps are immutable, so `assoc` and
+//! `dissoc` give changed copies.
+//!
+//! Maps remember the order keys were first added in, which is how they
+//! print and how `keys`, `vals` and `map->list` list them, so a JSON object
+//! read into a map is written back with its fields in the same order.
+//! Replacing a key's value keeps its place. Two maps with the same entries
+//! are `=` whatever their order.
+
+use indexmap::IndexMap;
+
+use crate::lang::{CrispError, CrispExpr, CrispFn, CrispResult, MapKey, Primitive};
+
+/// The map builtins, registered under `map/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "hash-map",
+            CrispFn::new(|args: &[CrispExpr]| {
+                assoc_pairs("hash-map", IndexMap::new(), args).map(CrispExpr::Map)
+            }),
+        ),
+        ("get", CrispFn::new(get)),
+        (
+            "assoc",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map), pairs @ ..] if !pairs.is_empty() => {
+                    assoc_pairs("assoc", map.clone(), pairs).map(CrispExpr::Map)
+                }
+                _ => Err(CrispError::EvalError(
+                    "assoc takes a map and keys and values in pairs".to_string(),
+                )),
+            }),
+        ),
+        (
+            "dissoc",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map), keys @ ..] => {
+                    let mut map = map.clone();
+                    for key in keys {
+                        map.shift_remove(&MapKey::new(key.clone())?);
+                    }
+                    Ok(CrispExpr::Map(map))
+                }
+                _ => Err(CrispError::EvalError(
+                    "dissoc takes a map and the keys to remove".to_string(),
+                )),
+            }),
+        ),
+        (
+            "keys",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map)] => Ok(CrispExpr::List(
+                    map.keys().map(|key| key.expr().clone()).collect(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "keys takes exactly one map".to_string(),
+                )),
+            }),
+        ),
+        (
+            "vals",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map)] => Ok(CrispExpr::List(map.values().cloned().collect())),
+                _ => Err(CrispError::EvalError(
+                    "vals takes exactly one map".to_string(),
+                )),
+            }),
+        ),
+        (
+            "contains?",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map), key] => Ok(CrispExpr::Primitive(Primitive::Bool(
+                    map.contains_key(&MapKey::new(key.clone())?),
+                ))),
+                _ => Err(CrispError::EvalError(
+                    "contains? takes a map and a key".to_string(),
+                )),
+            }),
+        ),
+        (
+            "map->list",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Map(map)] => Ok(CrispExpr::List(
+                    map.iter()
+                        .map(|(key, value)| {
+                            CrispExpr::List(vec![key.expr().clone(), value.clone()])
+                        })
+                        .collect(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "map->list takes exactly one map".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// The value of a key in a map, or the default, or nil
+fn get(args: &[CrispExpr]) -> CrispResult {
+    let (map, key, default) = match args {
+        [CrispExpr::Map(map), key] => (map, key, None),
+        [CrispExpr::Map(map), key, default] => (map, key, Some(default)),
+        _ => {
+            return Err(CrispError::EvalError(
+                "get takes a map, a key and optionally a default".to_string(),
+            ))
+        }
+    };
+
+    Ok(map
+        .get(&MapKey::new(key.clone())?)
+        .or(default)
+        .cloned()
+        .unwrap_or(CrispExpr::Primitive(Primitive::Nil)))
+}
+
+/// Add keys and values given in pairs to a map, replacing the values of
+/// keys it already has
+fn assoc_pairs(
+    name: &str,
+    mut map: IndexMap<MapKey, CrispExpr>,
+    pairs: &[CrispExpr],
+) -> Result<IndexMap<MapKey, CrispExpr>, CrispError> {
+    if !pairs.len().is_multiple_of(2) {
+        return Err(CrispError::EvalError(format!(
+            "{name} takes keys and values in pairs, but the last key has no value"
+        )));
+    }
+
+    for pair in pairs.chunks(2) {
+        map.insert(MapKey::new(pair[0].clone())?, pair[1].clone());
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn build_and_look_up_maps() {
+        assert_eq!(
+            eval_source("{:name \"jim\" :age (+ 2 2)}"),
+            Ok("{:name \"jim\" :age 4}".to_string())
+        );
+        let m = "(def m {:a 1 \"b\" 2 [1 2] 3}) ";
+        assert_eq!(
+            eval_source(&format!("{m} (get m [1 2])")),
+            Ok("3".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{m} (get m :c)")),
+            Ok("nil".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{m} (get m :c 0)")),
+            Ok("0".to_string())
+        );
+        let m = "(def m (assoc {:a 1} :b 2 :a 3)) ";
+        assert_eq!(
+            eval_source(&format!("{m} (keys m)")),
+            Ok("(:a :b)".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{m} (vals m)")),
+            Ok("(3 2)".to_string())
+        );
+        assert_eq!(
+            eval_source(&format!("{m} (contains? m :b)")),
+            Ok("true".to_string())
+        );
+        assert_eq!(
+            eval_source("(= (dissoc {:a 1 :b 2} :b :c) {:a 1})"),
+            Ok("true".to_string())
+        );
+        // Keys are compared like `=` compares them
+        assert_eq!(
+            eval_source("(get {0.0 :zero 1.5m :d} -0.0)"),
+            Ok(":zero".to_string())
+        );
+        assert_eq!(eval_source("(get {1.5m :d} 1.50m)"), Ok(":d".to_string()));
+        assert_eq!(eval_source("(get {1 :int} 1.0)"), Ok("nil".to_string()));
+
+        assert!(eval_source("(hash-map :a)").is_err());
+        assert!(eval_source("(map->list '((:a 1)))").is_err());
+        assert!(eval_source("{(fn (x) x) 1}")
+            .unwrap_err()
+            .contains("can't be a map key"));
+    }
+
+    #[test]
+    fn maps_keep_insertion_order() {
+        assert_eq!(
+            eval_source("(map->list (assoc {:z 1 :a 2} :m 3 :z 4))"),
+            Ok("((:z 4) (:a 2) (:m 3))".to_string())
+        );
+        assert_eq!(
+            eval_source("(keys (dissoc {:c 1 :b 2 :a 3} :b))"),
+            Ok("(:c :a)".to_string())
+        );
+        assert_eq!(eval_source("(map->list {})"), Ok("()".to_string()));
+        assert_eq!(
+            eval_source("(= {:a 1 :b 2} {:b 2 :a 1})"),
+            Ok("true".to_string())
+        );
+    }
+}
@@ -0,0 +1,871 @@
+//! The math builtins: arithmetic, comparisons, rounding and the usual
+//! functions on floats.
+//!
+//! Numbers form a tower, from ints to decimals to floats. Ints and decimals
+//! are exact, floats are inexact. Arithmetic and comparisons promote their
+//! arguments to the highest level among them, so ints give ints, failing
+//! rather than overflowing, any decimal makes the result an exact decimal,
+//! and any float makes it a float. `/` on ints gives a float, as there's no
+//! exact type for their quotients. Functions whose results are rarely exact,
+//! like `sqrt`, only take ints and floats; `exact->inexact` converts a
+//! decimal for them.
+
+use std::cmp;
+use std::sync::OnceLock;
+
+use crate::{
+    decimal::Decimal,
+    eval::CrispEnv,
+    format::to_source,
+    kernel::{self, Kernel},
+    lang::{CrispError, CrispExpr, CrispFn, CrispResult, Primitive},
+    parse::parse_floats,
+};
+
+/// The math builtins are created once and shared by every env, so they can
+/// be recognised by identity, e.g. by the JIT
+pub(crate) fn builtins() -> &'static [(&'static str, CrispFn)] {
+    static MATH: OnceLock<Vec<(&str, CrispFn)>> = OnceLock::new();
+
+    MATH.get_or_init(|| {
+        vec![
+            (
+                "+",
+                CrispFn::new(|args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    #[cfg(feature = "ndarray")]
+                    if let Some(res) = crate::array::elementwise(args, |a, b| a + b) {
+                        return res;
+                    }
+                    match numbers(args)? {
+                        Numbers::Ints(ints) => fold_ints(0, &ints, i64::checked_add),
+                        Numbers::Decimals(decimals) => {
+                            fold_decimals(Decimal::ZERO, &decimals, Decimal::checked_add)
+                        }
+                        Numbers::Floats(floats) => Ok(CrispExpr::Primitive(Primitive::Number(
+                            floats.into_iter().fold(0., |acc, x| acc + x),
+                        ))),
+                    }
+                }),
+            ),
+            (
+                "-",
+                CrispFn::new(|args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    #[cfg(feature = "ndarray")]
+                    if let Some(res) = crate::array::elementwise(args, |a, b| a - b) {
+                        return res;
+                    }
+                    match numbers(args)? {
+                        Numbers::Ints(ints) => match ints.split_first() {
+                            Some((first, rest)) => fold_ints(*first, rest, i64::checked_sub),
+                            None => Err(at_least_one("-")),
+                        },
+                        Numbers::Decimals(decimals) => match decimals.split_first() {
+                            Some((first, rest)) => {
+                                fold_decimals(*first, rest, Decimal::checked_sub)
+                            }
+                            None => Err(at_least_one("-")),
+                        },
+                        Numbers::Floats(floats) => {
+                            let (first, rest) =
+                                floats.split_first().ok_or_else(|| at_least_one("-"))?;
+
+                            Ok(CrispExpr::Primitive(Primitive::Number(
+                                rest.iter().fold(*first, |acc, &x| acc - x),
+                            )))
+                        }
+                    }
+                }),
+            ),
+            (
+                "*",
+                CrispFn::new(|args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    #[cfg(feature = "ndarray")]
+                    if let Some(res) = crate::array::elementwise(args, |a, b| a * b) {
+                        return res;
+                    }
+                    match numbers(args)? {
+                        Numbers::Ints(ints) => fold_ints(1, &ints, i64::checked_mul),
+                        Numbers::Decimals(decimals) => {
+                            fold_decimals(Decimal::ONE, &decimals, Decimal::checked_mul)
+                        }
+                        Numbers::Floats(floats) => Ok(CrispExpr::Primitive(Primitive::Number(
+                            floats.into_iter().fold(1., |acc, x| acc * x),
+                        ))),
+                    }
+                }),
+            ),
+            (
+                "/",
+                CrispFn::new(|args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    let floats = match numbers(args)? {
+                        Numbers::Decimals(decimals) => {
+                            let (first, rest) = match decimals.as_slice() {
+                                [] => return Err(at_least_one("/")),
+                                [only] => (Decimal::ONE, std::slice::from_ref(only)),
+                                [first, rest @ ..] => (*first, rest),
+                            };
+                            if rest.iter().any(|d| d.is_zero()) {
+                                return Err(CrispError::DivisionByZero);
+                            }
+                            return fold_decimals(first, rest, Decimal::checked_div);
+                        }
+                        Numbers::Ints(ints) => ints.into_iter().map(|n| n as f32).collect(),
+                        Numbers::Floats(floats) => floats,
+                    };
+                    let (first, rest) = match floats.as_slice() {
+                        [] => return Err(at_least_one("/")),
+                        [only] => (1., std::slice::from_ref(only)),
+                        [first, rest @ ..] => (*first, rest),
+                    };
+                    if rest.contains(&0.) {
+                        return Err(CrispError::DivisionByZero);
+                    }
+
+                    Ok(CrispExpr::Primitive(Primitive::Number(
+                        rest.iter().fold(first, |acc, &x| acc / x),
+                    )))
+                }),
+            ),
+            ("mod", remainder("mod", true)),
+            ("rem", remainder("rem", false)),
+            (">", comparison(">", cmp::Ordering::is_gt)),
+            ("<", comparison("<", cmp::Ordering::is_lt)),
+            (">=", comparison(">=", cmp::Ordering::is_ge)),
+            ("<=", comparison("<=", cmp::Ordering::is_le)),
+            ("=", equality("=", true)),
+            ("!=", equality("!=", false)),
+            ("abs", CrispFn::new(abs)),
+            ("min", extremum("min", cmp::Ordering::Less)),
+            ("max", extremum("max", cmp::Ordering::Greater)),
+            ("floor", rounding("floor", Decimal::floor, f32::floor)),
+            ("ceil", rounding("ceil", Decimal::ceil, f32::ceil)),
+            ("round", rounding("round", Decimal::round, f32::round)),
+            ("sqrt", float_fn("sqrt", f32::sqrt)),
+            ("exp", float_fn("exp", f32::exp)),
+            ("ln", float_fn("ln", f32::ln)),
+            ("sin", float_fn("sin", f32::sin)),
+            ("cos", float_fn("cos", f32::cos)),
+            ("tan", float_fn("tan", f32::tan)),
+            ("asin", float_fn("asin", f32::asin)),
+            ("acos", float_fn("acos", f32::acos)),
+            ("atan", CrispFn::new(atan)),
+            ("pow", CrispFn::new(pow)),
+            ("sum", CrispFn::with_env(sum)),
+            (
+                "product",
+                CrispFn::new(|args: &[CrispExpr]| -> Result<CrispExpr, CrispError> {
+                    match list_numbers("product", args)? {
+                        Numbers::Ints(ints) => fold_ints(1, &ints, i64::checked_mul),
+                        Numbers::Decimals(decimals) => {
+                            fold_decimals(Decimal::ONE, &decimals, Decimal::checked_mul)
+                        }
+                        Numbers::Floats(floats) => Ok(CrispExpr::Primitive(Primitive::Number(
+                            floats.into_iter().fold(1., |acc, x| acc * x),
+                        ))),
+                    }
+                }),
+            ),
+            ("quotient", CrispFn::new(quotient)),
+            ("even?", parity("even?", true)),
+            ("odd?", parity("odd?", false)),
+            ("exact?", exactness("exact?", true)),
+            ("inexact?", exactness("inexact?", false)),
+            ("exact->inexact", CrispFn::new(exact_to_inexact)),
+            ("inexact->exact", CrispFn::new(inexact_to_exact)),
+        ]
+    })
+}
+
+/// `(sum list)`, adding up a list of numbers, or `(sum f list)`, adding up
+/// what `f` gives for each item, running it as a kernel if it's simple
+/// enough
+fn sum(args: &[CrispExpr], env: &CrispEnv) -> CrispResult {
+    let numbers = match args {
+        [f, CrispExpr::List(items)] => {
+            let kernel = Kernel::compile(f, env);
+            let values = items
+                .iter()
+                .map(|item| kernel::call(f, kernel.as_ref(), &[item], env))
+                .collect::<Result<Vec<_>, _>>()?;
+            numbers(&values)?
+        }
+        [CrispExpr::List(items)] => numbers(items)?,
+        _ => {
+            return Err(CrispError::EvalError(
+                "sum takes a list of numbers, or a function and a list".to_string(),
+            ))
+        }
+    };
+
+    match numbers {
+        Numbers::Ints(ints) => fold_ints(0, &ints, i64::checked_add),
+        Numbers::Decimals(decimals) => {
+            fold_decimals(Decimal::ZERO, &decimals, Decimal::checked_add)
+        }
+        Numbers::Floats(floats) => Ok(CrispExpr::Primitive(Primitive::Number(
+            floats.into_iter().fold(0., |acc, x| acc + x),
+        ))),
+    }
+}
+
+fn at_least_one(name: &str) -> CrispError {
+    CrispError::EvalError(format!("{name} takes at least one argument"))
+}
+
+/// `mod`, whose result has the sign of the divisor, so `(mod -7 2)` is 1, or
+/// `rem`, whose result has the sign of the dividend, so `(rem -7 2)` is -1
+fn remainder(name: &'static str, floored: bool) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        if args.len() != 2 {
+            return Err(CrispError::EvalError(format!(
+                "{name} takes exactly two numbers"
+            )));
+        }
+
+        match numbers(args)? {
+            Numbers::Ints(ints) => {
+                let (a, b) = (ints[0], ints[1]);
+                if b == 0 {
+                    return Err(CrispError::DivisionByZero);
+                }
+                let mut r = a.wrapping_rem(b);
+                if floored && r != 0 && (r < 0) != (b < 0) {
+                    r += b;
+                }
+                Ok(CrispExpr::Primitive(Primitive::Int(r)))
+            }
+            Numbers::Decimals(decimals) => {
+                let (a, b) = (decimals[0], decimals[1]);
+                if b.is_zero() {
+                    return Err(CrispError::DivisionByZero);
+                }
+                let mut r = a.checked_rem(b).ok_or(decimal_overflow())?;
+                if floored && !r.is_zero() && r.is_negative() != b.is_negative() {
+                    r = r.checked_add(b).ok_or(decimal_overflow())?;
+                }
+                Ok(CrispExpr::Primitive(Primitive::Decimal(r)))
+            }
+            Numbers::Floats(floats) => {
+                let (a, b) = (floats[0], floats[1]);
+                if b == 0. {
+                    return Err(CrispError::DivisionByZero);
+                }
+                let mut r = a % b;
+                if floored && r != 0. && (r < 0.) != (b < 0.) {
+                    r += b;
+                }
+                Ok(CrispExpr::Primitive(Primitive::Number(r)))
+            }
+        }
+    })
+}
+
+/// `(quotient a b)`, dividing two ints and dropping the remainder
+fn quotient(args: &[CrispExpr]) -> CrispResult {
+    match args {
+        [CrispExpr::Primitive(Primitive::Int(_)), CrispExpr::Primitive(Primitive::Int(0))] => {
+            Err(CrispError::DivisionByZero)
+        }
+        [CrispExpr::Primitive(Primitive::Int(a)), CrispExpr::Primitive(Primitive::Int(b))] => a
+            .checked_div(*b)
+            .map(|q| CrispExpr::Primitive(Primitive::Int(q)))
+            .ok_or(int_overflow()),
+        _ => Err(CrispError::EvalError(
+            "quotient takes exactly two integers".to_string(),
+        )),
+    }
+}
+
+/// `even?`, or `odd?` when `even` is false
+fn parity(name: &'static str, even: bool) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        match args {
+            [CrispExpr::Primitive(Primitive::Int(n))] => {
+                Ok(CrispExpr::Primitive(Primitive::Bool((n % 2 == 0) == even)))
+            }
+            _ => Err(CrispError::EvalError(format!(
+                "{name} takes exactly one integer"
+            ))),
+        }
+    })
+}
+
+/// `exact?`, true for ints and decimals, or `inexact?`, true for floats,
+/// when `exact` is false
+fn exactness(name: &'static str, exact: bool) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        match args {
+            [CrispExpr::Primitive(Primitive::Int(_) | Primitive::Decimal(_))] => {
+                Ok(CrispExpr::Primitive(Primitive::Bool(exact)))
+            }
+            [CrispExpr::Primitive(Primitive::Number(_))] => {
+                Ok(CrispExpr::Primitive(Primitive::Bool(!exact)))
+            }
+            _ => Err(CrispError::EvalError(format!(
+                "{name} takes exactly one number"
+            ))),
+        }
+    })
+}
+
+/// `(exact->inexact x)`, the nearest float to a number
+fn exact_to_inexact(args: &[CrispExpr]) -> CrispResult {
+    match args {
+        [arg @ CrispExpr::Primitive(
+            Primitive::Int(_) | Primitive::Decimal(_) | Primitive::Number(_),
+        )] => Ok(CrispExpr::Primitive(Primitive::Number(as_float(arg)))),
+        _ => Err(CrispError::EvalError(
+            "exact->inexact takes exactly one number".to_string(),
+        )),
+    }
+}
+
+/// `(inexact->exact x)`, a float as an int if it's whole, and otherwise as
+/// the shortest decimal that reads back as the same float, so
+/// `(inexact->exact 0.1)` is `0.1m`
+fn inexact_to_exact(args: &[CrispExpr]) -> CrispResult {
+    let n = match args {
+        [CrispExpr::Primitive(Primitive::Number(n))] => *n,
+        [exact @ CrispExpr::Primitive(Primitive::Int(_) | Primitive::Decimal(_))] => {
+            return Ok(exact.clone())
+        }
+        _ => {
+            return Err(CrispError::EvalError(
+                "inexact->exact takes exactly one number".to_string(),
+            ))
+        }
+    };
+
+    if !n.is_finite() {
+        return Err(CrispError::EvalError(format!("{n} has no exact value")));
+    }
+    if n.fract() == 0. && n.abs() < i64::MAX as f32 {
+        return Ok(CrispExpr::Primitive(Primitive::Int(n as i64)));
+    }
+    Decimal::parse(&n.to_string())
+        .map(|d| CrispExpr::Primitive(Primitive::Decimal(d)))
+        .ok_or(CrispError::EvalError(format!(
+            "{n} has too many digits to be an exact decimal"
+        )))
+}
+
+fn abs(args: &[CrispExpr]) -> CrispResult {
+    if args.len() != 1 {
+        return Err(CrispError::EvalError(
+            "abs takes exactly one number".to_string(),
+        ));
+    }
+
+    Ok(CrispExpr::Primitive(match numbers(args)? {
+        Numbers::Ints(ints) => Primitive::Int(ints[0].checked_abs().ok_or(int_overflow())?),
+        Numbers::Decimals(decimals) => {
+            Primitive::Decimal(decimals[0].checked_abs().ok_or(decimal_overflow())?)
+        }
+        Numbers::Floats(floats) => Primitive::Number(floats[0].abs()),
+    }))
+}
+
+/// `min` or `max`, picking one of the arguments as it is, so `(min 1 2m)`
+/// is 1 rather than 1m
+fn extremum(name: &'static str, pick: cmp::Ordering) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        let index = match numbers(args)? {
+            Numbers::Ints(ints) => extreme(&ints, pick),
+            Numbers::Decimals(decimals) => extreme(&decimals, pick),
+            Numbers::Floats(floats) => extreme(&floats, pick),
+        };
+        index
+            .map(|i| args[i].clone())
+            .ok_or_else(|| at_least_one(name))
+    })
+}
+
+/// The index of the first least item, or greatest if `pick` is `Greater`
+fn extreme<T: PartialOrd>(items: &[T], pick: cmp::Ordering) -> Option<usize> {
+    (0..items.len()).reduce(|best, i| match items[i].partial_cmp(&items[best]) {
+        Some(ordering) if ordering == pick => i,
+        _ => best,
+    })
+}
+
+/// `floor`, `ceil` or `round`, which give whole numbers of the same type as
+/// their argument
+fn rounding(name: &'static str, exact: fn(Decimal) -> Decimal, float: fn(f32) -> f32) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        if args.len() != 1 {
+            return Err(CrispError::EvalError(format!(
+                "{name} takes exactly one number"
+            )));
+        }
+
+        Ok(CrispExpr::Primitive(match numbers(args)? {
+            Numbers::Ints(ints) => Primitive::Int(ints[0]),
+            Numbers::Decimals(decimals) => Primitive::Decimal(exact(decimals[0])),
+            Numbers::Floats(floats) => Primitive::Number(float(floats[0])),
+        }))
+    })
+}
+
+/// A builtin applying `f` to one float
+fn float_fn(name: &'static str, f: fn(f32) -> f32) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        match float_args(name, args)?.as_slice() {
+            [x] => finite(name, args, f(*x)),
+            _ => Err(CrispError::EvalError(format!(
+                "{name} takes exactly one number"
+            ))),
+        }
+    })
+}
+
+/// `(atan y)`, or `(atan y x)` for the angle of the point (x, y)
+fn atan(args: &[CrispExpr]) -> CrispResult {
+    match float_args("atan", args)?.as_slice() {
+        [y] => finite("atan", args, y.atan()),
+        [y, x] => finite("atan", args, y.atan2(*x)),
+        _ => Err(CrispError::EvalError(
+            "atan takes one or two numbers".to_string(),
+        )),
+    }
+}
+
+/// `(pow base exponent)`, an int for an int raised to a non-negative int and
+/// a float otherwise
+fn pow(args: &[CrispExpr]) -> CrispResult {
+    if let [CrispExpr::Primitive(Primitive::Int(base)), CrispExpr::Primitive(Primitive::Int(exponent))] =
+        args
+    {
+        if let Ok(exponent) = u32::try_from(*exponent) {
+            return base
+                .checked_pow(exponent)
+                .map(|n| CrispExpr::Primitive(Primitive::Int(n)))
+                .ok_or(int_overflow());
+        }
+    }
+
+    match float_args("pow", args)?.as_slice() {
+        [base, exponent] => finite("pow", args, base.powf(*exponent)),
+        _ => Err(CrispError::EvalError(
+            "pow takes exactly two numbers".to_string(),
+        )),
+    }
+}
+
+/// The arguments of a builtin whose results are rarely exact, as floats
+fn float_args(name: &str, args: &[CrispExpr]) -> Result<Vec<f32>, CrispError> {
+    if args
+        .iter()
+        .any(|arg| matches!(arg, CrispExpr::Primitive(Primitive::Decimal(_))))
+    {
+        return Err(CrispError::EvalError(format!(
+            "{name} only takes floats, since its result can't be an exact decimal"
+        )));
+    }
+    parse_floats(args)
+}
+
+/// A float result, or an error in place of NaN or infinity, like for
+/// `(sqrt -1)` or `(ln 0)`
+fn finite(name: &str, args: &[CrispExpr], result: f32) -> CrispResult {
+    if !result.is_finite() {
+        let call = std::iter::once(name.to_string())
+            .chain(args.iter().map(to_source))
+            .collect::<Vec<_>>()
+            .join(" ");
+        return Err(CrispError::EvalError(format!(
+            "({call}) has no finite result"
+        )));
+    }
+    Ok(CrispExpr::Primitive(Primitive::Number(result)))
+}
+
+/// A builtin comparing numbers, true when `holds` for the ordering of every
+/// argument and the one after it, so `(< 1 2 3)` checks they're increasing
+fn comparison(name: &'static str, holds: fn(cmp::Ordering) -> bool) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        if args.len() < 2 {
+            return Err(CrispError::EvalError(format!(
+                "{name} takes at least two numbers"
+            )));
+        }
+
+        let chained = match numbers(args)? {
+            Numbers::Ints(ints) => chain(&ints, holds),
+            Numbers::Decimals(decimals) => chain(&decimals, holds),
+            Numbers::Floats(floats) => chain(&floats, holds),
+        };
+        Ok(CrispExpr::Primitive(Primitive::Bool(chained)))
+    })
+}
+
+/// Whether `holds` for the ordering of each item and the next. Items that
+/// can't be ordered, like NaN, fail every comparison.
+fn chain<T: PartialOrd>(items: &[T], holds: fn(cmp::Ordering) -> bool) -> bool {
+    items
+        .windows(2)
+        .all(|pair| pair[0].partial_cmp(&pair[1]).is_some_and(holds))
+}
+
+/// `=`, true when every argument equals the one after it, or `!=`, true when
+/// `=` would be false. Numbers are compared by value, so `(= 2 2.0 2m)`,
+/// and anything else structurally.
+fn equality(name: &'static str, equal: bool) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        if args.len() < 2 {
+            return Err(CrispError::EvalError(format!(
+                "{name} takes at least two arguments"
+            )));
+        }
+
+        let all_numbers = args.iter().all(|arg| {
+            matches!(
+                arg,
+                CrispExpr::Primitive(
+                    Primitive::Number(_) | Primitive::Int(_) | Primitive::Decimal(_)
+                )
+            )
+        });
+        let all_equal = match all_numbers {
+            true => match numbers(args)? {
+                Numbers::Ints(ints) => ints.windows(2).all(|pair| pair[0] == pair[1]),
+                Numbers::Decimals(decimals) => decimals.windows(2).all(|pair| pair[0] == pair[1]),
+                Numbers::Floats(floats) => floats.windows(2).all(|pair| pair[0] == pair[1]),
+            },
+            false => args.windows(2).all(|pair| pair[0] == pair[1]),
+        };
+        Ok(CrispExpr::Primitive(Primitive::Bool(all_equal == equal)))
+    })
+}
+
+/// The arguments of a math builtin promoted to the highest level of the
+/// tower among them: ints if every one is an int, decimals if any is a
+/// decimal and none is a float, and floats if any is a float
+enum Numbers {
+    Ints(Vec<i64>),
+    Decimals(Vec<Decimal>),
+    Floats(Vec<f32>),
+}
+
+fn numbers(args: &[CrispExpr]) -> Result<Numbers, CrispError> {
+    let mut level = 0;
+    for arg in args {
+        level = level.max(match arg {
+            CrispExpr::Primitive(Primitive::Int(_)) => 0,
+            CrispExpr::Primitive(Primitive::Decimal(_)) => 1,
+            CrispExpr::Primitive(Primitive::Number(_)) => 2,
+            _ => return Err(CrispError::EvalError("Expected a number".to_string())),
+        });
+    }
+
+    Ok(match level {
+        0 => Numbers::Ints(
+            args.iter()
+                .filter_map(|arg| match arg {
+                    CrispExpr::Primitive(Primitive::Int(n)) => Some(*n),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        1 => Numbers::Decimals(
+            args.iter()
+                .filter_map(|arg| match arg {
+                    CrispExpr::Primitive(Primitive::Int(n)) => Some(Decimal::from_i64(*n)),
+                    CrispExpr::Primitive(Primitive::Decimal(d)) => Some(*d),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => Numbers::Floats(args.iter().map(as_float).collect()),
+    })
+}
+
+/// `(/ x ...)` divided as IEEE floats, so dividing by zero gives infinity,
+/// or NaN for zero divided by zero. `None` if any argument is a decimal,
+/// since decimals have no infinity.
+pub(crate) fn float_quotient(args: &[CrispExpr]) -> Option<f32> {
+    let floats = match numbers(args).ok()? {
+        Numbers::Ints(ints) => ints.into_iter().map(|n| n as f32).collect(),
+        Numbers::Decimals(_) => return None,
+        Numbers::Floats(floats) => floats,
+    };
+
+    match floats.as_slice() {
+        [] => None,
+        [only] => Some(1. / only),
+        [first, rest @ ..] => Some(rest.iter().fold(*first, |acc, &x| acc / x)),
+    }
+}
+
+/// How two numbers compare once promoted to the same level of the tower,
+/// or `None` if either isn't a number or they can't be ordered, like NaN
+pub(crate) fn compare(a: &CrispExpr, b: &CrispExpr) -> Option<cmp::Ordering> {
+    match numbers(&[a.clone(), b.clone()]).ok()? {
+        Numbers::Ints(ints) => ints[0].partial_cmp(&ints[1]),
+        Numbers::Decimals(decimals) => decimals[0].partial_cmp(&decimals[1]),
+        Numbers::Floats(floats) => floats[0].partial_cmp(&floats[1]),
+    }
+}
+
+/// The nearest float to a number, which must be an int, decimal or float
+fn as_float(number: &CrispExpr) -> f32 {
+    match number {
+        CrispExpr::Primitive(Primitive::Int(n)) => *n as f32,
+        CrispExpr::Primitive(Primitive::Decimal(d)) => d.to_f32(),
+        CrispExpr::Primitive(Primitive::Number(n)) => *n,
+        _ => f32::NAN,
+    }
+}
+
+fn fold_ints(init: i64, ints: &[i64], op: fn(i64, i64) -> Option<i64>) -> CrispResult {
+    ints.iter()
+        .try_fold(init, |acc, &n| op(acc, n))
+        .map(|n| CrispExpr::Primitive(Primitive::Int(n)))
+        .ok_or(int_overflow())
+}
+
+fn int_overflow() -> CrispError {
+    CrispError::EvalError("integer overflow".to_string())
+}
+
+fn fold_decimals(
+    init: Decimal,
+    decimals: &[Decimal],
+    op: fn(Decimal, Decimal) -> Option<Decimal>,
+) -> CrispResult {
+    decimals
+        .iter()
+        .try_fold(init, |acc, &d| op(acc, d))
+        .map(|d| CrispExpr::Primitive(Primitive::Decimal(d)))
+        .ok_or(decimal_overflow())
+}
+
+fn decimal_overflow() -> CrispError {
+    CrispError::EvalError("decimal overflow".to_string())
+}
+
+/// The numbers in a reduction's single list argument
+fn list_numbers(name: &str, args: &[CrispExpr]) -> Result<Numbers, CrispError> {
+    match args {
+        [CrispExpr::List(items)] => numbers(items),
+        _ => Err(CrispError::EvalError(format!(
+            "{name} takes exactly one list of numbers"
+        ))),
+    }
+}
+
+/// The shared instance of a math builtin
+pub(crate) fn builtin(name: &str) -> Option<&'static CrispFn> {
+    builtins()
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, f)| f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::CrispEnv;
+
+    #[test]
+    fn eval_comparisons() {
+        let mut env = CrispEnv::default();
+
+        for (prog, expected) in [
+            ("(< 1 2 3)", true),
+            ("(< 1 3 2)", false),
+            ("(> 3 2 1)", true),
+            ("(>= 3 3 1)", true),
+            ("(<= 1 1 0)", false),
+            ("(< 0.1m 0.2m 1)", true),
+            ("(= 2 2 2m)", true),
+            ("(= (quote a) (quote a) (quote b))", false),
+            ("(= \"a\" 1m)", false),
+            ("(!= 1 1 2)", true),
+            ("(!= 1 1)", false),
+        ] {
+            assert_eq!(
+                crate::run_program(prog, &mut env),
+                Ok(CrispExpr::Primitive(Primitive::Bool(expected))),
+                "{prog}"
+            );
+        }
+        assert!(crate::run_program("(< 1)", &mut env).is_err());
+        assert!(crate::run_program("(< 1 (quote a))", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_division() {
+        let mut env = CrispEnv::default();
+        let run =
+            |prog, env: &mut CrispEnv| crate::run_program(prog, env).map(|value| to_source(&value));
+
+        for (prog, expected) in [
+            ("(/ 12 2 3)", "2.0"),
+            ("(/ 4)", "0.25"),
+            ("(/ 1.50m 2)", "0.75m"),
+            ("(mod -7 2)", "1"),
+            ("(rem -7 2)", "-1"),
+            ("(mod 7 -2)", "-1"),
+            ("(mod 7.5m 2)", "1.5m"),
+        ] {
+            assert_eq!(run(prog, &mut env), Ok(expected.to_string()), "{prog}");
+        }
+        for prog in ["(/ 1 0)", "(/ 0)", "(mod 1 0)", "(rem 1m 0)"] {
+            assert_eq!(
+                run(prog, &mut env).map_err(|err| err.inner().clone()),
+                Err(CrispError::DivisionByZero),
+                "{prog}"
+            );
+        }
+        assert!(run("(mod 1 2 3)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_ints() {
+        let mut env = CrispEnv::default();
+        let run =
+            |prog, env: &mut CrispEnv| crate::run_program(prog, env).map(|value| to_source(&value));
+
+        for (prog, expected) in [
+            ("(+ 1 2)", "3"),
+            ("(+ 1 2.0)", "3.0"),
+            ("(* 2 0.5)", "1.0"),
+            ("(- 10 0.25m)", "9.75m"),
+            ("(/ 7 2)", "3.5"),
+            ("(quotient 7 2)", "3"),
+            ("(quotient -7 2)", "-3"),
+            ("(mod -7 2)", "1"),
+            ("(even? -4)", "true"),
+            ("(odd? 4)", "false"),
+            ("(pow 2 -1)", "0.5"),
+            ("(= 2 2.0)", "true"),
+            ("(< 9007199254740992 9007199254740993)", "true"),
+        ] {
+            assert_eq!(run(prog, &mut env), Ok(expected.to_string()), "{prog}");
+        }
+
+        assert_eq!(
+            run("(* 9223372036854775807 2)", &mut env).map_err(|err| err.message()),
+            Err("integer overflow".to_string())
+        );
+        assert_eq!(
+            run("(quotient 1 0)", &mut env).map_err(|err| err.inner().clone()),
+            Err(CrispError::DivisionByZero)
+        );
+        for prog in ["(quotient 7 2.0)", "(even? 1.0)", "(odd? 1 2)"] {
+            assert!(run(prog, &mut env).is_err(), "{prog}");
+        }
+    }
+
+    #[test]
+    fn eval_across_the_tower() {
+        let mut env = CrispEnv::default();
+        let run = |prog: &str, env: &mut CrispEnv| {
+            crate::run_program(prog, env).map(|value| to_source(&value))
+        };
+
+        // Each op on every pair of an int, a decimal and a float, row by row
+        let operands = ["2", "0.5m", "0.25"];
+        for (op, results) in [
+            (
+                "+",
+                [
+                    "4", "2.5m", "2.25", "2.5m", "1.0m", "0.75", "2.25", "0.75", "0.5",
+                ],
+            ),
+            (
+                "-",
+                [
+                    "0", "1.5m", "1.75", "-1.5m", "0.0m", "0.25", "-1.75", "-0.25", "0.0",
+                ],
+            ),
+            (
+                "*",
+                [
+                    "4", "1.0m", "0.5", "1.0m", "0.25m", "0.125", "0.5", "0.125", "0.0625",
+                ],
+            ),
+            (
+                "/",
+                [
+                    "1.0", "4m", "8.0", "0.25m", "1.0m", "2.0", "0.125", "0.5", "1.0",
+                ],
+            ),
+            (
+                "<",
+                [
+                    "false", "false", "false", "true", "false", "false", "true", "true", "false",
+                ],
+            ),
+            (
+                "=",
+                [
+                    "true", "false", "false", "false", "true", "false", "false", "false", "true",
+                ],
+            ),
+        ] {
+            for (i, expected) in results.into_iter().enumerate() {
+                let prog = format!("({op} {} {})", operands[i / 3], operands[i % 3]);
+                assert_eq!(run(&prog, &mut env), Ok(expected.to_string()), "{prog}");
+            }
+        }
+
+        for (prog, expected) in [
+            ("(exact? 1)", "true"),
+            ("(exact? 1.5m)", "true"),
+            ("(exact? 1.5)", "false"),
+            ("(inexact? 1.5)", "true"),
+            ("(inexact? 1)", "false"),
+            ("(exact->inexact 3)", "3.0"),
+            ("(exact->inexact 1.25m)", "1.25"),
+            ("(exact->inexact 0.5)", "0.5"),
+            ("(inexact->exact 0.1)", "0.1m"),
+            ("(inexact->exact -4.0)", "-4"),
+            ("(inexact->exact 2m)", "2m"),
+            ("(sqrt (exact->inexact 2.25m))", "1.5"),
+        ] {
+            assert_eq!(run(prog, &mut env), Ok(expected.to_string()), "{prog}");
+        }
+        for prog in [
+            "(exact? \"1\")",
+            "(inexact->exact (/ 1 3) 1)",
+            "(exact->inexact)",
+        ] {
+            assert!(run(prog, &mut env).is_err(), "{prog}");
+        }
+    }
+
+    #[test]
+    fn eval_math_functions() {
+        let mut env = CrispEnv::default();
+        let run =
+            |prog, env: &mut CrispEnv| crate::run_program(prog, env).map(|value| to_source(&value));
+
+        for (prog, expected) in [
+            ("(abs -2.5)", "2.5"),
+            ("(abs -1.50m)", "1.50m"),
+            ("(min 3 1 2)", "1"),
+            ("(max 1 2.50m 2)", "2.50m"),
+            ("(floor -1.5)", "-2.0"),
+            ("(ceil 1.2)", "2.0"),
+            ("(round 2.5)", "3.0"),
+            ("(round 2.345m)", "2m"),
+            ("(sqrt 16)", "4.0"),
+            ("(pow 2 10)", "1024"),
+            ("(exp 0)", "1.0"),
+            ("(ln 1)", "0.0"),
+            ("(cos 0)", "1.0"),
+            ("(atan 0 1)", "0.0"),
+        ] {
+            assert_eq!(run(prog, &mut env), Ok(expected.to_string()), "{prog}");
+        }
+
+        assert_eq!(
+            run("(sqrt -1)", &mut env).map_err(|err| err.message()),
+            Err("(sqrt -1) has no finite result".to_string())
+        );
+        for prog in ["(ln 0)", "(sqrt 2m)", "(min)", "(abs 1 2)", "(pow 2)"] {
+            assert!(run(prog, &mut env).is_err(), "{prog}");
+        }
+    }
+}
@@ -0,0 +1,812 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::{
+    decimal::Decimal,
+    lang::{CrispError, CrispExpr, Primitive, Span},
+    lex_with_comments, Token,
+};
+
+pub fn parse(tokens: &[Token]) -> Result<(CrispExpr, &[Token]), CrispError> {
+    let (first, rest) = tokens.split_first().ok_or(CrispError::MissingParen(1, 0))?;
+
+    match first.text.as_str() {
+        "(" | "[" | "{" => parse_list(first, rest),
+        "'" if rest.is_empty() => Err(expected_after_quote(first)),
+        "'" => parse(rest).map(|(expr, rest)| (quoted(expr), rest)),
+        ")" | "]" | "}" => Err(unexpected(first)),
+        _ => Ok((
+            parse_atom(&first.text).map_err(|err| err.at(first.span))?,
+            rest,
+        )),
+    }
+}
+
+/// `'expr`, which reads as `(quote expr)`
+fn quoted(expr: CrispExpr) -> CrispExpr {
+    CrispExpr::List(vec![CrispExpr::Symbol("quote".to_string()), expr])
+}
+
+/// The token that closes a list, vector or map opened with `open`
+fn closing(open: &str) -> &'static str {
+    match open {
+        "[" => "]",
+        "{" => "}",
+        _ => ")",
+    }
+}
+
+fn is_closing(token: &Token) -> bool {
+    matches!(token.text.as_str(), ")" | "]" | "}")
+}
+
+/// A list, vector or map of `items`, depending on how it was opened. Maps
+/// are built when they're evaluated, so `{k v}` reads as `(hash-map k v)`.
+fn sequence(open: &Token, items: Vec<CrispExpr>) -> CrispExpr {
+    match open.text.as_str() {
+        "[" => CrispExpr::Vector(items),
+        "{" => CrispExpr::List(
+            std::iter::once(CrispExpr::Symbol("hash-map".to_string()))
+                .chain(items)
+                .collect(),
+        ),
+        _ => CrispExpr::List(items),
+    }
+}
+
+/// An error for a map literal from `open` to `close` with a key left
+/// without a value
+fn missing_value(open: &Token, close: &Token, items: &[CrispExpr]) -> Option<CrispError> {
+    (open.text == "{" && !items.len().is_multiple_of(2)).then(|| {
+        CrispError::SyntaxError("A map literal needs a value for every key".to_string())
+            .at(open.span.to(close.span))
+    })
+}
+
+fn unexpected(close: &Token) -> CrispError {
+    CrispError::SyntaxError(format!("Unexpected '{}'", close.text)).at(close.span)
+}
+
+/// An error for a list, vector or map left open at `at`, the token after the
+/// end of it
+fn unclosed(open: &Token, at: &Token) -> CrispError {
+    CrispError::SyntaxError(format!(
+        "Expected a '{}' to close the '{}' at line {}, char {}",
+        closing(&open.text),
+        open.text,
+        open.span.line,
+        open.span.col
+    ))
+    .at(at.span)
+}
+
+fn expected_after_quote(quote: &Token) -> CrispError {
+    CrispError::SyntaxError("Expected an expression after '".to_string()).at(quote.span)
+}
+
+/// The span covered by the tokens consumed from `tokens` to leave `rest`
+pub fn consumed_span(tokens: &[Token], rest: &[Token]) -> Span {
+    let last = tokens.len() - rest.len() - 1;
+    tokens[0].span.to(tokens[last].span)
+}
+
+/// The span of every expression in a parsed token stream, in the order the
+/// expressions are visited depth-first
+pub fn expr_spans(tokens: &[Token]) -> Vec<Span> {
+    let mut spans = vec![];
+    // Lists still being read, and whether each is a `'` waiting for the
+    // expression it quotes
+    let mut open: Vec<(usize, bool)> = vec![];
+    for token in tokens {
+        match token.text.as_str() {
+            "(" | "[" => {
+                open.push((spans.len(), false));
+                spans.push(token.span);
+                continue;
+            }
+            "{" => {
+                // The `(hash-map ...)` list, then the `hash-map` symbol
+                open.push((spans.len(), false));
+                spans.extend([token.span, token.span]);
+                continue;
+            }
+            "'" => {
+                // The `(quote ...)` list, then the `quote` symbol
+                open.push((spans.len(), true));
+                spans.extend([token.span, token.span]);
+                continue;
+            }
+            ")" | "]" | "}" => {
+                if let Some((i, _)) = open.pop() {
+                    spans[i] = spans[i].to(token.span);
+                }
+            }
+            _ => spans.push(token.span),
+        }
+
+        // An expression just ended, which finishes any quotes before it
+        while let Some(&(i, true)) = open.last() {
+            spans[i] = spans[i].to(token.span);
+            open.pop();
+        }
+    }
+
+    spans
+}
+
+/// A top-level form and where it is in the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct Form {
+    pub expr: CrispExpr,
+    pub span: Span,
+    /// Spans of the expressions in `expr`, in the order `visit::walk`
+    /// visits them, or empty if they aren't known
+    pub spans: Vec<Span>,
+    /// The `;;` comment lines written right before a definition, without
+    /// the `;;`, if it was parsed from source with its comments
+    pub doc: Option<String>,
+}
+
+impl Form {
+    /// The name the form binds, if it's a `def`, `def-global` or `defmacro`
+    pub fn defines(&self) -> Option<&str> {
+        let CrispExpr::List(list) = &self.expr else {
+            return None;
+        };
+        match list.as_slice() {
+            [CrispExpr::Symbol(head), CrispExpr::Symbol(name), ..]
+                if matches!(head.as_str(), "def" | "def-global" | "defmacro") =>
+            {
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parse every top-level form in a token stream, keeping their spans
+pub fn parse_forms(tokens: &[Token]) -> Result<Vec<Form>, CrispError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("parse", tokens = tokens.len()).entered();
+
+    let mut forms = vec![];
+    let mut xs = tokens;
+    while !xs.is_empty() {
+        let (expr, rest) = parse(xs)?;
+        forms.push(Form {
+            expr,
+            span: consumed_span(xs, rest),
+            spans: expr_spans(&xs[..xs.len() - rest.len()]),
+            doc: None,
+        });
+        xs = rest;
+    }
+
+    Ok(forms)
+}
+
+/// Parse every top-level form in a token stream
+pub fn parse_all(tokens: &[Token]) -> Result<Vec<CrispExpr>, CrispError> {
+    Ok(parse_forms(tokens)?
+        .into_iter()
+        .map(|form| form.expr)
+        .collect())
+}
+
+/// Limits on the source `parse_source` accepts, so services running
+/// untrusted programs can turn away pathological inputs before spending any
+/// time on them. The default only limits nesting, to `DEFAULT_MAX_DEPTH`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// Maximum length of the source in bytes
+    pub max_bytes: Option<usize>,
+    /// Maximum number of tokens in the source
+    pub max_tokens: Option<usize>,
+    /// Maximum number of lists and quotes an expression can be nested in.
+    /// Parsing, printing and evaluating recurse into nested expressions, so
+    /// without a limit deep enough input overflows the stack.
+    pub max_depth: Option<usize>,
+}
+
+/// How deep `ParseOptions::default()` lets expressions nest, with room to
+/// spare for a debug build evaluating on a main thread's 8 MiB stack.
+/// Threads with bigger stacks can allow more.
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            max_tokens: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+/// Lex and parse `src` into its top-level forms, with the docs written
+/// before their definitions, failing with `CrispError::TooLarge` if it's
+/// over any of the limits in `options`
+pub fn parse_source(src: &str, options: &ParseOptions) -> Result<Vec<Form>, CrispError> {
+    over_limit("bytes", src.len(), options.max_bytes)?;
+    let (tokens, comments) = lex_with_comments(src);
+    over_limit("tokens", tokens.len(), options.max_tokens)?;
+    if let Some(max) = options.max_depth {
+        check_depth(&tokens, max)?;
+    }
+
+    let mut forms = parse_forms(&tokens)?;
+    attach_docs(&mut forms, &tokens, &comments);
+    Ok(forms)
+}
+
+/// Give each definition in `forms` the block of `;;` comments on the lines
+/// right above it as its doc. Only comments on lines of their own count, and
+/// only for definitions that start their line. `tokens` and `comments` are
+/// the source of `forms` as split by `lex_with_comments`.
+pub fn attach_docs(forms: &mut [Form], tokens: &[Token], comments: &[Token]) {
+    // Where the first token on each line with code starts
+    let mut line_starts: HashMap<u32, usize> = HashMap::new();
+    for token in tokens {
+        line_starts
+            .entry(token.span.line)
+            .or_insert(token.span.start);
+    }
+    let doc_lines: HashMap<u32, &str> = comments
+        .iter()
+        .filter(|comment| !line_starts.contains_key(&comment.span.line))
+        .filter_map(|comment| Some((comment.span.line, comment.text.strip_prefix(";;")?)))
+        .collect();
+
+    for form in forms {
+        if form.defines().is_none() || line_starts.get(&form.span.line) != Some(&form.span.start) {
+            continue;
+        }
+
+        let mut lines = vec![];
+        let mut line = form.span.line;
+        while let Some(text) = line.checked_sub(1).and_then(|above| doc_lines.get(&above)) {
+            lines.push(text.strip_prefix(' ').unwrap_or(text));
+            line -= 1;
+        }
+        if !lines.is_empty() {
+            lines.reverse();
+            form.doc = Some(lines.join("\n"));
+        }
+    }
+}
+
+fn over_limit(what: &str, len: usize, max: Option<usize>) -> Result<(), CrispError> {
+    match max {
+        Some(max) if len > max => Err(CrispError::TooLarge(format!(
+            "input has {len} {what}, over the limit of {max}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Fail at the first token nested deeper than `max`, without recursing, so
+/// deep inputs can't overflow the parser's stack
+fn check_depth(tokens: &[Token], max: usize) -> Result<(), CrispError> {
+    // Whether each list or quote still open is a `'` waiting for the
+    // expression it quotes, as in `expr_spans`
+    let mut open: Vec<bool> = vec![];
+    for token in tokens {
+        match token.text.as_str() {
+            "(" | "[" | "{" | "'" => {
+                open.push(token.text == "'");
+                if open.len() > max {
+                    return Err(CrispError::TooLarge(format!(
+                        "input is nested more than {max} deep"
+                    ))
+                    .at(token.span));
+                }
+                continue;
+            }
+            ")" | "]" | "}" => {
+                open.pop();
+            }
+            _ => {}
+        }
+
+        while open.last() == Some(&true) {
+            open.pop();
+        }
+    }
+
+    Ok(())
+}
+
+/// The forms found by `parse_recovering`, along with the syntax errors it
+/// recovered from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Recovered {
+    pub forms: Vec<Form>,
+    pub errors: Vec<CrispError>,
+}
+
+/// Parse every top-level form like `parse_forms`, but carry on past syntax
+/// errors to find as much of the program as possible. Stray `)`s are skipped
+/// and lists left open are closed at the end of the input, with each error
+/// pointing at the token where parsing went wrong.
+pub fn parse_recovering(tokens: &[Token]) -> Recovered {
+    let mut forms = vec![];
+    let mut errors = vec![];
+    let mut xs = tokens;
+    while let Some((first, rest)) = xs.split_first() {
+        if is_closing(first) {
+            errors.push(unexpected(first));
+            xs = rest;
+            continue;
+        }
+
+        let (expr, rest) = parse_recovering_expr(xs, &mut errors);
+        forms.push(Form {
+            expr,
+            span: consumed_span(xs, rest),
+            spans: expr_spans(&xs[..xs.len() - rest.len()]),
+            doc: None,
+        });
+        xs = rest;
+    }
+
+    Recovered { forms, errors }
+}
+
+/// Parse an expression from a non-empty token stream, closing any lists
+/// still open at the end of it
+fn parse_recovering_expr<'a>(
+    tokens: &'a [Token],
+    errors: &mut Vec<CrispError>,
+) -> (CrispExpr, &'a [Token]) {
+    let (open, mut xs) = match tokens.split_first() {
+        Some((open, rest)) if matches!(open.text.as_str(), "(" | "[" | "{") => (open, rest),
+        Some((quote, rest)) if quote.text == "'" => {
+            if rest.first().is_none_or(is_closing) {
+                errors.push(expected_after_quote(quote));
+                return (quoted(CrispExpr::Primitive(Primitive::Nil)), rest);
+            }
+            let (expr, rest) = parse_recovering_expr(rest, errors);
+            return (quoted(expr), rest);
+        }
+        Some((atom, rest)) => {
+            let expr = parse_atom(&atom.text).unwrap_or_else(|err| {
+                errors.push(err.at(atom.span));
+                CrispExpr::Symbol(atom.text.clone())
+            });
+            return (expr, rest);
+        }
+        None => return (CrispExpr::List(vec![]), tokens),
+    };
+
+    let mut exps = vec![];
+    loop {
+        match xs.split_first() {
+            Some((close, rest)) if close.text == closing(&open.text) => {
+                errors.extend(missing_value(open, close, &exps));
+                return (sequence(open, exps), rest);
+            }
+            // A mismatched bracket closes this list too, since it most
+            // likely closes an enclosing one
+            Some((close, _)) if is_closing(close) => {
+                errors.push(unclosed(open, close));
+                return (sequence(open, exps), xs);
+            }
+            Some(_) => {
+                let (expr, rest) = parse_recovering_expr(xs, errors);
+                exps.push(expr);
+                xs = rest;
+            }
+            None => {
+                let last = tokens.last().unwrap_or(open);
+                errors.push(unclosed(open, last));
+                return (sequence(open, exps), xs);
+            }
+        }
+    }
+}
+
+fn parse_list<'a>(
+    open: &Token,
+    tokens: &'a [Token],
+) -> Result<(CrispExpr, &'a [Token]), CrispError> {
+    let mut exps: Vec<CrispExpr> = vec![];
+    let mut xs = tokens;
+    loop {
+        let close = closing(&open.text);
+        let (next, rest) = xs
+            .split_first()
+            .ok_or(CrispError::SyntaxError(format!("Expected a '{close}'")).at(open.span))?;
+
+        if next.text == close {
+            if let Some(err) = missing_value(open, next, &exps) {
+                return Err(err);
+            }
+            return Ok((sequence(open, exps), rest));
+        }
+        if is_closing(next) {
+            return Err(unclosed(open, next));
+        }
+
+        let (expr, rest) = parse(xs)?;
+        exps.push(expr);
+        xs = rest;
+    }
+}
+
+pub fn parse_floats(tokens: &[CrispExpr]) -> Result<Vec<f32>, CrispError> {
+    parse_while(tokens, parse_float)
+}
+
+pub fn parse_param_list(params: &[CrispExpr]) -> Result<Vec<String>, CrispError> {
+    parse_while(params, parse_symbol)
+        .map_err(|_| CrispError::EvalError("Param list must contain only symbols".to_string()))
+}
+
+fn parse_symbol(token: &CrispExpr) -> Result<String, CrispError> {
+    match token {
+        CrispExpr::Symbol(name) => Ok(name.clone()),
+        _ => Err(CrispError::EvalError("Expected a symbol".to_string())),
+    }
+}
+
+fn parse_float(expr: &CrispExpr) -> Result<f32, CrispError> {
+    expr.as_f32()
+        .ok_or(CrispError::EvalError("Expected a number".to_string()))
+}
+
+fn parse_while<T>(
+    tokens: &[CrispExpr],
+    predicate: fn(&CrispExpr) -> Result<T, CrispError>,
+) -> Result<Vec<T>, CrispError> {
+    tokens.iter().map(predicate).collect()
+}
+
+fn parse_atom(token: &str) -> Result<CrispExpr, CrispError> {
+    if let Some(literal) = token.strip_prefix('"') {
+        return parse_str(literal).map(|s| CrispExpr::Primitive(Primitive::Str(s)));
+    }
+
+    let digits = token.strip_prefix(['-', '+']).unwrap_or(token);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return match token.parse::<i64>() {
+            Ok(n) => Ok(CrispExpr::Primitive(Primitive::Int(n))),
+            Err(_) => Err(CrispError::SyntaxError(format!(
+                "integer {token} is too large, write it as {token}.0 for a float"
+            ))),
+        };
+    }
+
+    let float = token.parse::<f32>();
+
+    match float {
+        Ok(f) => Ok(CrispExpr::Primitive(Primitive::Number(f))),
+        Err(_) => match token {
+            "true" => Ok(CrispExpr::Primitive(Primitive::Bool(true))),
+            "false" => Ok(CrispExpr::Primitive(Primitive::Bool(false))),
+            "nil" => Ok(CrispExpr::Primitive(Primitive::Nil)),
+            _ => match token.strip_suffix('m').and_then(Decimal::parse) {
+                Some(d) => Ok(CrispExpr::Primitive(Primitive::Decimal(d))),
+                None => Ok(CrispExpr::Symbol(token.to_string())),
+            },
+        },
+    }
+}
+
+/// Read the contents of a string literal after its opening quote, undoing
+/// the escapes written by `format::quote_str`
+fn parse_str(literal: &str) -> Result<String, CrispError> {
+    let mut out = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(out),
+            '\\' => match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(c) => {
+                    return Err(CrispError::SyntaxError(format!(
+                        "Unknown escape '\\{c}' in string literal"
+                    )))
+                }
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+
+    Err(CrispError::SyntaxError(
+        "Unterminated string literal".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    #[test]
+    fn parse_basic() {
+        let tokens = lexer("(3 5 7)");
+        let (expr, rest) = parse(&tokens).unwrap();
+
+        assert!(rest.is_empty());
+
+        assert_eq!(
+            expr,
+            CrispExpr::List(vec![
+                CrispExpr::Primitive(Primitive::Int(3)),
+                CrispExpr::Primitive(Primitive::Int(5)),
+                CrispExpr::Primitive(Primitive::Int(7))
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_fn_call() {
+        let tokens = lexer("(+ 5 7)");
+        let (expr, rest) = parse(&tokens).unwrap();
+
+        assert!(rest.is_empty());
+
+        assert_eq!(
+            expr,
+            CrispExpr::List(vec![
+                CrispExpr::Symbol("+".to_string()),
+                CrispExpr::Primitive(Primitive::Int(5)),
+                CrispExpr::Primitive(Primitive::Int(7))
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_recovers_from_syntax_errors() {
+        let src = "(+ 1 2)) (def x\n  (* 3 4";
+        let recovered = parse_recovering(&lexer(src));
+
+        let forms = recovered
+            .forms
+            .iter()
+            .map(|form| crate::format::to_source(&form.expr))
+            .collect::<Vec<_>>();
+        assert_eq!(forms, ["(+ 1 2)", "(def x (* 3 4))"]);
+
+        let errors = recovered
+            .errors
+            .iter()
+            .map(|err| (err.message(), err.span().map(|span| (span.line, span.col))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                ("Unexpected ')'".to_string(), Some((1, 8))),
+                (
+                    "Expected a ')' to close the '(' at line 2, char 3".to_string(),
+                    Some((2, 8))
+                ),
+                (
+                    "Expected a ')' to close the '(' at line 1, char 10".to_string(),
+                    Some((2, 8))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_vectors() {
+        let tokens = lexer("[1 [a] (f [])]");
+        let (expr, rest) = parse(&tokens).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(crate::format::to_source(&expr), "[1 [a] (f [])]");
+        assert_eq!(expr_spans(&tokens).len(), 7);
+
+        let err = parse(&lexer("(f [1 2)")).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Expected a ']' to close the '[' at line 1, char 4"
+        );
+        let recovered = parse_recovering(&lexer("[1 (2]"));
+        assert_eq!(
+            crate::format::to_source(&recovered.forms[0].expr),
+            "[1 (2)]"
+        );
+        assert_eq!(recovered.errors.len(), 1);
+    }
+
+    #[test]
+    fn parse_maps() {
+        let tokens = lexer("{:a [1] :b {}}");
+        let (expr, rest) = parse(&tokens).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            crate::format::to_source(&expr),
+            "(hash-map :a [1] :b (hash-map))"
+        );
+        let spans = expr_spans(&tokens);
+        assert_eq!(spans.len(), 8);
+        assert_eq!((spans[0].start, spans[0].end), (0, 14));
+        assert_eq!((spans[1].start, spans[1].end), (0, 1));
+
+        let err = parse(&lexer("{:a 1 :b}")).unwrap_err();
+        assert_eq!(err.message(), "A map literal needs a value for every key");
+        assert_eq!(parse_recovering(&lexer("{:a}")).errors.len(), 1);
+    }
+
+    #[test]
+    fn parse_nested_lists() {
+        let tokens = lexer("((-1 10 4) 6 7)");
+        let (expr, rest) = parse(&tokens).unwrap();
+
+        assert!(rest.is_empty());
+
+        assert_eq!(
+            expr,
+            CrispExpr::List(vec![
+                CrispExpr::List(vec![
+                    CrispExpr::Primitive(Primitive::Int(-1)),
+                    CrispExpr::Primitive(Primitive::Int(10)),
+                    CrispExpr::Primitive(Primitive::Int(4))
+                ]),
+                CrispExpr::Primitive(Primitive::Int(6)),
+                CrispExpr::Primitive(Primitive::Int(7))
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_string_literals() {
+        let tokens = lexer(r#"(print "hello (world)" "say \"hi\"\n")"#);
+        let (expr, _) = parse(&tokens).unwrap();
+
+        assert_eq!(
+            expr,
+            CrispExpr::List(vec![
+                CrispExpr::Symbol("print".to_string()),
+                CrispExpr::Primitive(Primitive::Str("hello (world)".to_string())),
+                CrispExpr::Primitive(Primitive::Str("say \"hi\"\n".to_string())),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_ints_and_floats() {
+        let tokens = lexer("(3 -3 3.0 +4 1e3)");
+        let (expr, _) = parse(&tokens).unwrap();
+
+        assert_eq!(
+            expr,
+            CrispExpr::List(vec![
+                CrispExpr::Primitive(Primitive::Int(3)),
+                CrispExpr::Primitive(Primitive::Int(-3)),
+                CrispExpr::Primitive(Primitive::Number(3.)),
+                CrispExpr::Primitive(Primitive::Int(4)),
+                CrispExpr::Primitive(Primitive::Number(1000.)),
+            ])
+        );
+
+        let err = parse(&lexer("99999999999999999999")).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "integer 99999999999999999999 is too large, write it as 99999999999999999999.0 for a float"
+        );
+    }
+
+    #[test]
+    fn reject_bad_string_literals() {
+        for (src, message) in [
+            (r#"(print "oops)"#, "Unterminated string literal"),
+            (
+                r#"(print "a\qb")"#,
+                "Unknown escape '\\q' in string literal",
+            ),
+        ] {
+            let err = parse(&lexer(src)).unwrap_err();
+            assert_eq!(err.message(), message);
+            assert_eq!(err.span().map(|span| span.start), Some(7));
+        }
+    }
+
+    #[test]
+    fn parse_quote_sugar() {
+        let tokens = lexer("('(1 2) 'a ''b)");
+        let (expr, _) = parse(&tokens).unwrap();
+
+        assert_eq!(
+            expr,
+            parse(&lexer("((quote (1 2)) (quote a) (quote (quote b)))"))
+                .unwrap()
+                .0
+        );
+        assert!(parse(&lexer("'")).is_err());
+        assert_eq!(
+            expr_spans(&tokens).len(),
+            parse_forms(&lexer("((quote (1 2)) (quote a) (quote (quote b)))")).unwrap()[0]
+                .spans
+                .len()
+        );
+    }
+
+    #[test]
+    fn parse_source_limits() {
+        let options = ParseOptions {
+            max_bytes: Some(20),
+            max_tokens: Some(6),
+            max_depth: Some(2),
+        };
+        let too_large = |src| match parse_source(src, &options) {
+            Err(err) => err.kind() == "limit",
+            Ok(_) => false,
+        };
+
+        assert!(parse_source("(+ 1 (* 2 3))", &ParseOptions::default()).is_ok());
+        assert!(!too_large("(+ 1 '2)"));
+        assert!(too_large("(quote aaaaaaaaaaaaaaaaaaaa)"));
+        assert!(too_large("(+ 1 2 3 4 5)"));
+        assert!(too_large("(((1)))"));
+        assert!(too_large("(''a)"));
+        assert_eq!(
+            parse_source("(('a))", &options).unwrap_err().span(),
+            Some(lexer("(('a))")[2].span)
+        );
+    }
+
+    #[test]
+    fn default_limits_nesting() {
+        let deep = "(".repeat(100_000) + &")".repeat(100_000);
+        let err = parse_source(&deep, &ParseOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), "limit");
+
+        // Anything within the limit can be parsed and evaluated on a main
+        // thread's stack
+        let within = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| {
+                let src = "(+ 1 ".repeat(DEFAULT_MAX_DEPTH - 1)
+                    + "1"
+                    + &")".repeat(DEFAULT_MAX_DEPTH - 1);
+                let mut env = crate::eval::CrispEnv::default();
+                crate::run_program(&format!("'{src}"), &mut env)?;
+                crate::run_program(&src, &mut env)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            within,
+            Ok(CrispExpr::Primitive(Primitive::Int(
+                DEFAULT_MAX_DEPTH as i64
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_source_attaches_docs() {
+        let src = "\
+;; Add one.
+;;   Works on decimals too.
+(def inc (fn (x) (+ x 1)))
+;; not a definition
+(inc 1)
+; one semicolon isn't a doc
+(def a 1) ;; trailing
+(def b 2)";
+        let docs: Vec<_> = parse_source(src, &ParseOptions::default())
+            .unwrap()
+            .into_iter()
+            .map(|form| form.doc)
+            .collect();
+
+        assert_eq!(
+            docs,
+            [
+                Some("Add one.\n  Works on decimals too.".to_string()),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use crate::{
+    eval::CrispEnv,
+    lang::{CrispError, CrispExpr},
+    run_program,
+};
+
+/// Hands out envs for running scripts concurrently, e.g. one per request in
+/// a web server.
+///
+/// The builtins and prelude are set up once in a shared global env. Each env
+/// handed out is a fork of it, so definitions a script makes stay private to
+/// that script and are cleared when the env is returned to the pool. So are
+/// its warnings, including which deprecated names it's been warned about.
+pub struct EnvPool {
+    base: CrispEnv<'static>,
+    /// Emptied symbol tables from returned envs, kept to reuse their memory
+    idle: Mutex<Vec<HashMap<String, CrispExpr>>>,
+}
+
+impl EnvPool {
+    /// Create a pool whose envs see everything in `base` plus the
+    /// definitions made by running `prelude` in it
+    pub fn new(mut base: CrispEnv<'static>, prelude: &str) -> Result<Self, CrispError> {
+        if !prelude.trim().is_empty() {
+            run_program(prelude, &mut base)?;
+        }
+
+        Ok(Self {
+            base,
+            idle: Mutex::new(vec![]),
+        })
+    }
+
+    /// Take an env from the pool. It's returned when the guard is dropped.
+    pub fn get(&self) -> PooledEnv<'_> {
+        let symbols = self
+            .idle
+            .lock()
+            .ok()
+            .and_then(|mut idle| idle.pop())
+            .unwrap_or_default();
+
+        let mut env = self.base.fork();
+        env.symbols = symbols;
+
+        PooledEnv { env, pool: self }
+    }
+
+    /// Number of returned envs waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.idle.lock().map_or(0, |idle| idle.len())
+    }
+}
+
+/// An env borrowed from an `EnvPool`
+pub struct PooledEnv<'p> {
+    env: CrispEnv<'p>,
+    pool: &'p EnvPool,
+}
+
+impl<'p> Deref for PooledEnv<'p> {
+    type Target = CrispEnv<'p>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.env
+    }
+}
+
+impl<'p> DerefMut for PooledEnv<'p> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.env
+    }
+}
+
+impl<'p> Drop for PooledEnv<'p> {
+    fn drop(&mut self) {
+        let mut symbols = std::mem::take(&mut self.env.symbols);
+        symbols.clear();
+
+        if let Ok(mut idle) = self.pool.idle.lock() {
+            idle.push(symbols);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::Primitive;
+
+    #[test]
+    fn pooled_envs_share_prelude() {
+        let pool = EnvPool::new(CrispEnv::default(), "(def double (fn (x) (* x 2)))").unwrap();
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let pool = &pool;
+                scope.spawn(move || {
+                    let mut env = pool.get();
+                    let prog = format!("(begin (def x {i}) (double x))");
+
+                    assert_eq!(
+                        run_program(&prog, &mut env),
+                        Ok(CrispExpr::Primitive(Primitive::Int(i * 2)))
+                    );
+                });
+            }
+        });
+
+        assert!(pool.idle() > 0);
+    }
+
+    #[test]
+    fn returned_envs_are_reset() {
+        let pool = EnvPool::new(CrispEnv::default(), "").unwrap();
+
+        {
+            let mut env = pool.get();
+            run_program("(def x 1)", &mut env).unwrap();
+        }
+
+        let env = pool.get();
+        assert_eq!(pool.idle(), 0);
+        assert!(env.get("x").is_none());
+    }
+
+    #[test]
+    fn pooled_envs_keep_their_own_diagnostics() {
+        let pool = EnvPool::new(CrispEnv::default(), "(def old 1) (deprecate old)").unwrap();
+
+        std::thread::scope(|scope| {
+            for padding in [0, 20] {
+                let pool = &pool;
+                scope.spawn(move || {
+                    let prog = format!("{}(+ old (car 1))", " ".repeat(padding));
+                    for _ in 0..200 {
+                        let mut env = pool.get();
+                        let err = run_program(&prog, &mut env).unwrap_err();
+                        assert_eq!(
+                            err.span().map(|span| (span.start, span.end)),
+                            Some((padding + 7, padding + 14))
+                        );
+                        assert_eq!(env.take_warnings(), vec!["'old' is deprecated"]);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn deprecation_warnings_are_per_script() {
+        let pool = EnvPool::new(CrispEnv::default(), "(def old 1) (deprecate old)").unwrap();
+
+        for _ in 0..2 {
+            let mut env = pool.get();
+            run_program("old", &mut env).unwrap();
+            assert_eq!(env.take_warnings(), vec!["'old' is deprecated"]);
+            run_program("old", &mut env).unwrap();
+            assert!(env.take_warnings().is_empty());
+        }
+        assert!(pool.base.take_warnings().is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+//! Stacks and queues, for algorithms that would otherwise keep reversing
+//! lists. `(stack)` makes a stack, which `push` adds to and `pop` takes the
+//! most recently pushed item from, and `(queue)` a queue, which `enqueue`
+//! adds to and `dequeue` takes the oldest item from. `peek` looks at the
+//! item the next `pop` or `dequeue` would take without taking it.
+//!
+//! `(pqueue)` makes a priority queue, backed by a binary heap, which
+//! `pq-push!` adds to with a number as the priority and `pq-pop!` takes the
+//! item with the lowest priority from, the oldest first among equals, as
+//! schedulers and pathfinding like Dijkstra's algorithm want.
+//!
+//! Like string builders, these are all handles, so every copy of one sees
+//! what's added and taken through the others. `empty?` and `length` work on
+//! them as on lists.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::Mutex;
+
+use crate::{
+    format::to_source,
+    lang::{CrispError, CrispExpr, CrispFn, CrispResult, Handle, Resource},
+    math,
+};
+
+/// The stack, queue and priority queue builtins, registered under `queue/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "stack",
+            CrispFn::new(|args: &[CrispExpr]| Ok(Items::handle(Kind::Stack, args))),
+        ),
+        (
+            "queue",
+            CrispFn::new(|args: &[CrispExpr]| Ok(Items::handle(Kind::Queue, args))),
+        ),
+        ("push", add("push", Kind::Stack)),
+        ("enqueue", add("enqueue", Kind::Queue)),
+        ("pop", take("pop", Kind::Stack)),
+        ("dequeue", take("dequeue", Kind::Queue)),
+        (
+            "peek",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => {
+                    let items = Items::from_handle("peek", handle, None)?;
+                    items
+                        .with_items(|xs| match items.kind {
+                            Kind::Stack => xs.back().cloned(),
+                            Kind::Queue => xs.front().cloned(),
+                        })?
+                        .ok_or_else(|| items.empty_error("peek"))
+                }
+                _ => Err(CrispError::EvalError(
+                    "peek takes exactly one stack or queue".to_string(),
+                )),
+            }),
+        ),
+        (
+            "pqueue",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [] => Ok(CrispExpr::Handle(Handle::new(PriorityQueue::new()))),
+                _ => Err(CrispError::EvalError(
+                    "pqueue takes no arguments".to_string(),
+                )),
+            }),
+        ),
+        (
+            "pq-push!",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle), priority, value] => {
+                    if math::compare(priority, priority).is_none() {
+                        return Err(CrispError::EvalError(format!(
+                            "pq-push! takes a number as the priority, not {}",
+                            to_source(priority)
+                        )));
+                    }
+                    PriorityQueue::from_handle("pq-push!", handle)?.with_heap(|heap| {
+                        heap.added += 1;
+                        heap.entries.push(Entry {
+                            priority: priority.clone(),
+                            order: heap.added,
+                            value: value.clone(),
+                        });
+                    })?;
+                    Ok(CrispExpr::Handle(handle.clone()))
+                }
+                _ => Err(CrispError::EvalError(
+                    "pq-push! takes a priority queue, a priority and a value".to_string(),
+                )),
+            }),
+        ),
+        (
+            "pq-pop!",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => PriorityQueue::from_handle("pq-pop!", handle)?
+                    .with_heap(|heap| heap.entries.pop())?
+                    .map(|entry| entry.value)
+                    .ok_or(CrispError::EvalError(
+                        "pq-pop! of an empty priority queue".to_string(),
+                    )),
+                _ => Err(CrispError::EvalError(
+                    "pq-pop! takes exactly one priority queue".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// The number of items in a stack, queue or priority queue, if `handle` is
+/// one
+pub(crate) fn len(handle: &Handle) -> Option<Result<usize, CrispError>> {
+    if let Some(queue) = handle.downcast::<PriorityQueue>() {
+        return Some(queue.with_heap(|heap| heap.entries.len()));
+    }
+    let items: &Items = handle.downcast()?;
+    Some(items.with_items(|xs| xs.len()))
+}
+
+/// `push` or `enqueue`, which add their values in order and return the
+/// stack or queue
+fn add(builtin: &'static str, kind: Kind) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| match args {
+        [CrispExpr::Handle(handle), values @ ..] if !values.is_empty() => {
+            Items::from_handle(builtin, handle, Some(kind))?
+                .with_items(|xs| xs.extend(values.iter().cloned()))?;
+            Ok(CrispExpr::Handle(handle.clone()))
+        }
+        _ => Err(CrispError::EvalError(format!(
+            "{builtin} takes a {} and the values to add",
+            kind.name()
+        ))),
+    })
+}
+
+/// `pop` or `dequeue`, which remove and return the next item
+fn take(builtin: &'static str, kind: Kind) -> CrispFn {
+    CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+        match args {
+            [CrispExpr::Handle(handle)] => {
+                let items = Items::from_handle(builtin, handle, Some(kind))?;
+                items
+                    .with_items(|xs| match kind {
+                        Kind::Stack => xs.pop_back(),
+                        Kind::Queue => xs.pop_front(),
+                    })?
+                    .ok_or_else(|| items.empty_error(builtin))
+            }
+            _ => Err(CrispError::EvalError(format!(
+                "{builtin} takes exactly one {}",
+                kind.name()
+            ))),
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Stack,
+    Queue,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Stack => "stack",
+            Kind::Queue => "queue",
+        }
+    }
+}
+
+/// The resource behind the handles `stack` and `queue` return. Items go in
+/// at the back; stacks take them from the back and queues from the front.
+/// Closing it frees the items.
+pub struct Items {
+    kind: Kind,
+    items: Mutex<Option<VecDeque<CrispExpr>>>,
+}
+
+impl Items {
+    /// A handle to a new stack or queue holding `items`
+    fn handle(kind: Kind, items: &[CrispExpr]) -> CrispExpr {
+        CrispExpr::Handle(Handle::new(Self {
+            kind,
+            items: Mutex::new(Some(items.iter().cloned().collect())),
+        }))
+    }
+
+    /// The stack or queue behind `handle`, which has to be of `kind` if
+    /// one's given
+    fn from_handle<'h>(
+        builtin: &str,
+        handle: &'h Handle,
+        kind: Option<Kind>,
+    ) -> Result<&'h Items, CrispError> {
+        let expected = kind.map_or("stack or queue", Kind::name);
+        handle
+            .downcast()
+            .filter(|items: &&Items| kind.is_none_or(|kind| items.kind == kind))
+            .ok_or(CrispError::EvalError(format!(
+                "{builtin} expects a {expected}, not {}",
+                handle.name()
+            )))
+    }
+
+    /// Run `f` on the items, failing if the stack or queue's been closed
+    fn with_items<T>(
+        &self,
+        f: impl FnOnce(&mut VecDeque<CrispExpr>) -> T,
+    ) -> Result<T, CrispError> {
+        let name = self.kind.name();
+        let mut items = self
+            .items
+            .lock()
+            .map_err(|_| CrispError::EvalError(format!("{name} is poisoned")))?;
+        items
+            .as_mut()
+            .map(f)
+            .ok_or(CrispError::EvalError(format!("{name} is closed")))
+    }
+
+    fn empty_error(&self, builtin: &str) -> CrispError {
+        CrispError::EvalError(format!("{builtin} of an empty {}", self.kind.name()))
+    }
+}
+
+impl Resource for Items {
+    fn name(&self) -> &str {
+        self.kind.name()
+    }
+
+    fn close(&self) {
+        if let Ok(mut items) = self.items.lock() {
+            *items = None;
+        }
+    }
+}
+
+/// The resource behind the handles `pqueue` returns. Closing it frees the
+/// items.
+pub struct PriorityQueue {
+    heap: Mutex<Option<Heap>>,
+}
+
+struct Heap {
+    entries: BinaryHeap<Entry>,
+    /// How many items have ever been pushed, numbering them so that items
+    /// of equal priority come out in the order they went in
+    added: u64,
+}
+
+/// An item in a priority queue. `BinaryHeap` pops the greatest entry first,
+/// so entries order as the reverse of their priority, then of when they
+/// were pushed.
+struct Entry {
+    priority: CrispExpr,
+    order: u64,
+    value: CrispExpr,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Priorities are checked to be orderable when pushed
+        math::compare(&other.priority, &self.priority)
+            .unwrap_or(Ordering::Equal)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PriorityQueue {
+    fn new() -> Self {
+        Self {
+            heap: Mutex::new(Some(Heap {
+                entries: BinaryHeap::new(),
+                added: 0,
+            })),
+        }
+    }
+
+    fn from_handle<'h>(builtin: &str, handle: &'h Handle) -> Result<&'h PriorityQueue, CrispError> {
+        handle.downcast().ok_or(CrispError::EvalError(format!(
+            "{builtin} expects a priority queue, not {}",
+            handle.name()
+        )))
+    }
+
+    /// Run `f` on the heap, failing if the queue's been closed
+    fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> Result<T, CrispError> {
+        let mut heap = self
+            .heap
+            .lock()
+            .map_err(|_| CrispError::EvalError("priority queue is poisoned".to_string()))?;
+        heap.as_mut().map(f).ok_or(CrispError::EvalError(
+            "priority queue is closed".to_string(),
+        ))
+    }
+}
+
+impl Resource for PriorityQueue {
+    fn name(&self) -> &str {
+        "priority queue"
+    }
+
+    fn close(&self) {
+        if let Ok(mut heap) = self.heap.lock() {
+            *heap = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn stacks_and_queues() {
+        assert_eq!(
+            eval_source(
+                "(def s (push (stack 1) 2 3))\n\
+                 (list (pop s) (peek s) (pop s) (length s) (pop s) (empty? s))"
+            ),
+            Ok("(3 2 2 1 1 true)".to_string())
+        );
+        assert_eq!(
+            eval_source(
+                "(def q (enqueue (queue 1) 2 3))\n\
+                 (list (dequeue q) (peek q) (dequeue q) (length q) (dequeue q) (empty? q))"
+            ),
+            Ok("(1 2 2 1 3 true)".to_string())
+        );
+        // Every copy of a queue is the same queue
+        assert_eq!(
+            eval_source(
+                "(def q (queue))\n\
+                 (def add (fn (q x) (enqueue q x)))\n\
+                 (add q :a)\n\
+                 (dequeue q)"
+            ),
+            Ok(":a".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(pop (stack))"),
+            Err("pop of an empty stack".to_string())
+        );
+        assert_eq!(
+            eval_source("(dequeue (stack 1))"),
+            Err("dequeue expects a queue, not stack".to_string())
+        );
+        assert!(eval_source("(peek (string-builder))").is_err());
+        assert!(eval_source("(push (stack))").is_err());
+    }
+
+    #[test]
+    fn priority_queues() {
+        assert_eq!(
+            eval_source(
+                "(def q (pqueue))\n\
+                 (pq-push! (pq-push! q 3 :c) 1 :a)\n\
+                 (pq-push! q 2.5 :b)\n\
+                 (pq-push! q 1m :a2)\n\
+                 (list (length q) (pq-pop! q) (pq-pop! q) (pq-pop! q) (pq-pop! q) (empty? q))"
+            ),
+            Ok("(4 :a :a2 :b :c true)".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(pq-pop! (pqueue))"),
+            Err("pq-pop! of an empty priority queue".to_string())
+        );
+        assert!(eval_source("(pq-push! (pqueue) :high 1)").is_err());
+        assert!(eval_source("(pq-pop! (queue 1))").is_err());
+    }
+}
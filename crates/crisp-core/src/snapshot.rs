@@ -0,0 +1,406 @@
+//! Snapshots of a global env, taken after running a prelude, so an embedder
+//! can start from the prelude's definitions without parsing or evaluating it
+//! again. A snapshot made with `crisp snapshot` can be embedded with
+//! `include_bytes!` and restored on startup.
+//!
+//! A snapshot starts with the bytes `CRISPS` and the format version as a
+//! little-endian `u16`, then holds the env's bindings, the builtins they
+//! rebind, its macros, docs and deprecations. Values are encoded as in
+//! `compile`, with three more tags: maps, lambdas, and builtins, which are
+//! stored by name and looked up in the env being restored into. Where each binding was
+//! defined isn't kept. The locals a lambda closed over are stored once for
+//! every lambda sharing them, as one scope holding every binding visible
+//! from them.
+
+use std::{cell::RefCell, sync::Arc};
+
+use indexmap::IndexMap;
+
+use crate::{
+    compile::{encode_expr, write_len, write_str, Reader, BUILTIN, LAMBDA, LIST, MAP, VECTOR},
+    eval::{CrispEnv, CrispEnvBuilder},
+    lang::{Closure, CrispError, CrispExpr, CrispFn, CrispLambda, MapKey},
+};
+
+const MAGIC: &[u8] = b"CRISPS";
+
+/// Bumped whenever the layout of snapshots changes
+pub const FORMAT_VERSION: u16 = 5;
+
+/// Encode everything defined in a global env since it was built. Fails if a
+/// binding holds a handle, an array, or a native function that isn't one of
+/// the env's builtins.
+pub fn snapshot(env: &CrispEnv) -> Result<Vec<u8>, CrispError> {
+    let mut builtins: Vec<(&str, &CrispFn)> = env
+        .bindings()
+        .into_iter()
+        .filter_map(|(name, _)| match env.original_builtin(name) {
+            Some(CrispExpr::Fn(f)) => Some((name, f)),
+            _ => None,
+        })
+        .collect();
+    // Prefer plain names, which are the ones scripts use
+    builtins.sort_by_key(|(name, _)| name.contains('/'));
+    let encoder = Encoder {
+        builtins,
+        closures: RefCell::default(),
+    };
+
+    let defined: Vec<_> = env
+        .bindings()
+        .into_iter()
+        .filter(|(name, value)| env.original_builtin(name) != Some(value))
+        .collect();
+
+    let mut out = MAGIC.to_vec();
+    out.extend(FORMAT_VERSION.to_le_bytes());
+
+    write_len(&mut out, defined.len());
+    for (name, value) in defined {
+        write_str(&mut out, name);
+        encoder.value(&mut out, value).map_err(|err| {
+            CrispError::FormatError(format!("can't snapshot {name}: {}", err.message()))
+        })?;
+    }
+
+    let mut shadowed: Vec<_> = env.shadowed.keys().collect();
+    shadowed.sort();
+    write_len(&mut out, shadowed.len());
+    for name in shadowed {
+        write_str(&mut out, name);
+    }
+
+    let mut macros: Vec<_> = env.macros.iter().collect();
+    macros.sort_by_key(|(name, _)| *name);
+    write_len(&mut out, macros.len());
+    for (name, lambda) in macros {
+        write_str(&mut out, name);
+        encoder.lambda(&mut out, lambda)?;
+    }
+
+    let mut docs: Vec<_> = env.docs.iter().collect();
+    docs.sort();
+    write_len(&mut out, docs.len());
+    for (name, doc) in docs {
+        write_str(&mut out, name);
+        write_str(&mut out, doc);
+    }
+
+    let mut deprecated: Vec<_> = env.deprecated.iter().collect();
+    deprecated.sort();
+    write_len(&mut out, deprecated.len());
+    for (name, instead) in deprecated {
+        write_str(&mut out, name);
+        match instead {
+            Some(instead) => {
+                out.push(1);
+                write_str(&mut out, instead);
+            }
+            None => out.push(0),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Build an env with `builder` and restore a snapshot into it. The builder
+/// needs every builtin the snapshot's values refer to.
+pub fn restore<'a>(bytes: &[u8], builder: CrispEnvBuilder) -> Result<CrispEnv<'a>, CrispError> {
+    let mut env = builder.build();
+    let mut reader = Reader::new(bytes);
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(CrispError::FormatError("not a crisp snapshot".to_string()));
+    }
+
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != FORMAT_VERSION {
+        return Err(CrispError::FormatError(format!(
+            "snapshot has format version {version}, but this version of crisp reads version \
+             {FORMAT_VERSION}; take it again with `crisp snapshot`"
+        )));
+    }
+
+    let mut closures = vec![];
+    let mut defined = vec![];
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        defined.push((name, value(&mut reader, &env, &mut closures)?));
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        let original = env.original_builtin(&name).cloned().ok_or_else(|| {
+            CrispError::FormatError(format!("snapshot rebinds {name}, which isn't a builtin"))
+        })?;
+        env.shadowed.insert(name, original);
+    }
+    env.symbols.extend(defined);
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        let lambda = lambda(&mut reader, &env, &mut closures)?;
+        env.macros.insert(name, lambda);
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        env.docs.insert(name, reader.string()?);
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        let instead = match reader.array()? {
+            [0] => None,
+            _ => Some(reader.string()?),
+        };
+        env.deprecated.insert(name, instead);
+    }
+
+    if !reader.at_end() {
+        return Err(CrispError::FormatError(
+            "unexpected data at the end of the snapshot".to_string(),
+        ));
+    }
+
+    Ok(env)
+}
+
+struct Encoder<'e> {
+    /// The env's builtin functions, by the name to store them under
+    builtins: Vec<(&'e str, &'e CrispFn)>,
+    /// The closures written so far, which later lambdas sharing them refer
+    /// to by index
+    closures: RefCell<Vec<Arc<Closure>>>,
+}
+
+impl Encoder<'_> {
+    fn value(&self, out: &mut Vec<u8>, expr: &CrispExpr) -> Result<(), CrispError> {
+        match expr {
+            CrispExpr::List(xs) | CrispExpr::Vector(xs) => {
+                out.push(match expr {
+                    CrispExpr::Vector(_) => VECTOR,
+                    _ => LIST,
+                });
+                write_len(out, xs.len());
+                for x in xs {
+                    self.value(out, x)?;
+                }
+            }
+            CrispExpr::Map(map) => {
+                out.push(MAP);
+                write_len(out, map.len());
+                for (key, value) in map {
+                    self.value(out, key.expr())?;
+                    self.value(out, value)?;
+                }
+            }
+            CrispExpr::Lambda(lambda) => {
+                out.push(LAMBDA);
+                self.lambda(out, lambda)?;
+            }
+            CrispExpr::Fn(f) => {
+                let (name, _) = self
+                    .builtins
+                    .iter()
+                    .find(|(_, builtin)| *builtin == f)
+                    .ok_or(CrispError::FormatError(
+                        "native functions other than builtins can't be snapshotted".to_string(),
+                    ))?;
+                out.push(BUILTIN);
+                write_str(out, name);
+            }
+            CrispExpr::Symbol(_) | CrispExpr::Primitive(_) => encode_expr(out, expr)?,
+            _ => {
+                return Err(CrispError::FormatError(
+                    "handles and arrays can't be snapshotted".to_string(),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lambda(&self, out: &mut Vec<u8>, lambda: &CrispLambda) -> Result<(), CrispError> {
+        for names in [&lambda.params, &lambda.keywords] {
+            write_len(out, names.len());
+            for name in names {
+                write_str(out, name);
+            }
+        }
+        self.value(out, &lambda.body)?;
+
+        let Some(closure) = &lambda.closure else {
+            out.push(0);
+            return Ok(());
+        };
+        let seen = self
+            .closures
+            .borrow()
+            .iter()
+            .position(|c| Arc::ptr_eq(c, closure));
+        if let Some(i) = seen {
+            out.push(1);
+            write_len(out, i);
+            return Ok(());
+        }
+
+        // Lambdas in the closure can share it, so it's numbered before
+        // they're written
+        self.closures.borrow_mut().push(closure.clone());
+        out.push(2);
+        let bindings = closure.bindings();
+        write_len(out, bindings.len());
+        for (name, value) in &bindings {
+            write_str(out, name);
+            self.value(out, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a value, adding the closures it has that weren't read before to
+/// `closures`
+fn value(
+    reader: &mut Reader,
+    env: &CrispEnv,
+    closures: &mut Vec<Arc<Closure>>,
+) -> Result<CrispExpr, CrispError> {
+    let [tag] = reader.array()?;
+
+    Ok(match tag {
+        LIST | VECTOR => {
+            let len = reader.len()?;
+            let mut xs = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                xs.push(value(reader, env, closures)?);
+            }
+            match tag {
+                VECTOR => CrispExpr::Vector(xs),
+                _ => CrispExpr::List(xs),
+            }
+        }
+        MAP => {
+            let len = reader.len()?;
+            let mut map = IndexMap::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let key = MapKey::new(value(reader, env, closures)?)
+                    .map_err(|err| CrispError::FormatError(err.message()))?;
+                map.insert(key, value(reader, env, closures)?);
+            }
+            CrispExpr::Map(map)
+        }
+        LAMBDA => CrispExpr::Lambda(lambda(reader, env, closures)?),
+        BUILTIN => {
+            let name = reader.string()?;
+            match env.original_builtin(&name) {
+                Some(f @ CrispExpr::Fn(_)) => f.clone(),
+                _ => {
+                    return Err(CrispError::FormatError(format!(
+                        "snapshot uses the builtin {name}, which the env being restored into \
+                         doesn't have"
+                    )))
+                }
+            }
+        }
+        tag => reader.tagged(tag)?,
+    })
+}
+
+fn lambda(
+    reader: &mut Reader,
+    env: &CrispEnv,
+    closures: &mut Vec<Arc<Closure>>,
+) -> Result<CrispLambda, CrispError> {
+    let mut names = || -> Result<Vec<String>, CrispError> {
+        (0..reader.len()?).map(|_| reader.string()).collect()
+    };
+    let params = names()?;
+    let keywords = names()?;
+
+    let mut lambda = CrispLambda::new(params, value(reader, env, closures)?);
+    lambda.keywords = keywords;
+    lambda.closure = match reader.array()? {
+        [0] => None,
+        [1] => {
+            let i = reader.len()?;
+            let closure = closures.get(i).ok_or(CrispError::FormatError(format!(
+                "snapshot refers to closure {i} before it's defined"
+            )))?;
+            Some(closure.clone())
+        }
+        _ => {
+            let closure = Arc::new(Closure::default());
+            closures.push(closure.clone());
+            for _ in 0..reader.len()? {
+                let name = reader.string()?;
+                closure.insert(name, value(reader, env, closures)?);
+            }
+            Some(closure)
+        }
+    };
+
+    Ok(lambda)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::to_source, run_program};
+
+    #[test]
+    fn restored_env_keeps_definitions() {
+        let mut env = CrispEnvBuilder::new().pure().build();
+        run_program(
+            ";; Add one\n(def inc (fn (x) (+ x 1)))\n\
+             (def add-two (fn (x) (inc (inc x))))\n\
+             (def sum-of (fn (xs) (sum xs)))\n\
+             (def data (quote (1 2 3)))\n\
+             (def ages {:jim 4 [:a 1.5m] 2})\n\
+             (defmacro swap (f a b) (f b a))\n\
+             (def parity (fn () \
+               (def ev (fn (n) (if (= n 0) \"e\" (od (- n 1))))) \
+               (def od (fn (n) (if (= n 0) \"o\" (ev (- n 1))))) \
+               ev))\n\
+             (def even-odd (parity))",
+            &mut env,
+        )
+        .unwrap();
+
+        let bytes = snapshot(&env).unwrap();
+        let mut restored = restore(&bytes, CrispEnvBuilder::new().pure()).unwrap();
+
+        let res = run_program(
+            "(str (add-two 1) (sum-of data) (swap - 1 10) (even-odd 3))",
+            &mut restored,
+        );
+        assert_eq!(
+            res.map(|value| to_source(&value)),
+            Ok("\"369o\"".to_string())
+        );
+        assert_eq!(restored.get("ages"), env.get("ages"));
+        assert_eq!(restored.doc("inc"), Some("Add one"));
+        // Builtins come from the builder, not the snapshot
+        assert_eq!(restored.get("+"), env.get("+"));
+    }
+
+    #[test]
+    fn restore_needs_the_snapshots_builtins() {
+        let mut env = CrispEnvBuilder::new().pure().build();
+        run_program("(def total (fn (xs) (sum xs))) (def plus +)", &mut env).unwrap();
+        let bytes = snapshot(&env).unwrap();
+
+        let Err(err) = restore(&bytes, CrispEnvBuilder::new().core()) else {
+            panic!("restored without +");
+        };
+        assert!(err.message().contains("builtin +"), "{err}");
+
+        let mut other = MAGIC.to_vec();
+        other.extend(99u16.to_le_bytes());
+        let Err(err) = restore(&other, CrispEnvBuilder::new()) else {
+            panic!("restored another format version");
+        };
+        assert!(err.message().contains("format version 99"));
+    }
+}
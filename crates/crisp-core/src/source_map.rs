@@ -0,0 +1,150 @@
+//! Mapping the expressions an error passed through back to where they were
+//! written. While an error propagates, `eval` records the address of each
+//! list it was evaluating, innermost first. Addresses inside a top-level
+//! form resolve to spans through a `SourceMap`; those inside a macro
+//! expansion are first pointed back at the arguments of the call they were
+//! copied from, so errors land on the code the user wrote rather than code
+//! they never saw.
+
+use std::collections::HashMap;
+
+use crate::{
+    lang::{CrispExpr, Span},
+    parse::Form,
+    visit::{walk, Visitor, Walk},
+};
+
+/// Where each expression of a top-level form was written, by address
+pub(crate) struct SourceMap(HashMap<usize, Span>);
+
+impl SourceMap {
+    /// Pair the expressions of `form` with its spans. Forms without spans
+    /// for every expression, like compiled ones, map nothing.
+    pub(crate) fn new(form: &Form) -> Self {
+        let mut addresses = Addresses(vec![]);
+        walk(&form.expr, &mut addresses);
+
+        let spans = if addresses.0.len() == form.spans.len() {
+            addresses
+                .0
+                .into_iter()
+                .zip(form.spans.iter().copied())
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Self(spans)
+    }
+
+    /// The span of the innermost expression in `trace` that was written in
+    /// this form
+    pub(crate) fn span(&self, trace: &[usize]) -> Option<Span> {
+        trace.iter().find_map(|addr| self.0.get(addr).copied())
+    }
+}
+
+/// The address identifying an expression in a trace
+pub(crate) fn address(expr: &CrispExpr) -> usize {
+    expr as *const CrispExpr as usize
+}
+
+/// Point the addresses in `trace` that are inside `expansion` at the
+/// argument of the macro call they were copied from, if there is one
+pub(crate) fn map_expansion(trace: &mut [usize], expansion: &CrispExpr, args: &[CrispExpr]) {
+    let mut expanded = Expanded {
+        trace,
+        exprs: HashMap::new(),
+    };
+    walk(expansion, &mut expanded);
+    if expanded.exprs.is_empty() {
+        return;
+    }
+
+    let mut written = Written {
+        exprs: expanded.exprs,
+        found: HashMap::new(),
+    };
+    for arg in args {
+        walk(arg, &mut written);
+    }
+
+    for addr in trace.iter_mut() {
+        if let Some(arg) = written.found.get(addr) {
+            *addr = *arg;
+        }
+    }
+}
+
+/// Collects the address of every expression in depth-first order
+struct Addresses(Vec<usize>);
+
+impl Visitor for Addresses {
+    fn enter(&mut self, expr: &CrispExpr) -> Walk {
+        self.0.push(address(expr));
+        Walk::Continue
+    }
+}
+
+/// Copies the expressions of an expansion that are in a trace
+struct Expanded<'t> {
+    trace: &'t [usize],
+    exprs: HashMap<usize, CrispExpr>,
+}
+
+impl Visitor for Expanded<'_> {
+    fn enter(&mut self, expr: &CrispExpr) -> Walk {
+        let addr = address(expr);
+        if self.trace.contains(&addr) {
+            self.exprs.insert(addr, expr.clone());
+        }
+        Walk::Continue
+    }
+}
+
+/// Finds where in a macro call's arguments each expanded expression was
+/// copied from
+struct Written {
+    exprs: HashMap<usize, CrispExpr>,
+    found: HashMap<usize, usize>,
+}
+
+impl Visitor for Written {
+    fn enter(&mut self, expr: &CrispExpr) -> Walk {
+        for (addr, expanded) in &self.exprs {
+            if expanded == expr {
+                self.found.entry(*addr).or_insert(address(expr));
+            }
+        }
+        Walk::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval::CrispEnv, eval_str};
+
+    /// The source the error from evaluating `src` points at
+    fn error_at(src: &str) -> &str {
+        let err = eval_str(src, &mut CrispEnv::default()).unwrap_err();
+        let span = err.span().unwrap();
+        &src[span.start..span.end]
+    }
+
+    #[test]
+    fn errors_point_at_the_innermost_expression() {
+        assert_eq!(error_at("(def x 1)\n(+ x (* 2 y))"), "y");
+        assert_eq!(error_at("(+ 1 (- 2 (quote a)))"), "(- 2 (quote a))");
+    }
+
+    #[test]
+    fn errors_in_expansions_point_at_the_code_written() {
+        // The failing argument was copied into the expansion
+        let src = "(defmacro twice (x) (+ x x)) (twice (- 1 (quote a)))";
+        assert_eq!(error_at(src), "(- 1 (quote a))");
+
+        // The failing code is only in the macro's template
+        let src = "(defmacro broken (x) (- x (quote a))) (+ 1 (broken 2))";
+        assert_eq!(error_at(src), "(broken 2)");
+    }
+}
@@ -0,0 +1,147 @@
+//! String builders, for building up a long string a piece at a time. Joining
+//! strings with `str` in a loop copies everything built so far on every
+//! step, while appending to a builder only copies the new piece.
+//!
+//! A builder is a handle, the one kind of value that can change, so every
+//! copy of it sees what's appended through the others.
+
+use std::sync::Mutex;
+
+use crate::{
+    format::to_display,
+    lang::{CrispError, CrispExpr, CrispFn, Handle, Primitive, Resource},
+};
+
+/// The string builder builtins, registered under `str/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "string-builder",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [] => Ok(CrispExpr::Handle(Handle::new(StringBuilder::new()))),
+                _ => Err(CrispError::EvalError(
+                    "string-builder takes no arguments".to_string(),
+                )),
+            }),
+        ),
+        (
+            "sb-append!",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle), values @ ..] => {
+                    StringBuilder::from_handle("sb-append!", handle)?.with_text(|text| {
+                        for value in values {
+                            text.push_str(&to_display(value));
+                        }
+                    })?;
+                    Ok(CrispExpr::Handle(handle.clone()))
+                }
+                _ => Err(CrispError::EvalError(
+                    "sb-append! takes a string builder and the values to append".to_string(),
+                )),
+            }),
+        ),
+        (
+            "sb-build",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => {
+                    let text = StringBuilder::from_handle("sb-build", handle)?
+                        .with_text(|text| text.clone())?;
+                    Ok(CrispExpr::Primitive(Primitive::Str(text)))
+                }
+                _ => Err(CrispError::EvalError(
+                    "sb-build takes exactly one string builder".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// The resource behind the handles `string-builder` returns. Closing it
+/// frees the text.
+pub struct StringBuilder {
+    text: Mutex<Option<String>>,
+}
+
+impl StringBuilder {
+    fn new() -> Self {
+        Self {
+            text: Mutex::new(Some(String::new())),
+        }
+    }
+
+    fn from_handle<'h>(builtin: &str, handle: &'h Handle) -> Result<&'h StringBuilder, CrispError> {
+        handle.downcast().ok_or(CrispError::EvalError(format!(
+            "{builtin} expects a string builder, not {}",
+            handle.name()
+        )))
+    }
+
+    /// Run `f` on the text built so far, failing if the builder's been
+    /// closed
+    fn with_text<T>(&self, f: impl FnOnce(&mut String) -> T) -> Result<T, CrispError> {
+        let mut text = self
+            .text
+            .lock()
+            .map_err(|_| CrispError::EvalError("string builder is poisoned".to_string()))?;
+        text.as_mut().map(f).ok_or(CrispError::EvalError(
+            "string builder is closed".to_string(),
+        ))
+    }
+}
+
+impl Resource for StringBuilder {
+    fn name(&self) -> &str {
+        "string-builder"
+    }
+
+    fn close(&self) {
+        if let Ok(mut text) = self.text.lock() {
+            *text = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval::CrispEnv, lang::CrispExpr, run_program, test_util::eval_source};
+
+    #[test]
+    fn build_strings() {
+        assert_eq!(
+            eval_source(
+                "(def b (string-builder))\n\
+                 (def fill (fn (n) (when (> n 0) (sb-append! b n \",\") (fill (- n 1)))))\n\
+                 (fill 3)\n\
+                 (sb-append! b \"go\" '!)\n\
+                 (sb-build b)"
+            ),
+            Ok("\"3,2,1,go!\"".to_string())
+        );
+        assert_eq!(
+            eval_source("(sb-build (sb-append! (sb-append! (string-builder) 1) 2))"),
+            Ok("\"12\"".to_string())
+        );
+        assert_eq!(
+            eval_source("(sb-build (string-builder))"),
+            Ok("\"\"".to_string())
+        );
+
+        assert!(eval_source("(sb-build \"text\")").is_err());
+        assert!(eval_source("(sb-append! (string-builder))").is_ok());
+    }
+
+    #[test]
+    fn closed_builders_fail() {
+        let mut env = CrispEnv::default();
+        let b = run_program("(def b (string-builder)) b", &mut env).unwrap();
+        let CrispExpr::Handle(handle) = b else {
+            panic!("string-builder made {b:?}");
+        };
+        handle.close();
+
+        assert_eq!(
+            run_program("(sb-build b)", &mut env).map_err(|err| err.message()),
+            Err("string builder is closed".to_string())
+        );
+    }
+}
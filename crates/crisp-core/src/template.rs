@@ -0,0 +1,97 @@
+//! `render-template`, which fills in the `{{name}}` placeholders of a
+//! template from a map, like a very small mustache. A name is looked up as
+//! a keyword, then as a string, and `{{user.name}}` looks up `name` in the
+//! map under `user`.
+
+use crate::{
+    format::to_display,
+    lang::{CrispError, CrispExpr, CrispFn, CrispResult, MapKey, Primitive},
+};
+
+/// The template builtins, registered under `str/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![("render-template", CrispFn::new(render_template))]
+}
+
+/// `(render-template template map)`
+fn render_template(args: &[CrispExpr]) -> CrispResult {
+    let [CrispExpr::Primitive(Primitive::Str(template)), values @ CrispExpr::Map(_)] = args else {
+        return Err(CrispError::EvalError(
+            "render-template takes a template string and a map".to_string(),
+        ));
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            return Err(CrispError::EvalError(format!(
+                "the template has a '{{{{' without a '}}}}' after it: {}",
+                &rest[start..]
+            )));
+        };
+
+        let name = rest[start + 2..start + end].trim();
+        rendered.push_str(&to_display(lookup(values, name)?));
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(CrispExpr::Primitive(Primitive::Str(rendered)))
+}
+
+/// The value a placeholder's name refers to, following its dots
+fn lookup<'a>(values: &'a CrispExpr, name: &str) -> Result<&'a CrispExpr, CrispError> {
+    let missing = || {
+        CrispError::EvalError(format!(
+            "the template uses {{{{{name}}}}}, which the map doesn't have"
+        ))
+    };
+
+    let mut value = values;
+    for part in name.split('.') {
+        let CrispExpr::Map(map) = value else {
+            return Err(missing());
+        };
+        let keyword = MapKey::new(CrispExpr::Symbol(format!(":{part}")))?;
+        let string = MapKey::new(CrispExpr::Primitive(Primitive::Str(part.to_string())))?;
+        value = map
+            .get(&keyword)
+            .or_else(|| map.get(&string))
+            .ok_or_else(missing)?;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn render_templates() {
+        assert_eq!(
+            eval_source("(render-template \"Hello {{name}}!\" {:name \"Ana\"})"),
+            Ok("\"Hello Ana!\"".to_string())
+        );
+        assert_eq!(
+            eval_source(
+                "(render-template \"{{ user.name }} is {{user.age}}: {{n}}\" \
+                 {:user {:name \"Ana\" :age 4} \"n\" [1 2]})"
+            ),
+            Ok("\"Ana is 4: [1 2]\"".to_string())
+        );
+        assert_eq!(
+            eval_source("(render-template \"no placeholders\" {})"),
+            Ok("\"no placeholders\"".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(render-template \"{{name}}\" {:user 1})"),
+            Err("the template uses {{name}}, which the map doesn't have".to_string())
+        );
+        assert!(eval_source("(render-template \"{{user.name}}\" {:user 1})").is_err());
+        assert!(eval_source("(render-template \"Hi {{name\" {:name 1})").is_err());
+        assert!(eval_source("(render-template \"{{name}}\" '((:name 1)))").is_err());
+    }
+}
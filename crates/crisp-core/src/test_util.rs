@@ -0,0 +1,16 @@
+//! Helpers shared by the unit tests of the builtin modules
+
+use crate::{eval::CrispEnv, format::to_source, run_program};
+
+/// Run a program in a fresh default env, giving its value as source or its
+/// error message
+pub(crate) fn eval_source(src: &str) -> Result<String, String> {
+    eval_source_in(src, &mut CrispEnv::default())
+}
+
+/// Like `eval_source`, in an env that outlives the program
+pub(crate) fn eval_source_in(src: &str, env: &mut CrispEnv) -> Result<String, String> {
+    run_program(src, env)
+        .map(|val| to_source(&val))
+        .map_err(|err| err.message())
+}
@@ -0,0 +1,169 @@
+//! Proptest strategies for generating programs, enabled by the `testing`
+//! feature, so embedders can property-test their own builtins and preludes
+//! the same way crisp tests itself:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn never_panics(expr in crisp_core::testing::builtin_call()) {
+//!         let _ = crisp_core::eval::eval(&expr, &mut my_env());
+//!     }
+//! }
+//! ```
+
+use arbitrary::{Arbitrary, Unstructured};
+use proptest::prelude::*;
+
+use crate::{
+    doc::BUILTINS,
+    eval::SPECIAL_FORMS,
+    fuzz::Program,
+    lang::{CrispExpr, Primitive},
+};
+
+/// Most bytes fed to the `Arbitrary` impls behind these strategies
+const MAX_BYTES: usize = 512;
+
+/// Any expression that can be written in source, well-formed or not
+pub fn expr() -> impl Strategy<Value = CrispExpr> {
+    from_bytes()
+}
+
+/// A well-formed program, which always evaluates without errors
+pub fn program() -> impl Strategy<Value = Program> {
+    from_bytes()
+}
+
+/// A call to a builtin or special form with arbitrary arguments, which are
+/// mostly of the wrong type or number
+pub fn builtin_call() -> impl Strategy<Value = CrispExpr> {
+    let names: Vec<&str> = BUILTINS
+        .iter()
+        .map(|doc| doc.name)
+        .chain(SPECIAL_FORMS.iter().copied())
+        .collect();
+
+    (
+        proptest::sample::select(names),
+        proptest::collection::vec(expr(), 0..4),
+    )
+        .prop_map(|(name, args)| {
+            let mut list = vec![CrispExpr::Symbol(name.to_string())];
+            list.extend(args);
+            CrispExpr::List(list)
+        })
+}
+
+/// Nested `+`, `-` and `*` calls on small ints, for checking against
+/// `reference_arithmetic`
+pub fn arithmetic() -> impl Strategy<Value = CrispExpr> {
+    let leaf = (-100..=100i64).prop_map(|n| CrispExpr::Primitive(Primitive::Int(n)));
+
+    leaf.prop_recursive(4, 64, 3, |inner| {
+        (
+            proptest::sample::select(vec!["+", "-", "*"]),
+            proptest::collection::vec(inner, 0..=3),
+        )
+            .prop_map(|(op, args)| {
+                let mut list = vec![CrispExpr::Symbol(op.to_string())];
+                list.extend(args);
+                CrispExpr::List(list)
+            })
+    })
+}
+
+/// What an expression from `arithmetic` should evaluate to, worked out
+/// independently of the evaluator, or `None` if it should fail, like on
+/// overflow
+pub fn reference_arithmetic(expr: &CrispExpr) -> Option<i64> {
+    match expr {
+        CrispExpr::Primitive(Primitive::Int(n)) => Some(*n),
+        CrispExpr::List(list) => {
+            let (CrispExpr::Symbol(op), args) = list.split_first()? else {
+                return None;
+            };
+            let args = args
+                .iter()
+                .map(reference_arithmetic)
+                .collect::<Option<Vec<i64>>>()?;
+
+            match op.as_str() {
+                "+" => args.iter().try_fold(0i64, |acc, x| acc.checked_add(*x)),
+                "*" => args.iter().try_fold(1i64, |acc, x| acc.checked_mul(*x)),
+                "-" => {
+                    let (first, rest) = args.split_first()?;
+                    rest.iter().try_fold(*first, |acc, x| acc.checked_sub(*x))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A strategy building values from random bytes with an `Arbitrary` impl,
+/// so proptest and the fuzzers generate the same kinds of programs
+fn from_bytes<T>() -> impl Strategy<Value = T>
+where
+    T: for<'a> Arbitrary<'a> + std::fmt::Debug,
+{
+    proptest::collection::vec(any::<u8>(), 0..MAX_BYTES)
+        .prop_filter_map("not enough bytes", |bytes| {
+            T::arbitrary(&mut Unstructured::new(&bytes)).ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        eval::{eval, CrispEnv, EvalOptions},
+        format::to_source,
+        run_program,
+    };
+
+    fn limited_env() -> CrispEnv<'static> {
+        CrispEnv::with_options(EvalOptions {
+            max_depth: Some(64),
+            max_steps: Some(10_000),
+            ..Default::default()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn eval_never_panics(expr in builtin_call()) {
+            let _ = eval(&expr, &mut limited_env());
+        }
+
+        #[test]
+        fn arithmetic_matches_reference(expr in arithmetic()) {
+            let res = eval(&expr, &mut limited_env());
+
+            match (res, reference_arithmetic(&expr)) {
+                (Ok(CrispExpr::Primitive(Primitive::Int(n))), Some(expected)) => {
+                    prop_assert_eq!(n, expected);
+                }
+                (Err(_), None) => {}
+                (res, expected) => prop_assert!(false, "got {res:?}, expected {expected:?}"),
+            }
+        }
+
+        #[test]
+        fn printed_programs_evaluate_the_same(program in program()) {
+            let mut env = limited_env();
+            let direct = program
+                .forms
+                .iter()
+                .map(|form| eval(form, &mut env))
+                .last()
+                .unwrap();
+
+            let printed = run_program(&program.source(), &mut limited_env());
+            prop_assert_eq!(
+                direct.map(|value| to_source(&value)),
+                printed.map(|value| to_source(&value))
+            );
+        }
+    }
+}
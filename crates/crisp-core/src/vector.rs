@@ -0,0 +1,104 @@
+//! Vectors, written like `[1 2 3]`, hold values that can be looked up by
+//! index in constant time. Like every other value they're immutable, so
+//! `vector-set` gives a changed copy.
+
+use crate::lang::{CrispError, CrispExpr, CrispFn, CrispResult, Primitive};
+
+/// The vector builtins, registered under `vector/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "vec",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::List(items) | CrispExpr::Vector(items)] => {
+                    Ok(CrispExpr::Vector(items.clone()))
+                }
+                _ => Err(CrispError::EvalError(
+                    "vec takes exactly one list or vector".to_string(),
+                )),
+            }),
+        ),
+        (
+            "vector-ref",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Vector(items), i] => Ok(items[index(i, items.len())?].clone()),
+                _ => Err(CrispError::EvalError(
+                    "vector-ref takes a vector and an index".to_string(),
+                )),
+            }),
+        ),
+        ("vector-set", CrispFn::new(vector_set)),
+        (
+            "vector-len",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Vector(items)] => {
+                    Ok(CrispExpr::Primitive(Primitive::Int(items.len() as i64)))
+                }
+                _ => Err(CrispError::EvalError(
+                    "vector-len takes exactly one vector".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// A copy of a vector with the item at an index replaced
+fn vector_set(args: &[CrispExpr]) -> CrispResult {
+    let [CrispExpr::Vector(items), i, item] = args else {
+        return Err(CrispError::EvalError(
+            "vector-set takes a vector, an index and a value".to_string(),
+        ));
+    };
+
+    let i = index(i, items.len())?;
+    let mut items = items.clone();
+    items[i] = item.clone();
+    Ok(CrispExpr::Vector(items))
+}
+
+/// Check that `i` is an int indexing a vector of length `len`
+fn index(i: &CrispExpr, len: usize) -> Result<usize, CrispError> {
+    match i {
+        CrispExpr::Primitive(Primitive::Int(i)) if (0..len as i64).contains(i) => Ok(*i as usize),
+        CrispExpr::Primitive(Primitive::Int(i)) => Err(CrispError::EvalError(format!(
+            "index {i} is out of bounds for a vector of length {len}"
+        ))),
+        _ => Err(CrispError::EvalError(
+            "vector indexes must be ints".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_source;
+
+    #[test]
+    fn build_and_index_vectors() {
+        assert_eq!(
+            eval_source("[1 (+ 1 1) \"three\"]"),
+            Ok("[1 2 \"three\"]".to_string())
+        );
+        assert_eq!(eval_source("(quote [a (b)])"), Ok("[a (b)]".to_string()));
+        assert_eq!(eval_source("(vector-ref [1 2 3] 2)"), Ok("3".to_string()));
+        assert_eq!(
+            eval_source("(def v [1 2 3]) (vector-set v 0 [v])"),
+            Ok("[[[1 2 3]] 2 3]".to_string())
+        );
+        assert_eq!(
+            eval_source("(vector-len (vec (quote (a b))))"),
+            Ok("2".to_string())
+        );
+        assert_eq!(
+            eval_source("(= [1 2] (vec (quote (1 2))))"),
+            Ok("true".to_string())
+        );
+
+        assert_eq!(
+            eval_source("(vector-ref [1 2 3] 3)"),
+            Err("index 3 is out of bounds for a vector of length 3".to_string())
+        );
+        assert!(eval_source("(vector-ref [1 2 3] 1.0)").is_err());
+        assert!(eval_source("(vector-ref (quote (1 2)) 0)").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Walking and rewriting expressions. The traversals keep their own stack
+//! rather than recursing, so deeply nested programs can't overflow the
+//! Rust stack however tools use them.
+
+use crate::lang::{CrispExpr, Primitive};
+
+/// What to do after entering an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Walk {
+    /// Visit the items of a list or vector
+    Continue,
+    /// Go straight to `exit`, e.g. for quoted data
+    SkipChildren,
+}
+
+/// Callbacks for `walk`, called on every expression in depth-first order
+pub trait Visitor {
+    /// Called before the items of a list or vector are visited
+    fn enter(&mut self, _expr: &CrispExpr) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called once the items of a list or vector have been visited, or
+    /// straight after `enter` for anything else
+    fn exit(&mut self, _expr: &CrispExpr) {}
+}
+
+/// Visit `expr` and everything nested in it
+pub fn walk(expr: &CrispExpr, visitor: &mut impl Visitor) {
+    enum Step<'e> {
+        Enter(&'e CrispExpr),
+        Exit(&'e CrispExpr),
+    }
+
+    let mut stack = vec![Step::Enter(expr)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Enter(expr) => {
+                let walk = visitor.enter(expr);
+                stack.push(Step::Exit(expr));
+                if let (Walk::Continue, CrispExpr::List(items) | CrispExpr::Vector(items)) =
+                    (walk, expr)
+                {
+                    stack.extend(items.iter().rev().map(Step::Enter));
+                }
+            }
+            Step::Exit(expr) => visitor.exit(expr),
+        }
+    }
+}
+
+/// Rebuild `expr` from the bottom up, replacing every expression with what
+/// `f` returns for it. Lists and vectors are passed to `f` after their
+/// items have been rewritten.
+pub fn rewrite(expr: CrispExpr, mut f: impl FnMut(CrispExpr) -> CrispExpr) -> CrispExpr {
+    enum Step {
+        Visit(CrispExpr),
+        /// Gather this many rewritten items back into a list
+        Build(usize),
+        /// Gather this many rewritten items back into a vector
+        BuildVector(usize),
+    }
+
+    let mut stack = vec![Step::Visit(expr)];
+    let mut done = vec![];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Visit(CrispExpr::List(items)) => {
+                stack.push(Step::Build(items.len()));
+                stack.extend(items.into_iter().rev().map(Step::Visit));
+            }
+            Step::Visit(CrispExpr::Vector(items)) => {
+                stack.push(Step::BuildVector(items.len()));
+                stack.extend(items.into_iter().rev().map(Step::Visit));
+            }
+            Step::Visit(expr) => done.push(f(expr)),
+            Step::Build(len) => {
+                let items = done.split_off(done.len() - len);
+                done.push(f(CrispExpr::List(items)));
+            }
+            Step::BuildVector(len) => {
+                let items = done.split_off(done.len() - len);
+                done.push(f(CrispExpr::Vector(items)));
+            }
+        }
+    }
+
+    done.pop().unwrap_or(CrispExpr::Primitive(Primitive::Nil))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::to_source, lexer, parse::parse};
+
+    fn expr(src: &str) -> CrispExpr {
+        parse(&lexer(src)).unwrap().0
+    }
+
+    /// Records the order expressions are entered and exited in
+    struct Trace(Vec<String>);
+
+    impl Visitor for Trace {
+        fn enter(&mut self, expr: &CrispExpr) -> Walk {
+            self.0.push(format!("> {}", to_source(expr)));
+            match expr {
+                CrispExpr::List(items) if matches!(items.first(), Some(CrispExpr::Symbol(head)) if head == "quote") => {
+                    Walk::SkipChildren
+                }
+                _ => Walk::Continue,
+            }
+        }
+
+        fn exit(&mut self, expr: &CrispExpr) {
+            self.0.push(format!("< {}", to_source(expr)));
+        }
+    }
+
+    #[test]
+    fn walk_enters_and_exits_in_order() {
+        let mut trace = Trace(vec![]);
+        walk(&expr("(f 1 (quote (2)))"), &mut trace);
+
+        assert_eq!(
+            trace.0,
+            [
+                "> (f 1 (quote (2)))",
+                "> f",
+                "< f",
+                "> 1",
+                "< 1",
+                "> (quote (2))",
+                "< (quote (2))",
+                "< (f 1 (quote (2)))",
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrite_from_the_bottom_up() {
+        // Fold additions of numbers, which only works once the inner ones
+        // have already been folded
+        let folded = rewrite(expr("(* x (+ 1 (+ 2 3)))"), |expr| match &expr {
+            CrispExpr::List(items) if items.first() == Some(&CrispExpr::Symbol("+".into())) => {
+                let numbers = items[1..]
+                    .iter()
+                    .map(|item| match item {
+                        CrispExpr::Primitive(Primitive::Int(n)) => Some(*n),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<i64>>>();
+                numbers.map_or(expr, |numbers| {
+                    CrispExpr::Primitive(Primitive::Int(numbers.iter().sum()))
+                })
+            }
+            _ => expr,
+        });
+
+        assert_eq!(to_source(&folded), "(* x 6)");
+    }
+}
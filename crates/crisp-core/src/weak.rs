@@ -0,0 +1,101 @@
+//! Weak references, for caches in long-running sessions that shouldn't keep
+//! what they cache alive forever.
+//!
+//! Handles are the only values that are shared rather than copied, so
+//! they're the only ones a weak reference can point to. `(weak-ref h)`
+//! doesn't keep the resource behind `h` alive, and `(deref-weak w)` gives a
+//! handle to it again while some other copy of `h` still does, or nil once
+//! every copy is gone.
+
+use std::sync::Weak;
+
+use crate::lang::{CrispError, CrispExpr, CrispFn, Handle, Primitive, Resource};
+
+/// The weak reference builtins, registered under `core/`
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    vec![
+        (
+            "weak-ref",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => Ok(CrispExpr::Handle(Handle::new(WeakRef {
+                    name: format!("weak ref to {}", handle.name()),
+                    target: handle.downgrade(),
+                }))),
+                [_] => Err(CrispError::EvalError(
+                    "weak-ref takes a handle, like a string builder or queue; other values are \
+                     copied rather than shared, so there's nothing to refer to weakly"
+                        .to_string(),
+                )),
+                _ => Err(CrispError::EvalError(
+                    "weak-ref takes exactly one handle".to_string(),
+                )),
+            }),
+        ),
+        (
+            "deref-weak",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => {
+                    let weak: &WeakRef = handle.downcast().ok_or(CrispError::EvalError(
+                        format!("deref-weak expects a weak ref, not {}", handle.name()),
+                    ))?;
+                    Ok(match Handle::upgrade(&weak.target) {
+                        Some(target) => CrispExpr::Handle(target),
+                        None => CrispExpr::Primitive(Primitive::Nil),
+                    })
+                }
+                _ => Err(CrispError::EvalError(
+                    "deref-weak takes exactly one weak ref".to_string(),
+                )),
+            }),
+        ),
+    ]
+}
+
+/// The resource behind the handles `weak-ref` returns. It holds nothing
+/// that needs releasing, so closing it does nothing.
+pub struct WeakRef {
+    name: String,
+    target: Weak<dyn Resource>,
+}
+
+impl Resource for WeakRef {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn close(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval::CrispEnv, test_util::eval_source_in};
+
+    #[test]
+    fn weak_refs_follow_their_handle() {
+        let mut env = CrispEnv::default();
+        eval_source_in(
+            "(def q (queue 1 2)) (def w (weak-ref q)) (def dropped (weak-ref (stack 3)))",
+            &mut env,
+        )
+        .unwrap();
+
+        // Still alive through q, and sharing its items
+        assert_eq!(
+            eval_source_in("(dequeue (deref-weak w)) (peek q)", &mut env),
+            Ok("2".to_string())
+        );
+        // Nothing else kept the stack alive
+        assert_eq!(
+            eval_source_in("(deref-weak dropped)", &mut env),
+            Ok("nil".to_string())
+        );
+        eval_source_in("(undef q)", &mut env).unwrap();
+        assert_eq!(
+            eval_source_in("(deref-weak w)", &mut env),
+            Ok("nil".to_string())
+        );
+
+        assert!(eval_source_in("(weak-ref (quote (1 2)))", &mut env).is_err());
+        assert!(eval_source_in("(deref-weak (queue))", &mut env).is_err());
+    }
+}
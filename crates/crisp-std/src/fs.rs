@@ -0,0 +1,679 @@
+//! Builtins for finding, reading and writing files, for build-script style
+//! automation. Paths are strings, and can also be written as quoted symbols
+//! like `(glob (quote src/*.crisp))`.
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::format::to_display;
+
+use crate::lang::{CrispError, CrispExpr, CrispFn, Handle, Loader, Primitive, Resource};
+
+/// The file system builtins, registered under `fs/`. The temp files and
+/// directories they create are removed once the env is dropped.
+pub(crate) fn builtins() -> Vec<(&'static str, CrispFn)> {
+    let temps = Arc::new(TempPaths::default());
+    let temp_dirs = temps.clone();
+
+    vec![
+        (
+            "glob",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let pattern = path_arg("glob", args)?;
+                Ok(paths(glob(&pattern)?))
+            }),
+        ),
+        (
+            "list-dir",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let dir = path_arg("list-dir", args)?;
+                Ok(paths(entries(Path::new(&dir))?))
+            }),
+        ),
+        (
+            "open",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let (path, mode) = match args {
+                    [path] => (path, ":read"),
+                    [path, CrispExpr::Symbol(mode)] => (path, mode.as_str()),
+                    _ => {
+                        return Err(CrispError::EvalError(
+                            "open takes a path and optionally :read, :write or :append".to_string(),
+                        ))
+                    }
+                };
+                let path = path_arg("open", std::slice::from_ref(path))?;
+                Ok(CrispExpr::Handle(Handle::new(File::open(&path, mode)?)))
+            }),
+        ),
+        (
+            "close",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => {
+                    handle.close();
+                    Ok(CrispExpr::Primitive(Primitive::Nil))
+                }
+                _ => Err(CrispError::EvalError(
+                    "close takes exactly one handle".to_string(),
+                )),
+            }),
+        ),
+        (
+            "read-text",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle)] => {
+                    let text = File::from_handle("read-text", handle)?.read_to_end()?;
+                    Ok(CrispExpr::Primitive(Primitive::Str(text)))
+                }
+                _ => Err(CrispError::EvalError(
+                    "read-text takes exactly one handle".to_string(),
+                )),
+            }),
+        ),
+        (
+            "write-text",
+            CrispFn::new(|args: &[CrispExpr]| match args {
+                [CrispExpr::Handle(handle), values @ ..] => {
+                    let text = values.iter().map(to_display).collect::<String>();
+                    File::from_handle("write-text", handle)?.write(&text)?;
+                    Ok(CrispExpr::Handle(handle.clone()))
+                }
+                _ => Err(CrispError::EvalError(
+                    "write-text takes a handle and the values to write".to_string(),
+                )),
+            }),
+        ),
+        (
+            "temp-file",
+            CrispFn::new(move |_: &[CrispExpr]| {
+                let path = temps.create(|path| {
+                    OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(path)
+                        .map(drop)
+                })?;
+                Ok(CrispExpr::Primitive(Primitive::Str(path)))
+            }),
+        ),
+        (
+            "temp-dir",
+            CrispFn::new(move |_: &[CrispExpr]| {
+                let path = temp_dirs.create(|path| fs::create_dir(path))?;
+                Ok(CrispExpr::Primitive(Primitive::Str(path)))
+            }),
+        ),
+        (
+            "is-dir?",
+            CrispFn::new(|args: &[CrispExpr]| {
+                let path = path_arg("is-dir?", args)?;
+                Ok(CrispExpr::Primitive(Primitive::Bool(
+                    Path::new(&path).is_dir(),
+                )))
+            }),
+        ),
+    ]
+}
+
+/// An open file, the resource behind the handles `open` returns. It's
+/// closed by `close` or when the `with-open` that opened it ends.
+pub struct File {
+    path: String,
+    file: Mutex<Option<OpenFile>>,
+}
+
+enum OpenFile {
+    Read(BufReader<fs::File>),
+    Write(fs::File),
+}
+
+impl File {
+    /// Open a file for `:read`, `:write` (truncating it) or `:append`
+    pub fn open(path: &str, mode: &str) -> Result<Self, CrispError> {
+        let mut options = OpenOptions::new();
+        match mode {
+            ":read" => options.read(true),
+            ":write" => options.write(true).create(true).truncate(true),
+            ":append" => options.append(true).create(true),
+            _ => {
+                return Err(CrispError::EvalError(format!(
+                    "can't open a file for {mode}, only :read, :write or :append"
+                )))
+            }
+        };
+
+        let file = options
+            .open(path)
+            .map_err(|err| CrispError::EvalError(format!("can't open {path}: {err}")))?;
+        let file = match mode {
+            ":read" => OpenFile::Read(BufReader::new(file)),
+            _ => OpenFile::Write(file),
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// The file a handle refers to, failing for other resources
+    pub fn from_handle<'h>(builtin: &str, handle: &'h Handle) -> Result<&'h File, CrispError> {
+        handle.downcast().ok_or(CrispError::EvalError(format!(
+            "{builtin} expects a file, not {}",
+            handle.name()
+        )))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.file.lock().is_ok_and(|file| file.is_some())
+    }
+
+    /// Run `f` on the open file, failing if it's been closed
+    fn with_file<T>(
+        &self,
+        f: impl FnOnce(&mut OpenFile) -> std::io::Result<T>,
+    ) -> Result<T, CrispError> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| CrispError::EvalError(format!("{} is poisoned", self.path)))?;
+        let file = file
+            .as_mut()
+            .ok_or(CrispError::EvalError(format!("{} is closed", self.path)))?;
+
+        f(file).map_err(|err| CrispError::EvalError(format!("can't use {}: {err}", self.path)))
+    }
+
+    /// Read a line without its line ending, or `None` at the end of the file
+    pub fn read_line(&self) -> Result<Option<String>, CrispError> {
+        self.with_file(|file| {
+            let OpenFile::Read(reader) = file else {
+                return Err(std::io::Error::other("it's open for writing"));
+            };
+
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(Some(line))
+        })
+    }
+
+    /// Read the rest of the file
+    pub fn read_to_end(&self) -> Result<String, CrispError> {
+        self.with_file(|file| {
+            let OpenFile::Read(reader) = file else {
+                return Err(std::io::Error::other("it's open for writing"));
+            };
+
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            Ok(text)
+        })
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), CrispError> {
+        self.with_file(|file| match file {
+            OpenFile::Write(file) => file.write_all(text.as_bytes()),
+            OpenFile::Read(_) => Err(std::io::Error::other("it's open for reading")),
+        })
+    }
+}
+
+impl Resource for File {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    /// Close the file, flushing anything written
+    fn close(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(OpenFile::Write(file)) = file.take() {
+                let _ = file.sync_all();
+            }
+        }
+    }
+}
+
+/// Loads files for `load` from the file system. A relative path is looked
+/// for in the directory of the file loading it, or the working directory
+/// outside any file, then in each directory of the search path, unless it
+/// starts with `./` or `../`. A path without an extension gets `.crisp`.
+#[derive(Debug, Default, Clone)]
+pub struct FileLoader {
+    /// Directories to look for files in, in order
+    pub search_path: Vec<PathBuf>,
+}
+
+impl FileLoader {
+    /// A loader searching the directories in `CRISP_PATH`, which are
+    /// separated like those in `PATH`
+    pub fn from_env() -> Self {
+        Self {
+            search_path: std::env::var_os("CRISP_PATH")
+                .map(|dirs| std::env::split_paths(&dirs).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Where to look for `path` when it's loaded from the file `from`, in
+    /// order
+    fn candidates(&self, path: &Path, from: Option<&str>) -> Vec<PathBuf> {
+        if path.is_absolute() {
+            return vec![path.to_path_buf()];
+        }
+
+        let dir = from
+            .and_then(|from| Path::new(from).parent())
+            .unwrap_or(Path::new(""));
+        let explicit = matches!(
+            path.components().next(),
+            Some(Component::CurDir | Component::ParentDir)
+        );
+        let search_path = if explicit {
+            &[][..]
+        } else {
+            &self.search_path[..]
+        };
+
+        std::iter::once(dir)
+            .chain(search_path.iter().map(PathBuf::as_path))
+            .map(|dir| normalize(&dir.join(path)))
+            .collect()
+    }
+}
+
+impl Loader for FileLoader {
+    fn load(&self, name: &str, from: Option<&str>) -> Result<(String, String), CrispError> {
+        let mut path = PathBuf::from(name);
+        if path.extension().is_none() {
+            path.set_extension("crisp");
+        }
+
+        let candidates = self.candidates(&path, from);
+        for candidate in &candidates {
+            match fs::read_to_string(candidate) {
+                Ok(src) => return Ok((candidate.display().to_string(), src)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(CrispError::EvalError(format!(
+                        "can't load {}: {err}",
+                        candidate.display()
+                    )))
+                }
+            }
+        }
+
+        let searched: Vec<_> = candidates
+            .iter()
+            .map(|candidate| candidate.display().to_string())
+            .collect();
+        Err(CrispError::EvalError(format!(
+            "can't find {}, searched {}",
+            path.display(),
+            searched.join(", ")
+        )))
+    }
+}
+
+/// A path without `.` parts and with `..` parts applied where they can be,
+/// so one file loaded through different paths is recognised
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            part => normal.push(part),
+        }
+    }
+    normal
+}
+
+/// The temp files and directories an env's builtins have created, deleted
+/// along with them
+#[derive(Default)]
+struct TempPaths(Mutex<Vec<PathBuf>>);
+
+impl TempPaths {
+    /// Create a file or directory with a name in the temp dir that isn't
+    /// taken yet, returning its path
+    fn create(&self, create: impl Fn(&Path) -> std::io::Result<()>) -> Result<String, CrispError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("crisp-{}-{n}", std::process::id()));
+            match create(&path) {
+                Ok(()) => {
+                    let display = path.display().to_string();
+                    self.0
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .push(path);
+                    return Ok(display);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(CrispError::EvalError(format!(
+                        "can't create a temp file: {err}"
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TempPaths {
+    fn drop(&mut self) {
+        for path in self
+            .0
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner())
+            .drain(..)
+        {
+            // Whatever the script already removed or moved is left alone
+            let _ = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+        }
+    }
+}
+
+/// The single path argument of a builtin
+fn path_arg(name: &str, args: &[CrispExpr]) -> Result<String, CrispError> {
+    match args {
+        [CrispExpr::Primitive(Primitive::Str(path)) | CrispExpr::Symbol(path)] => Ok(path.clone()),
+        _ => Err(CrispError::EvalError(format!(
+            "{name} takes exactly one path"
+        ))),
+    }
+}
+
+fn paths(paths: Vec<PathBuf>) -> CrispExpr {
+    CrispExpr::List(
+        paths
+            .into_iter()
+            .map(|path| CrispExpr::Primitive(Primitive::Str(path.display().to_string())))
+            .collect(),
+    )
+}
+
+/// The entries of a directory, sorted so results don't depend on the OS
+fn entries(dir: &Path) -> Result<Vec<PathBuf>, CrispError> {
+    let read = fs::read_dir(dir)
+        .map_err(|err| CrispError::EvalError(format!("can't list {}: {err}", dir.display())))?;
+
+    let mut paths = read
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    Ok(paths)
+}
+
+/// The paths matching a pattern, sorted. In each `/`-separated part of the
+/// pattern `*` matches any run of characters and `?` any one character,
+/// and a part that's just `**` matches any number of directories. Wildcards
+/// don't match hidden names, starting with `.`, unless the part does too.
+pub fn glob(pattern: &str) -> Result<Vec<PathBuf>, CrispError> {
+    let (root, parts) = match pattern.strip_prefix('/') {
+        Some(rest) => (PathBuf::from("/"), rest),
+        None => (PathBuf::new(), pattern),
+    };
+    let parts = parts
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+
+    let mut found = vec![];
+    glob_in(&root, &parts, &mut found)?;
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+fn glob_in(dir: &Path, parts: &[&str], found: &mut Vec<PathBuf>) -> Result<(), CrispError> {
+    let Some((part, rest)) = parts.split_first() else {
+        found.push(dir.to_path_buf());
+        return Ok(());
+    };
+
+    let listed = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    if *part == "**" {
+        glob_in(dir, rest, found)?;
+        if !listed.is_dir() {
+            return Ok(());
+        }
+        for entry in entries(listed)? {
+            if entry.is_dir() && !is_hidden(&entry) {
+                glob_in(&dir.join(file_name(&entry)), parts, found)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if !part.contains(['*', '?']) {
+        let path = dir.join(part);
+        if path.exists() {
+            glob_in(&path, rest, found)?;
+        }
+        return Ok(());
+    }
+
+    if !listed.is_dir() {
+        return Ok(());
+    }
+    for entry in entries(listed)? {
+        let name = file_name(&entry);
+        if (part.starts_with('.') || !is_hidden(&entry)) && matches(part, &name) {
+            glob_in(&dir.join(name), rest, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    file_name(path).starts_with('.')
+}
+
+/// Whether a name matches a pattern of `*` and `?` wildcards
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+
+    // Where to resume after the last `*`, if there was one
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the `*` match one more character and try again
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::CrispEnvBuilder, StdBuilder};
+
+    #[test]
+    fn with_open_closes_handles() {
+        let mut env = CrispEnvBuilder::new().all().build();
+        crate::run_program("(def path (temp-file))", &mut env).unwrap();
+
+        let prog = "(with-open (f (open path :write)) (write-text f 1 (quote x)) (+ 1 y))";
+        assert!(crate::run_program(prog, &mut env).is_err());
+        assert_eq!(
+            crate::run_program(
+                "(with-open (f (open path)) (def-global g f) (read-text f))",
+                &mut env
+            ),
+            Ok(CrispExpr::Primitive(Primitive::Str("1x".to_string())))
+        );
+        // The handle is closed even though it escaped
+        assert!(crate::run_program("(read-text g)", &mut env).is_err());
+    }
+
+    #[test]
+    fn temp_paths_are_removed_with_the_env() {
+        let mut env = CrispEnvBuilder::new().all().build();
+        let prog = "(def dir (temp-dir)) \
+                    (with-open (f (open (str dir \"/inner\") :write)) (write-text f 1)) \
+                    (list (temp-file) dir)";
+        let paths = match crate::run_program(prog, &mut env) {
+            Ok(CrispExpr::List(paths)) => paths,
+            other => panic!("expected a list of paths, got {other:?}"),
+        };
+        let paths: Vec<_> = paths
+            .iter()
+            .map(|path| match path {
+                CrispExpr::Primitive(Primitive::Str(path)) => PathBuf::from(path),
+                other => panic!("expected a path, got {other:?}"),
+            })
+            .collect();
+        assert!(paths.iter().all(|path| path.exists()));
+
+        drop(env);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+
+    #[test]
+    fn load_files_relative_to_each_other() {
+        let dir = std::env::temp_dir().join(format!("crisp-load-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.crisp"),
+            "(load \"lib/a\") (load \"lib/b.crisp\")",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/a.crisp"),
+            "(load \"../lib/./b\") (def a (+ b 1))",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/b.crisp"), "(def b 1)").unwrap();
+
+        let mut env = CrispEnvBuilder::new().all().build();
+        let main = dir.join("main.crisp").display().to_string();
+        crate::run_program(&format!("(load \"{main}\")"), &mut env).unwrap();
+        assert_eq!(
+            crate::run_program("a", &mut env),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+        // b was loaded once, through two different paths
+        assert_eq!(env.loaded.len(), 3);
+
+        // Files that aren't next to the loading file are found on the search
+        // path, but explicitly relative paths aren't searched for
+        let mut env = CrispEnvBuilder::new()
+            .all()
+            .loader(FileLoader {
+                search_path: vec![dir.join("lib")],
+            })
+            .build();
+        crate::run_program("(load \"b\")", &mut env).unwrap();
+        let err = crate::run_program("(load \"./b\")", &mut env).unwrap_err();
+        assert_eq!(err.message(), "can't find ./b.crisp, searched b.crisp");
+        let err = crate::run_program("(load \"missing\")", &mut env).unwrap_err();
+        assert_eq!(
+            err.message(),
+            format!(
+                "can't find missing.crisp, searched missing.crisp, {}",
+                dir.join("lib/missing.crisp").display()
+            )
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.crisp", "test.crisp"));
+        assert!(matches("t?st*", "test.crisp"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.crisp", "test.crispc"));
+        assert!(!matches("a?c", "ac"));
+    }
+
+    #[test]
+    fn glob_files() {
+        let dir = std::env::temp_dir().join(format!("crisp-glob-{}", std::process::id()));
+        for file in [
+            "a.crisp",
+            "b.txt",
+            "sub/c.crisp",
+            "sub/deep/d.crisp",
+            ".hidden/e.crisp",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let root = dir.display().to_string();
+        let relative = |paths: Vec<PathBuf>| {
+            paths
+                .into_iter()
+                .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            relative(glob(&format!("{root}/*.crisp")).unwrap()),
+            vec!["a.crisp"]
+        );
+        assert_eq!(
+            relative(glob(&format!("{root}/**/*.crisp")).unwrap()),
+            vec!["a.crisp", "sub/c.crisp", "sub/deep/d.crisp"]
+        );
+        assert_eq!(
+            relative(entries(&dir).unwrap()),
+            vec![".hidden", "a.crisp", "b.txt", "sub"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    eval::{CrispEnv, CrispEnvBuilder},
+    io::Io,
+    lang::{CrispError, CrispResult},
+    run_program, snapshot, StdBuilder,
+};
+
+/// An env along with the streams its scripts print to and read from, for
+/// embedding crisp in other programs
+pub struct Interpreter {
+    pub env: CrispEnv<'static>,
+    io: Io,
+}
+
+impl Interpreter {
+    /// Create an interpreter with every group of builtins
+    pub fn new() -> Self {
+        Self::with_builder(CrispEnvBuilder::new().all())
+    }
+
+    /// Create an interpreter with the builtins from `builder`, plus `print`
+    /// and `read-line` bound to the interpreter's streams
+    pub fn with_builder(builder: CrispEnvBuilder) -> Self {
+        let io = Io::default();
+
+        Self {
+            env: builder.io(io.clone()).build(),
+            io,
+        }
+    }
+
+    /// Create an interpreter with every group of builtins and the
+    /// definitions in a snapshot, which is much faster than running the
+    /// program it was taken from. Snapshots made with `crisp snapshot` can
+    /// be embedded with `include_bytes!`.
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self, CrispError> {
+        let io = Io::default();
+
+        Ok(Self {
+            env: snapshot::restore(snapshot, CrispEnvBuilder::new().all().io(io.clone()))?,
+            io,
+        })
+    }
+
+    /// Take a snapshot of everything defined so far, to start another
+    /// interpreter from with `from_snapshot`
+    pub fn snapshot(&self) -> Result<Vec<u8>, CrispError> {
+        snapshot::snapshot(&self.env)
+    }
+
+    /// Send everything scripts print to `out` instead of stdout
+    pub fn set_stdout(&mut self, out: impl Write + Send + 'static) {
+        self.io.set_stdout(out);
+    }
+
+    /// Read lines for `read-line` from `input` instead of stdin
+    pub fn set_stdin(&mut self, input: impl BufRead + Send + 'static) {
+        self.io.set_stdin(input);
+    }
+
+    /// Run a program, returning the value of its last form
+    pub fn eval(&mut self, prog: &str) -> CrispResult {
+        run_program(prog, &mut self.env)
+    }
+
+    /// Run a program like `eval`, returning everything it printed alongside
+    /// its value rather than writing it to stdout
+    pub fn eval_capture(&mut self, prog: &str) -> (CrispResult, String) {
+        let io = self.io.clone();
+        io.capture(|| self.eval(prog))
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::{CrispExpr, Primitive};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer whose contents can be read after handing it over
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn redirect_print_and_read_line() {
+        let out = SharedBuf::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_stdout(out.clone());
+        interpreter.set_stdin(Cursor::new("first\nsecond\n"));
+
+        assert_eq!(
+            interpreter.eval("(print (read-line) 1 (+ 1 1))"),
+            Ok(CrispExpr::Primitive(Primitive::Int(2)))
+        );
+        assert_eq!(
+            interpreter.eval("(read-line)"),
+            Ok(CrispExpr::Primitive(Primitive::Str("second".to_string())))
+        );
+        assert_eq!(
+            interpreter.eval("(read-line)"),
+            Ok(CrispExpr::Primitive(Primitive::Bool(false)))
+        );
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "first 1 2\n"
+        );
+    }
+
+    #[test]
+    fn start_from_snapshot() {
+        let mut prelude = Interpreter::new();
+        prelude
+            .eval("(def greet (fn (name) (print \"hi\" name))) (def say print)")
+            .unwrap();
+        let snapshot = prelude.snapshot().unwrap();
+
+        let mut interpreter = Interpreter::from_snapshot(&snapshot).unwrap();
+        let (_, printed) = interpreter.eval_capture("(greet \"you\") (say 1)");
+        // `say` is the new interpreter's `print`, writing to its own streams
+        assert_eq!(printed, "hi you\n1\n");
+    }
+
+    #[test]
+    fn display_and_write() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_stdin(Cursor::new("say \"hi\"\n"));
+
+        let (res, printed) =
+            interpreter.eval_capture("(def line (read-line)) (display line) (write line 1)");
+        assert_eq!(res, Ok(CrispExpr::Primitive(Primitive::Int(1))));
+        assert_eq!(printed, r#"say "hi""say \"hi\"" 1"#);
+    }
+
+    #[test]
+    fn eval_capture_keeps_output_separate() {
+        let out = SharedBuf::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_stdout(out.clone());
+
+        assert_eq!(
+            interpreter.eval_capture("(print 1) (print (+ 1 1)) 3"),
+            (
+                Ok(CrispExpr::Primitive(Primitive::Int(3))),
+                "1\n2\n".to_string()
+            )
+        );
+        // Errors keep what was printed before them
+        let (res, printed) = interpreter.eval_capture("(print 4) (+ 1 x)");
+        assert!(res.is_err());
+        assert_eq!(printed, "4\n");
+
+        // Printing goes back to the interpreter's stdout afterwards
+        interpreter.eval("(print 5)").unwrap();
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "5\n"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::format::{pretty, to_display, to_source, to_table};
+use crate::fs::File;
+use crate::lang::{CrispError, CrispExpr, CrispFn, CrispResult, Primitive, Tracer};
+
+/// `print`, `display`, `write`, `pprint`, `print-table` and `read-line`,
+/// using the streams in `io`, registered under `io/`
+pub(crate) fn builtins(io: Io) -> Vec<(&'static str, CrispFn)> {
+    // `display` prints strings as their contents and `write` as literals
+    // that can be read back; `print` displays and ends the line, and
+    // `pprint` writes across lines like the formatter
+    let printers = [
+        ("print", to_display as fn(&CrispExpr) -> String, "\n"),
+        ("display", to_display, ""),
+        ("write", to_source, ""),
+        ("pprint", |expr| pretty(expr, 0), "\n"),
+    ];
+
+    let mut builtins = vec![];
+    for (name, show, end) in printers {
+        let out = io.clone();
+        builtins.push((
+            name,
+            CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+                let text = args.iter().map(show).collect::<Vec<String>>().join(" ");
+                out.print(&(text + end))
+                    .map_err(|err| CrispError::EvalError(format!("couldn't print: {err}")))?;
+
+                Ok(args
+                    .last()
+                    .cloned()
+                    .unwrap_or(CrispExpr::Primitive(Primitive::Nil)))
+            }),
+        ));
+    }
+
+    let out = io.clone();
+    builtins.push((
+        "print-table",
+        CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+            let table = match args {
+                [CrispExpr::List(rows)] if rows.is_empty() => String::new(),
+                [rows] => to_table(rows)
+                    .map(|table| table + "\n")
+                    .ok_or(CrispError::EvalError(
+                        "print-table takes a list of rows".to_string(),
+                    ))?,
+                _ => {
+                    return Err(CrispError::EvalError(
+                        "print-table takes exactly one list of rows".to_string(),
+                    ))
+                }
+            };
+            out.print(&table)
+                .map_err(|err| CrispError::EvalError(format!("couldn't print: {err}")))?;
+
+            Ok(args[0].clone())
+        }),
+    ));
+
+    builtins.push((
+        "read-line",
+        CrispFn::new(move |args: &[CrispExpr]| -> CrispResult {
+            let line = match args {
+                [CrispExpr::Handle(handle)] => {
+                    File::from_handle("read-line", handle)?.read_line()?
+                }
+                _ => io
+                    .read_line()
+                    .map_err(|err| CrispError::EvalError(format!("couldn't read a line: {err}")))?,
+            };
+
+            Ok(CrispExpr::Primitive(match line {
+                Some(line) => Primitive::Str(line),
+                None => Primitive::Bool(false),
+            }))
+        }),
+    ));
+
+    builtins
+}
+
+/// Where `print` writes and `read-line` reads. Clones share the same
+/// streams, so an embedder can keep one to redirect them after the builtins
+/// have been created.
+#[derive(Clone)]
+pub struct Io {
+    stdout: Arc<Mutex<Box<dyn Write + Send>>>,
+    stdin: Arc<Mutex<Input>>,
+}
+
+/// A writer that can still be read after being handed over
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self
+            .0
+            .lock()
+            .map_err(|_| io::Error::other("buffer is poisoned"))?;
+        inner.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Input {
+    /// The process's stdin. Read through `io::stdin()` rather than a reader
+    /// of our own, so lines we don't ask for stay buffered for everyone else.
+    Stdin,
+    Reader(Box<dyn BufRead + Send>),
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Self {
+            stdout: Arc::new(Mutex::new(Box::new(io::stdout()))),
+            stdin: Arc::new(Mutex::new(Input::Stdin)),
+        }
+    }
+}
+
+impl Io {
+    pub fn set_stdout(&self, out: impl Write + Send + 'static) {
+        if let Ok(mut stdout) = self.stdout.lock() {
+            *stdout = Box::new(out);
+        }
+    }
+
+    pub fn set_stdin(&self, input: impl BufRead + Send + 'static) {
+        if let Ok(mut stdin) = self.stdin.lock() {
+            *stdin = Input::Reader(Box::new(input));
+        }
+    }
+
+    /// Run `f` with everything printed going to a buffer instead, returning
+    /// its result along with what was printed
+    pub fn capture<T>(&self, f: impl FnOnce() -> T) -> (T, String) {
+        let buf = Buffer::default();
+        let previous = self.replace_stdout(Box::new(buf.clone()));
+        let res = f();
+        if let Some(previous) = previous {
+            self.replace_stdout(previous);
+        }
+
+        let printed = buf.0.lock().map(|buf| buf.clone()).unwrap_or_default();
+        (res, String::from_utf8_lossy(&printed).into_owned())
+    }
+
+    fn replace_stdout(&self, out: Box<dyn Write + Send>) -> Option<Box<dyn Write + Send>> {
+        let mut stdout = self.stdout.lock().ok()?;
+        Some(std::mem::replace(&mut *stdout, out))
+    }
+
+    pub fn print(&self, text: &str) -> io::Result<()> {
+        let mut stdout = self
+            .stdout
+            .lock()
+            .map_err(|_| io::Error::other("stdout is poisoned"))?;
+
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Read a line without its line ending, or `None` at the end of input
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        let mut stdin = self
+            .stdin
+            .lock()
+            .map_err(|_| io::Error::other("stdin is poisoned"))?;
+
+        let mut line = String::new();
+        let read = match &mut *stdin {
+            Input::Stdin => io::stdin().read_line(&mut line)?,
+            Input::Reader(reader) => reader.read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+}
+
+/// Writes the calls and results traced with `EvalOptions::trace` to the
+/// process's stderr
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrTracer;
+
+impl Tracer for StderrTracer {
+    fn trace(&self, line: &str) {
+        eprintln!("{line}");
+    }
+}
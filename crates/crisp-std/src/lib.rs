@@ -0,0 +1,88 @@
+//! The crisp interpreter with its standard library. Everything from
+//! `crisp-core` is re-exported, along with the builtins that reach outside
+//! the interpreter: printing and reading lines, and the file system.
+
+pub use crisp_core::*;
+
+use eval::CrispEnvBuilder;
+use io::Io;
+use lang::CrispExpr;
+
+pub mod fs;
+pub mod interpreter;
+pub mod io;
+
+/// Groups of builtins with capabilities the core leaves out, added to
+/// `CrispEnvBuilder` so embedders opt into each one
+pub trait StdBuilder {
+    /// Every group of builtins, with `print` and `read-line` using the
+    /// process's stdout and stdin
+    fn all(self) -> Self;
+
+    /// `print`, `display`, `write`, `pprint`, `print-table` and `read-line`,
+    /// using the streams in `io`
+    fn io(self, io: Io) -> Self;
+
+    /// `glob`, `list-dir` and `is-dir?`, plus `open` and the builtins that
+    /// use the handles it returns. Also lets `load` read files, searching the
+    /// directories in `CRISP_PATH` too.
+    fn fs(self) -> Self;
+}
+
+impl StdBuilder for CrispEnvBuilder {
+    fn all(self) -> Self {
+        self.pure().io(Io::default()).fs()
+    }
+
+    fn io(mut self, io: Io) -> Self {
+        for (name, f) in io::builtins(io) {
+            self.builtin("io", name, CrispExpr::Fn(f));
+        }
+
+        self
+    }
+
+    fn fs(mut self) -> Self {
+        for (name, f) in fs::builtins() {
+            self.builtin("fs", name, CrispExpr::Fn(f));
+        }
+
+        self.loader(fs::FileLoader::from_env())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        doc::{lookup, BUILTINS},
+        eval::SPECIAL_FORMS,
+        interpreter::Interpreter,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn std_builtins_are_documented() {
+        let interpreter = Interpreter::new();
+
+        for (name, _) in interpreter.env.bindings() {
+            assert!(lookup(name).is_some(), "{name} has no docs");
+        }
+    }
+
+    #[test]
+    fn examples_run() {
+        for doc in BUILTINS {
+            let mut interpreter = Interpreter::new();
+            // Builtins behind features that aren't enabled can't be run
+            if interpreter.env.get(doc.name).is_none() && !SPECIAL_FORMS.contains(&doc.name) {
+                continue;
+            }
+
+            for example in doc.examples {
+                interpreter.set_stdin(Cursor::new(""));
+                let (res, _) = interpreter.eval_capture(example);
+                assert!(res.is_ok(), "{example} failed: {res:?}");
+            }
+        }
+    }
+}